@@ -0,0 +1,196 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{CheckResultStatus, PinglowCheck};
+
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct SimpleCheckDto {
+    pub check_name: String, // namespace/name outside of the namespace of the controller
+    #[serde(default)]
+    pub namespace: Option<String>,
+    pub passive: bool,
+    pub interval: Option<u64>,
+    //pub language: Option<ScriptLanguage>,
+    pub status: CheckResultStatus,
+    pub timestamp: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+    #[serde(default)]
+    pub suspended: bool,
+}
+
+impl From<&Arc<PinglowCheck>> for SimpleCheckDto {
+    fn from(value: &Arc<PinglowCheck>) -> Self {
+        Self {
+            check_name: value.check_name.clone(),
+            namespace: value.namespace.clone(),
+            passive: value.passive,
+            interval: value.interval,
+            //language: value.as_ref().script.as_ref().map(|c| c.language.clone()),
+            status: CheckResultStatus::Pending,
+            timestamp: None,
+            labels: value.labels.clone(),
+            suspended: value.suspended,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct SimpleCheckResultDto {
+    pub check_name: String,
+    pub passive: bool,
+    pub output: String,
+    #[serde(default)]
+    pub long_output: Option<String>, // Lines following the first one of the output
+    pub status: CheckResultStatus,
+    pub timestamp: Option<DateTime<Utc>>,
+    pub notifications_muted: Option<bool>,
+    pub notifications_muted_until: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub suspended: bool,
+    #[serde(default)]
+    pub soft_failure: bool,
+    #[serde(default)]
+    pub suppressed_by: Option<String>,
+    #[serde(default)]
+    pub ack: Option<CheckAckDto>,
+    #[serde(default)]
+    pub flapping_since: Option<DateTime<Utc>>, // Set while the notifications are suppressed
+}
+
+/// The acknowledgement of a failing check, cleared when the check recovers
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct CheckAckDto {
+    pub acked_by: String,
+    pub comment: Option<String>,
+    pub timestamp: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct AckCheckPayload {
+    /// Who is taking care of the failure
+    pub user: String,
+    #[serde(default)]
+    pub comment: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct CheckResultHistoryDto {
+    pub timestamp: DateTime<Utc>,
+    pub status: CheckResultStatus,
+    pub output: String,
+    #[serde(default)]
+    pub long_output: Option<String>, // Lines following the first one of the output
+    pub soft_failure: bool,
+    pub suppressed_by: Option<String>,
+    #[serde(default)]
+    pub script_hash: Option<String>, // Hash of the script producing the result, if any
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Debug, Default)]
+pub struct StatusCountsDto {
+    pub ok: i64,
+    pub warning: i64,
+    pub critical: i64,
+    pub check_error: i64,
+}
+
+/// The last heartbeat published by a runner
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct RunnerDto {
+    pub name: String,
+    pub version: String,
+    pub in_flight_checks: usize,
+    pub started_at: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    /// Whether the last heartbeat is recent enough for the runner to be considered alive
+    #[serde(default)]
+    pub healthy: bool,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct GroupStatusDto {
+    /// The label selector identifying the group, e.g. team=payments
+    pub group: String,
+    /// The worst status among the checks of the group
+    pub status: CheckResultStatus,
+    pub checks: usize,
+    pub status_counts: StatusCountsDto,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct CheckAvailabilityDto {
+    pub check_name: String,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    /// Percentage of Ok results, None if the check has no results in the window
+    pub availability_percentage: Option<f64>,
+    pub status_counts: StatusCountsDto,
+    /// Longest period without Ok results, from the first failure to the following Ok result
+    pub longest_outage_seconds: f64,
+    pub longest_outage_start: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct CheckDiagnosticsDto {
+    pub check_name: String,
+    pub timestamp: DateTime<Utc>,
+    pub diagnostics: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct NotificationAttemptDto {
+    pub notification_id: i64,
+    pub check_name: String,
+    pub channel_kind: String,
+    pub channel_id: String,
+    pub timestamp: DateTime<Utc>,
+    pub success: bool,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ProcessCheckResultPayload {
+    pub output: String,
+    pub status: i32,
+    #[serde(default)]
+    pub image_jpg_base64: Option<String>, // Base64 image
+}
+
+/// The public summary of the checks selected for the status page, without their output
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone)]
+pub struct StatusPageDto {
+    /// The worst status among the checks of the page
+    pub status: CheckResultStatus,
+    pub updated_at: DateTime<Utc>,
+    pub checks: Vec<StatusPageCheckDto>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Debug, Clone)]
+pub struct StatusPageCheckDto {
+    /// The display name of the check, its name if it has none
+    pub name: String,
+    pub status: CheckResultStatus,
+    /// When the check entered its current status, if it has a result
+    pub since: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct CheckGroupDto {
+    pub name: String,
+    /// How the statuses of the members make up the one of the group: worstOf, quorum or weighted
+    pub aggregation: String,
+    /// The aggregated status of the members
+    pub status: CheckResultStatus,
+    pub members: Vec<GroupMemberDto>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Debug)]
+pub struct GroupMemberDto {
+    pub check_name: String,
+    /// The last status of the check other than a soft failure, Pending if it has no result
+    pub status: CheckResultStatus,
+}
@@ -18,4 +18,12 @@ pub enum ExecutionError {
 pub enum ScriptError {
     #[error("Error: No script found for check: {0}")]
     NoScriptFound(String),
+    #[error("Error: Cannot read the content of the script: {0}")]
+    ContentError(String),
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum SecretError {
+    #[error("Error: No secret {0} found")]
+    NoSecretFound(String),
 }
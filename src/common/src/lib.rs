@@ -1,17 +1,31 @@
 use chrono::{DateTime, Utc};
+use k8s_openapi::api::core::v1::{ConfigMap, ResourceRequirements, Toleration};
 use kube::CustomResource;
-use log::warn;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use std::{cmp::Ordering, collections::HashMap, fmt::Display, sync::Arc};
+use sha2::{Digest, Sha256};
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap},
+    fmt::Display,
+    sync::Arc,
+};
 use tokio::time::Instant;
 use tokio_postgres::Client;
 use utoipa::ToSchema;
 
+use crate::perfdata::{PerfData, PluginOutput};
+
+pub mod dto;
 pub mod error;
+pub mod manifests;
+pub mod parameters;
+pub mod perfdata;
 pub mod redis;
+pub mod source;
+pub mod telemetry;
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, ToSchema)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, ToSchema)]
 pub enum CheckResultStatus {
     Ok,
     Warning,
@@ -20,6 +34,39 @@ pub enum CheckResultStatus {
     Pending,
 }
 
+impl CheckResultStatus {
+    /**
+     * This function tells whether a result with this status is worth a notification, given
+     * the status of the previous result of the check, if any
+     */
+    pub fn is_notable_transition(&self, previous: Option<&CheckResultStatus>) -> bool {
+        let previous = previous.filter(|status| **status != CheckResultStatus::Pending);
+
+        match self {
+            CheckResultStatus::Pending => false,
+            // Recovery
+            CheckResultStatus::Ok => {
+                previous.is_some_and(|status| *status != CheckResultStatus::Ok)
+            }
+            status => previous != Some(status),
+        }
+    }
+
+    /**
+     * This function ranks the statuses from the least to the most severe, to find the worst one
+     * among many checks
+     */
+    pub fn severity(&self) -> u8 {
+        match self {
+            CheckResultStatus::Pending => 0,
+            CheckResultStatus::Ok => 1,
+            CheckResultStatus::Warning => 2,
+            CheckResultStatus::CheckError => 3,
+            CheckResultStatus::Critical => 4,
+        }
+    }
+}
+
 impl From<i32> for CheckResultStatus {
     fn from(value: i32) -> Self {
         match value {
@@ -62,6 +109,12 @@ pub enum ScriptLanguage {
     Python,
     #[serde(rename = "Bash")]
     Bash,
+    #[serde(rename = "Wasm")]
+    Wasm,
+    #[serde(rename = "Node")]
+    Node,
+    #[serde(rename = "PowerShell")]
+    PowerShell,
 }
 
 impl Display for ScriptLanguage {
@@ -69,6 +122,9 @@ impl Display for ScriptLanguage {
         match self {
             ScriptLanguage::Python => write!(f, "python"),
             ScriptLanguage::Bash => write!(f, "bash"),
+            ScriptLanguage::Wasm => write!(f, "wasm"),
+            ScriptLanguage::Node => write!(f, "node"),
+            ScriptLanguage::PowerShell => write!(f, "powershell"),
         }
     }
 }
@@ -77,6 +133,70 @@ impl Display for ScriptLanguage {
 pub struct ConcreteTelegramChannel {
     pub chat_id: String,
     pub bot_token: String, // The name of the secret
+    #[serde(default)]
+    pub proxy: Option<String>, // Proxy used to reach Telegram, overriding the global one
+    #[serde(default)]
+    pub message_template: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConcreteSlackChannel {
+    pub name: String,
+    pub webhook_url: String,
+    #[serde(default)]
+    pub message_template: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConcreteDiscordChannel {
+    pub name: String,
+    pub webhook_url: String,
+    #[serde(default)]
+    pub message_template: Option<String>,
+}
+
+/// The service delivering the notifications of a push channel to the phones
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, JsonSchema, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PushService {
+    Ntfy,
+    Gotify,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConcretePushChannel {
+    pub name: String,
+    pub service: PushService,
+    pub server_url: String,
+    #[serde(default)]
+    pub topic: Option<String>, // Required by ntfy
+    #[serde(default)]
+    pub token: Option<String>, // Access token for ntfy, application token for Gotify
+    #[serde(default)]
+    pub priorities: BTreeMap<String, u8>, // Status -> priority
+    #[serde(default)]
+    pub message_template: Option<String>,
+}
+
+impl ConcretePushChannel {
+    /**
+     * This function returns the priority of the notifications of a status, following the mapping
+     * of the channel and the scale of the service otherwise
+     */
+    pub fn priority(&self, status: &CheckResultStatus) -> u8 {
+        if let Some(priority) = self.priorities.get(&format!("{status:?}")) {
+            return *priority;
+        }
+
+        match (self.service, status) {
+            (PushService::Ntfy, CheckResultStatus::Critical) => 5,
+            (PushService::Ntfy, CheckResultStatus::Warning | CheckResultStatus::CheckError) => 4,
+            (PushService::Ntfy, _) => 3,
+            (PushService::Gotify, CheckResultStatus::Critical) => 8,
+            (PushService::Gotify, CheckResultStatus::Warning | CheckResultStatus::CheckError) => 5,
+            (PushService::Gotify, _) => 2,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -85,9 +205,42 @@ pub struct CheckResult {
     pub output: String,
     pub status: CheckResultStatus,
     pub timestamp: Option<DateTime<Utc>>,
-    pub telegram_channels: Arc<Vec<ConcreteTelegramChannel>>,
+    #[serde(skip)]
+    pub telegram_channels: Arc<Vec<ConcreteTelegramChannel>>, // Set by the results consumer
     pub mute_notifications: Option<bool>,
     pub mute_notifications_until: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub maintenance_windows: Arc<Vec<MaintenanceWindow>>,
+    #[serde(default)]
+    pub webhooks: Arc<Vec<String>>,
+    #[serde(default)]
+    pub diagnostics: Option<String>, // Details collected on CheckError
+    #[serde(skip)]
+    pub slack_channels: Arc<Vec<ConcreteSlackChannel>>, // Set by the results consumer
+    #[serde(skip)]
+    pub push_channels: Arc<Vec<ConcretePushChannel>>, // Set by the results consumer
+    #[serde(skip)]
+    pub discord_channels: Arc<Vec<ConcreteDiscordChannel>>, // Set by the results consumer
+    #[serde(default)]
+    pub notify_on_state_change: bool,
+    #[serde(default)]
+    pub notify_on_recovery: bool,
+    #[serde(default)]
+    pub renotify_interval_minutes: Option<u64>,
+    #[serde(default)]
+    pub min_notification_interval_seconds: Option<u64>, // At most one message per channel in the window
+    #[serde(default)]
+    pub soft_failure: bool, // Failed attempt which is going to be retried
+    #[serde(default)]
+    pub depends_on: Arc<Vec<String>>,
+    #[serde(default)]
+    pub suppressed_by: Option<String>, // Critical parent check suppressing the notifications
+    #[serde(default)]
+    pub anomaly_sigma: Option<f64>, // Deviation from the baseline flagging a metric as anomalous
+    #[serde(default)]
+    pub script_hash: Option<String>, // Hash of the script, as read from its source, producing the result
+    #[serde(default)]
+    pub run_id: Option<String>, // Run requested through the API producing the result
 }
 
 impl CheckResult {
@@ -95,86 +248,204 @@ impl CheckResult {
         self.timestamp = Some(timestamp);
     }
 
+    /**
+     * This function builds the CheckError result of a check which could not be executed, carrying
+     * its channels and notification settings so that the failure is notified like any other
+     */
     pub fn map_to_check_error(
-        check_name: &String,
+        check: &PinglowCheck,
         error_message: String,
-        mute_notifications: Option<bool>,
-        mute_notifications_until: Option<DateTime<Utc>>,
+        diagnostics: Option<String>,
     ) -> Self {
         Self {
-            check_name: check_name.to_string(),
+            check_name: check.check_name.clone(),
             output: error_message,
             status: CheckResultStatus::CheckError,
             timestamp: None,
-            telegram_channels: Arc::from(vec![]),
-            mute_notifications,
-            mute_notifications_until,
+            telegram_channels: check.telegram_channels.clone().into(),
+            mute_notifications: check.mute_notifications,
+            mute_notifications_until: check.mute_notifications_until,
+            maintenance_windows: check.maintenance_windows.clone().into(),
+            webhooks: check.webhooks.clone().into(),
+            diagnostics,
+            slack_channels: check.slack_channels.clone().into(),
+            push_channels: check.push_channels.clone().into(),
+            discord_channels: check.discord_channels.clone().into(),
+            notify_on_state_change: check.notify_on_state_change,
+            notify_on_recovery: check.notify_on_recovery,
+            renotify_interval_minutes: check.renotify_interval_minutes,
+            min_notification_interval_seconds: check.min_notification_interval_seconds,
+            soft_failure: false,
+            depends_on: check.depends_on.clone().into(),
+            suppressed_by: None,
+            anomaly_sigma: check.anomaly_sigma,
+            script_hash: None,
+            run_id: check.run_id.clone(),
         }
     }
 
+    /// The first line of the output, without the performance data
     pub fn get_output(&self) -> String {
-        let (output, _perf_data) = match self.output.split_once("|") {
-            Some((out, perf)) => (out, perf),
-            None => (self.output.as_ref(), ""),
-        };
+        PluginOutput::parse(&self.output).status_line
+    }
 
-        output.to_string()
+    /// The lines following the first one of the output, without the performance data
+    pub fn get_long_output(&self) -> Option<String> {
+        PluginOutput::parse(&self.output).long_output
     }
 
-    pub fn get_perf_data(&self) -> Vec<(String, f32)> {
-        let (_output, perf_data) = match self.output.split_once("|") {
-            Some((out, perf)) => (out, perf),
-            None => (self.output.as_ref(), ""),
+    /// The whole output, without the performance data
+    pub fn get_full_output(&self) -> String {
+        let output = PluginOutput::parse(&self.output);
+
+        match output.long_output {
+            Some(long_output) => format!("{}\n{long_output}", output.status_line),
+            None => output.status_line,
+        }
+    }
+
+    /// The performance data of all the lines of the output, as written by the check
+    pub fn get_raw_perf_data(&self) -> String {
+        PluginOutput::parse(&self.output).perf_data
+    }
+
+    pub fn get_perf_data(&self) -> Vec<PerfData> {
+        PerfData::parse_all(&PluginOutput::parse(&self.output).perf_data)
+    }
+
+    /**
+     * This function appends a note to the first line of the output, before its performance data
+     */
+    pub fn annotate_status_line(&mut self, note: &str) {
+        let (first_line, rest) = match self.output.split_once('\n') {
+            Some((first_line, rest)) => (first_line, Some(rest)),
+            None => (self.output.as_str(), None),
         };
 
-        let perf_data_list: Vec<(String, f32)> = perf_data
-            .split(",")
-            .filter_map(|pair| {
-                pair.split_once('=') // Split each entry into key=value
-                    .map(|(k, v)| {
-                        (
-                            k.trim().to_string(),
-                            v.trim().to_string().parse::<f32>().unwrap_or_else(|e| {
-                                warn!("Unable to parse performance metric as a float, setting it to 0.0 - {e}");
-                                0.0
-                            }),
-                        )
-                    })
-            })
-            .collect();
-
-        perf_data_list
-    }
-
-    pub async fn write_to_db(&self, client: Arc<Client>) -> Result<(), tokio_postgres::Error> {
-        // Parse the output to remove the performance data, if any
-        let output = self.get_output();
-
-        let perf_data_list = self.get_perf_data();
-
-        // If by chance we do not set the timestamp before, it is set to now
-        let timestamp = match self.timestamp {
-            Some(t) => t,
-            None => Utc::now(),
+        let first_line = match first_line.split_once('|') {
+            Some((text, perf_data)) => format!("{} ({note})|{perf_data}", text.trim_end()),
+            None => format!("{} ({note})", first_line.trim_end()),
         };
 
-        // Insert the main check result
-        client
-            .execute(
-                "INSERT INTO check_result (timestamp, check_name, status, output) VALUES ($1, $2, $3, $4)",
-                &[&timestamp, &self.check_name, &self.status.to_number(), &output],
-            )
-            .await?;
+        self.output = match rest {
+            Some(rest) => format!("{first_line}\n{rest}"),
+            None => first_line,
+        };
+    }
+
+    pub async fn write_to_db(&self, client: &Client) -> Result<(), tokio_postgres::Error> {
+        Self::write_batch_to_db(&[self], client).await
+    }
+
+    /**
+     * This function writes a batch of results, with their performance data and diagnostics,
+     * in a single statement: either all of them are written or none
+     */
+    pub async fn write_batch_to_db(
+        results: &[&CheckResult],
+        client: &Client,
+    ) -> Result<(), tokio_postgres::Error> {
+        let mut timestamps = Vec::with_capacity(results.len());
+        let mut check_names = Vec::with_capacity(results.len());
+        let mut namespaces = Vec::with_capacity(results.len());
+        let mut script_hashes = Vec::with_capacity(results.len());
+        let mut statuses = Vec::with_capacity(results.len());
+        let mut outputs = Vec::with_capacity(results.len());
+        let mut long_outputs = Vec::with_capacity(results.len());
+        let mut soft_failures = Vec::with_capacity(results.len());
+        let mut suppressed_by = Vec::with_capacity(results.len());
+
+        let mut perf_timestamps = vec![];
+        let mut perf_check_names = vec![];
+        let mut perf_keys = vec![];
+        let mut perf_values = vec![];
+        let mut perf_uoms = vec![];
+        let mut perf_warns = vec![];
+        let mut perf_crits = vec![];
+        let mut perf_mins = vec![];
+        let mut perf_maxes = vec![];
+
+        let mut diagnostics_timestamps = vec![];
+        let mut diagnostics_check_names = vec![];
+        let mut diagnostics = vec![];
 
-        // Insert performance data, if needed
-        for (perf_key, perf_value) in perf_data_list {
-            client
+        for result in results {
+            // If by chance we do not set the timestamp before, it is set to now
+            let timestamp = result.timestamp.unwrap_or_else(Utc::now);
+
+            // Parse the output to split the long output and the performance data, if any
+            let output = PluginOutput::parse(&result.output);
+
+            for perf_data in PerfData::parse_all(&output.perf_data) {
+                perf_timestamps.push(timestamp);
+                perf_check_names.push(result.check_name.as_str());
+                perf_keys.push(perf_data.label);
+                perf_values.push(perf_data.value);
+                perf_uoms.push(perf_data.uom);
+                perf_warns.push(perf_data.warn);
+                perf_crits.push(perf_data.crit);
+                perf_mins.push(perf_data.min);
+                perf_maxes.push(perf_data.max);
+            }
+
+            if let Some(result_diagnostics) = &result.diagnostics {
+                diagnostics_timestamps.push(timestamp);
+                diagnostics_check_names.push(result.check_name.as_str());
+                diagnostics.push(result_diagnostics.as_str());
+            }
+
+            timestamps.push(timestamp);
+            check_names.push(result.check_name.as_str());
+            namespaces.push(
+                result
+                    .check_name
+                    .split_once('/')
+                    .map(|(namespace, _)| namespace),
+            );
+            statuses.push(result.status.to_number());
+            outputs.push(output.status_line);
+            long_outputs.push(output.long_output);
+            soft_failures.push(result.soft_failure);
+            suppressed_by.push(result.suppressed_by.as_deref());
+            script_hashes.push(result.script_hash.as_deref());
+        }
+
+        client
             .execute(
-                "INSERT INTO check_result_perf_data (timestamp, check_name, perf_key, perf_value) VALUES ($1, $2, $3, $4)",
-                &[&timestamp, &self.check_name, &perf_key, &perf_value],
+                "WITH results AS (
+                    INSERT INTO check_result (timestamp, check_name, status, output, soft_failure, suppressed_by, long_output, namespace, script_hash)
+                    SELECT * FROM UNNEST($1::timestamptz[], $2::text[], $3::smallint[], $4::text[], $5::bool[], $6::text[], $14::text[], $20::text[], $21::text[])
+                ), perf_data AS (
+                    INSERT INTO check_result_perf_data (timestamp, check_name, perf_key, perf_value, uom, warn, crit, min, max)
+                    SELECT * FROM UNNEST($7::timestamptz[], $8::text[], $9::text[], $10::real[], $15::text[], $16::text[], $17::text[], $18::real[], $19::real[])
+                )
+                INSERT INTO check_diagnostics (timestamp, check_name, diagnostics)
+                SELECT * FROM UNNEST($11::timestamptz[], $12::text[], $13::text[])",
+                &[
+                    &timestamps,
+                    &check_names,
+                    &statuses,
+                    &outputs,
+                    &soft_failures,
+                    &suppressed_by,
+                    &perf_timestamps,
+                    &perf_check_names,
+                    &perf_keys,
+                    &perf_values,
+                    &diagnostics_timestamps,
+                    &diagnostics_check_names,
+                    &diagnostics,
+                    &long_outputs,
+                    &perf_uoms,
+                    &perf_warns,
+                    &perf_crits,
+                    &perf_mins,
+                    &perf_maxes,
+                    &namespaces,
+                    &script_hashes,
+                ],
             )
             .await?;
-        }
 
         Ok(())
     }
@@ -188,9 +459,111 @@ impl CheckResult {
     namespaced
 )]
 pub struct ScriptSpec {
-    //pub language: ScriptLanguage,
+    pub language: Option<ScriptLanguage>, // Python when not specified
+    #[serde(default)]
     pub content: String,
     pub python_requirements: Option<Vec<String>>,
+    pub npm_dependencies: Option<Vec<String>>, // npm packages installed for the Node scripts
+    pub wasm_module: Option<String>,           // Base64 encoded WASI module
+    pub wasm_module_url: Option<String>,       // URL the WASI module is downloaded from
+    #[serde(rename = "contentFrom")]
+    pub content_from: Option<ScriptContentSource>, // Source of the content, in place of inlining it
+    pub image: Option<String>, // Image of the Jobs running the script, in place of the default one
+
+    #[serde(rename = "timeoutSeconds")]
+    pub timeout_seconds: Option<u64>, // Default timeout of the checks running the script
+}
+
+impl ScriptSpec {
+    /// Returns the hex encoded SHA-256 of everything that affects the script execution
+    pub fn content_hash(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.content.as_bytes());
+
+        for requirement in self
+            .python_requirements
+            .iter()
+            .chain(&self.npm_dependencies)
+            .flatten()
+        {
+            hasher.update(b"\n");
+            hasher.update(requirement.as_bytes());
+        }
+
+        for module in [&self.wasm_module, &self.wasm_module_url]
+            .into_iter()
+            .flatten()
+        {
+            hasher.update(b"\n");
+            hasher.update(module.as_bytes());
+        }
+
+        if let Some(image) = &self.image {
+            hasher.update(format!("\nimage:{image}").as_bytes());
+        }
+
+        if let Some(source) = &self.content_from {
+            if let Some(key_ref) = &source.configMapKeyRef {
+                hasher.update(format!("\nconfigmap:{}/{}", key_ref.name, key_ref.key).as_bytes());
+            }
+            if let Some(url) = &source.gitUrl {
+                hasher.update(format!("\ngit:{url}").as_bytes());
+            }
+        }
+
+        format!("{:x}", hasher.finalize())
+    }
+
+    /**
+     * This function returns the placeholders of the content of the script without a value among
+     * the parameters of a check. The scripts of the checks without parameters are not templated
+     */
+    pub fn missing_parameters(&self, parameters: &BTreeMap<String, String>) -> Vec<String> {
+        if parameters.is_empty() {
+            return vec![];
+        }
+
+        parameters::placeholders(&self.content)
+            .into_iter()
+            .filter(|name| !parameters.contains_key(name))
+            .collect()
+    }
+
+    /**
+     * This function returns the script with the content read from its source, which is then
+     * part of its hash
+     */
+    pub fn with_content(&self, content: String) -> ScriptSpec {
+        ScriptSpec {
+            content,
+            ..self.clone()
+        }
+    }
+}
+
+/// Where the content of a script is read from, in place of inlining it: a key of a ConfigMap of
+/// the namespace of the check, or a file of a git repository
+#[derive(Clone, Debug, Default, Serialize, Deserialize, JsonSchema)]
+#[allow(non_snake_case)]
+pub struct ScriptContentSource {
+    pub configMapKeyRef: Option<ConfigMapKeyRef>,
+    pub gitUrl: Option<String>, // URL of the raw file, pinned to a commit to make the results reproducible
+}
+
+/// A key of a ConfigMap
+#[derive(Clone, Debug, Default, Serialize, Deserialize, JsonSchema)]
+pub struct ConfigMapKeyRef {
+    pub name: String,
+    pub key: String,
+}
+
+impl ConfigMapKeyRef {
+    /**
+     * This function returns the value of the key in the ConfigMap, if any
+     */
+    pub fn value(&self, config_map: &ConfigMap) -> Option<String> {
+        config_map.data.as_ref()?.get(&self.key).cloned()
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -200,16 +573,299 @@ pub struct Secret {
     pub value: String,
 }
 
+/// The customization of the Kubernetes Job running a check, instead of the runners
+#[derive(Clone, Debug, Default, Serialize, Deserialize, JsonSchema)]
+#[allow(non_snake_case)]
+pub struct CheckJobSpec {
+    pub image: Option<String>,
+    pub imagePullSecrets: Option<Vec<String>>,
+    pub nodeSelector: Option<BTreeMap<String, String>>,
+    pub tolerations: Option<Vec<Toleration>>,
+    pub resources: Option<ResourceRequirements>,
+    pub activeDeadlineSeconds: Option<i64>,
+}
+
+/// A probe of an HTTP endpoint, executed by the runners without any script
+#[derive(Clone, Debug, Default, Serialize, Deserialize, JsonSchema)]
+#[allow(non_snake_case)]
+pub struct HttpCheckSpec {
+    pub url: String,
+    pub method: Option<String>, // GET when not specified
+    pub headers: Option<BTreeMap<String, String>>,
+    pub body: Option<String>,
+    pub expectedStatusCodes: Option<Vec<u16>>, // 200 when not specified
+    pub bodyRegex: Option<String>,             // The response body must match it
+    pub warningLatencyMs: Option<u64>,
+    pub criticalLatencyMs: Option<u64>,
+    pub insecureSkipTlsVerify: Option<bool>,
+}
+
+/// A connection to a TCP port, executed by the runners without any script
+#[derive(Clone, Debug, Default, Serialize, Deserialize, JsonSchema)]
+#[allow(non_snake_case)]
+pub struct TcpCheckSpec {
+    pub host: String,
+    pub port: u16,
+    pub connectTimeoutSeconds: Option<u64>, // 10 when not specified
+    pub send: Option<String>,               // Written once connected
+    pub expect: Option<String>,             // Regex the banner or the response must match
+}
+
+/// The ranges, in the Nagios syntax, a performance metric escalates the result beyond
+#[derive(Clone, Debug, Default, Serialize, Deserialize, JsonSchema)]
+pub struct ThresholdSpec {
+    pub warn: Option<String>,
+    pub crit: Option<String>,
+}
+
+/// A planned downtime of a check, during which its notifications are muted
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct MaintenanceWindow {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub description: Option<String>,
+}
+
+impl MaintenanceWindow {
+    /**
+     * This function returns whether the window is in progress at the given time
+     */
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        self.start <= now && now < self.end
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct PinglowCheck {
     pub passive: bool,
-    pub script: Option<ScriptSpec>,
+    pub script_ref: Option<String>,
+    pub script_hash: Option<String>,
     pub interval: Option<u64>,
     pub check_name: String,
-    pub secrets: Option<HashMap<String, String>>,
-    pub telegram_channels: Vec<ConcreteTelegramChannel>,
+    #[serde(default)]
+    pub namespace: Option<String>, // Namespace of the Check resource, none in standalone mode
+    #[serde(skip)]
+    pub secrets: Option<HashMap<String, String>>, // Resolved by the runner from the secret refs
+    #[serde(skip)]
+    pub telegram_channels: Vec<ConcreteTelegramChannel>, // Never sent through the queue
+    #[serde(skip)]
+    pub slack_channels: Vec<ConcreteSlackChannel>, // Never sent through the queue
+    #[serde(skip)]
+    pub push_channels: Vec<ConcretePushChannel>, // Never sent through the queue
+    #[serde(skip)]
+    pub discord_channels: Vec<ConcreteDiscordChannel>, // Never sent through the queue
+    #[serde(default)]
+    pub notify_on_state_change: bool,
+    #[serde(default)]
+    pub notify_on_recovery: bool,
+    #[serde(default)]
+    pub renotify_interval_minutes: Option<u64>,
+    #[serde(default)]
+    pub min_notification_interval_seconds: Option<u64>, // At most one message per channel in the window
+    #[serde(default)]
+    pub timeout_seconds: Option<u64>,
+    #[serde(default)]
+    pub retries: u32,
+    #[serde(default)]
+    pub retry_delay_seconds: Option<u64>,
+    #[serde(default)]
+    pub depends_on: Vec<String>,
     pub mute_notifications: Option<bool>,
     pub mute_notifications_until: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub mute_notifications_since: Option<DateTime<Utc>>, // Set by the mute endpoints
+    #[serde(default)]
+    pub maintenance_windows: Vec<MaintenanceWindow>,
+    #[serde(default)]
+    pub suspended: bool, // Removed from the scheduler queue, still visible in the API
+    pub result_retention_days: Option<u64>,
+    #[serde(default)]
+    pub webhooks: Vec<String>,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    #[serde(default)]
+    pub parameters: BTreeMap<String, String>, // Values of the {{name}} placeholders of the script
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+    #[serde(default)]
+    pub annotations: BTreeMap<String, String>,
+    #[serde(default)]
+    pub secret_refs: Vec<String>,
+    #[serde(default)]
+    pub secret_versions: BTreeMap<String, String>, // Secret name -> resource version
+    #[serde(default)]
+    pub job: Option<CheckJobSpec>,
+    #[serde(default)]
+    pub http_check: Option<HttpCheckSpec>,
+    #[serde(default)]
+    pub tcp_check: Option<TcpCheckSpec>,
+    #[serde(default)]
+    pub runner_selector: BTreeMap<String, String>, // Labels of the runners allowed to execute the check
+    #[serde(default)]
+    pub exit_code_mapping: BTreeMap<i32, i32>, // Exit code -> status code
+    #[serde(default)]
+    pub thresholds: BTreeMap<String, ThresholdSpec>, // Perf key -> ranges escalating the result
+    #[serde(default)]
+    pub anomaly_sigma: Option<f64>, // Deviation from the baseline flagging a metric as anomalous
+    #[serde(skip)]
+    pub hmac_key: Option<String>, // Never sent through the queue
+    #[serde(skip)]
+    pub heartbeat_token: Option<String>, // Never sent through the queue
+    #[serde(default)]
+    pub run_id: Option<String>, // Set on the runs requested through the API, to wait for their result
+}
+
+impl PinglowCheck {
+    /**
+     * This function returns the name of the Check resource, the identity of the checks outside of
+     * the target namespace being namespace/name
+     */
+    pub fn resource_name(&self) -> &str {
+        self.check_name
+            .rsplit_once('/')
+            .map_or(self.check_name.as_str(), |(_, name)| name)
+    }
+
+    /**
+     * This function tells whether the check is probed by the runners themselves, without a script
+     */
+    pub fn is_probe(&self) -> bool {
+        self.http_check.is_some() || self.tcp_check.is_some()
+    }
+
+    /**
+     * This function returns the status corresponding to the exit code of the script, following
+     * the mapping of the check and the Nagios convention otherwise
+     */
+    pub fn status_from_exit_code(&self, exit_code: i32) -> CheckResultStatus {
+        let status = self
+            .exit_code_mapping
+            .get(&exit_code)
+            .copied()
+            .unwrap_or(exit_code);
+
+        CheckResultStatus::from(status)
+    }
+
+    /**
+     * This function returns the environment describing the check to the script:
+     * PINGLOW_CHECK_NAME, PINGLOW_INTERVAL, PINGLOW_LABEL_<KEY> and PINGLOW_ANNOTATION_<KEY>
+     */
+    pub fn metadata_env(&self) -> BTreeMap<String, String> {
+        let mut env = BTreeMap::new();
+
+        env.insert("PINGLOW_CHECK_NAME".to_string(), self.check_name.clone());
+        if let Some(interval) = self.interval {
+            env.insert("PINGLOW_INTERVAL".to_string(), interval.to_string());
+        }
+
+        for (key, value) in self.labels.iter() {
+            env.insert(
+                format!("PINGLOW_LABEL_{}", env_var_name(key)),
+                value.clone(),
+            );
+        }
+
+        for (key, value) in self.annotations.iter() {
+            env.insert(
+                format!("PINGLOW_ANNOTATION_{}", env_var_name(key)),
+                value.clone(),
+            );
+        }
+
+        env
+    }
+
+    /**
+     * This function returns the script arguments, replacing the ${check_name}, ${interval},
+     * ${labels.<key>} and ${annotations.<key>} placeholders. Unknown placeholders are kept as they are
+     */
+    pub fn templated_args(&self) -> Vec<String> {
+        self.args
+            .iter()
+            .map(|arg| self.render_template(arg))
+            .collect()
+    }
+
+    /**
+     * This function returns the content of a script with its {{name}} placeholders replaced by the
+     * parameters of the check
+     */
+    pub fn render_script(&self, content: &str) -> String {
+        parameters::render(content, &self.parameters)
+    }
+
+    /**
+     * This function replaces the placeholders supported by the arguments in any value of the check
+     */
+    pub fn render_template(&self, template: &str) -> String {
+        let mut rendered = String::with_capacity(template.len());
+        let mut rest = template;
+
+        while let Some(start) = rest.find("${") {
+            rendered.push_str(&rest[..start]);
+
+            let Some(end) = rest[start..].find('}') else {
+                // Unterminated placeholder, keep it as it is
+                rest = &rest[start..];
+                break;
+            };
+
+            let placeholder = &rest[start + 2..start + end];
+            match self.placeholder_value(placeholder) {
+                Some(value) => rendered.push_str(&value),
+                None => rendered.push_str(&rest[start..start + end + 1]),
+            }
+
+            rest = &rest[start + end + 1..];
+        }
+
+        rendered.push_str(rest);
+        rendered
+    }
+
+    fn placeholder_value(&self, placeholder: &str) -> Option<String> {
+        match placeholder {
+            "check_name" => Some(self.check_name.clone()),
+            "interval" => self.interval.map(|i| i.to_string()),
+            _ => {
+                if let Some(key) = placeholder.strip_prefix("labels.") {
+                    self.labels.get(key).cloned()
+                } else if let Some(key) = placeholder.strip_prefix("annotations.") {
+                    self.annotations.get(key).cloned()
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
+
+/// Converts a label or annotation key (e.g. app.kubernetes.io/name) into an environment variable name
+fn env_var_name(key: &str) -> String {
+    key.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Number of log lines kept in the diagnostics of a CheckError
+pub const DIAGNOSTICS_LOG_LINES: usize = 50;
+
+/**
+ * This function returns the last lines of a text, used to keep the diagnostics short
+ */
+pub fn last_lines(text: &str, count: usize) -> String {
+    let lines: Vec<&str> = text.lines().collect();
+
+    lines[lines.len().saturating_sub(count)..].join("\n")
 }
 
 #[derive(Clone, Debug)]
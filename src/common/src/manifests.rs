@@ -0,0 +1,112 @@
+use std::{
+    fs,
+    hash::{DefaultHasher, Hash, Hasher},
+    path::{Path, PathBuf},
+};
+
+use serde::{de::DeserializeOwned, Deserialize};
+
+use crate::error::SerializeError;
+
+/// A resource definition read from a local manifest, as it would be applied to Kubernetes
+#[derive(Debug, Clone)]
+pub struct Manifest {
+    pub kind: String,
+    pub name: String,
+    pub document: serde_yaml::Value,
+}
+
+#[derive(Deserialize)]
+struct ManifestHeader {
+    kind: String,
+    metadata: ManifestMetadata,
+}
+
+#[derive(Deserialize)]
+struct ManifestMetadata {
+    name: String,
+}
+
+impl Manifest {
+    /// Deserializes the manifest into the corresponding resource
+    pub fn parse<T: DeserializeOwned>(&self) -> Result<T, SerializeError> {
+        serde_yaml::from_value(self.document.clone()).map_err(|e| {
+            SerializeError::DeserializationError(format!("{} {}: {e}", self.kind, self.name))
+        })
+    }
+}
+
+/**
+ * This function returns the YAML (or JSON) files of a directory, sorted by name
+ */
+fn manifest_files(dir: &Path) -> Result<Vec<PathBuf>, std::io::Error> {
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| {
+            path.is_file()
+                && path
+                    .extension()
+                    .and_then(|e| e.to_str())
+                    .is_some_and(|e| matches!(e, "yaml" | "yml" | "json"))
+        })
+        .collect();
+
+    files.sort();
+
+    Ok(files)
+}
+
+/**
+ * This function reads all the resource definitions of a directory, each file possibly
+ * containing multiple YAML documents
+ */
+pub fn read_manifests(dir: &Path) -> Result<Vec<Manifest>, SerializeError> {
+    let files = manifest_files(dir)
+        .map_err(|e| SerializeError::DeserializationError(format!("{}: {e}", dir.display())))?;
+
+    let mut manifests = vec![];
+
+    for file in files {
+        let content = fs::read_to_string(&file).map_err(|e| {
+            SerializeError::DeserializationError(format!("{}: {e}", file.display()))
+        })?;
+
+        for document in serde_yaml::Deserializer::from_str(&content) {
+            let document = serde_yaml::Value::deserialize(document).map_err(|e| {
+                SerializeError::DeserializationError(format!("{}: {e}", file.display()))
+            })?;
+
+            // Skip empty documents, e.g. a trailing separator
+            if document.is_null() {
+                continue;
+            }
+
+            let header: ManifestHeader = serde_yaml::from_value(document.clone()).map_err(|e| {
+                SerializeError::DeserializationError(format!("{}: {e}", file.display()))
+            })?;
+
+            manifests.push(Manifest {
+                kind: header.kind,
+                name: header.metadata.name,
+                document,
+            });
+        }
+    }
+
+    Ok(manifests)
+}
+
+/**
+ * This function returns a fingerprint of the content of the manifests of a directory,
+ * used to detect changes without parsing them
+ */
+pub fn manifests_fingerprint(dir: &Path) -> Result<u64, std::io::Error> {
+    let mut hasher = DefaultHasher::new();
+
+    for file in manifest_files(dir)? {
+        file.hash(&mut hasher);
+        fs::read(&file)?.hash(&mut hasher);
+    }
+
+    Ok(hasher.finish())
+}
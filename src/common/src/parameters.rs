@@ -0,0 +1,101 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+/// A {{name}} placeholder of a script, the name being made of letters, digits and underscores,
+/// optionally surrounded by spaces
+struct Placeholder<'a> {
+    start: usize, // Offset of the opening braces
+    end: usize,   // Offset following the closing braces
+    name: &'a str,
+}
+
+/**
+ * This function finds the placeholders of a script, skipping the braces which do not enclose a
+ * valid name
+ */
+fn find_placeholders(content: &str) -> Vec<Placeholder<'_>> {
+    let mut placeholders = vec![];
+    let mut offset = 0;
+
+    while let Some(start) = content[offset..].find("{{").map(|start| offset + start) {
+        let Some(length) = content[start + 2..].find("}}") else {
+            break;
+        };
+
+        let name = content[start + 2..start + 2 + length].trim();
+        let valid = !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+
+        if valid {
+            let end = start + 2 + length + 2;
+            placeholders.push(Placeholder { start, end, name });
+            offset = end;
+        } else {
+            offset = start + 2;
+        }
+    }
+
+    placeholders
+}
+
+/**
+ * This function returns the names of the placeholders of a script
+ */
+pub fn placeholders(content: &str) -> BTreeSet<String> {
+    find_placeholders(content)
+        .into_iter()
+        .map(|placeholder| placeholder.name.to_string())
+        .collect()
+}
+
+/**
+ * This function replaces the placeholders of a script with the parameters of a check, keeping
+ * the ones without a parameter as they are
+ */
+pub fn render(content: &str, parameters: &BTreeMap<String, String>) -> String {
+    if parameters.is_empty() {
+        return content.to_string();
+    }
+
+    let mut rendered = String::with_capacity(content.len());
+    let mut rest = 0;
+
+    for placeholder in find_placeholders(content) {
+        if let Some(value) = parameters.get(placeholder.name) {
+            rendered.push_str(&content[rest..placeholder.start]);
+            rendered.push_str(value);
+            rest = placeholder.end;
+        }
+    }
+
+    rendered.push_str(&content[rest..]);
+    rendered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rendering() {
+        let parameters = BTreeMap::from([
+            ("host".to_string(), "db.example.com".to_string()),
+            ("port".to_string(), "5432".to_string()),
+        ]);
+
+        assert_eq!(
+            render("connect({{host}}, {{ port }}) {{other}}", &parameters),
+            "connect(db.example.com, 5432) {{other}}"
+        );
+        assert_eq!(
+            render("f\"{{{{host}}}}\" {{a-b}}", &parameters),
+            "f\"{{db.example.com}}\" {{a-b}}"
+        );
+    }
+
+    #[test]
+    fn placeholder_names() {
+        assert_eq!(
+            placeholders("{{host}} {{ port }} {{host}} {{not valid}} {{"),
+            BTreeSet::from(["host".to_string(), "port".to_string()])
+        );
+    }
+}
@@ -0,0 +1,295 @@
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+/// A performance metric of a result, in the format of the Nagios plugins:
+/// 'label'=value[UOM];[warn];[crit];[min];[max]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PerfData {
+    pub label: String,
+    pub value: f32,
+    pub uom: Option<String>,
+    pub warn: Option<String>, // Range in the Nagios syntax, e.g. 10:20 or @~:5
+    pub crit: Option<String>, // Range in the Nagios syntax, e.g. 10:20 or @~:5
+    pub min: Option<f32>,
+    pub max: Option<f32>,
+}
+
+/// A range in the Nagios syntax, [@]start:end: the start defaults to 0 and is minus infinity when
+/// written ~, while a missing end is infinity. A value alerts when it is outside of the range or,
+/// with @, inside of it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Range {
+    pub start: f64,
+    pub end: f64,
+    pub inside: bool,
+}
+
+impl Range {
+    /**
+     * This function parses a range, e.g. 10 (alert outside 0..10), 10: (below 10), ~:10 (above
+     * 10), 10:20 (outside 10..20) or @10:20 (inside 10..20)
+     */
+    pub fn parse(range: &str) -> Option<Range> {
+        let range = range.trim();
+        let (inside, range) = match range.strip_prefix('@') {
+            Some(range) => (true, range),
+            None => (false, range),
+        };
+
+        if range.is_empty() {
+            return None;
+        }
+
+        let (start, end) = range.split_once(':').unwrap_or(("", range));
+
+        let start = match start {
+            "~" => f64::NEG_INFINITY,
+            "" => 0.0,
+            start => start.parse().ok()?,
+        };
+        let end = match end {
+            "" => f64::INFINITY,
+            end => end.parse().ok()?,
+        };
+
+        (start <= end).then_some(Range { start, end, inside })
+    }
+
+    /**
+     * This function tells whether a value alerts
+     */
+    pub fn alerts(&self, value: f64) -> bool {
+        let within = self.start <= value && value <= self.end;
+        within == self.inside
+    }
+}
+
+/// The parts of the output of a plugin
+#[derive(Debug, Default, PartialEq)]
+pub struct PluginOutput {
+    /// The text of the first line
+    pub status_line: String,
+    /// The text of the following lines, if any
+    pub long_output: Option<String>,
+    /// The performance data of all the lines, separated by spaces
+    pub perf_data: String,
+}
+
+impl PluginOutput {
+    /**
+     * This function splits the output of a plugin: the performance data of the first line follows
+     * its '|', while the one of the long output follows the first '|' of the following lines and
+     * takes the rest of the output
+     *
+     * TEXT OUTPUT | PERFDATA
+     * LONG TEXT LINE 1
+     * LONG TEXT LINE 2 | PERFDATA LINE 2
+     * PERFDATA LINE 3
+     */
+    pub fn parse(output: &str) -> PluginOutput {
+        let mut lines = output.lines();
+
+        let first_line = lines.next().unwrap_or_default();
+        let (status_line, first_perf_data) = first_line.split_once('|').unwrap_or((first_line, ""));
+
+        let mut long_output = vec![];
+        let mut perf_data = vec![first_perf_data.trim()];
+        let mut in_perf_data = false;
+
+        for line in lines {
+            if in_perf_data {
+                perf_data.push(line.trim());
+                continue;
+            }
+
+            match line.split_once('|') {
+                Some((text, data)) => {
+                    long_output.push(text.trim_end());
+                    perf_data.push(data.trim());
+                    in_perf_data = true;
+                }
+                None => long_output.push(line.trim_end()),
+            }
+        }
+
+        let long_output = long_output.join("\n").trim_end().to_string();
+
+        PluginOutput {
+            status_line: status_line.trim_end().to_string(),
+            long_output: (!long_output.is_empty()).then_some(long_output),
+            perf_data: perf_data
+                .into_iter()
+                .filter(|data| !data.is_empty())
+                .collect::<Vec<_>>()
+                .join(" "),
+        }
+    }
+}
+
+impl PerfData {
+    /**
+     * This function parses the performance data of a plugin, separated by spaces or, as accepted
+     * by the earlier versions, by commas. Labels containing separators are quoted, a quote within
+     * them being doubled
+     */
+    pub fn parse_all(perf_data: &str) -> Vec<PerfData> {
+        let mut items = vec![];
+        let mut current = String::new();
+        let mut quoted = false;
+
+        for c in perf_data.chars() {
+            match c {
+                '\'' => {
+                    quoted = !quoted;
+                    current.push(c);
+                }
+                c if !quoted && (c.is_whitespace() || c == ',') => {
+                    if !current.is_empty() {
+                        items.push(std::mem::take(&mut current));
+                    }
+                }
+                c => current.push(c),
+            }
+        }
+        if !current.is_empty() {
+            items.push(current);
+        }
+
+        items
+            .iter()
+            .filter_map(|item| PerfData::parse(item))
+            .collect()
+    }
+
+    /**
+     * This function parses a single metric, skipping the ones whose value is unknown (U) or
+     * invalid
+     */
+    fn parse(item: &str) -> Option<PerfData> {
+        let Some((label, fields)) = item.rsplit_once('=') else {
+            warn!("Ignoring the performance data {item}, which is not label=value");
+            return None;
+        };
+
+        let label = match label.strip_prefix('\'').and_then(|l| l.strip_suffix('\'')) {
+            Some(quoted) => quoted.replace("''", "'"),
+            None => label.to_string(),
+        };
+
+        let mut fields = fields.split(';');
+        let value_field = fields.next().unwrap_or_default().trim();
+
+        if value_field == "U" {
+            return None;
+        }
+
+        let number_end = value_field
+            .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-' || c == '+'))
+            .unwrap_or(value_field.len());
+        let (value, uom) = value_field.split_at(number_end);
+
+        let value = match value.parse::<f32>() {
+            Ok(value) => value,
+            Err(e) => {
+                warn!("Ignoring the performance data {label}, whose value {value_field} is not a number: {e}");
+                return None;
+            }
+        };
+
+        let mut next_field = || {
+            fields
+                .next()
+                .map(str::trim)
+                .filter(|field| !field.is_empty())
+                .map(str::to_string)
+        };
+
+        let warn = next_field();
+        let crit = next_field();
+        let min = next_field().and_then(|min| min.parse().ok());
+        let max = next_field().and_then(|max| max.parse().ok());
+
+        Some(PerfData {
+            label,
+            value,
+            uom: (!uom.is_empty()).then(|| uom.to_string()),
+            warn,
+            crit,
+            min,
+            max,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn full_metric() {
+        assert_eq!(
+            PerfData::parse_all("'used disk'=80.5%;70;90;0;100 time=12ms"),
+            vec![
+                PerfData {
+                    label: "used disk".into(),
+                    value: 80.5,
+                    uom: Some("%".into()),
+                    warn: Some("70".into()),
+                    crit: Some("90".into()),
+                    min: Some(0.0),
+                    max: Some(100.0),
+                },
+                PerfData {
+                    label: "time".into(),
+                    value: 12.0,
+                    uom: Some("ms".into()),
+                    warn: None,
+                    crit: None,
+                    min: None,
+                    max: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn legacy_and_partial_metrics() {
+        let perf_data = PerfData::parse_all("a=1,b=2;;@10:20 c=U 'it''s'=3c");
+
+        let labels: Vec<_> = perf_data.iter().map(|p| p.label.as_str()).collect();
+        assert_eq!(labels, vec!["a", "b", "it's"]);
+        assert_eq!(perf_data[1].warn, None);
+        assert_eq!(perf_data[1].crit.as_deref(), Some("@10:20"));
+        assert_eq!(perf_data[2].uom.as_deref(), Some("c"));
+    }
+
+    #[test]
+    fn ranges() {
+        let alerts = |range: &str, value: f64| Range::parse(range).unwrap().alerts(value);
+
+        assert!(!alerts("10", 5.0) && alerts("10", 11.0) && alerts("10", -1.0));
+        assert!(alerts("10:", 9.0) && !alerts("10:", 1e9));
+        assert!(alerts("~:10", 11.0) && !alerts("~:10", -1e9));
+        assert!(alerts("10:20", 9.0) && !alerts("10:20", 15.0) && alerts("10:20", 21.0));
+        assert!(alerts("@10:20", 15.0) && !alerts("@10:20", 21.0));
+        assert_eq!(Range::parse("20:10"), None);
+        assert_eq!(Range::parse("@"), None);
+    }
+
+    #[test]
+    fn long_output() {
+        let output = PluginOutput::parse(
+            "DISK OK - free space | /=2643MB;5948;5958;0;5968\n/ 15272 MB (77%);\n/boot 68 MB (69%); | /boot=68MB;88;93;0;98\n/home=69357MB;253404;253409;0;253414",
+        );
+
+        assert_eq!(output.status_line, "DISK OK - free space");
+        assert_eq!(
+            output.long_output.as_deref(),
+            Some("/ 15272 MB (77%);\n/boot 68 MB (69%);")
+        );
+        assert_eq!(
+            output.perf_data,
+            "/=2643MB;5948;5958;0;5968 /boot=68MB;88;93;0;98 /home=69357MB;253404;253409;0;253414"
+        );
+    }
+}
@@ -1,7 +1,25 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 use redis::{Client as RedisClient, RedisError, Value};
 
+// Id and fields of a stream entry
+pub type StreamEntry = (String, HashMap<String, String>);
+
+// Stream of the checks which can be executed by any runner
+pub const CHECKS_STREAM: &str = "pinglow:checks";
+
+// Set of the streams of the checks restricted to some runners by their runnerSelector
+pub const ROUTED_CHECKS_STREAMS_KEY: &str = "pinglow:checks:routed";
+
+// Stream of the results pushed by external agents, kept apart from the results of the runners so
+// that the access of the agents can be restricted to it
+pub const AGENT_RESULTS_STREAM: &str = "pinglow:results:agents";
+
+// Hash holding the last heartbeat of every runner, by runner name
+pub const RUNNERS_KEY: &str = "pinglow:runners";
+
+pub const RUNNER_HEARTBEAT_INTERVAL_SECONDS: u64 = 10;
+
 pub fn redis_client() -> Result<RedisClient, RedisError> {
     let host = std::env::var("REDIS_HOST").expect("REDIS_HOST must be set");
     let password = std::env::var("REDIS_PASSWORD").expect("REDIS_PASSWORD must be set");
@@ -10,19 +28,22 @@ pub fn redis_client() -> Result<RedisClient, RedisError> {
 
 pub async fn init_streams(conn: &mut redis::aio::MultiplexedConnection) {
     // Tasks stream
+    init_checks_stream(conn, CHECKS_STREAM).await;
+
+    // Results stream
     let _: Result<(), _> = redis::cmd("XGROUP")
         .arg("CREATE")
-        .arg("pinglow:checks")
-        .arg("workers")
+        .arg("pinglow:results")
+        .arg("controller")
         .arg("0")
         .arg("MKSTREAM")
         .query_async(conn)
         .await;
 
-    // Results stream
+    // Agent results stream
     let _: Result<(), _> = redis::cmd("XGROUP")
         .arg("CREATE")
-        .arg("pinglow:results")
+        .arg(AGENT_RESULTS_STREAM)
         .arg("controller")
         .arg("0")
         .arg("MKSTREAM")
@@ -30,7 +51,108 @@ pub async fn init_streams(conn: &mut redis::aio::MultiplexedConnection) {
         .await;
 }
 
+/**
+ * This function creates the group of the runners on a checks stream, if missing
+ */
+pub async fn init_checks_stream(conn: &mut redis::aio::MultiplexedConnection, stream: &str) {
+    let _: Result<(), _> = redis::cmd("XGROUP")
+        .arg("CREATE")
+        .arg(stream)
+        .arg("workers")
+        .arg("0")
+        .arg("MKSTREAM")
+        .query_async(conn)
+        .await;
+}
+
+/**
+ * This function returns the stream of the checks with the given runner selector, the default one
+ * if the checks can be executed by any runner
+ */
+pub fn checks_stream(runner_selector: &BTreeMap<String, String>) -> String {
+    if runner_selector.is_empty() {
+        return CHECKS_STREAM.to_string();
+    }
+
+    let selector: Vec<String> = runner_selector
+        .iter()
+        .map(|(key, value)| format!("{key}={value}"))
+        .collect();
+
+    format!("{CHECKS_STREAM}:runners:{}", selector.join(","))
+}
+
+/**
+ * This function returns all the checks streams in use: the default one and the routed ones
+ */
+pub async fn checks_streams(
+    conn: &mut redis::aio::MultiplexedConnection,
+) -> Result<Vec<String>, RedisError> {
+    let mut routed: Vec<String> = redis::cmd("SMEMBERS")
+        .arg(ROUTED_CHECKS_STREAMS_KEY)
+        .query_async(conn)
+        .await?;
+    routed.sort();
+
+    Ok(std::iter::once(CHECKS_STREAM.to_string())
+        .chain(routed)
+        .collect())
+}
+
 pub fn parse_stream_payload(value: Value) -> Option<(String, HashMap<String, String>)> {
+    parse_stream_entries(value)?.into_iter().next()
+}
+
+/**
+ * This function parses the entries returned by a XREADGROUP/XREAD call on many streams, together
+ * with the stream they belong to
+ */
+pub fn parse_multi_stream_entries(value: Value) -> Option<Vec<(String, StreamEntry)>> {
+    let entries = match value {
+        // RESP2: [[stream_name, entries], ...]
+        Value::Array(streams) => streams
+            .into_iter()
+            .filter_map(|stream| {
+                let Value::Array(stream) = stream else {
+                    return None;
+                };
+                let mut it = stream.into_iter();
+                Some((it.next()?, it.next()?))
+            })
+            .collect(),
+        // RESP3: {stream_name: entries, ...}
+        Value::Map(streams) => streams,
+        _ => return None,
+    };
+
+    Some(
+        entries
+            .into_iter()
+            .filter_map(|(name, entries)| {
+                let Value::BulkString(name) = name else {
+                    return None;
+                };
+                let Value::Array(entries) = entries else {
+                    return None;
+                };
+                let name: String = String::from_utf8_lossy(&name).into();
+                Some(
+                    entries
+                        .into_iter()
+                        .filter_map(parse_stream_entry)
+                        .map(move |entry| (name.clone(), entry))
+                        .collect::<Vec<_>>(),
+                )
+            })
+            .flatten()
+            .collect(),
+    )
+}
+
+/**
+ * This function parses all the entries returned by a XREADGROUP/XREAD call on a single stream
+ */
+pub fn parse_stream_entries(value: Value) -> Option<Vec<(String, HashMap<String, String>)>> {
     let Value::Array(streams) = value else {
         return None;
     };
@@ -46,7 +168,49 @@ pub fn parse_stream_payload(value: Value) -> Option<(String, HashMap<String, Str
     let Value::Array(entries) = entries else {
         return None;
     };
-    let Value::Array(entry) = entries.into_iter().next()? else {
+
+    Some(entries.into_iter().filter_map(parse_stream_entry).collect())
+}
+
+/**
+ * This function parses the reply of a XAUTOCLAIM call, returning the cursor of the next call
+ * and the claimed entries
+ */
+pub fn parse_autoclaim_entries(value: Value) -> Option<(String, Vec<StreamEntry>)> {
+    let Value::Array(reply) = value else {
+        return None;
+    };
+
+    // reply = [next_cursor, entries, deleted_ids]
+    let mut it = reply.into_iter();
+
+    let Value::BulkString(cursor) = it.next()? else {
+        return None;
+    };
+
+    let Value::Array(entries) = it.next()? else {
+        return None;
+    };
+
+    Some((
+        String::from_utf8_lossy(&cursor).into(),
+        entries.into_iter().filter_map(parse_stream_entry).collect(),
+    ))
+}
+
+/**
+ * This function parses the entries returned by a XRANGE call
+ */
+pub fn parse_range_entries(value: Value) -> Option<Vec<StreamEntry>> {
+    let Value::Array(entries) = value else {
+        return None;
+    };
+
+    Some(entries.into_iter().filter_map(parse_stream_entry).collect())
+}
+
+fn parse_stream_entry(entry: Value) -> Option<(String, HashMap<String, String>)> {
+    let Value::Array(entry) = entry else {
         return None;
     };
 
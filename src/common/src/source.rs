@@ -0,0 +1,95 @@
+use std::{future::Future, sync::OnceLock, time::Duration};
+
+use k8s_openapi::api::core::v1::ConfigMap;
+use reqwest::Client;
+
+use crate::{error::ScriptError, ScriptContentSource};
+
+// Maximum size of the content of a script read from a URL
+pub const MAX_BODY_SIZE: usize = 1024 * 1024;
+
+// Maximum duration of a download, including the read of the body
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Client shared by the downloads
+static CLIENT: OnceLock<Client> = OnceLock::new();
+
+/**
+ * This function reads the content of a script from its source: a key of a ConfigMap, looked up
+ * by name through the given function, or a file served at a git URL
+ */
+pub async fn read_content<F, Fut>(
+    source: &ScriptContentSource,
+    config_map: F,
+) -> Result<String, ScriptError>
+where
+    F: FnOnce(String) -> Fut,
+    Fut: Future<Output = Option<ConfigMap>>,
+{
+    match (&source.configMapKeyRef, &source.gitUrl) {
+        (Some(key_ref), _) => config_map(key_ref.name.clone())
+            .await
+            .and_then(|config_map| key_ref.value(&config_map))
+            .ok_or_else(|| {
+                ScriptError::ContentError(format!(
+                    "No key {} found in ConfigMap {}",
+                    key_ref.key, key_ref.name
+                ))
+            }),
+        (None, Some(url)) => {
+            let content = download(url, MAX_BODY_SIZE).await?;
+            String::from_utf8(content).map_err(|_| {
+                ScriptError::ContentError(format!("The content served at {url} is not UTF-8"))
+            })
+        }
+        (None, None) => Err(ScriptError::ContentError(
+            "The content source does not reference anything".into(),
+        )),
+    }
+}
+
+/**
+ * This function downloads a file with the shared client, failing if it is larger than the maximum
+ * size rather than reading it whole
+ */
+pub async fn download(url: &str, max_size: usize) -> Result<Vec<u8>, ScriptError> {
+    let error =
+        |e: reqwest::Error| ScriptError::ContentError(format!("Cannot download {url}: {e}"));
+    let too_large =
+        || ScriptError::ContentError(format!("The file served at {url} exceeds {max_size} bytes"));
+
+    let mut response = client()
+        .map_err(error)?
+        .get(url)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(error)?;
+
+    if response
+        .content_length()
+        .is_some_and(|length| length > max_size as u64)
+    {
+        return Err(too_large());
+    }
+
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await.map_err(error)? {
+        body.extend_from_slice(&chunk);
+        if body.len() > max_size {
+            return Err(too_large());
+        }
+    }
+
+    Ok(body)
+}
+
+fn client() -> Result<Client, reqwest::Error> {
+    if let Some(client) = CLIENT.get() {
+        return Ok(client.clone());
+    }
+
+    let client = Client::builder().timeout(DOWNLOAD_TIMEOUT).build()?;
+
+    Ok(CLIENT.get_or_init(|| client).clone())
+}
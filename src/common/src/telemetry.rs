@@ -0,0 +1,94 @@
+use std::collections::HashMap;
+
+use log::{error, info};
+use opentelemetry::{
+    global,
+    trace::{SpanKind, TraceContextExt, Tracer},
+    Context, KeyValue,
+};
+use opentelemetry_otlp::SpanExporter;
+use opentelemetry_sdk::{propagation::TraceContextPropagator, trace::SdkTracerProvider, Resource};
+
+// Name of the stream field carrying the trace context next to the payload
+pub const TRACE_CONTEXT_FIELD: &str = "trace";
+
+/**
+ * This function sets up the export of the traces through OTLP over HTTP, if a collector is
+ * configured with OTEL_EXPORTER_OTLP_ENDPOINT. The returned provider has to be shut down on exit
+ * to flush the pending spans
+ */
+pub fn init_tracing(service_name: &'static str) -> Option<SdkTracerProvider> {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    // Without a collector the spans are not recorded at all
+    let endpoint = std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT").ok()?;
+
+    let exporter = match SpanExporter::builder().with_http().build() {
+        Ok(exporter) => exporter,
+        Err(e) => {
+            error!("Error creating the OTLP exporter, traces are disabled: {e}");
+            return None;
+        }
+    };
+
+    // OTEL_SERVICE_NAME, if set, takes precedence over the default service name
+    let resource = match std::env::var("OTEL_SERVICE_NAME") {
+        Ok(_) => Resource::builder().build(),
+        Err(_) => Resource::builder().with_service_name(service_name).build(),
+    };
+
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(resource)
+        .build();
+    global::set_tracer_provider(provider.clone());
+
+    info!("Exporting traces to {endpoint}");
+
+    Some(provider)
+}
+
+/**
+ * This function starts a span for a check as a child of the given context, returning the
+ * context holding the new span
+ */
+pub fn start_span(
+    name: &'static str,
+    kind: SpanKind,
+    parent: &Context,
+    check_name: &str,
+) -> Context {
+    let tracer = global::tracer("pinglow");
+    let span = tracer.build_with_context(
+        tracer
+            .span_builder(name)
+            .with_kind(kind)
+            .with_attributes(vec![KeyValue::new("check.name", check_name.to_string())]),
+        parent,
+    );
+
+    parent.with_span(span)
+}
+
+/**
+ * This function serializes the trace context so that it can travel in a stream entry
+ */
+pub fn inject_context(cx: &Context) -> String {
+    let mut carrier: HashMap<String, String> = HashMap::new();
+    global::get_text_map_propagator(|propagator| propagator.inject_context(cx, &mut carrier));
+
+    serde_json::to_string(&carrier).unwrap_or_default()
+}
+
+/**
+ * This function restores the trace context of a stream entry, if any, falling back to an empty
+ * context which starts a new trace
+ */
+pub fn extract_context(fields: &HashMap<String, String>) -> Context {
+    let carrier: HashMap<String, String> = fields
+        .get(TRACE_CONTEXT_FIELD)
+        .and_then(|trace| serde_json::from_str(trace).ok())
+        .unwrap_or_default();
+
+    global::get_text_map_propagator(|propagator| propagator.extract(&carrier))
+}
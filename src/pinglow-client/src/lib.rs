@@ -0,0 +1,394 @@
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{DateTime, Utc};
+use pinglow_common::dto::{
+    AckCheckPayload, CheckAckDto, CheckAvailabilityDto, CheckDiagnosticsDto, CheckResultHistoryDto,
+    GroupStatusDto, NotificationAttemptDto, ProcessCheckResultPayload, RunnerDto, SimpleCheckDto,
+    SimpleCheckResultDto,
+};
+use reqwest::{RequestBuilder, Response};
+use serde::{de::DeserializeOwned, Serialize};
+
+pub use pinglow_common::dto;
+
+#[derive(thiserror::Error, Debug)]
+pub enum ClientError {
+    #[error("Request error: {0}")]
+    RequestError(#[from] reqwest::Error),
+    #[error("The API returned {status}: {message}")]
+    ApiError {
+        status: reqwest::StatusCode,
+        message: String,
+    },
+}
+
+/// The filters and pagination of the results history of a check
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ResultsQuery {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<i16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub order: Option<String>, // asc or desc
+}
+
+/// The filters, sorting and pagination of the list of checks
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ChecksQuery {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<i16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sort: Option<String>, // name, status or last_run
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub order: Option<String>, // asc or desc
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset: Option<i64>,
+}
+
+/// The time range and downsampling of the performance data of a check
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct PerformanceDataQuery {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub step: Option<String>, // e.g. 30s, 5m, 1h or 1d
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aggregation: Option<String>, // avg, min, max or p95
+}
+
+/// A typed client of the Pinglow RestAPI
+#[derive(Clone, Debug)]
+pub struct PinglowClient {
+    base_url: String,
+    api_key: String,
+    http_client: reqwest::Client,
+}
+
+impl PinglowClient {
+    /**
+     * This function creates a client for the API served at base_url (e.g. http://pinglow.pinglow.svc)
+     */
+    pub fn new(base_url: impl Into<String>, api_key: impl Into<String>) -> Self {
+        Self::with_http_client(base_url, api_key, reqwest::Client::new())
+    }
+
+    /**
+     * This function creates a client reusing an existing HTTP client
+     */
+    pub fn with_http_client(
+        base_url: impl Into<String>,
+        api_key: impl Into<String>,
+        http_client: reqwest::Client,
+    ) -> Self {
+        PinglowClient {
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            api_key: api_key.into(),
+            http_client,
+        }
+    }
+
+    /// Returns all the checks, with their last status
+    pub async fn list_checks(&self) -> Result<Vec<SimpleCheckDto>, ClientError> {
+        self.send_json(self.http_client.get(self.url("/checks")))
+            .await
+    }
+
+    /// Returns the checks matching all the label selectors (key=value or key), with their last status
+    pub async fn list_checks_by_labels(
+        &self,
+        selectors: &[&str],
+    ) -> Result<Vec<SimpleCheckDto>, ClientError> {
+        let query: Vec<(&str, &str)> = selectors.iter().map(|s| ("label", *s)).collect();
+        self.send_json(self.http_client.get(self.url("/checks")).query(&query))
+            .await
+    }
+
+    /// Returns a page of the checks matching all the label selectors, filtered and sorted as
+    /// requested
+    pub async fn query_checks(
+        &self,
+        selectors: &[&str],
+        query: &ChecksQuery,
+    ) -> Result<Vec<SimpleCheckDto>, ClientError> {
+        let labels: Vec<(&str, &str)> = selectors.iter().map(|s| ("label", *s)).collect();
+        self.send_json(
+            self.http_client
+                .get(self.url("/checks"))
+                .query(&labels)
+                .query(query),
+        )
+        .await
+    }
+
+    /// Returns every check matching all the label selectors with its last result, mute state,
+    /// acknowledgement and flapping state, in a single request
+    pub async fn overview(
+        &self,
+        selectors: &[&str],
+    ) -> Result<Vec<SimpleCheckResultDto>, ClientError> {
+        let query: Vec<(&str, &str)> = selectors.iter().map(|s| ("label", *s)).collect();
+        self.send_json(self.http_client.get(self.url("/overview")).query(&query))
+            .await
+    }
+
+    /// Returns the worst status among the checks matching a label selector such as team=payments
+    pub async fn group_status(&self, selector: &str) -> Result<GroupStatusDto, ClientError> {
+        self.send_json(
+            self.http_client
+                .get(self.url(&format!("/groups/{selector}/status"))),
+        )
+        .await
+    }
+
+    /// Returns the last result of a check
+    pub async fn check_status(
+        &self,
+        check_name: &str,
+    ) -> Result<SimpleCheckResultDto, ClientError> {
+        self.send_json(
+            self.http_client
+                .get(self.url(&format!("/check-status/{}", path_segment(check_name)))),
+        )
+        .await
+    }
+
+    /// Acknowledges the failure of a check, suppressing its notifications until it recovers
+    pub async fn ack(
+        &self,
+        check_name: &str,
+        payload: &AckCheckPayload,
+    ) -> Result<CheckAckDto, ClientError> {
+        self.send_json(
+            self.http_client
+                .post(self.url(&format!("/check/{}/ack", path_segment(check_name))))
+                .json(payload),
+        )
+        .await
+    }
+
+    /// Returns the performance data of a check, grouped by timestamp or aggregated by step
+    pub async fn performance_data(
+        &self,
+        check_name: &str,
+        query: &PerformanceDataQuery,
+    ) -> Result<BTreeMap<DateTime<Utc>, HashMap<String, f32>>, ClientError> {
+        self.send_json(
+            self.http_client
+                .get(self.url(&format!("/performance-data/{}", path_segment(check_name))))
+                .query(query),
+        )
+        .await
+    }
+
+    /// Returns the past results of a check, most recent first unless requested otherwise
+    pub async fn results(
+        &self,
+        check_name: &str,
+        query: &ResultsQuery,
+    ) -> Result<Vec<CheckResultHistoryDto>, ClientError> {
+        self.send_json(
+            self.http_client
+                .get(self.url(&format!("/check/{}/results", path_segment(check_name))))
+                .query(query),
+        )
+        .await
+    }
+
+    /// Returns the availability of a check over a window such as 24h or 30d (the default)
+    pub async fn availability(
+        &self,
+        check_name: &str,
+        window: Option<&str>,
+    ) -> Result<CheckAvailabilityDto, ClientError> {
+        let mut request = self
+            .http_client
+            .get(self.url(&format!("/check/{}/availability", path_segment(check_name))));
+
+        if let Some(window) = window {
+            request = request.query(&[("window", window)]);
+        }
+
+        self.send_json(request).await
+    }
+
+    /// Returns the diagnostics collected on the last CheckError results of a check
+    pub async fn diagnostics(
+        &self,
+        check_name: &str,
+        limit: Option<i64>,
+    ) -> Result<Vec<CheckDiagnosticsDto>, ClientError> {
+        let mut request = self
+            .http_client
+            .get(self.url(&format!("/check/{}/diagnostics", path_segment(check_name))));
+
+        if let Some(limit) = limit {
+            request = request.query(&[("limit", limit)]);
+        }
+
+        self.send_json(request).await
+    }
+
+    /// Returns the delivery attempts of the notifications of a check, most recent first
+    pub async fn notifications(
+        &self,
+        check_name: &str,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        limit: Option<i64>,
+    ) -> Result<Vec<NotificationAttemptDto>, ClientError> {
+        let mut request = self.http_client.get(self.url(&format!(
+            "/check/{}/notifications",
+            path_segment(check_name)
+        )));
+
+        if let Some(from) = from {
+            request = request.query(&[("from", from.to_rfc3339())]);
+        }
+        if let Some(to) = to {
+            request = request.query(&[("to", to.to_rfc3339())]);
+        }
+        if let Some(limit) = limit {
+            request = request.query(&[("limit", limit)]);
+        }
+
+        self.send_json(request).await
+    }
+
+    /// Mutes the notifications of a check, forever or until the given time
+    pub async fn mute(
+        &self,
+        check_name: &str,
+        until: Option<DateTime<Utc>>,
+    ) -> Result<(), ClientError> {
+        let mut request = self
+            .http_client
+            .put(self.url(&format!("/check/{}/mute", path_segment(check_name))));
+
+        if let Some(until) = until {
+            request = request.query(&[("until", until.to_rfc3339())]);
+        }
+
+        self.send(request).await.map(|_| ())
+    }
+
+    /// Resumes the notifications of a check
+    pub async fn unmute(&self, check_name: &str) -> Result<(), ClientError> {
+        self.send(
+            self.http_client
+                .delete(self.url(&format!("/check/{}/mute", path_segment(check_name)))),
+        )
+        .await
+        .map(|_| ())
+    }
+
+    /// Stops scheduling a check until it is resumed, the check staying visible in the API
+    pub async fn suspend(&self, check_name: &str) -> Result<(), ClientError> {
+        self.send(
+            self.http_client
+                .put(self.url(&format!("/check/{}/suspend", path_segment(check_name)))),
+        )
+        .await
+        .map(|_| ())
+    }
+
+    /// Schedules a suspended check again
+    pub async fn resume(&self, check_name: &str) -> Result<(), ClientError> {
+        self.send(
+            self.http_client
+                .delete(self.url(&format!("/check/{}/suspend", path_segment(check_name)))),
+        )
+        .await
+        .map(|_| ())
+    }
+
+    /// Mutes the notifications of all the checks matching a label selector (e.g. team=payments),
+    /// returning their names
+    pub async fn mute_by_selector(
+        &self,
+        selector: &str,
+        until: Option<DateTime<Utc>>,
+    ) -> Result<Vec<String>, ClientError> {
+        let mut query = vec![("selector", selector.to_string())];
+        if let Some(until) = until {
+            query.push(("until", until.to_rfc3339()));
+        }
+
+        self.send_json(self.http_client.put(self.url("/checks/mute")).query(&query))
+            .await
+    }
+
+    /// Resumes the notifications of all the checks matching a label selector, returning their names
+    pub async fn unmute_by_selector(&self, selector: &str) -> Result<Vec<String>, ClientError> {
+        self.send_json(
+            self.http_client
+                .delete(self.url("/checks/mute"))
+                .query(&[("selector", selector)]),
+        )
+        .await
+    }
+
+    /// Returns the runners with their last heartbeat and health
+    pub async fn runners(&self) -> Result<Vec<RunnerDto>, ClientError> {
+        self.send_json(self.http_client.get(self.url("/runners")))
+            .await
+    }
+
+    /// Submits the result of a passive check
+    pub async fn submit_result(
+        &self,
+        check_name: &str,
+        result: &ProcessCheckResultPayload,
+    ) -> Result<(), ClientError> {
+        self.send(
+            self.http_client
+                .post(self.url(&format!("/check/{}/result", path_segment(check_name))))
+                .json(result),
+        )
+        .await
+        .map(|_| ())
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{path}", self.base_url)
+    }
+
+    async fn send(&self, request: RequestBuilder) -> Result<Response, ClientError> {
+        let response = request.header("x-api-key", &self.api_key).send().await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(ClientError::ApiError {
+                status,
+                message: response.text().await.unwrap_or_default(),
+            });
+        }
+
+        Ok(response)
+    }
+
+    async fn send_json<T: DeserializeOwned>(
+        &self,
+        request: RequestBuilder,
+    ) -> Result<T, ClientError> {
+        Ok(self.send(request).await?.json().await?)
+    }
+}
+
+/**
+ * This function encodes a check name as a path segment, the checks outside of the namespace of the
+ * controller being named namespace/name
+ */
+fn path_segment(check_name: &str) -> String {
+    check_name.replace('/', "%2F")
+}
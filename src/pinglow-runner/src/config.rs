@@ -1,4 +1,4 @@
-use std::env;
+use std::{collections::BTreeMap, env};
 
 #[derive(Debug, Clone)]
 pub struct PinglowRunnerConfig {
@@ -6,6 +6,10 @@ pub struct PinglowRunnerConfig {
     pub redis_password: String,
     pub runner_name: String,
     pub checks_base_path: String,
+    pub namespace: String,
+    pub standalone_config_dir: Option<String>,
+    pub max_concurrent_checks: usize,
+    pub labels: BTreeMap<String, String>, // Matched against the runnerSelector of the checks
 }
 
 /**
@@ -17,5 +21,26 @@ pub fn get_config_from_env() -> PinglowRunnerConfig {
         runner_name: env::var("RUNNER_NAME").unwrap_or_else(|_| "runner-unknown".into()),
         checks_base_path: env::var("CHECKS_BASE_PATH")
             .unwrap_or_else(|_| "/home/pinglow-runner/".into()),
+        namespace: env::var("NAMESPACE").unwrap_or_else(|_| "pinglow".into()),
+        standalone_config_dir: env::var("STANDALONE_CONFIG_DIR").ok(),
+        max_concurrent_checks: env::var("MAX_CONCURRENT_CHECKS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(10),
+        labels: env::var("RUNNER_LABELS")
+            .map(|labels| parse_labels(&labels))
+            .unwrap_or_default(),
     }
 }
+
+/**
+ * This function parses labels in the key=value,key=value format
+ */
+fn parse_labels(labels: &str) -> BTreeMap<String, String> {
+    labels
+        .split(',')
+        .filter_map(|label| label.split_once('='))
+        .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+        .filter(|(key, _)| !key.is_empty())
+        .collect()
+}
@@ -1,82 +1,90 @@
 use std::fs;
 use std::process::{Command, Stdio};
+use std::time::Duration;
 
 use anyhow::{bail, Error};
 use chrono::Utc;
-use pinglow_common::{CheckResult, CheckResultStatus, PinglowCheck};
+use pinglow_common::{
+    last_lines, CheckResult, CheckResultStatus, PinglowCheck, ScriptLanguage, ScriptSpec,
+    DIAGNOSTICS_LOG_LINES,
+};
 
-pub async fn execute_check(check: PinglowCheck, base_path: &str) -> Result<CheckResult, Error> {
-    // Get the script
-    let script = check
-        .script
-        .ok_or(pinglow_common::error::ScriptError::NoScriptFound(
-            check.check_name.clone(),
-        ))?;
+use crate::wasm::execute_wasm_check;
+
+pub async fn execute_check(
+    check: PinglowCheck,
+    script: &ScriptSpec,
+    base_path: &str,
+) -> Result<CheckResult, Error> {
+    // WASM modules run in the runner itself, without any environment to prepare
+    if script.language == Some(ScriptLanguage::Wasm) {
+        return execute_wasm_check(check, script).await;
+    }
 
     // Ensure we have a folder for this check
     let check_dir = format!("{}/check-{}", base_path, check.check_name);
-    let script_path = format!("{}/script.py", &check_dir);
-    let venv_path = format!("{}/venv", &check_dir);
-
-    fs::create_dir_all(&check_dir)?;
 
-    // Write the script in the check dir
-    fs::write(&script_path, &script.content)?;
-
-    // Create the venv
-    let output = Command::new("python3")
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .args(["-m", "venv", &venv_path])
-        .output()?;
-
-    if !output.status.success() {
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        bail!(
-            "Error creating venv (status: {}):\nstdout:\n{}\nstderr:\n{}",
-            output.status,
-            stdout,
-            stderr
-        );
+    // The content read from a source could not be checked against the parameters by the controller
+    let missing_parameters = script.missing_parameters(&check.parameters);
+    if !missing_parameters.is_empty() {
+        return Ok(check_error_result(
+            check,
+            "The script could not be rendered".to_string(),
+            format!("Missing parameters: {}", missing_parameters.join(", ")),
+        ));
     }
 
-    // Install dependencies, if any
-    if let Some(reqs) = script.python_requirements {
-        let mut args = vec!["install"];
-        args.extend(reqs.iter().map(|s| s.as_str()));
-
-        let output = Command::new(format!("{venv_path}/bin/pip"))
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .args(args)
-            .output()?;
-
-        if !output.status.success() {
-            let stdout = String::from_utf8_lossy(&output.stdout);
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            bail!(
-                "Error installing dependencies (status: {}):\nstdout:\n{}\nstderr:\n{}",
-                output.status,
-                stdout,
-                stderr
-            );
+    // A broken environment is reported as a CheckError, with the details of the failure
+    let content = check.render_script(&script.content);
+    let (program, program_args) = match prepare_environment(script, &content, &check_dir) {
+        Ok(program) => program,
+        Err(e) => {
+            let error = e.to_string();
+            let output = error.lines().next().unwrap_or_default().to_string();
+
+            return Ok(check_error_result(
+                check,
+                output,
+                last_lines(&error, DIAGNOSTICS_LOG_LINES),
+            ));
         }
-    }
+    };
 
-    // Run check in the venv
-    let mut command = Command::new(format!("{venv_path}/bin/python"));
-    command.arg(script_path).stdout(Stdio::piped());
+    // Run check with the interpreter of its language, the script is killed if the result is
+    // abandoned on timeout
+    let mut command = tokio::process::Command::new(program);
+    command
+        .kill_on_drop(true)
+        .args(program_args)
+        .args(check.templated_args())
+        .envs(check.metadata_env())
+        .envs(&check.env)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
 
-    // Check if we have secrets
-    if let Some(secrets) = check.secrets {
+    // Check if we have secrets, which take precedence over the plain environment
+    if let Some(secrets) = &check.secrets {
         // Inject secrets
         for (k, v) in secrets.iter() {
             command.env(k, v);
         }
     }
 
-    let output = command.output()?;
+    let output = match check.timeout_seconds {
+        Some(seconds) => {
+            match tokio::time::timeout(Duration::from_secs(seconds), command.output()).await {
+                Ok(output) => output?,
+                Err(_) => {
+                    return Ok(check_error_result(
+                        check,
+                        format!("Check timed out after {seconds} seconds"),
+                        format!("The script was killed after running for {seconds} seconds"),
+                    ));
+                }
+            }
+        }
+        None => command.output().await?,
+    };
 
     // Wait for completion
     let exit_status =
@@ -87,16 +95,201 @@ pub async fn execute_check(check: PinglowCheck, base_path: &str) -> Result<Check
                 "Cannot extract exit code".to_string(),
             ))?;
 
+    let status = check.status_from_exit_code(exit_status);
+
+    // Keep the end of stderr to explain the error
+    let diagnostics = (status == CheckResultStatus::CheckError).then(|| {
+        format!(
+            "Exit code: {exit_status}\nstderr:\n{}",
+            last_lines(
+                &String::from_utf8_lossy(&output.stderr),
+                DIAGNOSTICS_LOG_LINES
+            )
+        )
+    });
+
     // Return the check result object
     let result = CheckResult {
         check_name: check.check_name,
         output: String::from_utf8(output.stdout)?,
-        status: CheckResultStatus::from(exit_status),
+        status,
         timestamp: Some(Utc::now()),
         telegram_channels: check.telegram_channels.into(),
         mute_notifications: check.mute_notifications,
         mute_notifications_until: check.mute_notifications_until,
+        maintenance_windows: check.maintenance_windows.clone().into(),
+        webhooks: check.webhooks.into(),
+        diagnostics,
+        slack_channels: check.slack_channels.into(),
+        push_channels: check.push_channels.into(),
+        discord_channels: check.discord_channels.into(),
+        notify_on_state_change: check.notify_on_state_change,
+        notify_on_recovery: check.notify_on_recovery,
+        renotify_interval_minutes: check.renotify_interval_minutes,
+        min_notification_interval_seconds: check.min_notification_interval_seconds,
+        soft_failure: false,
+        depends_on: check.depends_on.clone().into(),
+        suppressed_by: None,
+        anomaly_sigma: check.anomaly_sigma,
+        script_hash: None,
+        run_id: check.run_id,
     };
 
     Ok(result)
 }
+
+/**
+ * This function builds the CheckError result of a check which could not be executed
+ */
+pub fn check_error_result(check: PinglowCheck, output: String, diagnostics: String) -> CheckResult {
+    let mut result = CheckResult::map_to_check_error(&check, output, Some(diagnostics));
+    result.set_check_result_timestamp(Utc::now());
+    result
+}
+
+/**
+ * This function builds the result of a check executed by the runner itself, without a script
+ */
+pub fn probe_result(
+    check: PinglowCheck,
+    status: CheckResultStatus,
+    output: String,
+    diagnostics: Option<String>,
+) -> CheckResult {
+    CheckResult {
+        check_name: check.check_name,
+        output,
+        status,
+        timestamp: Some(Utc::now()),
+        telegram_channels: check.telegram_channels.into(),
+        mute_notifications: check.mute_notifications,
+        mute_notifications_until: check.mute_notifications_until,
+        maintenance_windows: check.maintenance_windows.clone().into(),
+        webhooks: check.webhooks.into(),
+        diagnostics,
+        slack_channels: check.slack_channels.into(),
+        push_channels: check.push_channels.into(),
+        discord_channels: check.discord_channels.into(),
+        notify_on_state_change: check.notify_on_state_change,
+        notify_on_recovery: check.notify_on_recovery,
+        renotify_interval_minutes: check.renotify_interval_minutes,
+        min_notification_interval_seconds: check.min_notification_interval_seconds,
+        soft_failure: false,
+        depends_on: check.depends_on.clone().into(),
+        suppressed_by: None,
+        anomaly_sigma: check.anomaly_sigma,
+        script_hash: None,
+        run_id: check.run_id,
+    }
+}
+
+/**
+ * This function writes the script and prepares what its language needs to run it, returning the
+ * program and the arguments running it
+ */
+fn prepare_environment(
+    script: &ScriptSpec,
+    content: &str,
+    check_dir: &str,
+) -> Result<(String, Vec<String>), Error> {
+    fs::create_dir_all(check_dir)?;
+
+    match script.language.as_ref().unwrap_or(&ScriptLanguage::Python) {
+        ScriptLanguage::Python => {
+            let script_path = write_script(check_dir, "script.py", content)?;
+            let venv_path = format!("{check_dir}/venv");
+
+            // Create the venv
+            run_step(
+                Command::new("python3").args(["-m", "venv", &venv_path]),
+                "creating venv",
+            )?;
+
+            // Install dependencies, if any
+            if let Some(reqs) = &script.python_requirements {
+                run_step(
+                    Command::new(format!("{venv_path}/bin/pip"))
+                        .arg("install")
+                        .args(reqs),
+                    "installing dependencies",
+                )?;
+            }
+
+            Ok((format!("{venv_path}/bin/python"), vec![script_path]))
+        }
+        ScriptLanguage::Node => {
+            let script_path = write_script(check_dir, "script.js", content)?;
+
+            // The packages are installed next to the script, where node looks for them
+            if let Some(dependencies) = &script.npm_dependencies {
+                run_step(
+                    Command::new("npm")
+                        .args([
+                            "install",
+                            "--no-save",
+                            "--no-audit",
+                            "--no-fund",
+                            "--prefix",
+                        ])
+                        .arg(check_dir)
+                        .args(dependencies),
+                    "installing dependencies",
+                )?;
+            }
+
+            Ok(("node".to_string(), vec![script_path]))
+        }
+        ScriptLanguage::Bash => {
+            let script_path = write_script(check_dir, "script.sh", content)?;
+
+            Ok(("bash".to_string(), vec![script_path]))
+        }
+        ScriptLanguage::PowerShell => {
+            let script_path = write_script(check_dir, "script.ps1", content)?;
+
+            Ok((
+                "pwsh".to_string(),
+                vec![
+                    "-NoProfile".to_string(),
+                    "-NonInteractive".to_string(),
+                    "-File".to_string(),
+                    script_path,
+                ],
+            ))
+        }
+        ScriptLanguage::Wasm => bail!("WASM modules are not run as processes"),
+    }
+}
+
+/**
+ * This function writes the script in the check dir, returning its path
+ */
+fn write_script(check_dir: &str, file_name: &str, content: &str) -> Result<String, Error> {
+    let script_path = format!("{check_dir}/{file_name}");
+    fs::write(&script_path, content)?;
+
+    Ok(script_path)
+}
+
+/**
+ * This function runs a step of the preparation of the environment, failing with its output
+ */
+fn run_step(command: &mut Command, step: &str) -> Result<(), Error> {
+    let output = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .output()?;
+
+    if !output.status.success() {
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!(
+            "Error {step} (status: {}):\nstdout:\n{}\nstderr:\n{}",
+            output.status,
+            stdout,
+            stderr
+        );
+    }
+
+    Ok(())
+}
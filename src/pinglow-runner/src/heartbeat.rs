@@ -0,0 +1,101 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use chrono::Utc;
+use log::{debug, error};
+use pinglow_common::{
+    dto::RunnerDto,
+    redis::{RUNNERS_KEY, RUNNER_HEARTBEAT_INTERVAL_SECONDS},
+};
+use redis::Client as RedisClient;
+use tokio_util::sync::CancellationToken;
+
+/**
+ * Counts a check as in flight until dropped, whatever the outcome of its execution
+ */
+pub struct InFlightGuard(Arc<AtomicUsize>);
+
+impl InFlightGuard {
+    pub fn new(in_flight: &Arc<AtomicUsize>) -> Self {
+        in_flight.fetch_add(1, Ordering::Relaxed);
+        InFlightGuard(in_flight.clone())
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/**
+ * This function periodically publishes the heartbeat of the runner, so that the controller knows
+ * which runners are alive, removing it once the shutdown token is cancelled
+ */
+pub async fn run(
+    redis_client: RedisClient,
+    runner_name: String,
+    in_flight: Arc<AtomicUsize>,
+    shutdown: CancellationToken,
+) {
+    let started_at = Utc::now();
+    let mut interval =
+        tokio::time::interval(Duration::from_secs(RUNNER_HEARTBEAT_INTERVAL_SECONDS));
+
+    loop {
+        tokio::select! {
+            _ = shutdown.cancelled() => break,
+            _ = interval.tick() => {
+                let heartbeat = RunnerDto {
+                    name: runner_name.clone(),
+                    version: env!("CARGO_PKG_VERSION").to_string(),
+                    in_flight_checks: in_flight.load(Ordering::Relaxed),
+                    started_at,
+                    last_seen: Utc::now(),
+                    healthy: true,
+                };
+
+                if let Err(e) = publish(&redis_client, &heartbeat).await {
+                    error!("Error publishing the runner heartbeat: {e}");
+                }
+            }
+        }
+    }
+
+    // A runner leaving cleanly is not reported as unhealthy
+    if let Err(e) = remove(&redis_client, &runner_name).await {
+        error!("Error removing the runner heartbeat: {e}");
+    }
+}
+
+async fn publish(redis_client: &RedisClient, heartbeat: &RunnerDto) -> Result<(), anyhow::Error> {
+    let mut conn = redis_client.get_multiplexed_async_connection().await?;
+
+    redis::cmd("HSET")
+        .arg(RUNNERS_KEY)
+        .arg(&heartbeat.name)
+        .arg(serde_json::to_string(heartbeat)?)
+        .query_async::<()>(&mut conn)
+        .await?;
+
+    debug!("Heartbeat published at {}", heartbeat.last_seen);
+
+    Ok(())
+}
+
+async fn remove(redis_client: &RedisClient, runner_name: &str) -> Result<(), anyhow::Error> {
+    let mut conn = redis_client.get_multiplexed_async_connection().await?;
+
+    redis::cmd("HDEL")
+        .arg(RUNNERS_KEY)
+        .arg(runner_name)
+        .query_async::<()>(&mut conn)
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,216 @@
+use std::{
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
+
+use anyhow::Error;
+use pinglow_common::{CheckResult, CheckResultStatus, HttpCheckSpec, PinglowCheck};
+use regex::Regex;
+use reqwest::{
+    header::{HeaderName, HeaderValue},
+    Client, Method, RequestBuilder, Response, Url,
+};
+
+use crate::executor::{check_error_result, probe_result};
+
+// Timeout of the request when the check does not define one
+const DEFAULT_TIMEOUT_SECONDS: u64 = 10;
+
+// Maximum size of the body read from the response, the rest being ignored
+const MAX_BODY_SIZE: usize = 1024 * 1024;
+
+// Clients shared by the checks, verifying the certificates or not
+static CLIENT: OnceLock<Client> = OnceLock::new();
+static INSECURE_CLIENT: OnceLock<Client> = OnceLock::new();
+
+/**
+ * This function probes the endpoint of an HTTP check, reporting the latency and the status code
+ * as performance data
+ */
+pub async fn execute_http_check(
+    check: PinglowCheck,
+    http_check: &HttpCheckSpec,
+) -> Result<CheckResult, Error> {
+    // A check which cannot be built is reported as a CheckError, as a broken configuration
+    let (request, body_regex) = match build_request(&check, http_check) {
+        Ok(request) => request,
+        Err(e) => {
+            return Ok(check_error_result(
+                check,
+                format!("Invalid HTTP check: {e}"),
+                format!("{e:#}"),
+            ))
+        }
+    };
+
+    let start = Instant::now();
+
+    let response = match request.send().await {
+        Ok(response) => response,
+        Err(e) => {
+            let output = if e.is_timeout() {
+                "Request timed out".to_string()
+            } else {
+                format!("Request failed: {e}")
+            };
+            return Ok(probe_result(
+                check,
+                CheckResultStatus::Critical,
+                output,
+                None,
+            ));
+        }
+    };
+
+    let status_code = response.status();
+
+    let body = match read_body(response).await {
+        Ok(body) => body,
+        Err(e) => {
+            return Ok(probe_result(
+                check,
+                CheckResultStatus::Critical,
+                format!("Error reading the response: {e}"),
+                None,
+            ))
+        }
+    };
+
+    let latency_ms = start.elapsed().as_millis() as u64;
+
+    let expected_status_codes = http_check
+        .expectedStatusCodes
+        .clone()
+        .unwrap_or_else(|| vec![200]);
+
+    let (status, summary) = if !expected_status_codes.contains(&status_code.as_u16()) {
+        (
+            CheckResultStatus::Critical,
+            format!("Unexpected status {status_code}, expected one of {expected_status_codes:?}"),
+        )
+    } else if body_regex.is_some_and(|regex| !regex.is_match(&body)) {
+        (
+            CheckResultStatus::Critical,
+            format!("Status {status_code}, but the body does not match the expected pattern"),
+        )
+    } else if http_check
+        .criticalLatencyMs
+        .is_some_and(|threshold| latency_ms >= threshold)
+    {
+        (
+            CheckResultStatus::Critical,
+            format!("Status {status_code}, slow response in {latency_ms} ms"),
+        )
+    } else if http_check
+        .warningLatencyMs
+        .is_some_and(|threshold| latency_ms >= threshold)
+    {
+        (
+            CheckResultStatus::Warning,
+            format!("Status {status_code}, slow response in {latency_ms} ms"),
+        )
+    } else {
+        (
+            CheckResultStatus::Ok,
+            format!("Status {status_code} in {latency_ms} ms"),
+        )
+    };
+
+    Ok(probe_result(
+        check,
+        status,
+        format!(
+            "{summary}|latency_ms={latency_ms},status_code={}",
+            status_code.as_u16()
+        ),
+        None,
+    ))
+}
+
+/**
+ * This function returns the client shared by the checks with the same TLS verification, building
+ * it on first use
+ */
+fn client(insecure_skip_tls_verify: bool) -> Result<Client, Error> {
+    let cell = if insecure_skip_tls_verify {
+        &INSECURE_CLIENT
+    } else {
+        &CLIENT
+    };
+
+    if let Some(client) = cell.get() {
+        return Ok(client.clone());
+    }
+
+    let client = Client::builder()
+        .danger_accept_invalid_certs(insecure_skip_tls_verify)
+        .build()?;
+
+    Ok(cell.get_or_init(|| client).clone())
+}
+
+/**
+ * This function reads the body of a response, up to MAX_BODY_SIZE
+ */
+async fn read_body(mut response: Response) -> Result<String, reqwest::Error> {
+    let mut body = Vec::new();
+
+    while let Some(chunk) = response.chunk().await? {
+        body.extend_from_slice(&chunk);
+        if body.len() >= MAX_BODY_SIZE {
+            body.truncate(MAX_BODY_SIZE);
+            break;
+        }
+    }
+
+    Ok(String::from_utf8_lossy(&body).into_owned())
+}
+
+/**
+ * This function builds the request of a check, rendering the placeholders of the URL, the headers
+ * and the body. The headers can also reference the secrets of the check as ${secrets.<KEY>}
+ */
+fn build_request(
+    check: &PinglowCheck,
+    http_check: &HttpCheckSpec,
+) -> Result<(RequestBuilder, Option<Regex>), Error> {
+    let client = client(http_check.insecureSkipTlsVerify.unwrap_or(false))?;
+
+    let method = Method::from_bytes(
+        http_check
+            .method
+            .as_deref()
+            .unwrap_or("GET")
+            .to_uppercase()
+            .as_bytes(),
+    )?;
+
+    let url = Url::parse(&check.render_template(&http_check.url))?;
+    let mut request = client.request(method, url).timeout(Duration::from_secs(
+        check.timeout_seconds.unwrap_or(DEFAULT_TIMEOUT_SECONDS),
+    ));
+
+    for (name, value) in http_check.headers.iter().flatten() {
+        let mut value = check.render_template(value);
+        for (key, secret) in check.secrets.iter().flatten() {
+            value = value.replace(&format!("${{secrets.{key}}}"), secret);
+        }
+
+        request = request.header(
+            HeaderName::from_bytes(name.as_bytes())?,
+            HeaderValue::from_str(&value)?,
+        );
+    }
+
+    if let Some(body) = &http_check.body {
+        request = request.body(check.render_template(body));
+    }
+
+    let body_regex = http_check
+        .bodyRegex
+        .as_deref()
+        .map(Regex::new)
+        .transpose()?;
+
+    Ok((request, body_regex))
+}
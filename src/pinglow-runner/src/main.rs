@@ -1,9 +1,17 @@
 use env_logger::Builder;
+use log::error;
+use pinglow_common::telemetry::init_tracing;
 
 mod config;
 mod executor;
+mod heartbeat;
+mod http;
 mod queue;
 mod runner;
+mod scripts;
+mod secrets;
+mod tcp;
+mod wasm;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -11,5 +19,16 @@ async fn main() -> anyhow::Result<()> {
     Builder::from_env(env_logger::Env::default().default_filter_or("info,wasmtime_wasi::p1=warn"))
         .init();
 
-    runner::run().await
+    let tracer_provider = init_tracing("pinglow-runner");
+
+    let outcome = runner::run().await;
+
+    // Flush the spans still to be exported
+    if let Some(tracer_provider) = tracer_provider {
+        if let Err(e) = tracer_provider.shutdown() {
+            error!("Error flushing the traces: {e}");
+        }
+    }
+
+    outcome
 }
@@ -1,44 +1,134 @@
+use std::collections::BTreeMap;
+
 use anyhow::Error;
-use pinglow_common::redis::parse_stream_payload;
+use log::error;
+use opentelemetry::Context;
+use pinglow_common::redis::{checks_stream, parse_multi_stream_entries, CHECKS_STREAM};
+use pinglow_common::telemetry::extract_context;
 use pinglow_common::PinglowCheck;
 use redis::aio::MultiplexedConnection;
 use redis::Value;
 
-pub async fn fetch_task(
+// Labels of a runner considered when routing the checks, to bound the number of streams read
+const MAX_ROUTING_LABELS: usize = 8;
+
+/// A check to execute, read from one of the checks streams
+pub struct Task {
+    pub stream: String,
+    pub id: String,
+    pub check: PinglowCheck,
+    pub trace_context: Context, // Trace started by the scheduler
+}
+
+/**
+ * This function returns the streams read by a runner: the default one and the ones of the
+ * runner selectors matching its labels, i.e. every subset of them
+ */
+pub fn runner_streams(labels: &BTreeMap<String, String>) -> Vec<String> {
+    let labels: Vec<(&String, &String)> = labels.iter().take(MAX_ROUTING_LABELS).collect();
+
+    let mut streams = vec![CHECKS_STREAM.to_string()];
+
+    for subset in 1..(1u32 << labels.len()) {
+        let selector: BTreeMap<String, String> = labels
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| subset & (1 << i) != 0)
+            .map(|(_, (key, value))| (key.to_string(), value.to_string()))
+            .collect();
+        streams.push(checks_stream(&selector));
+    }
+
+    streams
+}
+
+/**
+ * This function fetches the next checks to execute from the streams of the runner, at most one
+ * per stream and no more than `limit`. When every stream can be read at once, it waits for the
+ * first checks; otherwise the streams are read in turn without waiting, until the limit is
+ * reached
+ */
+pub async fn fetch_tasks(
+    conn: &mut MultiplexedConnection,
+    runner_name: &str,
+    streams: &[String],
+    limit: usize,
+) -> Result<Vec<Task>, Error> {
+    if streams.len() <= limit {
+        return read_streams(conn, runner_name, streams, true).await;
+    }
+
+    let mut tasks = vec![];
+    for stream in streams {
+        if tasks.len() >= limit {
+            break;
+        }
+        tasks.extend(read_streams(conn, runner_name, std::slice::from_ref(stream), false).await?);
+    }
+
+    Ok(tasks)
+}
+
+/**
+ * This function reads at most one new check from each of the given streams, optionally waiting
+ * for them
+ */
+async fn read_streams(
     conn: &mut MultiplexedConnection,
     runner_name: &str,
-) -> Result<Option<(String, PinglowCheck)>, Error> {
-    let res: Option<Value> = redis::cmd("XREADGROUP")
-        .arg("GROUP")
-        .arg("workers")
-        .arg(runner_name) // consumer name
-        .arg("BLOCK")
-        .arg(15000)
-        .arg("COUNT")
-        .arg(1) // fetch one message at a time
+    streams: &[String],
+    block: bool,
+) -> Result<Vec<Task>, Error> {
+    let mut cmd = redis::cmd("XREADGROUP");
+    cmd.arg("GROUP").arg("workers").arg(runner_name); // consumer name
+    if block {
+        cmd.arg("BLOCK").arg(15000);
+    }
+    cmd.arg("COUNT")
+        .arg(1) // fetch one message at a time from each stream
         .arg("STREAMS")
-        .arg("pinglow:checks")
-        .arg(">") // fetch only new messages
-        .query_async(conn)
-        .await?;
+        .arg(streams);
+    for _ in streams {
+        cmd.arg(">"); // fetch only new messages
+    }
+
+    let res: Option<Value> = cmd.query_async(conn).await?;
 
     let Some(value) = res else {
-        return Ok(None);
+        return Ok(vec![]);
     };
 
-    let (id, fields) = parse_stream_payload(value).ok_or(
-        pinglow_common::error::SerializeError::DeserializationError(
-            "Cannot extract id and fields from redis message".into(),
-        ),
-    )?;
-
-    let payload = fields.get("payload").ok_or(
+    let entries = parse_multi_stream_entries(value).ok_or(
         pinglow_common::error::SerializeError::DeserializationError(
-            "The expected payload field was not found".into(),
+            "Cannot extract entries from redis message".into(),
         ),
     )?;
 
-    let check: PinglowCheck = serde_json::from_str(payload)?;
+    // An undecodable check is left pending, to be moved to the dead-letter stream by the controller
+    Ok(entries
+        .into_iter()
+        .filter_map(|(stream, (id, fields))| {
+            let check = match fields
+                .get("payload")
+                .map(|payload| serde_json::from_str::<PinglowCheck>(payload))
+            {
+                Some(Ok(check)) => check,
+                Some(Err(e)) => {
+                    error!("Cannot deserialize check {id}: {e}");
+                    return None;
+                }
+                None => {
+                    error!("The expected payload field was not found in check {id}");
+                    return None;
+                }
+            };
 
-    Ok(Some((id, check)))
+            Some(Task {
+                stream,
+                id,
+                trace_context: extract_context(&fields),
+                check,
+            })
+        })
+        .collect())
 }
@@ -1,25 +1,73 @@
-use std::time::Duration;
+use std::{
+    collections::HashMap,
+    sync::{atomic::AtomicUsize, Arc},
+    time::Duration,
+};
 
+use kube::Client;
 use log::{debug, error, info};
+use opentelemetry::{
+    trace::{SpanKind, TraceContextExt},
+    KeyValue,
+};
 use pinglow_common::{
     error::SerializeError,
-    redis::{init_streams, redis_client},
+    redis::{init_checks_stream, init_streams, redis_client},
+    telemetry::{inject_context, start_span, TRACE_CONTEXT_FIELD},
+    CheckResultStatus,
 };
 use redis::AsyncConnectionConfig;
+use tokio::sync::{RwLock, Semaphore};
 use tokio_util::sync::CancellationToken;
 
-use crate::{config::get_config_from_env, executor::execute_check, queue::fetch_task};
+use crate::{
+    config::get_config_from_env,
+    executor::{check_error_result, execute_check},
+    heartbeat::{self, InFlightGuard},
+    http::execute_http_check,
+    queue::{fetch_tasks, runner_streams, Task},
+    scripts::{resolve_script, ScriptSource, SharedScripts},
+    secrets::{resolve_secrets, SecretSource, SharedSecrets},
+    tcp::execute_tcp_check,
+};
+
+// Delay between the attempts of a failed check without retryDelaySeconds
+const DEFAULT_RETRY_DELAY_SECONDS: u64 = 30;
 
 pub async fn run() -> anyhow::Result<()> {
     let redis_client = redis_client()?;
 
+    let runner_config = get_config_from_env();
+
+    // Besides the default stream, the runner reads the ones of the checks routed to its labels
+    let mut streams = runner_streams(&runner_config.labels);
+
     // Init streams (short-lived connection)
     {
         let mut conn = redis_client.get_multiplexed_async_connection().await?;
         init_streams(&mut conn).await;
+        for stream in streams.iter().skip(1) {
+            init_checks_stream(&mut conn, stream).await;
+        }
     } // conn dropped here
 
-    let runner_config = get_config_from_env();
+    // Scripts and secrets are fetched from the cluster (or the local directory in standalone
+    // mode) and cached, only their reference travels in the queue
+    let (script_source, secret_source) = match &runner_config.standalone_config_dir {
+        Some(dir) => (
+            ScriptSource::Directory(dir.into()),
+            SecretSource::Directory(dir.into()),
+        ),
+        None => {
+            let kube_client = Client::try_default().await?;
+            (
+                ScriptSource::Kube(kube_client.clone(), runner_config.namespace.clone()),
+                SecretSource::Kube(kube_client, runner_config.namespace.clone()),
+            )
+        }
+    };
+    let scripts_cache: SharedScripts = Arc::new(RwLock::new(HashMap::new()));
+    let secrets_cache: SharedSecrets = Arc::new(RwLock::new(HashMap::new()));
 
     let shutdown = CancellationToken::new();
     let shutdown_signal = shutdown.clone();
@@ -34,6 +82,19 @@ pub async fn run() -> anyhow::Result<()> {
     async_connection = async_connection.set_connection_timeout(Some(Duration::from_secs(30)));
     async_connection = async_connection.set_response_timeout(Some(Duration::from_secs(30)));
 
+    // Publish the heartbeat of the runner, with the number of checks being executed
+    let in_flight = Arc::new(AtomicUsize::new(0));
+    let heartbeat = tokio::spawn(heartbeat::run(
+        redis_client.clone(),
+        runner_config.runner_name.clone(),
+        in_flight.clone(),
+        shutdown.clone(),
+    ));
+
+    // A check is read only when a slot is free to execute it, leaving the others in the queue:
+    // a check read and not executed would stay pending, to be reclaimed by another runner
+    let slots = Arc::new(Semaphore::new(runner_config.max_concurrent_checks.max(1)));
+
     info!("Runner started");
 
     loop {
@@ -42,6 +103,20 @@ pub async fn run() -> anyhow::Result<()> {
             break;
         }
 
+        let slot = tokio::select! {
+            _ = shutdown.cancelled() => continue,
+            slot = slots.clone().acquire_owned() => slot?,
+        };
+
+        // The other free slots, up to one per stream, are taken to read from several streams at once
+        let mut slots_taken = vec![slot];
+        while slots_taken.len() < streams.len() {
+            match slots.clone().try_acquire_owned() {
+                Ok(slot) => slots_taken.push(slot),
+                Err(_) => break,
+            }
+        }
+
         let mut redis_conn = redis_client
             .get_multiplexed_async_connection_with_config(&async_connection)
             .await?;
@@ -49,67 +124,211 @@ pub async fn run() -> anyhow::Result<()> {
         let base_path = runner_config.checks_base_path.clone();
         let connection_config = async_connection.clone();
 
-        match fetch_task(&mut redis_conn, &runner_config.runner_name).await {
-            Ok(Some((id, check))) => {
-                debug!("Received check to execute");
-                let redis_client = redis_client.clone();
-                tokio::spawn(async move {
-                    // Execute check
-                    let result = match execute_check(check, &base_path).await {
-                        Ok(r) => r,
-                        Err(e) => {
-                            error!("Error executing check: {e}");
-                            return;
-                        }
-                    };
-
-                    let mut redis_conn = match redis_client
-                        .get_multiplexed_async_connection_with_config(&connection_config)
-                        .await
-                    {
-                        Ok(c) => c,
-                        Err(e) => {
-                            error!("Error getting connection to redis: {e}");
-                            return;
+        let tasks = fetch_tasks(
+            &mut redis_conn,
+            &runner_config.runner_name,
+            &streams,
+            slots_taken.len(),
+        )
+        .await;
+
+        // The streams read first change at every fetch, not to starve the last ones
+        streams.rotate_left(1);
+
+        match tasks {
+            Ok(tasks) if !tasks.is_empty() => {
+                for (
+                    Task {
+                        stream,
+                        id,
+                        mut check,
+                        trace_context: parent_cx,
+                    },
+                    slot,
+                ) in tasks.into_iter().zip(slots_taken.drain(..))
+                {
+                    debug!("Received check to execute");
+                    let base_path = base_path.clone();
+                    let connection_config = connection_config.clone();
+                    let redis_client = redis_client.clone();
+                    let script_source = script_source.clone();
+                    let scripts_cache = scripts_cache.clone();
+                    let secret_source = secret_source.clone();
+                    let secrets_cache = secrets_cache.clone();
+                    let runner_name = runner_config.runner_name.clone();
+                    let in_flight_guard = InFlightGuard::new(&in_flight);
+                    tokio::spawn(async move {
+                        let _slot = slot;
+                        let _in_flight_guard = in_flight_guard;
+
+                        // The span lasts for the whole execution, retries included, and is ended
+                        // when dropped if the execution is interrupted
+                        let cx = start_span(
+                            "execute check",
+                            SpanKind::Consumer,
+                            &parent_cx,
+                            &check.check_name,
+                        );
+                        cx.span()
+                            .set_attribute(KeyValue::new("runner.name", runner_name));
+
+                        // A check which cannot be prepared is reported as a CheckError, so that
+                        // the failure is notified instead of the check silently going stale
+                        let mut setup_error = None;
+
+                        // Get the script to run, HTTP and TCP checks are probed by the runner itself
+                        let script = if check.is_probe() {
+                            None
+                        } else {
+                            match resolve_script(&check, &script_source, &scripts_cache).await {
+                                Ok(s) => Some(s),
+                                Err(e) => {
+                                    error!("Error resolving script: {e}");
+                                    setup_error = Some(format!("Error resolving script: {e}"));
+                                    None
+                                }
+                            }
+                        };
+
+                        if setup_error.is_none() {
+                            match resolve_secrets(&check, &secret_source, &secrets_cache).await {
+                                Ok(secrets) => check.secrets = secrets,
+                                Err(e) => {
+                                    error!("Error resolving secrets: {e}");
+                                    setup_error = Some(format!("Error resolving secrets: {e}"));
+                                }
+                            }
                         }
-                    };
-
-                    // Ack in redis
-                    if let Err(e) = redis::cmd("XACK")
-                        .arg("pinglow:checks")
-                        .arg("workers")
-                        .arg(id)
-                        .query_async::<()>(&mut redis_conn)
-                        .await
-                    {
-                        error!("Error sending ack to redis for check: {e}");
-                    }
-
-                    let payload = match serde_json::to_string(&result).map_err(|e| {
-                        SerializeError::SerializationError(format!("Error serializing check: {e}"))
-                    }) {
-                        Ok(p) => p,
-                        Err(e) => {
-                            error!("Error serializing check result: {e}");
-                            return;
+
+                        let mut attempt = 0;
+
+                        loop {
+                            // Execute check
+                            let execution = match (
+                                &setup_error,
+                                &check.http_check,
+                                &check.tcp_check,
+                                &script,
+                            ) {
+                                (Some(setup_error), _, _, _) => Ok(check_error_result(
+                                    check.clone(),
+                                    "The check could not be prepared".to_string(),
+                                    setup_error.clone(),
+                                )),
+                                (_, Some(http_check), _, _) => {
+                                    execute_http_check(check.clone(), http_check).await
+                                }
+                                (_, _, Some(tcp_check), _) => {
+                                    execute_tcp_check(check.clone(), tcp_check).await
+                                }
+                                (_, _, _, Some(script)) => {
+                                    execute_check(check.clone(), script, &base_path).await
+                                }
+                                (None, None, None, None) => {
+                                    unreachable!("A check without script is a probe")
+                                }
+                            };
+
+                            let mut result = match execution {
+                                Ok(r) => r,
+                                Err(e) => {
+                                    error!("Error executing check: {e}");
+                                    check_error_result(
+                                        check.clone(),
+                                        "Error executing check".to_string(),
+                                        e.to_string(),
+                                    )
+                                }
+                            };
+
+                            cx.span().add_event(
+                                "attempt completed",
+                                vec![
+                                    KeyValue::new("attempt", attempt as i64),
+                                    KeyValue::new("check.status", format!("{:?}", result.status)),
+                                ],
+                            );
+
+                            // A failure is retried, if requested, before being reported
+                            let retry = !matches!(
+                                result.status,
+                                CheckResultStatus::Ok | CheckResultStatus::Pending
+                            ) && attempt < check.retries;
+                            result.soft_failure = retry;
+                            result.script_hash = script.as_ref().map(|s| s.content_hash());
+
+                            let mut redis_conn = match redis_client
+                                .get_multiplexed_async_connection_with_config(&connection_config)
+                                .await
+                            {
+                                Ok(c) => c,
+                                Err(e) => {
+                                    error!("Error getting connection to redis: {e}");
+                                    return;
+                                }
+                            };
+
+                            // Ack in redis
+                            if attempt == 0 {
+                                if let Err(e) = redis::cmd("XACK")
+                                    .arg(&stream)
+                                    .arg("workers")
+                                    .arg(&id)
+                                    .query_async::<()>(&mut redis_conn)
+                                    .await
+                                {
+                                    error!("Error sending ack to redis for check: {e}");
+                                }
+                            }
+
+                            let payload = match serde_json::to_string(&result).map_err(|e| {
+                                SerializeError::SerializationError(format!(
+                                    "Error serializing check: {e}"
+                                ))
+                            }) {
+                                Ok(p) => p,
+                                Err(e) => {
+                                    error!("Error serializing check result: {e}");
+                                    return;
+                                }
+                            };
+
+                            // Send back the result
+                            debug!("Sending back the result");
+                            if let Err(e) = redis::cmd("XADD")
+                                .arg("pinglow:results")
+                                .arg("*")
+                                .arg("payload")
+                                .arg(payload)
+                                .arg(TRACE_CONTEXT_FIELD)
+                                .arg(inject_context(&cx))
+                                .query_async::<()>(&mut redis_conn)
+                                .await
+                            {
+                                error!("Error sending check result to redis: {e}");
+                            }
+
+                            if !retry {
+                                cx.span().end();
+                                break;
+                            }
+
+                            attempt += 1;
+                            debug!(
+                                "Retrying check {} (attempt {attempt} of {})",
+                                check.check_name, check.retries
+                            );
+                            tokio::time::sleep(Duration::from_secs(
+                                check
+                                    .retry_delay_seconds
+                                    .unwrap_or(DEFAULT_RETRY_DELAY_SECONDS),
+                            ))
+                            .await;
                         }
-                    };
-
-                    // Send back the result
-                    debug!("Sending back the result");
-                    if let Err(e) = redis::cmd("XADD")
-                        .arg("pinglow:results")
-                        .arg("*")
-                        .arg("payload")
-                        .arg(payload)
-                        .query_async::<()>(&mut redis_conn)
-                        .await
-                    {
-                        error!("Error sending check result to redis: {e}");
-                    }
-                });
+                    });
+                }
             }
-            Ok(None) => {
+            Ok(_) => {
                 // No task, sleep a bit to avoid busy loop
                 tokio::time::sleep(std::time::Duration::from_millis(1000)).await;
             }
@@ -124,6 +343,10 @@ pub async fn run() -> anyhow::Result<()> {
             }
         }
     }
+    if let Err(e) = heartbeat.await {
+        error!("Error stopping the runner heartbeat: {e}");
+    }
+
     info!("Runner stopped successfully");
     Ok(())
 }
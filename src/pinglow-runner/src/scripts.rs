@@ -0,0 +1,147 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::Error;
+use k8s_openapi::api::core::v1::ConfigMap;
+use kube::{Api, Client};
+use log::debug;
+use pinglow_common::{
+    error::ScriptError, manifests::read_manifests, source::read_content, PinglowCheck, Script,
+    ScriptContentSource, ScriptSpec,
+};
+use tokio::sync::RwLock;
+
+// How long the content read from a ConfigMap or a git URL is reused, as its changes do not
+// change the hash of the script
+const SOURCED_CONTENT_TTL: Duration = Duration::from_secs(60);
+
+/// A script with its content resolved, along with the hash of the resource it was read from
+pub struct CachedScript {
+    hash: String,
+    fetched_at: Instant,
+    spec: Arc<ScriptSpec>,
+}
+
+// Scripts already fetched by the runner, keyed by namespace/script name
+pub type SharedScripts = Arc<RwLock<HashMap<String, CachedScript>>>;
+
+/// Where the scripts are fetched from
+#[derive(Clone)]
+pub enum ScriptSource {
+    Kube(Client, String), // Client and namespace of the checks without one
+    Directory(PathBuf),   // Standalone mode
+}
+
+impl ScriptSource {
+    async fn fetch(&self, namespace: Option<&str>, script_name: &str) -> Option<Script> {
+        match self {
+            ScriptSource::Kube(client, default_namespace) => {
+                let scripts_api: Api<Script> =
+                    Api::namespaced(client.clone(), namespace.unwrap_or(default_namespace));
+                scripts_api.get(script_name).await.ok()
+            }
+            ScriptSource::Directory(dir) => read_manifests(dir)
+                .ok()?
+                .into_iter()
+                .find(|m| m.kind == "Script" && m.name == script_name)?
+                .parse()
+                .ok(),
+        }
+    }
+
+    async fn fetch_config_map(&self, namespace: Option<&str>, name: &str) -> Option<ConfigMap> {
+        match self {
+            ScriptSource::Kube(client, default_namespace) => {
+                let config_maps_api: Api<ConfigMap> =
+                    Api::namespaced(client.clone(), namespace.unwrap_or(default_namespace));
+                config_maps_api.get(name).await.ok()
+            }
+            ScriptSource::Directory(dir) => read_manifests(dir)
+                .ok()?
+                .into_iter()
+                .find(|m| m.kind == "ConfigMap" && m.name == name)?
+                .parse()
+                .ok(),
+        }
+    }
+
+    /**
+     * This function reads the content of a script from its source, a key of a ConfigMap of the
+     * namespace of the check or a file served at a git URL
+     */
+    async fn fetch_content(
+        &self,
+        namespace: Option<&str>,
+        source: &ScriptContentSource,
+    ) -> Result<String, Error> {
+        Ok(read_content(source, |name| async move {
+            self.fetch_config_map(namespace, &name).await
+        })
+        .await?)
+    }
+}
+
+/**
+ * This function returns the script referenced by a check, with its content read from its source
+ * if any. The cached version is used as long as its hash matches the one computed by the
+ * controller, the content read from a source being read again once expired
+ */
+pub async fn resolve_script(
+    check: &PinglowCheck,
+    script_source: &ScriptSource,
+    scripts_cache: &SharedScripts,
+) -> Result<Arc<ScriptSpec>, Error> {
+    let script_name = check
+        .script_ref
+        .as_ref()
+        .ok_or(ScriptError::NoScriptFound(check.check_name.clone()))?;
+
+    let cache_key = format!(
+        "{}/{script_name}",
+        check.namespace.as_deref().unwrap_or_default()
+    );
+
+    if let Some(cached) = scripts_cache.read().await.get(&cache_key) {
+        let expired =
+            cached.spec.content_from.is_some() && cached.fetched_at.elapsed() > SOURCED_CONTENT_TTL;
+
+        if check.script_hash.as_ref() == Some(&cached.hash) && !expired {
+            return Ok(cached.spec.clone());
+        }
+    }
+
+    // Cache miss or stale script, fetch it again
+    debug!("Fetching script {script_name}");
+    let script = script_source
+        .fetch(check.namespace.as_deref(), script_name)
+        .await
+        .ok_or(ScriptError::NoScriptFound(check.check_name.clone()))?;
+
+    let hash = script.spec.content_hash();
+
+    let spec = match &script.spec.content_from {
+        Some(source) => script.spec.with_content(
+            script_source
+                .fetch_content(check.namespace.as_deref(), source)
+                .await?,
+        ),
+        None => script.spec,
+    };
+
+    let script = Arc::new(spec);
+
+    scripts_cache.write().await.insert(
+        cache_key,
+        CachedScript {
+            hash,
+            fetched_at: Instant::now(),
+            spec: script.clone(),
+        },
+    );
+
+    Ok(script)
+}
@@ -0,0 +1,118 @@
+use std::{collections::HashMap, path::PathBuf, sync::Arc};
+
+use anyhow::Error;
+use k8s_openapi::api::core::v1::Secret;
+use kube::{Api, Client};
+use log::debug;
+use pinglow_common::{error::SecretError, manifests::read_manifests, PinglowCheck};
+use tokio::sync::RwLock;
+
+/// The values of a secret, along with the resource version they were read at
+pub struct CachedSecret {
+    version: Option<String>,
+    values: Arc<HashMap<String, String>>,
+}
+
+// Secrets already fetched by the runner, keyed by namespace/secret name
+pub type SharedSecrets = Arc<RwLock<HashMap<String, CachedSecret>>>;
+
+/// Where the secrets are fetched from
+#[derive(Clone)]
+pub enum SecretSource {
+    Kube(Client, String), // Client and namespace of the checks without one
+    Directory(PathBuf),   // Standalone mode
+}
+
+impl SecretSource {
+    async fn fetch(&self, namespace: Option<&str>, secret_name: &str) -> Option<Secret> {
+        match self {
+            SecretSource::Kube(client, default_namespace) => {
+                let secrets_api: Api<Secret> =
+                    Api::namespaced(client.clone(), namespace.unwrap_or(default_namespace));
+                secrets_api.get(secret_name).await.ok()
+            }
+            SecretSource::Directory(dir) => read_manifests(dir)
+                .ok()?
+                .into_iter()
+                .find(|m| m.kind == "Secret" && m.name == secret_name)?
+                .parse()
+                .ok(),
+        }
+    }
+}
+
+/**
+ * This function returns the values of a secret, merging its data and stringData
+ */
+fn secret_values(secret: &Secret) -> HashMap<String, String> {
+    let mut values: HashMap<String, String> = secret
+        .data
+        .iter()
+        .flatten()
+        .map(|(key, value)| (key.clone(), String::from_utf8_lossy(&value.0).to_string()))
+        .collect();
+
+    values.extend(secret.string_data.clone().unwrap_or_default());
+
+    values
+}
+
+/**
+ * This function resolves the secrets referenced by a check, which are not sent through the
+ * queue. The cached values are used as long as their resource version matches the one read
+ * by the controller, the secrets of the local directory are always read again
+ */
+pub async fn resolve_secrets(
+    check: &PinglowCheck,
+    secret_source: &SecretSource,
+    secrets_cache: &SharedSecrets,
+) -> Result<Option<HashMap<String, String>>, Error> {
+    if check.secret_refs.is_empty() {
+        return Ok(None);
+    }
+
+    let mut secrets = HashMap::new();
+
+    for secret_name in &check.secret_refs {
+        let version = check.secret_versions.get(secret_name);
+        let cache_key = format!(
+            "{}/{secret_name}",
+            check.namespace.as_deref().unwrap_or_default()
+        );
+
+        let cached = secrets_cache
+            .read()
+            .await
+            .get(&cache_key)
+            .filter(|cached| version.is_some() && cached.version.as_ref() == version)
+            .map(|cached| cached.values.clone());
+
+        let values = match cached {
+            Some(values) => values,
+            None => {
+                // Cache miss or stale secret, fetch it again
+                debug!("Fetching secret {secret_name}");
+                let secret = secret_source
+                    .fetch(check.namespace.as_deref(), secret_name)
+                    .await
+                    .ok_or(SecretError::NoSecretFound(secret_name.clone()))?;
+
+                let values = Arc::new(secret_values(&secret));
+
+                secrets_cache.write().await.insert(
+                    cache_key,
+                    CachedSecret {
+                        version: secret.metadata.resource_version.clone(),
+                        values: values.clone(),
+                    },
+                );
+
+                values
+            }
+        };
+
+        secrets.extend(values.iter().map(|(k, v)| (k.clone(), v.clone())));
+    }
+
+    Ok(Some(secrets))
+}
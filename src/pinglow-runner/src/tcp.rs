@@ -0,0 +1,142 @@
+use std::time::{Duration, Instant};
+
+use anyhow::Error;
+use pinglow_common::{CheckResult, CheckResultStatus, PinglowCheck, TcpCheckSpec};
+use regex::Regex;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+use crate::executor::{check_error_result, probe_result};
+
+// Timeout of the connection and of the response when the check does not define one
+const DEFAULT_TIMEOUT_SECONDS: u64 = 10;
+
+// Maximum size of the response matched against the expected pattern
+const MAX_RESPONSE_SIZE: usize = 64 * 1024;
+
+/**
+ * This function connects to the port of a TCP check, optionally matching the response to what
+ * is sent, and reports the connection time as performance data
+ */
+pub async fn execute_tcp_check(
+    check: PinglowCheck,
+    tcp_check: &TcpCheckSpec,
+) -> Result<CheckResult, Error> {
+    // A check which cannot be built is reported as a CheckError, as a broken configuration
+    let expect = match tcp_check.expect.as_deref().map(Regex::new).transpose() {
+        Ok(expect) => expect,
+        Err(e) => {
+            return Ok(check_error_result(
+                check,
+                format!("Invalid TCP check: {e}"),
+                format!("{e:#}"),
+            ))
+        }
+    };
+
+    let host = check.render_template(&tcp_check.host);
+    let address = format!("{host}:{}", tcp_check.port);
+    let connect_timeout = Duration::from_secs(
+        tcp_check
+            .connectTimeoutSeconds
+            .unwrap_or(DEFAULT_TIMEOUT_SECONDS),
+    );
+
+    let start = Instant::now();
+
+    let mut stream = match tokio::time::timeout(
+        connect_timeout,
+        TcpStream::connect((host.as_str(), tcp_check.port)),
+    )
+    .await
+    {
+        Ok(Ok(stream)) => stream,
+        Ok(Err(e)) => {
+            return Ok(probe_result(
+                check,
+                CheckResultStatus::Critical,
+                format!("Cannot connect to {address}: {e}"),
+                None,
+            ))
+        }
+        Err(_) => {
+            return Ok(probe_result(
+                check,
+                CheckResultStatus::Critical,
+                format!(
+                    "Connection to {address} timed out after {} seconds",
+                    connect_timeout.as_secs()
+                ),
+                None,
+            ))
+        }
+    };
+
+    let connect_ms = start.elapsed().as_millis() as u64;
+    let perf_data = format!("connect_ms={connect_ms}");
+
+    if let Some(send) = &tcp_check.send {
+        if let Err(e) = stream
+            .write_all(check.render_template(send).as_bytes())
+            .await
+        {
+            return Ok(probe_result(
+                check,
+                CheckResultStatus::Critical,
+                format!("Error sending data to {address}: {e}|{perf_data}"),
+                None,
+            ));
+        }
+    }
+
+    if let Some(expect) = expect {
+        let response_timeout =
+            Duration::from_secs(check.timeout_seconds.unwrap_or(DEFAULT_TIMEOUT_SECONDS));
+
+        let matched =
+            tokio::time::timeout(response_timeout, read_until_match(&mut stream, &expect))
+                .await
+                .unwrap_or(false);
+
+        if !matched {
+            return Ok(probe_result(
+                check,
+                CheckResultStatus::Critical,
+                format!("Connected to {address}, but the response does not match the expected pattern|{perf_data}"),
+                None,
+            ));
+        }
+    }
+
+    Ok(probe_result(
+        check,
+        CheckResultStatus::Ok,
+        format!("Connected to {address} in {connect_ms} ms|{perf_data}"),
+        None,
+    ))
+}
+
+/**
+ * This function reads the response until it matches the pattern, the connection is closed or
+ * the maximum size is reached
+ */
+async fn read_until_match(stream: &mut TcpStream, expect: &Regex) -> bool {
+    let mut response = Vec::new();
+    let mut buffer = [0; 4096];
+
+    while response.len() < MAX_RESPONSE_SIZE {
+        match stream.read(&mut buffer).await {
+            Ok(0) | Err(_) => break,
+            Ok(read) => {
+                response.extend_from_slice(&buffer[..read]);
+                if expect.is_match(&String::from_utf8_lossy(&response)) {
+                    return true;
+                }
+            }
+        }
+    }
+
+    false
+}
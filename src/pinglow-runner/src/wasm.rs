@@ -0,0 +1,250 @@
+use std::{
+    collections::HashMap,
+    sync::{LazyLock, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Error};
+use base64::{engine::general_purpose, Engine as _};
+use chrono::Utc;
+use pinglow_common::{
+    last_lines, source::download, CheckResult, CheckResultStatus, PinglowCheck, ScriptSpec,
+    DIAGNOSTICS_LOG_LINES,
+};
+use sha2::{Digest, Sha256};
+use wasmtime::{Config, Engine, Linker, Module, Store, Trap};
+use wasmtime_wasi::{
+    p1::{self, WasiP1Ctx},
+    p2::pipe::MemoryOutputPipe,
+    I32Exit, WasiCtxBuilder,
+};
+
+use crate::executor::check_error_result;
+
+// How often the epoch of the engine advances, which is the resolution of the timeouts
+const EPOCH_TICK: Duration = Duration::from_secs(1);
+
+// Maximum size of the stdout and stderr of a module
+const OUTPUT_CAPACITY: usize = 1024 * 1024;
+
+static ENGINE: LazyLock<Engine> = LazyLock::new(|| {
+    let mut config = Config::new();
+    config.epoch_interruption(true);
+    let engine = Engine::new(&config).expect("Invalid wasmtime configuration");
+
+    // Advance the epoch in the background, interrupting the modules past their deadline
+    let ticker = engine.clone();
+    thread::spawn(move || loop {
+        thread::sleep(EPOCH_TICK);
+        ticker.increment_epoch();
+    });
+
+    engine
+});
+
+// Maximum size of a module downloaded from its URL
+const MAX_MODULE_SIZE: usize = 32 * 1024 * 1024;
+
+// How long a module downloaded from its URL is reused, after which it is downloaded again so that
+// a module changed at the same URL is picked up
+const DOWNLOADED_MODULE_TTL: Duration = Duration::from_secs(60);
+
+// Compiled modules, keyed by the SHA-256 digest of their bytes
+static MODULES: LazyLock<Mutex<HashMap<String, Module>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+// Digests of the modules of the scripts, keyed by the content hash of the script, with the time
+// they were decoded or downloaded
+static DIGESTS: LazyLock<Mutex<HashMap<String, (Instant, String)>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+enum WasmExit {
+    Code(i32),
+    TimedOut,
+}
+
+/**
+ * This function runs the WASI module of a script, mapping its exit code to the status of the
+ * check and its stdout to the output
+ */
+pub async fn execute_wasm_check(
+    check: PinglowCheck,
+    script: &ScriptSpec,
+) -> Result<CheckResult, Error> {
+    // A module which cannot be loaded is reported as a CheckError, as a broken environment
+    let module = match load_module(script).await {
+        Ok(module) => module,
+        Err(e) => {
+            let error = format!("{e:#}");
+            let output = error.lines().next().unwrap_or_default().to_string();
+
+            return Ok(check_error_result(
+                check,
+                output,
+                last_lines(&error, DIAGNOSTICS_LOG_LINES),
+            ));
+        }
+    };
+
+    let stdout = MemoryOutputPipe::new(OUTPUT_CAPACITY);
+    let stderr = MemoryOutputPipe::new(OUTPUT_CAPACITY);
+
+    // The module receives the same arguments and environment of a script
+    let mut env = check.metadata_env();
+    env.extend(check.env.clone());
+    env.extend(check.secrets.clone().unwrap_or_default());
+    let env: Vec<(String, String)> = env.into_iter().collect();
+
+    let mut args = vec![check.check_name.clone()];
+    args.extend(check.templated_args());
+
+    let wasi = WasiCtxBuilder::new()
+        .stdout(stdout.clone())
+        .stderr(stderr.clone())
+        .args(&args)
+        .envs(&env)
+        .build_p1();
+
+    let timeout = check.timeout_seconds;
+    let exit = tokio::task::spawn_blocking(move || run_module(&module, wasi, timeout)).await?;
+
+    let stderr = String::from_utf8_lossy(&stderr.contents()).to_string();
+
+    let exit_code = match exit {
+        Ok(WasmExit::Code(exit_code)) => exit_code,
+        Ok(WasmExit::TimedOut) => {
+            let seconds = timeout.unwrap_or_default();
+            return Ok(check_error_result(
+                check,
+                format!("Check timed out after {seconds} seconds"),
+                format!("The module was interrupted after running for {seconds} seconds"),
+            ));
+        }
+        Err(e) => {
+            return Ok(check_error_result(
+                check,
+                format!("Error running the WASM module: {e}"),
+                format!(
+                    "Error: {e:#}\nstderr:\n{}",
+                    last_lines(&stderr, DIAGNOSTICS_LOG_LINES)
+                ),
+            ));
+        }
+    };
+
+    let status = check.status_from_exit_code(exit_code);
+
+    // Keep the end of stderr to explain the error
+    let diagnostics = (status == CheckResultStatus::CheckError).then(|| {
+        format!(
+            "Exit code: {exit_code}\nstderr:\n{}",
+            last_lines(&stderr, DIAGNOSTICS_LOG_LINES)
+        )
+    });
+
+    Ok(CheckResult {
+        check_name: check.check_name,
+        output: String::from_utf8_lossy(&stdout.contents()).to_string(),
+        status,
+        timestamp: Some(Utc::now()),
+        telegram_channels: check.telegram_channels.into(),
+        mute_notifications: check.mute_notifications,
+        mute_notifications_until: check.mute_notifications_until,
+        maintenance_windows: check.maintenance_windows.clone().into(),
+        webhooks: check.webhooks.into(),
+        diagnostics,
+        slack_channels: check.slack_channels.into(),
+        push_channels: check.push_channels.into(),
+        discord_channels: check.discord_channels.into(),
+        notify_on_state_change: check.notify_on_state_change,
+        notify_on_recovery: check.notify_on_recovery,
+        renotify_interval_minutes: check.renotify_interval_minutes,
+        min_notification_interval_seconds: check.min_notification_interval_seconds,
+        soft_failure: false,
+        depends_on: check.depends_on.into(),
+        suppressed_by: None,
+        anomaly_sigma: check.anomaly_sigma,
+        script_hash: None,
+        run_id: check.run_id,
+    })
+}
+
+/**
+ * This function returns the compiled module of a script, decoding or downloading it when it is not
+ * cached yet or, for a downloaded one, when it expired. A module is compiled again only if its
+ * digest changed
+ */
+async fn load_module(script: &ScriptSpec) -> Result<Module, Error> {
+    let hash = script.content_hash();
+    let downloaded = script.wasm_module.is_none();
+
+    if let Some((_, digest)) = DIGESTS
+        .lock()
+        .expect("Poisoned lock")
+        .get(&hash)
+        .filter(|(loaded_at, _)| !downloaded || loaded_at.elapsed() < DOWNLOADED_MODULE_TTL)
+    {
+        if let Some(module) = MODULES.lock().expect("Poisoned lock").get(digest) {
+            return Ok(module.clone());
+        }
+    }
+
+    let bytes = match (&script.wasm_module, &script.wasm_module_url) {
+        (Some(module), _) => general_purpose::STANDARD.decode(module.trim())?,
+        // Modules are only downloaded over HTTP, OCI registries are not supported
+        (None, Some(url)) if url.starts_with("http://") || url.starts_with("https://") => {
+            download(url, MAX_MODULE_SIZE).await?
+        }
+        (None, Some(url)) => bail!("Unsupported module URL {url}, only HTTP(S) URLs are supported"),
+        (None, None) => bail!("The script does not reference any WASM module"),
+    };
+
+    let digest = format!("{:x}", Sha256::digest(&bytes));
+
+    let cached = MODULES.lock().expect("Poisoned lock").get(&digest).cloned();
+    let module = match cached {
+        Some(module) => module,
+        None => tokio::task::spawn_blocking(move || Module::new(&ENGINE, bytes)).await??,
+    };
+
+    let mut digests = DIGESTS.lock().expect("Poisoned lock");
+    digests.insert(hash, (Instant::now(), digest.clone()));
+
+    // Drop the modules no script refers to anymore, such as the previous version of a module
+    let mut modules = MODULES.lock().expect("Poisoned lock");
+    modules.insert(digest, module.clone());
+    modules.retain(|digest, _| digests.values().any(|(_, current)| current == digest));
+
+    Ok(module)
+}
+
+/**
+ * This function instantiates a module and runs its entry point until it exits or the timeout
+ * expires
+ */
+fn run_module(module: &Module, wasi: WasiP1Ctx, timeout: Option<u64>) -> Result<WasmExit, Error> {
+    let mut linker: Linker<WasiP1Ctx> = Linker::new(&ENGINE);
+    p1::add_to_linker_sync(&mut linker, |ctx| ctx)?;
+
+    let mut store = Store::new(&ENGINE, wasi);
+    // The current tick may be already partially elapsed, allow an extra one
+    store.set_epoch_deadline(timeout.map_or(u64::MAX / 2, |seconds| seconds + 1));
+    store.epoch_deadline_trap();
+
+    let instance = linker.instantiate(&mut store, module)?;
+    let start = instance.get_typed_func::<(), ()>(&mut store, "_start")?;
+
+    match start.call(&mut store, ()) {
+        Ok(()) => Ok(WasmExit::Code(0)),
+        Err(e) => {
+            if let Some(exit) = e.downcast_ref::<I32Exit>() {
+                Ok(WasmExit::Code(exit.0))
+            } else if e.downcast_ref::<Trap>() == Some(&Trap::Interrupt) {
+                Ok(WasmExit::TimedOut)
+            } else {
+                Err(e)
+            }
+        }
+    }
+}
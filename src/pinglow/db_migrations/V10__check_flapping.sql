@@ -0,0 +1,5 @@
+-- Checks currently flapping, whose individual notifications are suppressed
+CREATE TABLE IF NOT EXISTS "check_flapping" (
+    check_name TEXT PRIMARY KEY,
+    started_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,5 @@
+-- Failed notifications are retried with an exponential backoff, from the time recorded here
+ALTER TABLE "notification_outbox" ADD COLUMN IF NOT EXISTS next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT NOW();
+
+DROP INDEX IF EXISTS notification_outbox_pending_idx;
+CREATE INDEX IF NOT EXISTS notification_outbox_pending_idx ON "notification_outbox" (next_attempt_at) WHERE status = 'pending';
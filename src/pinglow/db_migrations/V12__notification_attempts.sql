@@ -0,0 +1,12 @@
+-- Every delivery attempt of a notification, kept as an audit log after the outbox is pruned
+CREATE TABLE IF NOT EXISTS "notification_attempts" (
+    id BIGSERIAL PRIMARY KEY,
+    notification_id BIGINT NOT NULL,
+    check_name TEXT NOT NULL,
+    channel_kind TEXT NOT NULL,
+    channel_id TEXT NOT NULL,
+    timestamp TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    success BOOLEAN NOT NULL,
+    error TEXT
+);
+CREATE INDEX IF NOT EXISTS notification_attempts_check_idx ON "notification_attempts" (check_name, timestamp DESC);
@@ -0,0 +1,9 @@
+-- Last message sent to every channel of the checks with a cooldown, with the ones suppressed since
+CREATE TABLE IF NOT EXISTS "notification_cooldown" (
+    check_name TEXT NOT NULL,
+    channel_kind TEXT NOT NULL,
+    channel_id TEXT NOT NULL,
+    last_sent_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    suppressed INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (check_name, channel_kind, channel_id)
+);
@@ -0,0 +1,8 @@
+-- Lines following the first one of the output of the plugin
+ALTER TABLE "check_result" ADD COLUMN IF NOT EXISTS long_output TEXT;
+-- Unit of measurement, thresholds and bounds of the performance data, as printed by the plugin
+ALTER TABLE "check_result_perf_data" ADD COLUMN IF NOT EXISTS uom TEXT;
+ALTER TABLE "check_result_perf_data" ADD COLUMN IF NOT EXISTS warn TEXT;
+ALTER TABLE "check_result_perf_data" ADD COLUMN IF NOT EXISTS crit TEXT;
+ALTER TABLE "check_result_perf_data" ADD COLUMN IF NOT EXISTS min REAL;
+ALTER TABLE "check_result_perf_data" ADD COLUMN IF NOT EXISTS max REAL;
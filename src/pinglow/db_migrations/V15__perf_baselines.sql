@@ -0,0 +1,12 @@
+-- Rolling baseline of the performance data of the checks with anomaly detection, per hour of the
+-- week (0 = Monday 00:00 UTC) and over the whole window (-1)
+CREATE TABLE IF NOT EXISTS "perf_baseline" (
+    check_name TEXT NOT NULL,
+    perf_key TEXT NOT NULL,
+    hour_of_week SMALLINT NOT NULL,
+    median REAL NOT NULL,
+    mad REAL NOT NULL,
+    samples INTEGER NOT NULL,
+    computed_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (check_name, perf_key, hour_of_week)
+);
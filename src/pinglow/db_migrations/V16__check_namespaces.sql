@@ -0,0 +1,2 @@
+-- Namespace of the checks outside of the namespace of the controller, whose check_name is namespace/name
+ALTER TABLE "check_result" ADD COLUMN IF NOT EXISTS namespace TEXT;
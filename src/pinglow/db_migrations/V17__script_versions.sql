@@ -0,0 +1,2 @@
+-- Hash of the script producing a result, with its content as read from its source
+ALTER TABLE "check_result" ADD COLUMN IF NOT EXISTS script_hash TEXT;
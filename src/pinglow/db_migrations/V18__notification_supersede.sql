@@ -0,0 +1,2 @@
+-- The pending notifications of a check and channel are looked up to supersede the older ones
+CREATE INDEX IF NOT EXISTS notification_outbox_check_pending_idx ON "notification_outbox" (check_name, channel_kind, channel_id) WHERE status = 'pending';
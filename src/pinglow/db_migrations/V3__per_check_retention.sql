@@ -0,0 +1,3 @@
+-- Retention is enforced by the controller, which supports per-check overrides
+SELECT remove_retention_policy('check_result', if_exists => TRUE);
+SELECT remove_retention_policy('check_result_perf_data', if_exists => TRUE);
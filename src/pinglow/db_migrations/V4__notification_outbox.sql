@@ -0,0 +1,14 @@
+-- Notifications are recorded before being delivered, so that they survive a controller crash
+CREATE TABLE IF NOT EXISTS "notification_outbox" (
+    id BIGSERIAL PRIMARY KEY,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    check_name TEXT NOT NULL,
+    chat_id TEXT NOT NULL,
+    message TEXT NOT NULL,
+    image BYTEA,
+    status TEXT NOT NULL DEFAULT 'pending',
+    attempts INTEGER NOT NULL DEFAULT 0,
+    last_error TEXT
+);
+CREATE INDEX IF NOT EXISTS notification_outbox_pending_idx ON notification_outbox (updated_at) WHERE status = 'pending';
@@ -0,0 +1,8 @@
+-- Extra details collected when a check ends with a CheckError
+CREATE TABLE IF NOT EXISTS "check_diagnostics" (
+    timestamp TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    check_name TEXT NOT NULL,
+    diagnostics TEXT NOT NULL,
+    PRIMARY KEY (check_name, timestamp)
+);
+SELECT create_hypertable('check_diagnostics', 'timestamp', if_not_exists => TRUE);
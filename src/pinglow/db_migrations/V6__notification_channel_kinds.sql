@@ -0,0 +1,3 @@
+-- The outbox holds the notifications of every kind of channel, identified by chat id or name
+ALTER TABLE "notification_outbox" RENAME COLUMN chat_id TO channel_id;
+ALTER TABLE "notification_outbox" ADD COLUMN IF NOT EXISTS channel_kind TEXT NOT NULL DEFAULT 'telegram';
@@ -0,0 +1,2 @@
+-- Failed attempts followed by a retry, which are neither notified nor considered as the status of the check
+ALTER TABLE "check_result" ADD COLUMN IF NOT EXISTS soft_failure BOOLEAN NOT NULL DEFAULT FALSE;
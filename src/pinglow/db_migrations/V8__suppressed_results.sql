@@ -0,0 +1,2 @@
+-- Parent check whose failure suppressed the notifications of the result, if any
+ALTER TABLE "check_result" ADD COLUMN IF NOT EXISTS suppressed_by TEXT;
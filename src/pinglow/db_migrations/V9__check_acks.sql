@@ -0,0 +1,10 @@
+-- Acknowledgements of failing checks, suppressing their notifications until they recover
+CREATE TABLE IF NOT EXISTS "check_ack" (
+    id BIGSERIAL PRIMARY KEY,
+    check_name TEXT NOT NULL,
+    acked_by TEXT NOT NULL,
+    comment TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    cleared_at TIMESTAMPTZ
+);
+CREATE INDEX IF NOT EXISTS check_ack_active_idx ON check_ack (check_name) WHERE cleared_at IS NULL;
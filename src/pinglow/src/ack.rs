@@ -0,0 +1,68 @@
+use pinglow_common::dto::CheckAckDto;
+use tokio_postgres::Client as PostgresClient;
+
+/**
+ * This function acknowledges the failure of a check, replacing the previous acknowledgement if
+ * the check was already acknowledged
+ */
+pub async fn acknowledge(
+    db_client: &PostgresClient,
+    check_name: &str,
+    acked_by: &str,
+    comment: Option<&str>,
+) -> Result<CheckAckDto, tokio_postgres::Error> {
+    let row = db_client
+        .query_one(
+            "WITH cleared AS (
+                UPDATE check_ack SET cleared_at = NOW() WHERE check_name = $1 AND cleared_at IS NULL
+            )
+            INSERT INTO check_ack (check_name, acked_by, comment) VALUES ($1, $2, $3)
+            RETURNING acked_by, comment, created_at",
+            &[&check_name, &acked_by, &comment],
+        )
+        .await?;
+
+    Ok(CheckAckDto {
+        acked_by: row.get("acked_by"),
+        comment: row.get("comment"),
+        timestamp: row.get("created_at"),
+    })
+}
+
+/**
+ * This function returns the acknowledgement of the current failure of a check, if any
+ */
+pub async fn active_ack(
+    db_client: &PostgresClient,
+    check_name: &str,
+) -> Result<Option<CheckAckDto>, tokio_postgres::Error> {
+    let row = db_client
+        .query_opt(
+            "SELECT acked_by, comment, created_at FROM check_ack WHERE check_name = $1 AND cleared_at IS NULL ORDER BY created_at DESC LIMIT 1",
+            &[&check_name],
+        )
+        .await?;
+
+    Ok(row.map(|row| CheckAckDto {
+        acked_by: row.get("acked_by"),
+        comment: row.get("comment"),
+        timestamp: row.get("created_at"),
+    }))
+}
+
+/**
+ * This function clears the acknowledgement of a check once it recovers
+ */
+pub async fn clear(
+    db_client: &PostgresClient,
+    check_name: &str,
+) -> Result<(), tokio_postgres::Error> {
+    db_client
+        .execute(
+            "UPDATE check_ack SET cleared_at = NOW() WHERE check_name = $1 AND cleared_at IS NULL",
+            &[&check_name],
+        )
+        .await?;
+
+    Ok(())
+}
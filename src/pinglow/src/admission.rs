@@ -0,0 +1,269 @@
+use base64::{engine::general_purpose, Engine};
+use kube::{
+    core::{
+        admission::{AdmissionRequest, AdmissionResponse, AdmissionReview},
+        DynamicObject,
+    },
+    Api, Client,
+};
+use log::{error, warn};
+use pinglow_common::{perfdata::Range, HttpCheckSpec, Script, ScriptLanguage, ScriptSpec};
+use regex::Regex;
+use reqwest::Method;
+use rocket::{post, routes, serde::json::Json, Rocket, State};
+
+use crate::{
+    check::{Check, CheckSpec},
+    config::PinglowConfig,
+};
+
+/**
+ * This function builds the HTTPS server receiving the validation requests of the API server,
+ * if its certificate is configured
+ */
+pub async fn start_admission_server(
+    config: &PinglowConfig,
+    client: Client,
+) -> Result<Option<Rocket<rocket::Ignite>>, rocket::Error> {
+    let (Some(cert), Some(key)) = (&config.admission_tls_cert, &config.admission_tls_key) else {
+        return Ok(None);
+    };
+
+    let figment = rocket::Config::figment()
+        .merge(("address", "0.0.0.0"))
+        .merge(("port", config.admission_port))
+        .merge(("tls.certs", cert))
+        .merge(("tls.key", key))
+        // The main server already handles the signals
+        .merge(("shutdown.ctrlc", false))
+        .merge(("shutdown.signals", Vec::<String>::new()));
+
+    let rocket = rocket::custom(figment)
+        .manage(config.clone())
+        .manage(client)
+        .mount("/", routes![validate])
+        .ignite()
+        .await?;
+
+    Ok(Some(rocket))
+}
+
+#[post("/validate", data = "<review>")]
+async fn validate(
+    review: Json<AdmissionReview<DynamicObject>>,
+    config: &State<PinglowConfig>,
+    client: &State<Client>,
+) -> Json<AdmissionReview<DynamicObject>> {
+    let request: AdmissionRequest<DynamicObject> = match review.into_inner().try_into() {
+        Ok(request) => request,
+        Err(e) => {
+            warn!("Invalid admission review: {e}");
+            return Json(AdmissionResponse::invalid(e.to_string()).into_review());
+        }
+    };
+
+    let response = AdmissionResponse::from(&request);
+
+    let Some(object) = request.object else {
+        // Nothing to validate on deletion
+        return Json(response.into_review());
+    };
+
+    let outcome = match request.kind.kind.as_str() {
+        "Check" => match object.try_parse::<Check>() {
+            Ok(check) => {
+                let scripts: Api<Script> = Api::namespaced(
+                    client.inner().clone(),
+                    &request
+                        .namespace
+                        .unwrap_or_else(|| config.target_namespace.clone()),
+                );
+                validate_check(&check.spec, &scripts).await
+            }
+            Err(e) => Err(format!("Invalid check: {e}")),
+        },
+        "Script" => match object.try_parse::<Script>() {
+            Ok(script) => validate_script(&script.spec),
+            Err(e) => Err(format!("Invalid script: {e}")),
+        },
+        _ => Ok(()),
+    };
+
+    match outcome {
+        Ok(()) => Json(response.into_review()),
+        Err(reason) => Json(response.deny(reason).into_review()),
+    }
+}
+
+/**
+ * This function checks that an active check has a valid interval and references an existing script,
+ * defining its parameters
+ */
+async fn validate_check(spec: &CheckSpec, scripts: &Api<Script>) -> Result<(), String> {
+    validate_thresholds(spec)?;
+
+    if spec.anomalySigma.is_some_and(|sigma| sigma <= 0.0) {
+        return Err("The anomalySigma must be positive".to_string());
+    }
+
+    if spec.passive {
+        return Ok(());
+    }
+
+    match spec.interval {
+        None => return Err("An active check must have the interval defined".to_string()),
+        Some(0) => return Err("The interval must be a positive number of seconds".to_string()),
+        Some(_) => {}
+    }
+
+    if spec.httpCheck.is_some() || spec.tcpCheck.is_some() {
+        if spec.httpCheck.is_some() && spec.tcpCheck.is_some() {
+            return Err("A check cannot define both an httpCheck and a tcpCheck".to_string());
+        }
+
+        if spec.scriptRef.is_some() || spec.job.is_some() {
+            return Err("An HTTP or TCP check cannot define a scriptRef or a job".to_string());
+        }
+
+        if let Some(http_check) = &spec.httpCheck {
+            return validate_http_check(http_check);
+        }
+
+        if let Some(expect) = spec.tcpCheck.as_ref().and_then(|t| t.expect.as_ref()) {
+            Regex::new(expect).map_err(|e| format!("Invalid expect pattern: {e}"))?;
+        }
+
+        return Ok(());
+    }
+
+    let Some(script_ref) = &spec.scriptRef else {
+        return Err(
+            "An active check must have either the scriptRef, the httpCheck or the tcpCheck defined"
+                .to_string(),
+        );
+    };
+
+    match scripts.get_opt(script_ref).await {
+        Ok(Some(script)) => {
+            let missing = script
+                .spec
+                .missing_parameters(&spec.parameters.clone().unwrap_or_default());
+
+            if missing.is_empty() {
+                Ok(())
+            } else {
+                Err(format!(
+                    "Missing parameters of script '{script_ref}': {}",
+                    missing.join(", ")
+                ))
+            }
+        }
+        Ok(None) => Err(format!("Script '{script_ref}' not found")),
+        Err(e) => {
+            // Do not block the changes if the scripts cannot be read
+            error!("Error retrieving script {script_ref} for validation: {e}");
+            Ok(())
+        }
+    }
+}
+
+/**
+ * This function checks that the thresholds of a check are valid Nagios ranges
+ */
+fn validate_thresholds(spec: &CheckSpec) -> Result<(), String> {
+    for (key, threshold) in spec.thresholds.iter().flatten() {
+        for range in [&threshold.warn, &threshold.crit].into_iter().flatten() {
+            if Range::parse(range).is_none() {
+                return Err(format!("Invalid threshold '{range}' of '{key}'"));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/**
+ * This function checks the parts of an HTTP check which can be verified without rendering its
+ * placeholders
+ */
+fn validate_http_check(http_check: &HttpCheckSpec) -> Result<(), String> {
+    if let Some(method) = &http_check.method {
+        Method::from_bytes(method.to_uppercase().as_bytes())
+            .map_err(|_| format!("Invalid HTTP method '{method}'"))?;
+    }
+
+    if let Some(body_regex) = &http_check.bodyRegex {
+        Regex::new(body_regex).map_err(|e| format!("Invalid bodyRegex: {e}"))?;
+    }
+
+    Ok(())
+}
+
+/**
+ * This function checks that a script defines what is needed to run it in its language
+ */
+pub fn validate_script(spec: &ScriptSpec) -> Result<(), String> {
+    match spec.language.as_ref().unwrap_or(&ScriptLanguage::Python) {
+        ScriptLanguage::Wasm => {
+            match (&spec.wasm_module, &spec.wasm_module_url) {
+                (Some(module), _) => {
+                    general_purpose::STANDARD
+                        .decode(module.trim())
+                        .map_err(|e| format!("The WASM module is not valid base64: {e}"))?;
+                }
+                (None, Some(_)) => {}
+                (None, None) => {
+                    return Err(
+                        "A Wasm script must define either wasm_module or wasm_module_url"
+                            .to_string(),
+                    )
+                }
+            }
+
+            if !spec.content.trim().is_empty() || spec.content_from.is_some() {
+                return Err("A Wasm script cannot define content or contentFrom".to_string());
+            }
+        }
+        language => {
+            match (spec.content.trim().is_empty(), &spec.content_from) {
+                (true, None) => {
+                    return Err(format!(
+                        "A {language:?} script must define content or contentFrom"
+                    ))
+                }
+                (false, Some(_)) => {
+                    return Err(format!(
+                        "A {language:?} script cannot define both content and contentFrom"
+                    ))
+                }
+                (true, Some(source)) => {
+                    if source.configMapKeyRef.is_some() == source.gitUrl.is_some() {
+                        return Err(
+                            "contentFrom must define either configMapKeyRef or gitUrl".to_string()
+                        );
+                    }
+                }
+                (false, None) => {}
+            }
+
+            if spec.wasm_module.is_some() || spec.wasm_module_url.is_some() {
+                return Err(format!("A {language:?} script cannot define a WASM module"));
+            }
+        }
+    }
+
+    if spec.python_requirements.is_some()
+        && spec
+            .language
+            .as_ref()
+            .is_some_and(|l| *l != ScriptLanguage::Python)
+    {
+        return Err("python_requirements can only be used by Python scripts".to_string());
+    }
+
+    if spec.npm_dependencies.is_some() && spec.language != Some(ScriptLanguage::Node) {
+        return Err("npm_dependencies can only be used by Node scripts".to_string());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,205 @@
+use std::time::Duration;
+
+use anyhow::Error;
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use deadpool_postgres::Pool;
+use log::{error, info};
+use pinglow_common::{CheckResult, CheckResultStatus};
+use tokio_postgres::Client as PostgresClient;
+
+use crate::{check::SharedPinglowChecks, config::PinglowConfig};
+
+const REFRESH_INTERVAL: Duration = Duration::from_secs(3600);
+
+// Samples a baseline needs before its metric is judged, the hourly one falling back to the one of
+// the whole window until it has enough of them
+const MIN_BASELINE_SAMPLES: i32 = 20;
+
+// Scale making the median absolute deviation comparable to the standard deviation
+const MAD_SCALE: f64 = 1.4826;
+
+/**
+ * This function periodically computes the baseline of the performance data of the checks with
+ * anomaly detection
+ */
+pub async fn run(db_pool: Pool, shared_checks: SharedPinglowChecks, config: PinglowConfig) {
+    let mut interval = tokio::time::interval(REFRESH_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        match refresh_baselines(&db_pool, &shared_checks, &config).await {
+            Ok(0) => {}
+            Ok(baselines) => info!("Refreshed {baselines} performance data baseline(s)"),
+            Err(e) => error!("Error refreshing the performance data baselines: {e}"),
+        }
+    }
+}
+
+/**
+ * This function computes the median and the median absolute deviation of each metric of the
+ * checks with anomaly detection, per hour of the week and over the whole window, dropping the
+ * baselines of the other checks
+ */
+async fn refresh_baselines(
+    db_pool: &Pool,
+    shared_checks: &SharedPinglowChecks,
+    config: &PinglowConfig,
+) -> Result<u64, Error> {
+    let postgres_client = db_pool.get().await?;
+
+    let checks: Vec<String> = shared_checks
+        .iter()
+        .filter(|check| check.anomaly_sigma.is_some())
+        .map(|check| check.check_name.clone())
+        .collect();
+
+    postgres_client
+        .execute(
+            "DELETE FROM perf_baseline WHERE NOT (check_name = ANY($1))",
+            &[&checks],
+        )
+        .await?;
+
+    if checks.is_empty() {
+        return Ok(0);
+    }
+
+    let window_days = config.anomaly_baseline_days as i32;
+
+    let refreshed = postgres_client
+        .execute(
+            "WITH samples AS (
+                SELECT check_name, perf_key, perf_value,
+                    ((EXTRACT(ISODOW FROM timestamp AT TIME ZONE 'UTC') - 1) * 24 + EXTRACT(HOUR FROM timestamp AT TIME ZONE 'UTC'))::SMALLINT AS hour_of_week
+                FROM check_result_perf_data
+                WHERE check_name = ANY($1) AND timestamp >= NOW() - make_interval(days => $2)
+            ), medians AS (
+                SELECT check_name, perf_key, hour_of_week, COUNT(*) AS samples,
+                    percentile_cont(0.5) WITHIN GROUP (ORDER BY perf_value) AS median
+                FROM samples
+                GROUP BY GROUPING SETS ((check_name, perf_key, hour_of_week), (check_name, perf_key))
+            )
+            INSERT INTO perf_baseline (check_name, perf_key, hour_of_week, median, mad, samples, computed_at)
+            SELECT m.check_name, m.perf_key, COALESCE(m.hour_of_week, -1), m.median,
+                percentile_cont(0.5) WITHIN GROUP (ORDER BY ABS(s.perf_value - m.median)), m.samples, NOW()
+            FROM medians m
+            JOIN samples s ON s.check_name = m.check_name AND s.perf_key = m.perf_key
+                AND (m.hour_of_week IS NULL OR s.hour_of_week = m.hour_of_week)
+            GROUP BY m.check_name, m.perf_key, m.hour_of_week, m.median, m.samples
+            ON CONFLICT (check_name, perf_key, hour_of_week) DO UPDATE
+                SET median = EXCLUDED.median, mad = EXCLUDED.mad, samples = EXCLUDED.samples, computed_at = EXCLUDED.computed_at",
+            &[&checks, &window_days],
+        )
+        .await?;
+
+    // Metrics no longer reported keep a stale baseline until they drop out of the window
+    postgres_client
+        .execute(
+            "DELETE FROM perf_baseline WHERE computed_at < NOW() - make_interval(days => $1)",
+            &[&window_days],
+        )
+        .await?;
+
+    Ok(refreshed)
+}
+
+/**
+ * This function returns the hour of the week of an instant, 0 being Monday 00:00 UTC
+ */
+fn hour_of_week(timestamp: DateTime<Utc>) -> i16 {
+    (timestamp.weekday().num_days_from_monday() * 24 + timestamp.hour()) as i16
+}
+
+/**
+ * This function tells how far a value is from the median of its baseline, in robust standard
+ * deviations. A baseline without deviation cannot judge any value
+ */
+fn deviation(value: f64, median: f64, mad: f64) -> Option<f64> {
+    (mad > 0.0).then(|| (value - median).abs() / (MAD_SCALE * mad))
+}
+
+/**
+ * This function turns an Ok result whose metrics deviate from their baseline beyond the sigma of
+ * the check into a Warning, noting the metrics responsible in the output
+ */
+pub async fn flag_anomalies(
+    result: &mut CheckResult,
+    db_client: &PostgresClient,
+) -> Result<(), tokio_postgres::Error> {
+    let Some(sigma) = result.anomaly_sigma else {
+        return Ok(());
+    };
+
+    if result.status != CheckResultStatus::Ok {
+        return Ok(());
+    }
+
+    let perf_data = result.get_perf_data();
+    if perf_data.is_empty() {
+        return Ok(());
+    }
+
+    let keys: Vec<&str> = perf_data.iter().map(|p| p.label.as_str()).collect();
+    let hour = hour_of_week(result.timestamp.unwrap_or_else(Utc::now));
+
+    // The baseline of the hour of the week is preferred to the one of the whole window
+    let rows = db_client
+        .query(
+            "SELECT DISTINCT ON (perf_key) perf_key, median, mad FROM perf_baseline WHERE check_name = $1 AND perf_key = ANY($2) AND hour_of_week IN ($3, -1) AND samples >= $4 AND mad > 0 ORDER BY perf_key, hour_of_week DESC",
+            &[&result.check_name, &keys, &hour, &MIN_BASELINE_SAMPLES],
+        )
+        .await?;
+
+    let mut notes = vec![];
+
+    for row in rows {
+        let key: String = row.get("perf_key");
+        let median = row.get::<_, f32>("median") as f64;
+        let mad = row.get::<_, f32>("mad") as f64;
+
+        let Some(perf_data) = perf_data.iter().find(|p| p.label == key) else {
+            continue;
+        };
+
+        if let Some(deviation) = deviation(perf_data.value as f64, median, mad) {
+            if deviation > sigma {
+                notes.push(format!(
+                    "{key}={} anomalous, {deviation:.1} sigma from its usual {median}",
+                    perf_data.value
+                ));
+            }
+        }
+    }
+
+    if !notes.is_empty() {
+        result.annotate_status_line(&notes.join(", "));
+        result.status = CheckResultStatus::Warning;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn hours_of_the_week() {
+        let monday = Utc.with_ymd_and_hms(2024, 1, 1, 0, 30, 0).unwrap();
+        let sunday = Utc.with_ymd_and_hms(2024, 1, 7, 23, 59, 0).unwrap();
+
+        assert_eq!(hour_of_week(monday), 0);
+        assert_eq!(hour_of_week(sunday), 167);
+    }
+
+    #[test]
+    fn deviations() {
+        assert_eq!(deviation(10.0, 10.0, 0.0), None);
+        assert_eq!(deviation(10.0, 10.0, 1.0), Some(0.0));
+        assert!((deviation(13.0, 10.0, 1.0).unwrap() - 3.0 / MAD_SCALE).abs() < 1e-9);
+        assert_eq!(deviation(7.0, 10.0, 1.0), deviation(13.0, 10.0, 1.0));
+    }
+}
@@ -1,58 +1,188 @@
 use std::{
     collections::{BTreeMap, HashMap},
-    sync::Arc,
+    marker::PhantomData,
+    ops::Deref,
+    sync::{Arc, LazyLock},
+    time::{Duration, Instant},
 };
 
 use crate::{
-    check::{Check, SharedPinglowChecks},
+    ack,
+    admission::validate_script,
+    auth::{self, Admin, Mute, OidcVerifier, Read, RequiredScope, SubmitResult},
+    backlog::{dead_letters, queue_backlog, runners, DeadLetters, QueueBacklog},
+    calendar::calendar_to_ical,
+    check::{Check, NotificationRedirect, SharedPinglowChecks, SharedRedirects, SharedSecrets},
     config::PinglowConfig,
+    flapping, grafana, groups,
+    job::JobExecutor,
+    last_status, metrics, resolve_telegram_channel,
+    scheduler::enqueue_checks,
+    sinks::{ExportedResult, ResultSinks},
+    status_page,
+};
+use chrono::{DateTime, FixedOffset, TimeDelta, Utc};
+use dashmap::DashMap;
+use deadpool_postgres::{Object, Pool};
+use kube::{
+    api::{Patch, PatchParams},
+    Api, ResourceExt,
+};
+use log::{error, warn};
+use pinglow_common::{
+    dto::{
+        AckCheckPayload, CheckAckDto, CheckAvailabilityDto, CheckDiagnosticsDto, CheckGroupDto,
+        CheckResultHistoryDto, GroupMemberDto, GroupStatusDto, NotificationAttemptDto,
+        ProcessCheckResultPayload, RunnerDto, SimpleCheckDto, SimpleCheckResultDto,
+        StatusCountsDto,
+    },
+    CheckResult, CheckResultStatus, PinglowCheck, Script, ScriptLanguage,
 };
-use chrono::{DateTime, FixedOffset, Utc};
-use kube::Api;
-use log::warn;
-use pinglow_common::{CheckResult, CheckResultStatus, PinglowCheck, ScriptLanguage};
 use rocket::{
+    data::{Data, Limits, ToByteUnit},
     delete, get,
-    http::Status,
+    http::{ContentType, Status},
     post, put,
     request::{FromRequest, Outcome},
-    response::status,
+    response::{
+        content::RawHtml,
+        status,
+        stream::{Event, EventStream},
+    },
     routes,
     serde::json::Json,
     Request, Rocket, Shutdown, State,
 };
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
-use tokio_postgres::Client;
+use sha2::{Digest, Sha256};
+use tokio::sync::broadcast::error::RecvError;
 use utoipa::{
-    openapi::security::{ApiKeyValue, SecurityScheme},
+    openapi::security::{ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme},
     Modify, OpenApi, ToSchema,
 };
 
+#[allow(clippy::too_many_arguments)]
 pub async fn start_rocket(
     pinglow_config: PinglowConfig,
     shared_checks: SharedPinglowChecks,
-    client: Arc<tokio_postgres::Client>,
+    db_pool: Pool,
+    http_client: reqwest::Client,
+    redirects: SharedRedirects,
+    secret_cache: SharedSecrets,
+    sinks: Arc<ResultSinks>,
+    redis_client: redis::Client,
+    kube_client: Option<kube::Client>,
 ) -> Result<(Rocket<rocket::Ignite>, Shutdown), rocket::Error> {
-    let figment = rocket::Config::figment()
-        .merge(("address", "0.0.0.0"))
-        .merge(("port", 8000));
+    let mut figment = rocket::Config::figment()
+        .merge(("address", &pinglow_config.api_address))
+        .merge(("port", pinglow_config.api_port))
+        .merge(("limits.json", pinglow_config.api_max_payload_bytes))
+        // Let the requests in progress complete within the grace period of the controller
+        .merge((
+            "shutdown.grace",
+            pinglow_config.shutdown_grace_period_seconds,
+        ));
+
+    // The address of a client is read from a header only when it is set by a trusted proxy
+    figment = match &pinglow_config.api_trusted_proxy_header {
+        Some(header) => figment.merge(("ip_header", header)),
+        None => figment.merge(("ip_header", false)),
+    };
+
+    // The API is served over HTTPS when both the certificate and its key are configured
+    match (&pinglow_config.api_tls_cert, &pinglow_config.api_tls_key) {
+        (Some(cert), Some(key)) => {
+            figment = figment.merge(("tls.certs", cert)).merge(("tls.key", key));
+        }
+        (None, None) => {}
+        _ => {
+            warn!("Both API_TLS_CERT and API_TLS_KEY are needed to enable TLS, serving plain HTTP")
+        }
+    }
 
-    let rocket = rocket::custom(figment)
+    if pinglow_config.api_rate_limit > 0 {
+        tokio::spawn(evict_rate_limit_windows());
+    }
+
+    let oidc_verifier = OidcVerifier::from_config(&pinglow_config);
+    let api_docs = pinglow_config.api_docs;
+    let status_page = pinglow_config.status_page_label.is_some();
+
+    let mut rocket = rocket::custom(figment)
+        .manage(oidc_verifier)
         .manage(pinglow_config)
         .manage(shared_checks)
-        .manage(client)
+        .manage(db_pool)
+        .manage(http_client)
+        .manage(redirects)
+        .manage(secret_cache)
+        .manage(sinks)
+        .manage(redis_client)
+        .manage(kube_client)
         .mount(
             "/",
             routes![
                 get_checks,
+                get_groups,
+                get_group_status,
                 get_check_status,
+                get_overview,
+                stream_results,
+                run_check,
+                ack_check,
                 get_performance_data,
+                get_check_results,
+                get_check_availability,
+                get_check_diagnostics,
+                get_check_notifications,
                 mute_check,
                 unmute_check,
-                process_check_result
+                suspend_check,
+                resume_check,
+                bulk_mute_checks,
+                bulk_unmute_checks,
+                redirect_check,
+                remove_check_redirect,
+                process_check_result,
+                get_heartbeat,
+                post_heartbeat,
+                import_checks,
+                get_calendar,
+                get_queue_backlog,
+                get_runners,
+                get_dead_letters,
+                get_metrics,
+                get_perf_data_metrics
+            ],
+        )
+        .mount(
+            "/grafana",
+            routes![
+                grafana::grafana_test,
+                grafana::grafana_search,
+                grafana::grafana_metrics,
+                grafana::grafana_metric_payload_options,
+                grafana::grafana_query,
+                grafana::grafana_annotations
+            ],
+        );
+
+    // The OpenAPI document and the Swagger UI to explore the API of this deployment
+    if api_docs {
+        rocket = rocket.mount("/", routes![get_openapi, get_api_docs]);
+    }
+
+    // The public status page, which does not require the API key
+    if status_page {
+        rocket = rocket.mount(
+            "/",
+            routes![
+                status_page::get_status_page,
+                status_page::get_status_page_html
             ],
         );
+    }
 
     let rocket = rocket.ignite().await?;
 
@@ -61,11 +191,13 @@ pub async fn start_rocket(
     Ok((rocket, shutdown))
 }
 
-pub struct ApiKey;
+/// A valid API key, or bearer token of the OIDC issuer, granting the scope required by the
+/// endpoint, read by default
+pub struct ApiKey<S: RequiredScope = Read>(PhantomData<S>);
 
 // FromRequest trait to validate the provided ApiKey
 #[rocket::async_trait]
-impl<'r> FromRequest<'r> for ApiKey {
+impl<'r, S: RequiredScope> FromRequest<'r> for ApiKey<S> {
     type Error = ();
 
     async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
@@ -75,67 +207,371 @@ impl<'r> FromRequest<'r> for ApiKey {
             None => return Outcome::Error((Status::InternalServerError, ())),
         };
         let keys: Vec<_> = request.headers().get("x-api-key").collect();
+
+        // Without an API key, a bearer token is accepted if an OIDC issuer is configured
+        if keys.is_empty() {
+            let token = request
+                .headers()
+                .get_one("Authorization")
+                .and_then(|authorization| authorization.strip_prefix("Bearer "));
+            let verifier = request.rocket().state::<Option<OidcVerifier>>();
+
+            return match (token, verifier) {
+                (Some(token), Some(Some(verifier))) => match verifier.verify(token.trim()).await {
+                    Ok(scopes) if auth::grants(&scopes, S::SCOPE) => {
+                        Outcome::Success(ApiKey(PhantomData))
+                    }
+                    Ok(_) => Outcome::Error((Status::Forbidden, ())),
+                    Err(e) => {
+                        warn!("Invalid bearer token: {e}");
+                        Outcome::Error((Status::Unauthorized, ()))
+                    }
+                },
+                _ => Outcome::Error((Status::Unauthorized, ())),
+            };
+        }
+
         if keys.len() != 1 {
             return Outcome::Error((Status::Unauthorized, ()));
         }
 
         let client_key = keys[0];
+
+        // The global API key grants every scope
         if config.api_key == client_key {
-            Outcome::Success(ApiKey)
-        } else {
-            Outcome::Error((Status::Unauthorized, ()))
+            return Outcome::Success(ApiKey(PhantomData));
+        }
+
+        match config.api_keys.iter().find(|key| key.key == client_key) {
+            Some(key) if key.grants(S::SCOPE) => Outcome::Success(ApiKey(PhantomData)),
+            Some(key) => {
+                warn!(
+                    "API key {} lacks the {} scope required by {}",
+                    key.name,
+                    S::SCOPE.name(),
+                    request.uri()
+                );
+                Outcome::Error((Status::Forbidden, ()))
+            }
+            None => Outcome::Error((Status::Unauthorized, ())),
         }
     }
 }
 
-#[derive(Serialize, ToSchema, Debug)]
-pub struct SimpleCheckDto {
-    pub check_name: String,
-    pub passive: bool,
-    pub interval: Option<u64>,
-    //pub language: Option<ScriptLanguage>,
+/// A database connection taken from the pool for the duration of a request
+pub struct DbClient(Object);
+
+impl Deref for DbClient {
+    type Target = tokio_postgres::Client;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for DbClient {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let Some(db_pool) = request.rocket().state::<Pool>() else {
+            return Outcome::Error((Status::InternalServerError, ()));
+        };
+
+        match db_pool.get().await {
+            Ok(client) => Outcome::Success(DbClient(client)),
+            Err(e) => {
+                error!("Error getting a database connection: {e}");
+                Outcome::Error((Status::ServiceUnavailable, ()))
+            }
+        }
+    }
+}
+
+/// The Kubernetes client shared by the handlers, not available in standalone mode
+pub struct KubeClient(kube::Client);
+
+impl Deref for KubeClient {
+    type Target = kube::Client;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for KubeClient {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match request.rocket().state::<Option<kube::Client>>() {
+            Some(Some(client)) => Outcome::Success(KubeClient(client.clone())),
+            _ => Outcome::Error((Status::ServiceUnavailable, ())),
+        }
+    }
+}
+
+/**
+ * This function tells whether the labels of a check match a selector, either key=value or just
+ * key to match any value
+ */
+fn matches_label(labels: &BTreeMap<String, String>, selector: &str) -> bool {
+    match selector.split_once('=') {
+        Some((key, value)) => labels.get(key.trim()).is_some_and(|v| v == value.trim()),
+        None => labels.contains_key(selector.trim()),
+    }
+}
+
+/**
+ * This function tells whether the labels of a check match all the selectors
+ */
+pub(crate) fn matches_labels(labels: &BTreeMap<String, String>, selectors: &[&str]) -> bool {
+    selectors
+        .iter()
+        .all(|selector| matches_label(labels, selector))
 }
 
-impl From<&Arc<PinglowCheck>> for SimpleCheckDto {
-    fn from(value: &Arc<PinglowCheck>) -> Self {
-        Self {
-            check_name: value.check_name.clone(),
-            passive: value.passive,
-            interval: value.interval,
-            //language: value.as_ref().script.as_ref().map(|c| c.language.clone()),
+/**
+ * This function lists the checks matching all the label selectors, together with their last
+ * status
+ */
+async fn list_checks_with_status(
+    checks: &SharedPinglowChecks,
+    selectors: &[&str],
+    client: &DbClient,
+) -> Result<Vec<SimpleCheckDto>, status::Custom<String>> {
+    let mut simple_checks_to_return: Vec<SimpleCheckDto> = checks
+        .iter()
+        .filter(|check| matches_labels(&check.labels, selectors))
+        .map(|check| check.value().into())
+        .collect();
+
+    let check_names: Vec<&String> = simple_checks_to_return
+        .iter()
+        .map(|check| &check.check_name)
+        .collect();
+
+    // Fetch the last result of every check in a single query
+    let last_results = client
+        .query(
+            "SELECT DISTINCT ON (check_name) check_name, timestamp, status FROM check_result WHERE check_name = ANY($1) AND NOT soft_failure ORDER BY check_name, timestamp DESC",
+            &[&check_names],
+        )
+        .await
+        .map_err(|e| {
+            status::Custom(
+                Status::InternalServerError,
+                format!("Error retrieving the last check results: {e}"),
+            )
+        })?;
+
+    let mut last_results: HashMap<String, (DateTime<Utc>, i16)> = last_results
+        .into_iter()
+        .map(|row| {
+            (
+                row.get("check_name"),
+                (row.get("timestamp"), row.get("status")),
+            )
+        })
+        .collect();
+
+    for check in simple_checks_to_return.iter_mut() {
+        if let Some((timestamp, status)) = last_results.remove(&check.check_name) {
+            check.status = CheckResultStatus::from(status);
+            check.timestamp = Some(timestamp);
         }
     }
+
+    Ok(simple_checks_to_return)
 }
 
-#[derive(Serialize, ToSchema)]
-pub struct SimpleCheckResultDto {
-    pub check_name: String,
-    pub passive: bool,
-    pub output: String,
-    pub status: CheckResultStatus,
-    pub timestamp: Option<DateTime<Utc>>,
-    pub notifications_muted: Option<bool>,
-    pub notifications_muted_until: Option<DateTime<Utc>>,
+/**
+ * This function sorts the checks by name, severity of their last status or time of their last
+ * result, in the given order
+ */
+fn sort_checks(
+    checks: &mut [SimpleCheckDto],
+    sort: &str,
+    order: &str,
+) -> Result<(), status::Custom<String>> {
+    match sort {
+        "name" => checks.sort_by(|a, b| a.check_name.cmp(&b.check_name)),
+        // Checks with the same status are kept sorted by name
+        "status" => checks.sort_by(|a, b| {
+            a.status
+                .severity()
+                .cmp(&b.status.severity())
+                .then_with(|| a.check_name.cmp(&b.check_name))
+        }),
+        // Checks which never ran come first
+        "last_run" => checks.sort_by(|a, b| {
+            a.timestamp
+                .cmp(&b.timestamp)
+                .then_with(|| a.check_name.cmp(&b.check_name))
+        }),
+        _ => {
+            return Err(status::Custom(
+                Status::BadRequest,
+                "The sort must be one of name, status or last_run".into(),
+            ))
+        }
+    }
+
+    match order {
+        "asc" => Ok(()),
+        "desc" => {
+            checks.reverse();
+            Ok(())
+        }
+        _ => Err(status::Custom(
+            Status::BadRequest,
+            "The order must be either asc or desc".into(),
+        )),
+    }
 }
 
 #[utoipa::path(
     get,
     path = "/checks",
+    params(
+        ("label" = Option<Vec<String>>, Query, description = "Label selectors (key=value, or key for any value) the checks must all match, e.g. team=payments"),
+        ("namespace" = Option<String>, Query, description = "Only checks of this namespace"),
+        ("status" = Option<i16>, Query, description = "Only checks whose last status has this code (0 Ok, 1 Warning, 2 Critical, 3 CheckError, 4 Pending for the checks which never ran)"),
+        ("sort" = Option<String>, Query, description = "One of name (default), status, by severity, or last_run"),
+        ("order" = Option<String>, Query, description = "Either asc (default) or desc"),
+        ("limit" = Option<i64>, Query, description = "Maximum number of checks returned, all of them by default"),
+        ("offset" = Option<i64>, Query, description = "Number of checks to skip")
+    ),
     responses(
-        (status = 200, description = "List of checks", body = [SimpleCheckDto])
+        (status = 200, description = "List of checks, with their last status", body = [SimpleCheckDto]),
+        (status = 400, description = "Invalid status, sort or order")
     )
 )]
-#[get("/checks")]
+#[allow(clippy::too_many_arguments)]
+#[get("/checks?<label>&<namespace>&<status>&<sort>&<order>&<limit>&<offset>")]
 pub async fn get_checks(
     _key: ApiKey,
     checks: &State<SharedPinglowChecks>,
-) -> Json<Vec<SimpleCheckDto>> {
-    let runnable_checks = checks.read().await;
+    client: DbClient,
+    label: Vec<&str>,
+    namespace: Option<&str>,
+    status: Option<i16>,
+    sort: Option<&str>,
+    order: Option<&str>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+) -> Result<Json<Vec<SimpleCheckDto>>, status::Custom<String>> {
+    let mut checks = list_checks_with_status(checks, &label, &client).await?;
+
+    if let Some(namespace) = namespace {
+        checks.retain(|check| check.namespace.as_deref() == Some(namespace));
+    }
+
+    if let Some(status) = status.map(parse_status).transpose()? {
+        checks.retain(|check| check.status == status);
+    }
+
+    sort_checks(&mut checks, sort.unwrap_or("name"), order.unwrap_or("asc"))?;
+
+    let offset = offset.unwrap_or(0).max(0) as usize;
+    let limit = limit.map_or(usize::MAX, |limit| limit.max(0) as usize);
+
+    Ok(Json(checks.into_iter().skip(offset).take(limit).collect()))
+}
+
+#[utoipa::path(
+    get,
+    path = "/groups/{label}/status",
+    params(
+        ("label" = String, Path, description = "The label selector (key=value) identifying the group, e.g. team=payments")
+    ),
+    responses(
+        (status = 200, description = "The worst status among the checks of the group", body = GroupStatusDto),
+        (status = 404, description = "No check belongs to the group")
+    )
+)]
+#[get("/groups/<label>/status")]
+pub async fn get_group_status(
+    _key: ApiKey,
+    checks: &State<SharedPinglowChecks>,
+    client: DbClient,
+    label: &str,
+) -> Result<Json<GroupStatusDto>, status::Custom<String>> {
+    let group_checks = list_checks_with_status(checks, &[label], &client).await?;
+
+    if group_checks.is_empty() {
+        return Err(status::Custom(
+            Status::NotFound,
+            format!("No check matches the label {label}"),
+        ));
+    }
+
+    let mut status_counts = StatusCountsDto::default();
+    for check in &group_checks {
+        match check.status {
+            CheckResultStatus::Ok => status_counts.ok += 1,
+            CheckResultStatus::Warning => status_counts.warning += 1,
+            CheckResultStatus::Critical => status_counts.critical += 1,
+            CheckResultStatus::CheckError => status_counts.check_error += 1,
+            CheckResultStatus::Pending => {}
+        }
+    }
+
+    let checks_count = group_checks.len();
+    let status = group_checks
+        .into_iter()
+        .map(|check| check.status)
+        .max_by_key(CheckResultStatus::severity)
+        .unwrap_or(CheckResultStatus::Pending);
+
+    Ok(Json(GroupStatusDto {
+        group: label.to_string(),
+        status,
+        checks: checks_count,
+        status_counts,
+    }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/groups",
+    responses(
+        (status = 200, description = "The CheckGroup resources with their aggregated status and the ones of their members", body = [CheckGroupDto]),
+        (status = 503, description = "The groups are not available in standalone mode")
+    )
+)]
+#[get("/groups")]
+pub async fn get_groups(
+    _key: ApiKey,
+    kube_client: KubeClient,
+    checks: &State<SharedPinglowChecks>,
+    pinglow_config: &State<PinglowConfig>,
+    client: DbClient,
+) -> Result<Json<Vec<CheckGroupDto>>, status::Custom<String>> {
+    let groups = groups::list_groups(&kube_client, pinglow_config)
+        .await
+        .map_err(|e| {
+            status::Custom(
+                Status::InternalServerError,
+                format!("Error listing the check groups: {e}"),
+            )
+        })?;
 
-    let simple_checks_to_return: Vec<SimpleCheckDto> =
-        runnable_checks.iter().map(|check| check.1.into()).collect();
+    let mut evaluations = Vec::with_capacity(groups.len());
+    for group in groups.iter() {
+        let evaluation = groups::evaluate(group, checks, pinglow_config, &client)
+            .await
+            .map_err(|e| {
+                status::Custom(
+                    Status::InternalServerError,
+                    format!("Error evaluating the check group {}: {e}", group.name_any()),
+                )
+            })?;
+        evaluations.push(evaluation);
+    }
+    evaluations.sort_by(|a, b| a.name.cmp(&b.name));
 
-    Json(simple_checks_to_return)
+    Ok(Json(evaluations))
 }
 
 #[utoipa::path(
@@ -152,16 +588,12 @@ pub async fn get_checks(
 pub async fn get_check_status(
     _key: ApiKey,
     checks: &State<SharedPinglowChecks>,
-    client: &State<Arc<Client>>,
+    client: DbClient,
     target_check: &str,
 ) -> Option<Json<SimpleCheckResultDto>> {
-    let runnable_checks = checks.read().await;
-
-    let (_, check) = runnable_checks
-        .iter()
-        .find(|&check| check.0 == target_check)?;
+    let check = checks.get(target_check)?.value().clone();
 
-    let last_check_result_from_db = client.query_opt("SELECT timestamp,status,output from check_result where check_name = $1 order by timestamp desc limit 1", &[&target_check]).await.ok()?;
+    let last_check_result_from_db = client.query_opt("SELECT timestamp,status,output,long_output,soft_failure,suppressed_by from check_result where check_name = $1 order by timestamp desc limit 1", &[&target_check]).await.ok()?;
 
     let last_check_result = if let Some(last_check_result) = last_check_result_from_db {
         last_check_result
@@ -170,331 +602,2285 @@ pub async fn get_check_status(
             check_name: target_check.to_string(),
             passive: check.passive,
             output: "Check still needs to be executed".to_owned(),
+            long_output: None,
             status: CheckResultStatus::Pending,
             timestamp: None,
             notifications_muted: check.mute_notifications,
             notifications_muted_until: check.mute_notifications_until,
+            suspended: check.suspended,
+            soft_failure: false,
+            suppressed_by: None,
+            ack: None,
+            flapping_since: None,
         }));
     };
 
+    // The acknowledgement and the flapping only complete the status, which is returned without them
+    // if they cannot be read
+    let ack = ack::active_ack(&client, target_check)
+        .await
+        .unwrap_or_else(|e| {
+            error!("Error reading the acknowledgement of {target_check}: {e}");
+            None
+        });
+    let flapping_since = flapping::flapping_since(&client, target_check)
+        .await
+        .unwrap_or_else(|e| {
+            error!("Error reading the flapping state of {target_check}: {e}");
+            None
+        });
+
     let check_status: i16 = last_check_result.get("status");
     Some(Json(SimpleCheckResultDto {
         check_name: target_check.to_string(),
         passive: check.passive,
         output: last_check_result.get("output"),
+        long_output: last_check_result.get("long_output"),
         status: CheckResultStatus::from(check_status),
         timestamp: last_check_result.get("timestamp"),
         notifications_muted: check.mute_notifications,
         notifications_muted_until: check.mute_notifications_until,
+        suspended: check.suspended,
+        soft_failure: last_check_result.get("soft_failure"),
+        suppressed_by: last_check_result.get("suppressed_by"),
+        ack,
+        flapping_since,
     }))
 }
 
-#[derive(Debug, Deserialize)]
-struct GroupedPerfData {
-    timestamp: DateTime<Utc>,
-    perf_data: HashMap<String, f32>,
-}
-
 #[utoipa::path(
     get,
-    path = "/performance-data/{target_check}",
-     params(
-        ("target_check" = String, Path, description = "The check for which we would like to get the performance data")
+    path = "/overview",
+    params(
+        ("label" = Option<Vec<String>>, Query, description = "Label selectors (key=value, or key for any value) the checks must all match, e.g. team=payments")
     ),
     responses(
-        (status = 200, description = "The performance data of the check", body = [BTreeMap<DateTime<Utc>, HashMap<String, f32>>])
+        (status = 200, description = "Every check with its last result, mute state, acknowledgement and flapping state, sorted by name", body = [SimpleCheckResultDto])
     )
 )]
-#[get("/performance-data/<target_check>")]
-pub async fn get_performance_data(
+#[get("/overview?<label>")]
+pub async fn get_overview(
     _key: ApiKey,
     checks: &State<SharedPinglowChecks>,
-    client: &State<Arc<Client>>,
-    target_check: &str,
-) -> Option<Json<BTreeMap<DateTime<Utc>, HashMap<String, f32>>>> {
-    let runnable_checks = checks.read().await;
-
-    runnable_checks
+    client: DbClient,
+    label: Vec<&str>,
+) -> Result<Json<Vec<SimpleCheckResultDto>>, status::Custom<String>> {
+    let mut checks: Vec<Arc<PinglowCheck>> = checks
         .iter()
-        .find(|&check| check.0 == target_check)?;
-
-    let raw_perf_data_rows = client.query("SELECT timestamp, json_object_agg(perf_key, perf_value ORDER BY perf_key) AS perf_data FROM check_result_perf_data WHERE check_name = $1 GROUP BY timestamp ORDER BY timestamp;", &[&target_check]).await.ok()?;
+        .filter(|check| matches_labels(&check.labels, &label))
+        .map(|check| check.value().clone())
+        .collect();
+    checks.sort_by(|a, b| a.check_name.cmp(&b.check_name));
 
-    let mut perf_data = Vec::new();
+    let check_names: Vec<&String> = checks.iter().map(|check| &check.check_name).collect();
 
-    for raw_perf_data in raw_perf_data_rows {
-        let timestamp: chrono::DateTime<Utc> = raw_perf_data.get("timestamp");
-        let perf_data_json: Value = raw_perf_data.get("perf_data");
+    // The last result, active acknowledgement and flapping state of every check in a single query
+    let rows = client
+        .query(
+            "SELECT names.check_name, r.timestamp, r.status, r.output, r.long_output, r.soft_failure, r.suppressed_by, a.acked_by, a.comment, a.created_at AS acked_at, f.started_at AS flapping_since
+            FROM UNNEST($1::text[]) AS names(check_name)
+            LEFT JOIN LATERAL (
+                SELECT timestamp, status, output, long_output, soft_failure, suppressed_by FROM check_result WHERE check_name = names.check_name ORDER BY timestamp DESC LIMIT 1
+            ) r ON TRUE
+            LEFT JOIN LATERAL (
+                SELECT acked_by, comment, created_at FROM check_ack WHERE check_name = names.check_name AND cleared_at IS NULL ORDER BY created_at DESC LIMIT 1
+            ) a ON TRUE
+            LEFT JOIN check_flapping f ON f.check_name = names.check_name",
+            &[&check_names],
+        )
+        .await
+        .map_err(|e| {
+            status::Custom(
+                Status::InternalServerError,
+                format!("Error retrieving the overview of the checks: {e}"),
+            )
+        })?;
 
-        // Convert JSON object to HashMap<String, f32>
-        let perf_data_map: HashMap<String, f32> = serde_json::from_value(perf_data_json)
-            .map_err(|e| {
-                warn!("Failed to parse JSON perf_data: {e}");
-                pinglow_common::error::SerializeError::DeserializationError(e.to_string())
-            })
-            .ok()?;
+    let mut rows: HashMap<String, tokio_postgres::Row> = rows
+        .into_iter()
+        .map(|row| (row.get("check_name"), row))
+        .collect();
 
-        perf_data.push(GroupedPerfData {
-            timestamp,
-            perf_data: perf_data_map,
-        });
-    }
+    let overview = checks
+        .iter()
+        .map(|check| {
+            let row = rows.remove(&check.check_name);
+            let row = row.as_ref();
 
-    let map: BTreeMap<_, _> = perf_data
-        .into_iter()
-        .map(|entry| (entry.timestamp, entry.perf_data))
+            SimpleCheckResultDto {
+                check_name: check.check_name.clone(),
+                passive: check.passive,
+                output: row
+                    .and_then(|row| row.get::<_, Option<String>>("output"))
+                    .unwrap_or_else(|| "Check still needs to be executed".to_owned()),
+                long_output: row.and_then(|row| row.get("long_output")),
+                status: row
+                    .and_then(|row| row.get::<_, Option<i16>>("status"))
+                    .map_or(CheckResultStatus::Pending, CheckResultStatus::from),
+                timestamp: row.and_then(|row| row.get("timestamp")),
+                notifications_muted: check.mute_notifications,
+                notifications_muted_until: check.mute_notifications_until,
+                suspended: check.suspended,
+                soft_failure: row
+                    .and_then(|row| row.get::<_, Option<bool>>("soft_failure"))
+                    .unwrap_or(false),
+                suppressed_by: row.and_then(|row| row.get("suppressed_by")),
+                ack: row.and_then(|row| {
+                    Some(CheckAckDto {
+                        acked_by: row.get::<_, Option<String>>("acked_by")?,
+                        comment: row.get("comment"),
+                        timestamp: row.get("acked_at"),
+                    })
+                }),
+                flapping_since: row.and_then(|row| row.get("flapping_since")),
+            }
+        })
         .collect();
 
-    Some(Json(map))
+    Ok(Json(overview))
 }
 
 #[utoipa::path(
-    put,
-    path = "/check/{target_check}/mute?<until>",
-     params(
-        ("target_check" = String, Path, description = "The check we would like to mute")
+    get,
+    path = "/stream",
+    params(
+        ("label" = Option<Vec<String>>, Query, description = "Label selectors (key=value, or key for any value) the checks must all match, e.g. team=payments")
     ),
     responses(
-        (status = 200, description = "Whether the mute operation was successful")
+        (status = 200, description = "Server-Sent Events stream of the results processed from now on, as JSON result events", body = String, content_type = "text/event-stream")
     )
 )]
-#[put("/check/<target_check>/mute?<until>")]
-pub async fn mute_check(
+#[get("/stream?<label>")]
+pub fn stream_results(
     _key: ApiKey,
     checks: &State<SharedPinglowChecks>,
-    pinglow_config: &State<PinglowConfig>,
-    target_check: &str,
-    until: Option<String>,
-) -> Result<(), status::Custom<String>> {
-    // Read actual shared checks
-    let mut runnable_checks = checks.write().await;
+    sinks: &State<Arc<ResultSinks>>,
+    label: Vec<&str>,
+    mut shutdown: Shutdown,
+) -> EventStream![] {
+    let mut results = sinks.subscribe();
+    let checks = checks.inner().clone();
+    let selectors: Vec<String> = label.into_iter().map(String::from).collect();
 
-    // Ensure we can find the target check
-    runnable_checks
-        .iter()
-        .find(|&check| check.0 == target_check)
+    EventStream! {
+        loop {
+            let result = tokio::select! {
+                result = results.recv() => match result {
+                    Ok(result) => result,
+                    Err(RecvError::Closed) => break,
+                    // A slow client skips the results it could not keep up with
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!("Live results stream lagging, skipped {skipped} result(s)");
+                        continue;
+                    }
+                },
+                _ = &mut shutdown => break,
+            };
+
+            if !selectors.is_empty() {
+                let selectors: Vec<&str> = selectors.iter().map(String::as_str).collect();
+                let matches = checks
+                    .get(&result.check_name)
+                    .is_some_and(|check| matches_labels(&check.labels, &selectors));
+                if !matches {
+                    continue;
+                }
+            }
+
+            yield Event::json(&result).event("result");
+        }
+    }
+}
+
+// Maximum time a run requested through the API can be waited for
+const MAX_RUN_WAIT_SECONDS: u64 = 300;
+
+#[utoipa::path(
+    post,
+    path = "/check/{target_check}/run",
+    params(
+        ("target_check" = String, Path, description = "The check we would like to run right away"),
+        ("wait" = Option<u64>, Query, description = "Seconds to wait for the result, at most 300. The run is not awaited by default")
+    ),
+    responses(
+        (status = 202, description = "The check was queued for execution"),
+        (status = 200, description = "The result of the run", body = Object),
+        (status = 400, description = "Passive checks cannot be run"),
+        (status = 500, description = "The run could not be started"),
+        (status = 504, description = "The result did not arrive in time, the run is still queued")
+    ),
+    security(
+        ("api_key" = ["mute"]),
+        ("bearer" = ["mute"])
+    )
+)]
+#[post("/check/<target_check>/run?<wait>")]
+#[allow(clippy::too_many_arguments)]
+pub async fn run_check(
+    _key: ApiKey<Mute>,
+    checks: &State<SharedPinglowChecks>,
+    redis_client: &State<redis::Client>,
+    kube_client: &State<Option<kube::Client>>,
+    config: &State<PinglowConfig>,
+    sinks: &State<Arc<ResultSinks>>,
+    target_check: &str,
+    wait: Option<u64>,
+) -> Result<(Status, Option<Json<ExportedResult>>), status::Custom<String>> {
+    let check = checks
+        .get(target_check)
+        .map(|check| check.value().clone())
         .ok_or(status::Custom(
             Status::NotFound,
             "Invalid target check".into(),
         ))?;
 
-    // Prepare the patch object
-    let mut patch = serde_json::json!({
-        "spec": {
-            "muteNotifications": true
-        }
-    });
-
-    // If until is specified try to parse it and set it in the patch object
-    let mut until_date_time: Option<DateTime<FixedOffset>> = None;
-    if let Some(until) = until {
-        match chrono::DateTime::parse_from_rfc3339(&until) {
-            Ok(until) => {
-                until_date_time = Some(until);
-                if let Some(spec) = patch.get_mut("spec").and_then(Value::as_object_mut) {
-                    spec.insert(
-                        "muteNotificationsUntil".to_string(),
-                        json!(until.to_rfc3339()),
-                    );
-                }
-            }
-            Err(e) => {
-                return Err(status::Custom(
-                    Status::BadRequest,
-                    format!("Invalid datetime format: {e}"),
-                ))
-            }
-        }
+    if check.passive {
+        return Err(status::Custom(
+            Status::BadRequest,
+            "Passive checks cannot be run".into(),
+        ));
     }
 
-    // Get the checks Kube Api
-    let client = kube::Client::try_default().await.map_err(|e| {
-        status::Custom(
-            Status::InternalServerError,
-            format!("Error retrieving the Kube client: {e}"),
-        )
-    })?;
-    let checks_api: Api<Check> = Api::namespaced(client.clone(), &pinglow_config.target_namespace);
+    // The run is tagged, to tell its result apart from the ones of the scheduled runs
+    let run_id = format!("{:016x}", fastrand::u64(..));
+    let mut check = (*check).clone();
+    check.run_id = Some(run_id.clone());
+    let check = Arc::new(check);
 
-    checks_api
-        .patch(
-            target_check,
-            &kube::api::PatchParams::apply("pinglow"),
-            &kube::api::Patch::Merge(&patch),
-        )
-        .await
-        .map_err(|e| {
+    // Subscribe before queueing the run, so that its result cannot be missed
+    let mut results = sinks.subscribe();
+
+    if check.job.is_some() {
+        let job_executor = JobExecutor::new(
+            kube_client.inner().clone(),
+            config.target_namespace.clone(),
+            redis_client.inner().clone(),
+        );
+        job_executor.start(check.clone()).await.map_err(|e| {
+            status::Custom(
+                Status::InternalServerError,
+                format!("Error starting the check job: {e}"),
+            )
+        })?;
+    } else {
+        let mut redis_conn = redis_client
+            .get_multiplexed_async_connection()
+            .await
+            .map_err(|e| {
+                status::Custom(
+                    Status::ServiceUnavailable,
+                    format!("Error connecting to redis: {e}"),
+                )
+            })?;
+
+        enqueue_checks(&mut redis_conn, std::slice::from_ref(&check))
+            .await
+            .map_err(|e| {
+                status::Custom(
+                    Status::InternalServerError,
+                    format!("Error queueing the check: {e}"),
+                )
+            })?;
+    }
+    metrics::CHECKS_SCHEDULED.inc();
+
+    let Some(wait) = wait else {
+        return Ok((Status::Accepted, None));
+    };
+
+    let result = tokio::time::timeout(Duration::from_secs(wait.min(MAX_RUN_WAIT_SECONDS)), async {
+        loop {
+            match results.recv().await {
+                Ok(result) if result.run_id.as_ref() == Some(&run_id) => return Some(result),
+                Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    })
+    .await;
+
+    match result {
+        Ok(Some(result)) => Ok((Status::Ok, Some(Json(result)))),
+        _ => Err(status::Custom(
+            Status::GatewayTimeout,
+            "The result did not arrive in time, the run is still queued".into(),
+        )),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/check/{target_check}/ack",
+    params(
+        ("target_check" = String, Path, description = "The failing check we would like to acknowledge")
+    ),
+    request_body = AckCheckPayload,
+    responses(
+        (status = 200, description = "The acknowledgement, valid until the check recovers", body = CheckAckDto),
+        (status = 404, description = "Invalid target check"),
+        (status = 409, description = "The check is not failing")
+    ),
+    security(
+        ("api_key" = ["mute"]),
+        ("bearer" = ["mute"])
+    )
+)]
+#[post("/check/<target_check>/ack", data = "<payload>")]
+pub async fn ack_check(
+    _key: ApiKey<Mute>,
+    checks: &State<SharedPinglowChecks>,
+    client: DbClient,
+    target_check: &str,
+    payload: Json<AckCheckPayload>,
+) -> Result<Json<CheckAckDto>, status::Custom<String>> {
+    if !checks.contains_key(target_check) {
+        return Err(status::Custom(
+            Status::NotFound,
+            "Invalid target check".into(),
+        ));
+    }
+
+    let db_error = |e: tokio_postgres::Error| {
+        status::Custom(
+            Status::InternalServerError,
+            format!("Error acknowledging the check: {e}"),
+        )
+    };
+
+    // Only a failure can be acknowledged, as the acknowledgement is cleared on recovery
+    match last_status(&client, target_check).await.map_err(db_error)? {
+        Some(CheckResultStatus::Ok) | Some(CheckResultStatus::Pending) | None => {
+            return Err(status::Custom(
+                Status::Conflict,
+                format!("The check {target_check} is not failing"),
+            ))
+        }
+        Some(_) => {}
+    }
+
+    let ack = ack::acknowledge(
+        &client,
+        target_check,
+        &payload.user,
+        payload.comment.as_deref(),
+    )
+    .await
+    .map_err(db_error)?;
+
+    Ok(Json(ack))
+}
+
+#[derive(Debug, Deserialize)]
+struct GroupedPerfData {
+    timestamp: DateTime<Utc>,
+    perf_data: HashMap<String, f32>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/performance-data/{target_check}",
+     params(
+        ("target_check" = String, Path, description = "The check for which we would like to get the performance data"),
+        ("from" = Option<String>, Query, description = "Only data points at or after this RFC 3339 timestamp"),
+        ("to" = Option<String>, Query, description = "Only data points at or before this RFC 3339 timestamp"),
+        ("step" = Option<String>, Query, description = "Size of the buckets the data points are aggregated into, in seconds (s), minutes (m), hours (h) or days (d). Raw data points are returned when missing"),
+        ("aggregation" = Option<String>, Query, description = "Either avg (default), min, max or p95, applied to the data points of each bucket")
+    ),
+    responses(
+        (status = 200, description = "The performance data of the check", body = [BTreeMap<DateTime<Utc>, HashMap<String, f32>>])
+    )
+)]
+#[allow(clippy::too_many_arguments)]
+#[get("/performance-data/<target_check>?<from>&<to>&<step>&<aggregation>")]
+pub async fn get_performance_data(
+    _key: ApiKey,
+    checks: &State<SharedPinglowChecks>,
+    client: DbClient,
+    target_check: &str,
+    from: Option<&str>,
+    to: Option<&str>,
+    step: Option<&str>,
+    aggregation: Option<&str>,
+) -> Result<Json<BTreeMap<DateTime<Utc>, HashMap<String, f32>>>, status::Custom<String>> {
+    if !checks.contains_key(target_check) {
+        return Err(status::Custom(
+            Status::NotFound,
+            "Invalid target check".into(),
+        ));
+    }
+
+    let from = from.map(parse_timestamp).transpose()?;
+    let to = to.map(parse_timestamp).transpose()?;
+
+    let aggregate = match aggregation.unwrap_or("avg") {
+        "avg" => "AVG(perf_value)",
+        "min" => "MIN(perf_value)",
+        "max" => "MAX(perf_value)",
+        "p95" => "percentile_cont(0.95) WITHIN GROUP (ORDER BY perf_value)",
+        _ => {
+            return Err(status::Custom(
+                Status::BadRequest,
+                "The aggregation must be one of avg, min, max or p95".into(),
+            ))
+        }
+    };
+
+    let db_error = |e: tokio_postgres::Error| {
+        status::Custom(
+            Status::InternalServerError,
+            format!("Error retrieving the performance data: {e}"),
+        )
+    };
+
+    let raw_perf_data_rows = match step {
+        None => client.query("SELECT timestamp, json_object_agg(perf_key, perf_value ORDER BY perf_key) AS perf_data FROM check_result_perf_data WHERE check_name = $1 AND ($2::timestamptz IS NULL OR timestamp >= $2) AND ($3::timestamptz IS NULL OR timestamp <= $3) GROUP BY timestamp ORDER BY timestamp;", &[&target_check, &from, &to]).await.map_err(db_error)?,
+        Some(step) => {
+            let step = parse_window(step).ok_or(status::Custom(
+                Status::BadRequest,
+                "Invalid step, expected e.g. 30s, 5m, 1h or 1d".to_string(),
+            ))?;
+
+            // Each key is aggregated separately in every bucket, then the keys are grouped back
+            client
+                .query(
+                    &format!("SELECT bucket AS timestamp, json_object_agg(perf_key, perf_value ORDER BY perf_key) AS perf_data FROM (
+                        SELECT time_bucket($4::bigint * INTERVAL '1 second', timestamp) AS bucket, perf_key, ({aggregate})::real AS perf_value
+                        FROM check_result_perf_data WHERE check_name = $1 AND ($2::timestamptz IS NULL OR timestamp >= $2) AND ($3::timestamptz IS NULL OR timestamp <= $3)
+                        GROUP BY bucket, perf_key
+                    ) AS buckets GROUP BY bucket ORDER BY bucket;"),
+                    &[&target_check, &from, &to, &step.num_seconds()],
+                )
+                .await
+                .map_err(db_error)?
+        }
+    };
+
+    let mut perf_data = Vec::new();
+
+    for raw_perf_data in raw_perf_data_rows {
+        let timestamp: chrono::DateTime<Utc> = raw_perf_data.get("timestamp");
+        let perf_data_json: Value = raw_perf_data.get("perf_data");
+
+        // Convert JSON object to HashMap<String, f32>
+        let perf_data_map: HashMap<String, f32> =
+            serde_json::from_value(perf_data_json).map_err(|e| {
+                warn!("Failed to parse JSON perf_data: {e}");
+                status::Custom(
+                    Status::InternalServerError,
+                    format!("Invalid performance data: {e}"),
+                )
+            })?;
+
+        perf_data.push(GroupedPerfData {
+            timestamp,
+            perf_data: perf_data_map,
+        });
+    }
+
+    let map: BTreeMap<_, _> = perf_data
+        .into_iter()
+        .map(|entry| (entry.timestamp, entry.perf_data))
+        .collect();
+
+    Ok(Json(map))
+}
+
+// Number of results, or notification attempts, returned when no limit is requested, and the
+// maximum which can be requested
+const DEFAULT_RESULTS_LIMIT: i64 = 100;
+const MAX_RESULTS_LIMIT: i64 = 1000;
+
+#[utoipa::path(
+    get,
+    path = "/check/{target_check}/results",
+     params(
+        ("target_check" = String, Path, description = "The check for which we would like to get the results"),
+        ("from" = Option<String>, Query, description = "Only results at or after this RFC 3339 timestamp"),
+        ("to" = Option<String>, Query, description = "Only results at or before this RFC 3339 timestamp"),
+        ("status" = Option<i16>, Query, description = "Only results with this status code"),
+        ("limit" = Option<i64>, Query, description = "Maximum number of results returned, 100 by default and at most 1000"),
+        ("offset" = Option<i64>, Query, description = "Number of results to skip"),
+        ("order" = Option<String>, Query, description = "Either asc or desc (default), by timestamp")
+    ),
+    responses(
+        (status = 200, description = "The past results of the check", body = [CheckResultHistoryDto])
+    )
+)]
+#[allow(clippy::too_many_arguments)]
+#[get("/check/<target_check>/results?<from>&<to>&<status>&<limit>&<offset>&<order>")]
+pub async fn get_check_results(
+    _key: ApiKey,
+    checks: &State<SharedPinglowChecks>,
+    client: DbClient,
+    target_check: &str,
+    from: Option<&str>,
+    to: Option<&str>,
+    status: Option<i16>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+    order: Option<&str>,
+) -> Result<Json<Vec<CheckResultHistoryDto>>, status::Custom<String>> {
+    if !checks.contains_key(target_check) {
+        return Err(status::Custom(
+            Status::NotFound,
+            "Invalid target check".into(),
+        ));
+    }
+
+    let from = from.map(parse_timestamp).transpose()?;
+    let to = to.map(parse_timestamp).transpose()?;
+    if let Some(status) = status {
+        parse_status(status)?;
+    }
+
+    let order = match order.unwrap_or("desc") {
+        "asc" => "ASC",
+        "desc" => "DESC",
+        _ => {
+            return Err(status::Custom(
+                Status::BadRequest,
+                "The order must be either asc or desc".into(),
+            ))
+        }
+    };
+
+    let limit = limit
+        .unwrap_or(DEFAULT_RESULTS_LIMIT)
+        .clamp(0, MAX_RESULTS_LIMIT);
+    let offset = offset.unwrap_or(0).max(0);
+
+    let rows = client
+        .query(
+            &format!("SELECT timestamp, status, output, long_output, soft_failure, suppressed_by, script_hash FROM check_result WHERE check_name = $1 AND ($2::timestamptz IS NULL OR timestamp >= $2) AND ($3::timestamptz IS NULL OR timestamp <= $3) AND ($4::smallint IS NULL OR status = $4) ORDER BY timestamp {order} LIMIT $5 OFFSET $6"),
+            &[&target_check, &from, &to, &status, &limit, &offset],
+        )
+        .await
+        .map_err(|e| {
+            status::Custom(
+                Status::InternalServerError,
+                format!("Error retrieving the results: {e}"),
+            )
+        })?;
+
+    let results = rows
+        .into_iter()
+        .map(|row| CheckResultHistoryDto {
+            timestamp: row.get("timestamp"),
+            status: CheckResultStatus::from(row.get::<_, i16>("status")),
+            output: row.get("output"),
+            long_output: row.get("long_output"),
+            soft_failure: row.get("soft_failure"),
+            suppressed_by: row.get("suppressed_by"),
+            script_hash: row.get("script_hash"),
+        })
+        .collect();
+
+    Ok(Json(results))
+}
+
+fn parse_timestamp(value: &str) -> Result<DateTime<Utc>, status::Custom<String>> {
+    chrono::DateTime::parse_from_rfc3339(value)
+        .map(Into::into)
+        .map_err(|e| status::Custom(Status::BadRequest, format!("Invalid datetime format: {e}")))
+}
+
+/**
+ * This function parses a status code filter, rejecting the codes of no status
+ */
+fn parse_status(value: i16) -> Result<CheckResultStatus, status::Custom<String>> {
+    match value {
+        0..=4 => Ok(CheckResultStatus::from(value)),
+        _ => Err(status::Custom(
+            Status::BadRequest,
+            "The status must be a code between 0 and 4".into(),
+        )),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/check/{target_check}/availability",
+     params(
+        ("target_check" = String, Path, description = "The check for which we would like to get the availability"),
+        ("window" = Option<String>, Query, description = "Window ending now, in minutes (m), hours (h) or days (d), 30d by default")
+    ),
+    responses(
+        (status = 200, description = "The availability of the check over the window, soft failures excluded", body = CheckAvailabilityDto)
+    )
+)]
+#[get("/check/<target_check>/availability?<window>")]
+pub async fn get_check_availability(
+    _key: ApiKey,
+    checks: &State<SharedPinglowChecks>,
+    client: DbClient,
+    target_check: &str,
+    window: Option<&str>,
+) -> Result<Json<CheckAvailabilityDto>, status::Custom<String>> {
+    if !checks.contains_key(target_check) {
+        return Err(status::Custom(
+            Status::NotFound,
+            "Invalid target check".into(),
+        ));
+    }
+
+    let window = parse_window(window.unwrap_or("30d")).ok_or(status::Custom(
+        Status::BadRequest,
+        "Invalid window, expected e.g. 90m, 24h or 30d".to_string(),
+    ))?;
+
+    let to = Utc::now();
+    let from = to - window;
+
+    let db_error = |e: tokio_postgres::Error| {
+        status::Custom(
+            Status::InternalServerError,
+            format!("Error computing the availability: {e}"),
+        )
+    };
+
+    let count_rows = client
+        .query(
+            "SELECT status, COUNT(*) AS results FROM check_result WHERE check_name = $1 AND timestamp >= $2 AND NOT soft_failure GROUP BY status",
+            &[&target_check, &from],
+        )
+        .await
+        .map_err(db_error)?;
+
+    let mut status_counts = StatusCountsDto::default();
+    for row in count_rows {
+        let results: i64 = row.get("results");
+        match CheckResultStatus::from(row.get::<_, i16>("status")) {
+            CheckResultStatus::Ok => status_counts.ok += results,
+            CheckResultStatus::Warning => status_counts.warning += results,
+            CheckResultStatus::Critical => status_counts.critical += results,
+            CheckResultStatus::CheckError => status_counts.check_error += results,
+            CheckResultStatus::Pending => {}
+        }
+    }
+
+    let total = status_counts.ok
+        + status_counts.warning
+        + status_counts.critical
+        + status_counts.check_error;
+    let availability_percentage =
+        (total > 0).then(|| status_counts.ok as f64 * 100.0 / total as f64);
+
+    // Consecutive failures are grouped by the number of Ok results preceding them, each group
+    // lasting until the following result (or now, if the check is still failing)
+    let outage = client
+        .query_opt(
+            "SELECT outage_start, EXTRACT(EPOCH FROM outage_end - outage_start)::float8 AS duration FROM (
+                SELECT MIN(timestamp) AS outage_start, MAX(COALESCE(next_timestamp, NOW())) AS outage_end FROM (
+                    SELECT timestamp, status, LEAD(timestamp) OVER (ORDER BY timestamp) AS next_timestamp,
+                        COUNT(*) FILTER (WHERE status = 0) OVER (ORDER BY timestamp) AS outage
+                    FROM check_result WHERE check_name = $1 AND timestamp >= $2 AND NOT soft_failure AND status <> 4
+                ) AS results WHERE status <> 0 GROUP BY outage
+            ) AS outages ORDER BY duration DESC LIMIT 1",
+            &[&target_check, &from],
+        )
+        .await
+        .map_err(db_error)?;
+
+    Ok(Json(CheckAvailabilityDto {
+        check_name: target_check.to_string(),
+        from,
+        to,
+        availability_percentage,
+        status_counts,
+        longest_outage_seconds: outage
+            .as_ref()
+            .map(|row| row.get("duration"))
+            .unwrap_or(0.0),
+        longest_outage_start: outage.map(|row| row.get("outage_start")),
+    }))
+}
+
+/**
+ * This function parses a window such as 30s, 90m, 24h or 30d
+ */
+fn parse_window(window: &str) -> Option<TimeDelta> {
+    let (amount, unit) = window.split_at(window.char_indices().last()?.0);
+    let amount: i64 = amount.parse().ok().filter(|amount| *amount > 0)?;
+
+    match unit {
+        "s" => TimeDelta::try_seconds(amount),
+        "m" => TimeDelta::try_minutes(amount),
+        "h" => TimeDelta::try_hours(amount),
+        "d" => TimeDelta::try_days(amount),
+        _ => None,
+    }
+}
+
+// Number of diagnostics returned when no limit is requested
+const DEFAULT_DIAGNOSTICS_LIMIT: i64 = 10;
+
+#[utoipa::path(
+    get,
+    path = "/check/{target_check}/diagnostics",
+     params(
+        ("target_check" = String, Path, description = "The check for which we would like to get the diagnostics"),
+        ("limit" = Option<i64>, Query, description = "Maximum number of diagnostics returned, 10 by default")
+    ),
+    responses(
+        (status = 200, description = "The diagnostics collected on the last CheckError results, most recent first", body = [CheckDiagnosticsDto])
+    )
+)]
+#[get("/check/<target_check>/diagnostics?<limit>")]
+pub async fn get_check_diagnostics(
+    _key: ApiKey,
+    checks: &State<SharedPinglowChecks>,
+    client: DbClient,
+    target_check: &str,
+    limit: Option<i64>,
+) -> Result<Json<Vec<CheckDiagnosticsDto>>, status::Custom<String>> {
+    if !checks.contains_key(target_check) {
+        return Err(status::Custom(
+            Status::NotFound,
+            "Invalid target check".into(),
+        ));
+    }
+
+    let rows = client
+        .query(
+            "SELECT timestamp, diagnostics FROM check_diagnostics WHERE check_name = $1 ORDER BY timestamp DESC LIMIT $2",
+            &[&target_check, &limit.unwrap_or(DEFAULT_DIAGNOSTICS_LIMIT).max(0)],
+        )
+        .await
+        .map_err(|e| {
+            status::Custom(
+                Status::InternalServerError,
+                format!("Error retrieving the diagnostics: {e}"),
+            )
+        })?;
+
+    let diagnostics = rows
+        .into_iter()
+        .map(|row| CheckDiagnosticsDto {
+            check_name: target_check.to_string(),
+            timestamp: row.get("timestamp"),
+            diagnostics: row.get("diagnostics"),
+        })
+        .collect();
+
+    Ok(Json(diagnostics))
+}
+
+#[utoipa::path(
+    get,
+    path = "/check/{target_check}/notifications",
+     params(
+        ("target_check" = String, Path, description = "The check for which we would like to get the notification attempts"),
+        ("from" = Option<String>, Query, description = "Only attempts at or after this RFC 3339 timestamp"),
+        ("to" = Option<String>, Query, description = "Only attempts at or before this RFC 3339 timestamp"),
+        ("limit" = Option<i64>, Query, description = "Maximum number of attempts returned, 100 by default and at most 1000")
+    ),
+    responses(
+        (status = 200, description = "The delivery attempts of the notifications of the check, most recent first", body = [NotificationAttemptDto])
+    )
+)]
+#[get("/check/<target_check>/notifications?<from>&<to>&<limit>")]
+pub async fn get_check_notifications(
+    _key: ApiKey,
+    checks: &State<SharedPinglowChecks>,
+    client: DbClient,
+    target_check: &str,
+    from: Option<&str>,
+    to: Option<&str>,
+    limit: Option<i64>,
+) -> Result<Json<Vec<NotificationAttemptDto>>, status::Custom<String>> {
+    if !checks.contains_key(target_check) {
+        return Err(status::Custom(
+            Status::NotFound,
+            "Invalid target check".into(),
+        ));
+    }
+
+    let from = from.map(parse_timestamp).transpose()?;
+    let to = to.map(parse_timestamp).transpose()?;
+    let limit = limit
+        .unwrap_or(DEFAULT_RESULTS_LIMIT)
+        .clamp(0, MAX_RESULTS_LIMIT);
+
+    let rows = client
+        .query(
+            "SELECT notification_id, channel_kind, channel_id, timestamp, success, error FROM notification_attempts WHERE check_name = $1 AND ($2::timestamptz IS NULL OR timestamp >= $2) AND ($3::timestamptz IS NULL OR timestamp <= $3) ORDER BY timestamp DESC LIMIT $4",
+            &[&target_check, &from, &to, &limit],
+        )
+        .await
+        .map_err(|e| {
+            status::Custom(
+                Status::InternalServerError,
+                format!("Error retrieving the notification attempts: {e}"),
+            )
+        })?;
+
+    let attempts = rows
+        .into_iter()
+        .map(|row| NotificationAttemptDto {
+            notification_id: row.get("notification_id"),
+            check_name: target_check.to_string(),
+            channel_kind: row.get("channel_kind"),
+            channel_id: row.get("channel_id"),
+            timestamp: row.get("timestamp"),
+            success: row.get("success"),
+            error: row.get("error"),
+        })
+        .collect();
+
+    Ok(Json(attempts))
+}
+
+#[utoipa::path(
+    put,
+    path = "/check/{target_check}/mute?<until>",
+     params(
+        ("target_check" = String, Path, description = "The check we would like to mute")
+    ),
+    responses(
+        (status = 200, description = "Whether the mute operation was successful")
+    ),
+    security(
+        ("api_key" = ["mute"]),
+        ("bearer" = ["mute"])
+    )
+)]
+#[put("/check/<target_check>/mute?<until>")]
+pub async fn mute_check(
+    _key: ApiKey<Mute>,
+    client: KubeClient,
+    checks: &State<SharedPinglowChecks>,
+    pinglow_config: &State<PinglowConfig>,
+    target_check: &str,
+    until: Option<String>,
+) -> Result<(), status::Custom<String>> {
+    // Ensure we can find the target check
+    if !checks.contains_key(target_check) {
+        return Err(status::Custom(
+            Status::NotFound,
+            "Invalid target check".into(),
+        ));
+    }
+
+    // Prepare the patch object
+    let now = Utc::now();
+    let mut patch = serde_json::json!({
+        "spec": {
+            "muteNotifications": true,
+            "muteNotificationsSince": now.to_rfc3339()
+        }
+    });
+
+    // If until is specified try to parse it and set it in the patch object
+    let mut until_date_time: Option<DateTime<FixedOffset>> = None;
+    if let Some(until) = until {
+        match chrono::DateTime::parse_from_rfc3339(&until) {
+            Ok(until) => {
+                until_date_time = Some(until);
+                if let Some(spec) = patch.get_mut("spec").and_then(Value::as_object_mut) {
+                    spec.insert(
+                        "muteNotificationsUntil".to_string(),
+                        json!(until.to_rfc3339()),
+                    );
+                }
+            }
+            Err(e) => {
+                return Err(status::Custom(
+                    Status::BadRequest,
+                    format!("Invalid datetime format: {e}"),
+                ))
+            }
+        }
+    }
+
+    patch_check(&client, checks, pinglow_config, target_check, &patch)
+        .await
+        .map_err(|e| {
             status::Custom(
                 Status::InternalServerError,
                 format!("Error setting mute status: {e}"),
             )
         })?;
 
-    let check = runnable_checks.get(target_check);
+    if let Some(mut check) = checks.get_mut(target_check) {
+        let modified_check = Arc::make_mut(check.value_mut());
+        modified_check.mute_notifications = Some(true);
+        modified_check.mute_notifications_since = Some(now);
+
+        if let Some(until_date_time) = until_date_time {
+            modified_check.mute_notifications_until = Some(until_date_time.into());
+        }
+    }
+
+    Ok(())
+}
+
+#[utoipa::path(
+    delete,
+    path = "/check/{target_check}/mute",
+     params(
+        ("target_check" = String, Path, description = "The check we would like to unmute")
+    ),
+    responses(
+        (status = 200, description = "Whether the unmute operation was successful")
+    ),
+    security(
+        ("api_key" = ["mute"]),
+        ("bearer" = ["mute"])
+    )
+)]
+#[delete("/check/<target_check>/mute")]
+pub async fn unmute_check(
+    _key: ApiKey<Mute>,
+    client: KubeClient,
+    checks: &State<SharedPinglowChecks>,
+    pinglow_config: &State<PinglowConfig>,
+    target_check: &str,
+) -> Result<(), status::Custom<String>> {
+    // Ensure we can find the target check
+    if !checks.contains_key(target_check) {
+        return Err(status::Custom(
+            Status::NotFound,
+            "Invalid target check".into(),
+        ));
+    }
+
+    // Prepare the patch object
+    let patch = serde_json::json!({
+        "spec": {
+            "muteNotifications": false,
+            "muteNotificationsUntil": null,
+            "muteNotificationsSince": null
+        }
+    });
+
+    patch_check(&client, checks, pinglow_config, target_check, &patch)
+        .await
+        .map_err(|e| {
+            status::Custom(
+                Status::InternalServerError,
+                format!("Error setting unmute status: {e}"),
+            )
+        })?;
+
+    if let Some(mut check) = checks.get_mut(target_check) {
+        let modified_check = Arc::make_mut(check.value_mut());
+        modified_check.mute_notifications = Some(false);
+        modified_check.mute_notifications_until = None;
+        modified_check.mute_notifications_since = None;
+    }
+
+    Ok(())
+}
+
+#[utoipa::path(
+    put,
+    path = "/check/{target_check}/suspend",
+     params(
+        ("target_check" = String, Path, description = "The check we would like to stop scheduling")
+    ),
+    responses(
+        (status = 200, description = "Whether the suspend operation was successful")
+    ),
+    security(
+        ("api_key" = ["mute"]),
+        ("bearer" = ["mute"])
+    )
+)]
+#[put("/check/<target_check>/suspend")]
+pub async fn suspend_check(
+    _key: ApiKey<Mute>,
+    client: KubeClient,
+    checks: &State<SharedPinglowChecks>,
+    pinglow_config: &State<PinglowConfig>,
+    target_check: &str,
+) -> Result<(), status::Custom<String>> {
+    set_suspended(client, checks, pinglow_config, target_check, true).await
+}
+
+#[utoipa::path(
+    delete,
+    path = "/check/{target_check}/suspend",
+     params(
+        ("target_check" = String, Path, description = "The check we would like to schedule again")
+    ),
+    responses(
+        (status = 200, description = "Whether the resume operation was successful")
+    ),
+    security(
+        ("api_key" = ["mute"]),
+        ("bearer" = ["mute"])
+    )
+)]
+#[delete("/check/<target_check>/suspend")]
+pub async fn resume_check(
+    _key: ApiKey<Mute>,
+    client: KubeClient,
+    checks: &State<SharedPinglowChecks>,
+    pinglow_config: &State<PinglowConfig>,
+    target_check: &str,
+) -> Result<(), status::Custom<String>> {
+    set_suspended(client, checks, pinglow_config, target_check, false).await
+}
+
+/**
+ * This function sets the suspend field of a check. The scheduler picks the change up through
+ * the reconciliation of the check, taking it out of the queue or scheduling it again
+ */
+async fn set_suspended(
+    client: KubeClient,
+    checks: &State<SharedPinglowChecks>,
+    pinglow_config: &State<PinglowConfig>,
+    target_check: &str,
+    suspend: bool,
+) -> Result<(), status::Custom<String>> {
+    // Ensure we can find the target check
+    if !checks.contains_key(target_check) {
+        return Err(status::Custom(
+            Status::NotFound,
+            "Invalid target check".into(),
+        ));
+    }
+
+    let patch = serde_json::json!({
+        "spec": {
+            "suspend": suspend
+        }
+    });
+
+    patch_check(&client, checks, pinglow_config, target_check, &patch)
+        .await
+        .map_err(|e| {
+            status::Custom(
+                Status::InternalServerError,
+                format!("Error setting suspend status: {e}"),
+            )
+        })?;
+
+    if let Some(mut check) = checks.get_mut(target_check) {
+        Arc::make_mut(check.value_mut()).suspended = suspend;
+    }
 
-    if let Some(check) = check {
-        let mut modified_check = (**check).clone();
-        modified_check.mute_notifications = Some(true);
+    Ok(())
+}
+
+/**
+ * This function returns the names of the checks matching a bulk selector, made of comma separated
+ * label selectors (e.g. team=payments,env=prod). An empty selector is rejected, as it would
+ * affect all the checks
+ */
+fn select_checks(
+    checks: &SharedPinglowChecks,
+    selector: &str,
+) -> Result<Vec<String>, status::Custom<String>> {
+    let selectors: Vec<&str> = selector
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .collect();
+
+    if selectors.is_empty() {
+        return Err(status::Custom(
+            Status::BadRequest,
+            "A label selector is required".into(),
+        ));
+    }
+
+    let mut check_names: Vec<String> = checks
+        .iter()
+        .filter(|check| matches_labels(&check.labels, &selectors))
+        .map(|check| check.key().clone())
+        .collect();
+    check_names.sort();
+
+    Ok(check_names)
+}
+
+/**
+ * This function applies a merge patch to the Check resource of a check, found in its namespace
+ */
+async fn patch_check(
+    client: &kube::Client,
+    checks: &SharedPinglowChecks,
+    pinglow_config: &PinglowConfig,
+    check_name: &str,
+    patch: &Value,
+) -> Result<Check, kube::Error> {
+    let (namespace, name) = match checks.get(check_name) {
+        Some(check) => (
+            check
+                .namespace
+                .clone()
+                .unwrap_or_else(|| pinglow_config.target_namespace.clone()),
+            check.resource_name().to_string(),
+        ),
+        None => (
+            pinglow_config.target_namespace.clone(),
+            check_name.to_string(),
+        ),
+    };
+
+    let checks_api: Api<Check> = Api::namespaced(client.clone(), &namespace);
+
+    checks_api
+        .patch(
+            &name,
+            &kube::api::PatchParams::apply("pinglow"),
+            &kube::api::Patch::Merge(patch),
+        )
+        .await
+}
+
+/**
+ * This function applies the same merge patch to many checks, reflecting it on the loaded checks
+ * through update, and returns an error listing the checks which could not be patched
+ */
+async fn patch_checks(
+    client: &kube::Client,
+    checks: &SharedPinglowChecks,
+    pinglow_config: &PinglowConfig,
+    check_names: &[String],
+    patch: &Value,
+    update: impl Fn(&mut PinglowCheck),
+) -> Result<(), status::Custom<String>> {
+    let mut failures = Vec::new();
+    for check_name in check_names {
+        if let Err(e) = patch_check(client, checks, pinglow_config, check_name, patch).await {
+            error!("Error patching check {check_name}: {e}");
+            failures.push(format!("{check_name}: {e}"));
+        } else if let Some(mut check) = checks.get_mut(check_name) {
+            update(Arc::make_mut(check.value_mut()));
+        }
+    }
+
+    if !failures.is_empty() {
+        return Err(status::Custom(
+            Status::InternalServerError,
+            format!("Error patching some checks: {}", failures.join("; ")),
+        ));
+    }
+
+    Ok(())
+}
+
+#[utoipa::path(
+    put,
+    path = "/checks/mute",
+    params(
+        ("selector" = String, Query, description = "Comma separated label selectors (key=value or key) the checks must all match, e.g. team=payments"),
+        ("until" = Option<String>, Query, description = "RFC 3339 timestamp indicating when the mute expires")
+    ),
+    responses(
+        (status = 200, description = "The names of the muted checks", body = [String]),
+        (status = 400, description = "Missing selector or invalid until timestamp")
+    ),
+    security(
+        ("api_key" = ["mute"]),
+        ("bearer" = ["mute"])
+    )
+)]
+#[put("/checks/mute?<selector>&<until>")]
+pub async fn bulk_mute_checks(
+    _key: ApiKey<Mute>,
+    client: KubeClient,
+    checks: &State<SharedPinglowChecks>,
+    pinglow_config: &State<PinglowConfig>,
+    selector: &str,
+    until: Option<String>,
+) -> Result<Json<Vec<String>>, status::Custom<String>> {
+    let until_date_time = until
+        .map(|until| chrono::DateTime::parse_from_rfc3339(&until))
+        .transpose()
+        .map_err(|e| status::Custom(Status::BadRequest, format!("Invalid datetime format: {e}")))?;
+
+    let check_names = select_checks(checks, selector)?;
+
+    let now = Utc::now();
+    let patch = serde_json::json!({
+        "spec": {
+            "muteNotifications": true,
+            "muteNotificationsUntil": until_date_time.map(|until| until.to_rfc3339()),
+            "muteNotificationsSince": now.to_rfc3339()
+        }
+    });
+
+    patch_checks(
+        &client,
+        checks,
+        pinglow_config,
+        &check_names,
+        &patch,
+        |check| {
+            check.mute_notifications = Some(true);
+            check.mute_notifications_until = until_date_time.map(Into::into);
+            check.mute_notifications_since = Some(now);
+        },
+    )
+    .await?;
+
+    Ok(Json(check_names))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/checks/mute",
+    params(
+        ("selector" = String, Query, description = "Comma separated label selectors (key=value or key) the checks must all match, e.g. team=payments")
+    ),
+    responses(
+        (status = 200, description = "The names of the unmuted checks", body = [String]),
+        (status = 400, description = "Missing selector")
+    ),
+    security(
+        ("api_key" = ["mute"]),
+        ("bearer" = ["mute"])
+    )
+)]
+#[delete("/checks/mute?<selector>")]
+pub async fn bulk_unmute_checks(
+    _key: ApiKey<Mute>,
+    client: KubeClient,
+    checks: &State<SharedPinglowChecks>,
+    pinglow_config: &State<PinglowConfig>,
+    selector: &str,
+) -> Result<Json<Vec<String>>, status::Custom<String>> {
+    let check_names = select_checks(checks, selector)?;
+
+    let patch = serde_json::json!({
+        "spec": {
+            "muteNotifications": false,
+            "muteNotificationsUntil": null,
+            "muteNotificationsSince": null
+        }
+    });
+
+    patch_checks(
+        &client,
+        checks,
+        pinglow_config,
+        &check_names,
+        &patch,
+        |check| {
+            check.mute_notifications = Some(false);
+            check.mute_notifications_until = None;
+            check.mute_notifications_since = None;
+        },
+    )
+    .await?;
+
+    Ok(Json(check_names))
+}
+
+#[utoipa::path(
+    put,
+    path = "/check/{target_check}/redirect?<channel>&<until>",
+     params(
+        ("target_check" = String, Path, description = "The check whose notifications we would like to redirect"),
+        ("channel" = String, Query, description = "The TelegramChannel which should receive the notifications"),
+        ("until" = String, Query, description = "RFC 3339 timestamp indicating when the redirect expires")
+    ),
+    responses(
+        (status = 200, description = "Whether the redirect operation was successful")
+    ),
+    security(
+        ("api_key" = ["mute"]),
+        ("bearer" = ["mute"])
+    )
+)]
+#[put("/check/<target_check>/redirect?<channel>&<until>")]
+#[allow(clippy::too_many_arguments)]
+pub async fn redirect_check(
+    _key: ApiKey<Mute>,
+    client: KubeClient,
+    checks: &State<SharedPinglowChecks>,
+    redirects: &State<SharedRedirects>,
+    secret_cache: &State<SharedSecrets>,
+    pinglow_config: &State<PinglowConfig>,
+    target_check: &str,
+    channel: &str,
+    until: &str,
+) -> Result<(), status::Custom<String>> {
+    // Ensure we can find the target check
+    if !checks.contains_key(target_check) {
+        return Err(status::Custom(
+            Status::NotFound,
+            "Invalid target check".into(),
+        ));
+    }
+
+    // The redirect must be bounded in time
+    let until: DateTime<Utc> = chrono::DateTime::parse_from_rfc3339(until)
+        .map_err(|e| status::Custom(Status::BadRequest, format!("Invalid datetime format: {e}")))?
+        .into();
+
+    if until <= Utc::now() {
+        return Err(status::Custom(
+            Status::BadRequest,
+            "The redirect must end in the future".into(),
+        ));
+    }
+
+    // The channel is resolved in the namespace of the check
+    let namespace = checks
+        .get(target_check)
+        .and_then(|check| check.namespace.clone())
+        .unwrap_or_else(|| pinglow_config.target_namespace.clone());
+
+    let channel =
+        resolve_telegram_channel(channel, &namespace, &client, pinglow_config, secret_cache)
+            .await
+            .map_err(|e| status::Custom(Status::BadRequest, format!("Invalid channel: {e}")))?;
+
+    redirects.insert(
+        target_check.to_string(),
+        NotificationRedirect { channel, until },
+    );
+
+    Ok(())
+}
+
+#[utoipa::path(
+    delete,
+    path = "/check/{target_check}/redirect",
+     params(
+        ("target_check" = String, Path, description = "The check whose notifications redirect we would like to remove")
+    ),
+    responses(
+        (status = 200, description = "Whether the redirect was removed")
+    ),
+    security(
+        ("api_key" = ["mute"]),
+        ("bearer" = ["mute"])
+    )
+)]
+#[delete("/check/<target_check>/redirect")]
+pub async fn remove_check_redirect(
+    _key: ApiKey<Mute>,
+    redirects: &State<SharedRedirects>,
+    target_check: &str,
+) -> Result<(), status::Custom<String>> {
+    redirects.remove(target_check).ok_or(status::Custom(
+        Status::NotFound,
+        "No redirect set for the target check".into(),
+    ))?;
+
+    Ok(())
+}
+
+// Window of the rate limit of the endpoints receiving results
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(60);
+
+// Requests received from every client in the current window, by hash of its verified credential or
+// of its address
+static RATE_LIMIT_WINDOWS: LazyLock<DashMap<[u8; 32], (Instant, u32)>> =
+    LazyLock::new(DashMap::new);
+
+/**
+ * This function drops the expired windows of the rate limit, once per window
+ */
+async fn evict_rate_limit_windows() {
+    let mut interval = tokio::time::interval(RATE_LIMIT_WINDOW);
+
+    loop {
+        interval.tick().await;
+        RATE_LIMIT_WINDOWS.retain(|_, (start, _)| start.elapsed() < RATE_LIMIT_WINDOW);
+    }
+}
+
+/// A request within the API_RATE_LIMIT of its client, identified by its API key or bearer token, by
+/// the check of a heartbeat once the credential or token is verified or, failing that, by its address
+pub struct RateLimit;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RateLimit {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let Some(config) = request.rocket().state::<PinglowConfig>() else {
+            return Outcome::Error((Status::InternalServerError, ()));
+        };
+
+        // A limit of 0 disables the rate limiting
+        if config.api_rate_limit == 0 {
+            return Outcome::Success(RateLimit);
+        }
+
+        // Only the verified credentials identify a client, since made up ones would escape the
+        // limit. They are kept hashed. The heartbeats, which carry their token in the path, are
+        // told apart by their check, as the clients behind a proxy share its address
+        let mut client = Sha256::new();
+        let credential = request
+            .headers()
+            .get_one("x-api-key")
+            .or_else(|| request.headers().get_one("Authorization"));
+        let heartbeat = match (
+            request.param::<&str>(0).and_then(Result::ok),
+            request.param::<&str>(1).and_then(Result::ok),
+            request.rocket().state::<SharedPinglowChecks>(),
+        ) {
+            (Some(check_name), Some(token), Some(checks)) => checks
+                .get(check_name)
+                .and_then(|check| check.heartbeat_token.clone())
+                .filter(|expected| Sha256::digest(expected) == Sha256::digest(token))
+                .map(|_| check_name),
+            _ => None,
+        };
+
+        match (credential, heartbeat) {
+            (Some(credential), _)
+                if ApiKey::<SubmitResult>::from_request(request)
+                    .await
+                    .is_success() =>
+            {
+                client.update(credential)
+            }
+            (_, Some(check_name)) => client.update(format!("heartbeat/{check_name}")),
+            _ => client.update(
+                request
+                    .client_ip()
+                    .map(|ip| ip.to_string())
+                    .unwrap_or_default(),
+            ),
+        }
+        let client: [u8; 32] = client.finalize().into();
+
+        let mut window = RATE_LIMIT_WINDOWS
+            .entry(client)
+            .or_insert((Instant::now(), 0));
+
+        if window.0.elapsed() >= RATE_LIMIT_WINDOW {
+            *window = (Instant::now(), 0);
+        }
+
+        if window.1 >= config.api_rate_limit {
+            return Outcome::Error((Status::TooManyRequests, ()));
+        }
+
+        window.1 += 1;
+        Outcome::Success(RateLimit)
+    }
+}
+
+/// Authentication of a passive result: either the global API key or an HMAC signature
+/// of the payload computed with the shared key of the check
+pub enum ResultAuth {
+    ApiKey,
+    Signature {
+        timestamp: i64,
+        nonce: String,
+        signature: String,
+    },
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ResultAuth {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        if let Outcome::Success(_) = ApiKey::<SubmitResult>::from_request(request).await {
+            return Outcome::Success(ResultAuth::ApiKey);
+        }
+
+        let timestamp = request
+            .headers()
+            .get_one("x-pinglow-timestamp")
+            .and_then(|t| t.parse().ok());
+        let nonce = request.headers().get_one("x-pinglow-nonce");
+        let signature = request.headers().get_one("x-pinglow-signature");
+
+        match (timestamp, nonce, signature) {
+            (Some(timestamp), Some(nonce), Some(signature)) => {
+                Outcome::Success(ResultAuth::Signature {
+                    timestamp,
+                    nonce: nonce.to_string(),
+                    signature: signature.to_string(),
+                })
+            }
+            _ => Outcome::Error((Status::Unauthorized, ())),
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/check/{target_check}/result",
+     params(
+        ("target_check" = String, Path, description = "The check for which we would like to send a result")
+    ),
+    responses(
+        (status = 200, description = "Whether the processing of the check result was successful"),
+        (status = 413, description = "The payload exceeds API_MAX_PAYLOAD_BYTES"),
+        (status = 429, description = "The client exceeded API_RATE_LIMIT")
+    ),
+    security(
+        ("api_key" = ["submit-result"]),
+        ("bearer" = ["submit-result"]),
+        ("hmac_signature" = [], "hmac_timestamp" = [], "hmac_nonce" = [])
+    )
+)]
+#[post("/check/<target_check>/result", data = "<check_result_payload>")]
+#[allow(clippy::too_many_arguments)]
+pub async fn process_check_result(
+    _rate_limit: RateLimit,
+    auth: ResultAuth,
+    checks: &State<SharedPinglowChecks>,
+    db_pool: &State<Pool>,
+    redis_client: &State<redis::Client>,
+    http_client: &State<reqwest::Client>,
+    redirects: &State<SharedRedirects>,
+    sinks: &State<Arc<ResultSinks>>,
+    limits: &Limits,
+    target_check: &str,
+    check_result_payload: Data<'_>,
+) -> Result<(), status::Custom<String>> {
+    // Ensure we can find the target check, without holding a reference into the map
+    let check = checks
+        .get(target_check)
+        .map(|check| check.value().clone())
+        .ok_or(status::Custom(
+            Status::NotFound,
+            "Invalid target check".into(),
+        ))?;
+
+    // Read the raw payload, needed to verify its signature
+    let check_result_payload = check_result_payload
+        .open(limits.get("json").unwrap_or(Limits::JSON))
+        .into_string()
+        .await
+        .map_err(|e| status::Custom(Status::BadRequest, format!("Cannot read payload: {e}")))?;
+
+    if !check_result_payload.is_complete() {
+        return Err(status::Custom(
+            Status::PayloadTooLarge,
+            "The payload exceeds the maximum size".into(),
+        ));
+    }
+
+    if let ResultAuth::Signature {
+        timestamp,
+        nonce,
+        signature,
+    } = &auth
+    {
+        let valid = check.hmac_key.as_ref().is_some_and(|key| {
+            auth::verify_result_signature(key, *timestamp, nonce, signature, &check_result_payload)
+        });
+
+        if !valid {
+            return Err(status::Custom(
+                Status::Unauthorized,
+                "Invalid signature".into(),
+            ));
+        }
+
+        // A signed result is accepted once, the same nonce being rejected within its validity
+        let claimed = match redis_client.get_multiplexed_async_connection().await {
+            Ok(mut conn) => auth::claim_nonce(&mut conn, target_check, nonce).await,
+            Err(e) => Err(e),
+        }
+        .map_err(|e| {
+            status::Custom(
+                Status::ServiceUnavailable,
+                format!("Cannot record the nonce of the signature: {e}"),
+            )
+        })?;
+
+        if !claimed {
+            return Err(status::Custom(
+                Status::Unauthorized,
+                "The nonce has already been used".into(),
+            ));
+        }
+    }
+
+    // Extract the inner result object
+    let check_result_payload: ProcessCheckResultPayload =
+        serde_json::from_str(&check_result_payload)
+            .map_err(|e| status::Custom(Status::BadRequest, format!("Invalid payload: {e}")))?;
+
+    // Create the actual full check result
+    let check_result: CheckResult = CheckResult {
+        check_name: target_check.to_owned(),
+        output: check_result_payload.output,
+        status: check_result_payload.status.into(),
+        timestamp: Some(Utc::now()),
+        telegram_channels: check.telegram_channels.clone().into(),
+        mute_notifications: check.mute_notifications,
+        mute_notifications_until: check.mute_notifications_until,
+        maintenance_windows: check.maintenance_windows.clone().into(),
+        webhooks: check.webhooks.clone().into(),
+        diagnostics: None,
+        slack_channels: check.slack_channels.clone().into(),
+        push_channels: check.push_channels.clone().into(),
+        discord_channels: check.discord_channels.clone().into(),
+        notify_on_state_change: check.notify_on_state_change,
+        notify_on_recovery: check.notify_on_recovery,
+        renotify_interval_minutes: check.renotify_interval_minutes,
+        min_notification_interval_seconds: check.min_notification_interval_seconds,
+        soft_failure: false,
+        depends_on: check.depends_on.clone().into(),
+        suppressed_by: None,
+        anomaly_sigma: check.anomaly_sigma,
+        script_hash: None,
+        run_id: None,
+    };
+    crate::process_or_buffer_check_result(
+        check_result,
+        check_result_payload.image_jpg_base64,
+        db_pool,
+        redis_client,
+        http_client,
+        redirects,
+        sinks,
+    )
+    .await
+    .map_err(|err| {
+        status::Custom(
+            Status::InternalServerError,
+            format!("Error processing check result: {err}"),
+        )
+    })?;
+
+    Ok(())
+}
+
+/**
+ * This function records an Ok result for a passive check whose heartbeat token matches,
+ * so that cron jobs and devices can report with a plain HTTP request
+ */
+#[allow(clippy::too_many_arguments)]
+async fn record_heartbeat(
+    checks: &SharedPinglowChecks,
+    db_pool: &Pool,
+    redis_client: &redis::Client,
+    http_client: &reqwest::Client,
+    redirects: &SharedRedirects,
+    sinks: &ResultSinks,
+    target_check: &str,
+    token: &str,
+) -> Result<(), status::Custom<String>> {
+    let check = checks
+        .get(target_check)
+        .map(|check| check.value().clone())
+        .filter(|check| check.passive)
+        .ok_or(status::Custom(
+            Status::NotFound,
+            "Invalid target check".into(),
+        ))?;
 
-        if let Some(until_date_time) = until_date_time {
-            modified_check.mute_notifications_until = Some(until_date_time.into());
-        }
+    // Compare the digests, so that the comparison time does not depend on the token
+    let valid = check
+        .heartbeat_token
+        .as_ref()
+        .is_some_and(|expected| Sha256::digest(expected) == Sha256::digest(token));
 
-        runnable_checks.insert(target_check.to_string(), Arc::new(modified_check));
+    if !valid {
+        return Err(status::Custom(
+            Status::Unauthorized,
+            "Invalid heartbeat token".into(),
+        ));
     }
 
-    Ok(())
+    let check_result = CheckResult {
+        check_name: target_check.to_owned(),
+        output: "Heartbeat received".to_string(),
+        status: CheckResultStatus::Ok,
+        timestamp: Some(Utc::now()),
+        telegram_channels: check.telegram_channels.clone().into(),
+        mute_notifications: check.mute_notifications,
+        mute_notifications_until: check.mute_notifications_until,
+        maintenance_windows: check.maintenance_windows.clone().into(),
+        webhooks: check.webhooks.clone().into(),
+        diagnostics: None,
+        slack_channels: check.slack_channels.clone().into(),
+        push_channels: check.push_channels.clone().into(),
+        discord_channels: check.discord_channels.clone().into(),
+        notify_on_state_change: check.notify_on_state_change,
+        notify_on_recovery: check.notify_on_recovery,
+        renotify_interval_minutes: check.renotify_interval_minutes,
+        min_notification_interval_seconds: check.min_notification_interval_seconds,
+        soft_failure: false,
+        depends_on: check.depends_on.clone().into(),
+        suppressed_by: None,
+        anomaly_sigma: check.anomaly_sigma,
+        script_hash: None,
+        run_id: None,
+    };
+
+    crate::process_or_buffer_check_result(
+        check_result,
+        None,
+        db_pool,
+        redis_client,
+        http_client,
+        redirects,
+        sinks,
+    )
+    .await
+    .map_err(|err| {
+        status::Custom(
+            Status::InternalServerError,
+            format!("Error processing heartbeat: {err}"),
+        )
+    })
 }
 
 #[utoipa::path(
-    delete,
-    path = "/check/{target_check}/mute",
-     params(
-        ("target_check" = String, Path, description = "The check we would like to unmute")
+    get,
+    path = "/heartbeat/{target_check}/{token}",
+    params(
+        ("target_check" = String, Path, description = "The passive check sending the heartbeat"),
+        ("token" = String, Path, description = "The heartbeat token of the check")
     ),
     responses(
-        (status = 200, description = "Whether the unmute operation was successful")
+        (status = 200, description = "The heartbeat has been recorded as an Ok result"),
+        (status = 401, description = "Invalid heartbeat token"),
+        (status = 404, description = "No passive check with the given name"),
+        (status = 429, description = "The client exceeded API_RATE_LIMIT")
+    ),
+    security(())
+)]
+#[get("/heartbeat/<target_check>/<token>")]
+#[allow(clippy::too_many_arguments)]
+pub async fn get_heartbeat(
+    _rate_limit: RateLimit,
+    checks: &State<SharedPinglowChecks>,
+    db_pool: &State<Pool>,
+    redis_client: &State<redis::Client>,
+    http_client: &State<reqwest::Client>,
+    redirects: &State<SharedRedirects>,
+    sinks: &State<Arc<ResultSinks>>,
+    target_check: &str,
+    token: &str,
+) -> Result<(), status::Custom<String>> {
+    record_heartbeat(
+        checks,
+        db_pool,
+        redis_client,
+        http_client,
+        redirects,
+        sinks,
+        target_check,
+        token,
     )
+    .await
+}
+
+#[utoipa::path(
+    post,
+    path = "/heartbeat/{target_check}/{token}",
+    params(
+        ("target_check" = String, Path, description = "The passive check sending the heartbeat"),
+        ("token" = String, Path, description = "The heartbeat token of the check")
+    ),
+    responses(
+        (status = 200, description = "The heartbeat has been recorded as an Ok result"),
+        (status = 401, description = "Invalid heartbeat token"),
+        (status = 404, description = "No passive check with the given name"),
+        (status = 429, description = "The client exceeded API_RATE_LIMIT")
+    ),
+    security(())
 )]
-#[delete("/check/<target_check>/mute")]
-pub async fn unmute_check(
-    _key: ApiKey,
+#[post("/heartbeat/<target_check>/<token>")]
+#[allow(clippy::too_many_arguments)]
+pub async fn post_heartbeat(
+    _rate_limit: RateLimit,
     checks: &State<SharedPinglowChecks>,
-    pinglow_config: &State<PinglowConfig>,
+    db_pool: &State<Pool>,
+    redis_client: &State<redis::Client>,
+    http_client: &State<reqwest::Client>,
+    redirects: &State<SharedRedirects>,
+    sinks: &State<Arc<ResultSinks>>,
     target_check: &str,
+    token: &str,
 ) -> Result<(), status::Custom<String>> {
-    // Read actual shared checks
-    let mut runnable_checks = checks.write().await;
+    record_heartbeat(
+        checks,
+        db_pool,
+        redis_client,
+        http_client,
+        redirects,
+        sinks,
+        target_check,
+        token,
+    )
+    .await
+}
 
-    // Ensure we can find the target check
-    runnable_checks
-        .iter()
-        .find(|&check| check.0 == target_check)
-        .ok_or(status::Custom(
-            Status::NotFound,
-            "Invalid target check".into(),
-        ))?;
+#[utoipa::path(
+    get,
+    path = "/calendar.ics",
+    responses(
+        (status = 200, description = "iCalendar document with the maintenance windows and the active notification mutes", content_type = "text/calendar", body = String)
+    )
+)]
+#[get("/calendar.ics")]
+pub async fn get_calendar(
+    _key: ApiKey,
+    checks: &State<SharedPinglowChecks>,
+) -> (ContentType, String) {
+    let checks: Vec<Arc<PinglowCheck>> = checks.iter().map(|check| check.value().clone()).collect();
 
-    // Prepare the patch object
-    let patch = serde_json::json!({
-        "spec": {
-            "muteNotifications": false,
-            "muteNotificationsUntil": null
-        }
-    });
+    let calendar = calendar_to_ical(checks.iter().map(|check| check.as_ref()), Utc::now());
 
-    // Get the checks Kube Api
-    let client = kube::Client::try_default().await.map_err(|e| {
-        status::Custom(
-            Status::InternalServerError,
-            format!("Error retrieving the Kube client: {e}"),
-        )
-    })?;
-    let checks_api: Api<Check> = Api::namespaced(client.clone(), &pinglow_config.target_namespace);
+    (ContentType::Calendar, calendar)
+}
 
-    checks_api
-        .patch(
-            target_check,
-            &kube::api::PatchParams::apply("pinglow"),
-            &kube::api::Patch::Merge(&patch),
-        )
-        .await
-        .map_err(|e| {
-            status::Custom(
-                Status::InternalServerError,
-                format!("Error setting unmute status: {e}"),
-            )
-        })?;
+#[derive(Serialize, ToSchema, Debug)]
+pub struct ImportItemResultDto {
+    pub kind: String,
+    pub name: String,
+    pub applied: bool,
+    pub error: Option<String>,
+}
 
-    let check = runnable_checks.get(target_check);
+enum ImportItem {
+    Script(Box<Script>),
+    Check(Box<Check>),
+}
 
-    if let Some(check) = check {
-        let mut modified_check = (**check).clone();
-        modified_check.mute_notifications = Some(false);
-        modified_check.mute_notifications_until = None;
+impl ImportItem {
+    fn kind(&self) -> &'static str {
+        match self {
+            ImportItem::Script(_) => "Script",
+            ImportItem::Check(_) => "Check",
+        }
+    }
+
+    fn name(&self) -> String {
+        match self {
+            ImportItem::Script(script) => script.name_any(),
+            ImportItem::Check(check) => check.name_any(),
+        }
+    }
+}
 
-        runnable_checks.insert(target_check.to_string(), Arc::new(modified_check));
+/**
+ * This function parses a multi-document YAML (or JSON) payload into Check and Script definitions,
+ * forcing them in the target namespace
+ */
+fn parse_import_definitions(definitions: &str, namespace: &str) -> Vec<Result<ImportItem, String>> {
+    let mut documents = vec![];
+
+    for document in serde_yaml::Deserializer::from_str(definitions) {
+        match serde_yaml::Value::deserialize(document) {
+            // A JSON array (or YAML sequence) can hold several definitions
+            Ok(serde_yaml::Value::Sequence(values)) => documents.extend(values.into_iter().map(Ok)),
+            Ok(serde_yaml::Value::Null) => {}
+            Ok(value) => documents.push(Ok(value)),
+            Err(e) => documents.push(Err(format!("Invalid document: {e}"))),
+        }
     }
 
-    Ok(())
+    documents
+        .into_iter()
+        .map(|document| {
+            let document = document?;
+
+            let kind = document
+                .get("kind")
+                .and_then(serde_yaml::Value::as_str)
+                .unwrap_or_default()
+                .to_string();
+
+            let mut item = match kind.as_str() {
+                "Script" => serde_yaml::from_value(document)
+                    .map(|script| ImportItem::Script(Box::new(script))),
+                "Check" => {
+                    serde_yaml::from_value(document).map(|check| ImportItem::Check(Box::new(check)))
+                }
+                _ => return Err(format!("Unsupported kind '{kind}'")),
+            }
+            .map_err(|e| format!("Invalid {kind}: {e}"))?;
+
+            let metadata = match &mut item {
+                ImportItem::Script(script) => &mut script.metadata,
+                ImportItem::Check(check) => &mut check.metadata,
+            };
+
+            if metadata.name.is_none() {
+                return Err(format!("Missing name for {kind}"));
+            }
+
+            match &metadata.namespace {
+                Some(item_namespace) if item_namespace != namespace => {
+                    return Err(format!("{kind} must belong to the namespace '{namespace}'"))
+                }
+                _ => metadata.namespace = Some(namespace.to_string()),
+            }
+
+            if let ImportItem::Script(script) = &item {
+                validate_script(&script.spec)?;
+            }
+
+            Ok(item)
+        })
+        .collect()
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-pub struct ProcessCheckResultPayload {
-    output: String,
-    status: i32,
-    #[serde(default)]
-    image_jpg_base64: Option<String>, // Base64 image
+async fn apply_import_item(
+    item: &ImportItem,
+    client: &kube::Client,
+    namespace: &str,
+    dry_run: bool,
+) -> Result<(), kube::Error> {
+    let mut params = PatchParams::apply("pinglow").force();
+    if dry_run {
+        params = params.dry_run();
+    }
+
+    match item {
+        ImportItem::Script(script) => {
+            let api: Api<Script> = Api::namespaced(client.clone(), namespace);
+            api.patch(&item.name(), &params, &Patch::Apply(script.as_ref()))
+                .await?;
+        }
+        ImportItem::Check(check) => {
+            let api: Api<Check> = Api::namespaced(client.clone(), namespace);
+            api.patch(&item.name(), &params, &Patch::Apply(check.as_ref()))
+                .await?;
+        }
+    }
+
+    Ok(())
 }
 
 #[utoipa::path(
     post,
-    path = "/check/{target_check}/result",
-     params(
-        ("target_check" = String, Path, description = "The check for which we would like to send a result")
+    path = "/checks/import",
+    request_body(content = String, description = "Multi-document YAML or JSON with Check and Script definitions", content_type = "application/yaml"),
+    responses(
+        (status = 200, description = "All the definitions were applied", body = [ImportItemResultDto]),
+        (status = 422, description = "Some definitions are invalid, nothing was applied", body = [ImportItemResultDto]),
+        (status = 500, description = "Some definitions could not be applied", body = [ImportItemResultDto])
     ),
+    security(
+        ("api_key" = ["admin"]),
+        ("bearer" = ["admin"])
+    )
+)]
+#[post("/checks/import", data = "<definitions>")]
+pub async fn import_checks(
+    _key: ApiKey<Admin>,
+    client: KubeClient,
+    pinglow_config: &State<PinglowConfig>,
+    definitions: Data<'_>,
+) -> Result<status::Custom<Json<Vec<ImportItemResultDto>>>, status::Custom<String>> {
+    let definitions = definitions
+        .open(5.mebibytes())
+        .into_string()
+        .await
+        .map_err(|e| status::Custom(Status::BadRequest, format!("Cannot read payload: {e}")))?;
+
+    if !definitions.is_complete() {
+        return Err(status::Custom(
+            Status::PayloadTooLarge,
+            "The payload exceeds the maximum size".into(),
+        ));
+    }
+
+    let namespace = &pinglow_config.target_namespace;
+
+    let parsed = parse_import_definitions(&definitions, namespace);
+
+    // Nothing is applied if any of the definitions is invalid
+    if parsed.iter().any(Result::is_err) {
+        let report = parsed
+            .iter()
+            .map(|item| match item {
+                Ok(item) => ImportItemResultDto {
+                    kind: item.kind().to_string(),
+                    name: item.name(),
+                    applied: false,
+                    error: None,
+                },
+                Err(e) => ImportItemResultDto {
+                    kind: String::new(),
+                    name: String::new(),
+                    applied: false,
+                    error: Some(e.clone()),
+                },
+            })
+            .collect();
+
+        return Ok(status::Custom(Status::UnprocessableEntity, Json(report)));
+    }
+
+    // Scripts are applied first, since checks reference them
+    let mut items: Vec<ImportItem> = parsed.into_iter().filter_map(Result::ok).collect();
+    items.sort_by_key(|item| matches!(item, ImportItem::Check(_)));
+
+    // Get the Kube client
+    // Validate every definition against the API server first (dry run)
+    let mut report = Vec::with_capacity(items.len());
+    for item in items.iter() {
+        let error = apply_import_item(item, &client, namespace, true)
+            .await
+            .err()
+            .map(|e| e.to_string());
+
+        report.push(ImportItemResultDto {
+            kind: item.kind().to_string(),
+            name: item.name(),
+            applied: false,
+            error,
+        });
+    }
+
+    if report.iter().any(|item| item.error.is_some()) {
+        return Ok(status::Custom(Status::UnprocessableEntity, Json(report)));
+    }
+
+    // Actually apply the definitions
+    for (item, item_report) in items.iter().zip(report.iter_mut()) {
+        match apply_import_item(item, &client, namespace, false).await {
+            Ok(()) => item_report.applied = true,
+            Err(e) => item_report.error = Some(e.to_string()),
+        }
+    }
+
+    let status = if report.iter().all(|item| item.applied) {
+        Status::Ok
+    } else {
+        Status::InternalServerError
+    };
+
+    Ok(status::Custom(status, Json(report)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/queue-backlog",
     responses(
-        (status = 200, description = "Whether the processing of the check result was successful")
+        (status = 200, description = "The checks waiting for a runner, to scale the runners on", body = QueueBacklog)
     )
 )]
-#[post("/check/<target_check>/result", data = "<check_result_payload>")]
-pub async fn process_check_result(
+#[get("/queue-backlog")]
+pub async fn get_queue_backlog(
     _key: ApiKey,
-    checks: &State<SharedPinglowChecks>,
-    client: &State<Arc<Client>>,
-    target_check: &str,
-    check_result_payload: Json<ProcessCheckResultPayload>,
-) -> Result<(), status::Custom<String>> {
-    // Read actual shared checks
-    let runnable_checks = checks.read().await;
+    redis_client: &State<redis::Client>,
+) -> Result<Json<QueueBacklog>, status::Custom<String>> {
+    let backlog = queue_backlog(redis_client).await.map_err(|e| {
+        status::Custom(
+            Status::InternalServerError,
+            format!("Error retrieving the queue backlog: {e}"),
+        )
+    })?;
 
-    // Ensure we can find the target check
-    let (_check_name, check) = runnable_checks
-        .iter()
-        .find(|&check| check.0 == target_check)
-        .ok_or(status::Custom(
-            Status::NotFound,
-            "Invalid target check".into(),
-        ))?;
+    Ok(Json(backlog))
+}
 
-    // Extract the inner result object
-    let check_result_payload = check_result_payload.into_inner();
+#[utoipa::path(
+    get,
+    path = "/runners",
+    responses(
+        (status = 200, description = "The runners with their last heartbeat and health", body = [RunnerDto])
+    )
+)]
+#[get("/runners")]
+pub async fn get_runners(
+    _key: ApiKey,
+    redis_client: &State<redis::Client>,
+) -> Result<Json<Vec<RunnerDto>>, status::Custom<String>> {
+    let runners = runners(redis_client).await.map_err(|e| {
+        status::Custom(
+            Status::InternalServerError,
+            format!("Error retrieving the runners: {e}"),
+        )
+    })?;
 
-    // Create the actual full check result
-    let check_result: CheckResult = CheckResult {
-        check_name: target_check.to_owned(),
-        output: check_result_payload.output,
-        status: check_result_payload.status.into(),
-        timestamp: Some(Utc::now()),
-        telegram_channels: check.telegram_channels.clone().into(),
-        mute_notifications: check.mute_notifications,
-        mute_notifications_until: check.mute_notifications_until,
-    };
-    let http_client = reqwest::Client::new();
+    Ok(Json(runners))
+}
 
-    crate::process_check_result(
-        check_result,
-        check_result_payload.image_jpg_base64,
-        client,
-        &http_client,
+#[utoipa::path(
+    get,
+    path = "/dead-letters",
+    responses(
+        (status = 200, description = "The number of checks and results moved to the dead-letter streams", body = DeadLetters)
     )
-    .await
-    .map_err(|err| {
+)]
+#[get("/dead-letters")]
+pub async fn get_dead_letters(
+    _key: ApiKey,
+    redis_client: &State<redis::Client>,
+) -> Result<Json<DeadLetters>, status::Custom<String>> {
+    let dead_letters = dead_letters(redis_client).await.map_err(|e| {
         status::Custom(
             Status::InternalServerError,
-            format!("Error processing check result: {err}"),
+            format!("Error retrieving the dead letters: {e}"),
         )
     })?;
 
-    Ok(())
+    Ok(Json(dead_letters))
+}
+
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    responses(
+        (status = 200, description = "The metrics of the controller in the Prometheus text format", content_type = "text/plain", body = String),
+        (status = 401, description = "The API key is required to scrape the metrics")
+    ),
+    security(())
+)]
+#[get("/metrics")]
+pub async fn get_metrics(
+    key: Option<ApiKey>,
+    config: &State<PinglowConfig>,
+    redis_client: &State<redis::Client>,
+) -> Result<(ContentType, String), status::Custom<String>> {
+    if config.metrics_require_api_key && key.is_none() {
+        return Err(status::Custom(
+            Status::Unauthorized,
+            "Missing or invalid API key".to_string(),
+        ));
+    }
+
+    // The queue depth is read at scrape time, a failure leaves the previous values
+    match queue_backlog(redis_client).await {
+        Ok(backlog) => metrics::set_queue_backlog(&backlog),
+        Err(e) => warn!("Error retrieving the queue backlog for the metrics: {e}"),
+    }
+
+    let metrics = metrics::render().map_err(|e| {
+        status::Custom(
+            Status::InternalServerError,
+            format!("Error encoding the metrics: {e}"),
+        )
+    })?;
+
+    Ok((
+        ContentType::new("text", "plain").with_params(("version", "0.0.4")),
+        metrics,
+    ))
+}
+
+#[utoipa::path(
+    get,
+    path = "/metrics/perf-data",
+    responses(
+        (status = 200, description = "The latest performance data of the checks, measured in the last day, as OpenMetrics gauges", content_type = "application/openmetrics-text", body = String),
+        (status = 401, description = "The API key is required to scrape the metrics")
+    ),
+    security(())
+)]
+#[get("/metrics/perf-data")]
+pub async fn get_perf_data_metrics(
+    key: Option<ApiKey>,
+    config: &State<PinglowConfig>,
+    checks: &State<SharedPinglowChecks>,
+    client: DbClient,
+) -> Result<(ContentType, String), status::Custom<String>> {
+    if config.metrics_require_api_key && key.is_none() {
+        return Err(status::Custom(
+            Status::Unauthorized,
+            "Missing or invalid API key".to_string(),
+        ));
+    }
+
+    let check_names: Vec<String> = checks.iter().map(|check| check.key().clone()).collect();
+
+    // Keys not measured in the last day are considered gone
+    let rows = client
+        .query(
+            "SELECT DISTINCT ON (check_name, perf_key) check_name, perf_key, perf_value, uom, timestamp FROM check_result_perf_data WHERE check_name = ANY($1) AND timestamp >= NOW() - INTERVAL '1 day' ORDER BY check_name, perf_key, timestamp DESC",
+            &[&check_names],
+        )
+        .await
+        .map_err(|e| {
+            status::Custom(
+                Status::InternalServerError,
+                format!("Error retrieving the latest performance data: {e}"),
+            )
+        })?;
+
+    let samples: Vec<metrics::PerfDataSample> = rows
+        .iter()
+        .map(|row| metrics::PerfDataSample {
+            check_name: row.get("check_name"),
+            key: row.get("perf_key"),
+            uom: row.get("uom"),
+            value: row.get("perf_value"),
+            timestamp: row.get("timestamp"),
+        })
+        .collect();
+
+    Ok((
+        ContentType::new("application", "openmetrics-text")
+            .with_params([("version", "1.0.0"), ("charset", "utf-8")]),
+        metrics::render_perf_data(&samples),
+    ))
+}
+
+// Page of the Swagger UI, loaded from its CDN, exploring the OpenAPI document of the deployment
+const SWAGGER_UI_PAGE: &str = r##"<!DOCTYPE html>
+<html lang="en">
+  <head>
+    <meta charset="utf-8" />
+    <title>Pinglow RestAPI</title>
+    <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css" />
+  </head>
+  <body>
+    <div id="swagger-ui"></div>
+    <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+    <script>
+      window.ui = SwaggerUIBundle({ url: "openapi.json", dom_id: "#swagger-ui" });
+    </script>
+  </body>
+</html>
+"##;
+
+/**
+ * This function returns the OpenAPI document of the API, served against the deployment itself
+ */
+#[get("/openapi.json")]
+pub fn get_openapi() -> Json<utoipa::openapi::OpenApi> {
+    let mut apidoc = ApiDoc::openapi();
+    apidoc.info.version = env!("CARGO_PKG_VERSION").to_string();
+    apidoc.servers = Some(vec![utoipa::openapi::Server::new("/")]);
+
+    Json(apidoc)
+}
+
+/**
+ * This function returns the Swagger UI exploring the OpenAPI document of the API
+ */
+#[get("/docs")]
+pub fn get_api_docs() -> RawHtml<&'static str> {
+    RawHtml(SWAGGER_UI_PAGE)
 }
 
 #[derive(OpenApi)]
 #[openapi(
-    paths(get_checks, get_check_status, get_performance_data, mute_check, unmute_check, process_check_result),
+    paths(get_checks, get_groups, get_group_status, get_check_status, get_overview, stream_results, run_check, ack_check, get_performance_data, get_check_results, get_check_availability, get_check_diagnostics, get_check_notifications, mute_check, unmute_check, suspend_check, resume_check, bulk_mute_checks, bulk_unmute_checks, redirect_check, remove_check_redirect, process_check_result, get_heartbeat, post_heartbeat, import_checks, get_calendar, get_queue_backlog, get_runners, get_dead_letters, get_metrics, get_perf_data_metrics, grafana::grafana_test, grafana::grafana_search, grafana::grafana_metrics, grafana::grafana_metric_payload_options, grafana::grafana_query, grafana::grafana_annotations, status_page::get_status_page),
     components(schemas(
         SimpleCheckDto,
+        GroupStatusDto,
+        CheckGroupDto,
+        GroupMemberDto,
+        pinglow_common::dto::StatusPageDto,
+        pinglow_common::dto::StatusPageCheckDto,
+        RunnerDto,
         SimpleCheckResultDto,
+        CheckAckDto,
+        AckCheckPayload,
+        CheckResultHistoryDto,
+        CheckAvailabilityDto,
+        StatusCountsDto,
+        CheckDiagnosticsDto,
+        NotificationAttemptDto,
+        ImportItemResultDto,
+        QueueBacklog,
+        DeadLetters,
+        grafana::GrafanaRange,
+        grafana::GrafanaSearchRequest,
+        grafana::GrafanaMetricsRequest,
+        grafana::GrafanaMetric,
+        grafana::GrafanaTarget,
+        grafana::GrafanaQueryRequest,
+        grafana::GrafanaTimeSeries,
+        grafana::GrafanaAnnotationQuery,
+        grafana::GrafanaAnnotationsRequest,
+        grafana::GrafanaAnnotation,
         CheckResultStatus,
         ScriptLanguage
     )),
@@ -506,7 +2892,8 @@ pub async fn process_check_result(
     ),
     modifiers(&SecurityAddon),
     security(
-        ("api_key" = [])
+        ("api_key" = ["read"]),
+        ("bearer" = ["read"])
     )
 )]
 pub struct ApiDoc;
@@ -520,7 +2907,49 @@ impl Modify for SecurityAddon {
             components.security_schemes.insert(
                 "api_key".to_string(),
                 SecurityScheme::ApiKey(utoipa::openapi::security::ApiKey::Header(
-                    ApiKeyValue::new("x-api-key"),
+                    ApiKeyValue::with_description(
+                        "x-api-key",
+                        "The global API key, or a scoped key granting the scopes listed by the endpoint (read, mute, submit-result or admin, which grants them all)",
+                    ),
+                )),
+            );
+            components.security_schemes.insert(
+                "bearer".to_string(),
+                SecurityScheme::Http(
+                    HttpBuilder::new()
+                        .scheme(HttpAuthScheme::Bearer)
+                        .bearer_format("JWT")
+                        .description(Some(
+                            "A token of the configured OIDC issuer, whose scope claim grants the scopes listed by the endpoint",
+                        ))
+                        .build(),
+                ),
+            );
+            components.security_schemes.insert(
+                "hmac_signature".to_string(),
+                SecurityScheme::ApiKey(utoipa::openapi::security::ApiKey::Header(
+                    ApiKeyValue::with_description(
+                        "x-pinglow-signature",
+                        "Hex encoded HMAC-SHA256 of '<timestamp>.<nonce>.<payload>' with the key of the check",
+                    ),
+                )),
+            );
+            components.security_schemes.insert(
+                "hmac_nonce".to_string(),
+                SecurityScheme::ApiKey(utoipa::openapi::security::ApiKey::Header(
+                    ApiKeyValue::with_description(
+                        "x-pinglow-nonce",
+                        "Unique value of each signed result, used in the signature",
+                    ),
+                )),
+            );
+            components.security_schemes.insert(
+                "hmac_timestamp".to_string(),
+                SecurityScheme::ApiKey(utoipa::openapi::security::ApiKey::Header(
+                    ApiKeyValue::with_description(
+                        "x-pinglow-timestamp",
+                        "Unix timestamp (seconds) used in the signature",
+                    ),
                 )),
             );
         }
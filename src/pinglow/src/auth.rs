@@ -0,0 +1,452 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Error};
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use jsonwebtoken::{
+    decode, decode_header,
+    jwk::{Jwk, JwkSet},
+    Algorithm, DecodingKey, Validation,
+};
+use log::info;
+use redis::{aio::ConnectionLike, RedisError};
+use serde::Deserialize;
+use serde_json::Value;
+use sha2::Sha256;
+use tokio::sync::{Mutex, RwLock};
+
+use crate::config::PinglowConfig;
+
+// How long the signing keys of the issuer are cached
+const JWKS_TTL: Duration = Duration::from_secs(3600);
+
+// Minimum time between two fetches of the signing keys, when a token is signed by an unknown key or
+// a fetch failed
+const JWKS_MIN_REFRESH: Duration = Duration::from_secs(60);
+
+// Maximum allowed difference between the signature timestamp and the current time
+const SIGNATURE_TOLERANCE_SECONDS: i64 = 300;
+
+// Maximum length of the nonce of a signed result
+const MAX_NONCE_LENGTH: usize = 128;
+
+// Prefix of the keys recording the nonces of the signed results already accepted, which expire once
+// their results could no longer be accepted anyway
+const NONCE_KEY_PREFIX: &str = "pinglow:nonces:";
+
+/// What an API key or a bearer token is allowed to do, admin granting every other scope
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Scope {
+    Read,
+    Mute,
+    SubmitResult,
+    Admin,
+}
+
+impl Scope {
+    /**
+     * This function parses the name of a scope
+     */
+    pub fn from_name(name: &str) -> Option<Scope> {
+        match name {
+            "read" => Some(Scope::Read),
+            "mute" => Some(Scope::Mute),
+            "submit-result" => Some(Scope::SubmitResult),
+            "admin" => Some(Scope::Admin),
+            _ => None,
+        }
+    }
+
+    /// The name of the scope, as written in the keys file and in the OpenAPI document
+    pub fn name(&self) -> &'static str {
+        match self {
+            Scope::Read => "read",
+            Scope::Mute => "mute",
+            Scope::SubmitResult => "submit-result",
+            Scope::Admin => "admin",
+        }
+    }
+}
+
+/// An API key restricted to some scopes, in addition to the global API_KEY which has them all
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScopedApiKey {
+    pub name: String,
+    pub key: String,
+    pub scopes: Vec<Scope>,
+}
+
+impl ScopedApiKey {
+    /**
+     * This function tells whether the key grants the required scope
+     */
+    pub fn grants(&self, required: Scope) -> bool {
+        grants(&self.scopes, required)
+    }
+}
+
+/**
+ * This function tells whether some scopes grant the required one
+ */
+pub fn grants(scopes: &[Scope], required: Scope) -> bool {
+    scopes
+        .iter()
+        .any(|scope| *scope == Scope::Admin || *scope == required)
+}
+
+/**
+ * This function verifies the hex encoded HMAC-SHA256 of "<timestamp>.<nonce>.<payload>",
+ * rejecting timestamps too far from the current time. The replays within the tolerance are
+ * rejected by claim_nonce
+ */
+pub fn verify_result_signature(
+    key: &str,
+    timestamp: i64,
+    nonce: &str,
+    signature: &str,
+    payload: &str,
+) -> bool {
+    if (Utc::now().timestamp() - timestamp).abs() > SIGNATURE_TOLERANCE_SECONDS {
+        return false;
+    }
+
+    if nonce.is_empty() || nonce.len() > MAX_NONCE_LENGTH {
+        return false;
+    }
+
+    let Ok(signature) = hex::decode(signature) else {
+        return false;
+    };
+
+    let Ok(mut mac) = Hmac::<Sha256>::new_from_slice(key.as_bytes()) else {
+        return false;
+    };
+
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(nonce.as_bytes());
+    mac.update(b".");
+    mac.update(payload.as_bytes());
+
+    mac.verify_slice(&signature).is_ok()
+}
+
+/**
+ * This function records the nonce of a signed result of a check, returning false if it was
+ * already used. Nonces are kept in Redis, shared by all the instances of the controller
+ */
+pub async fn claim_nonce(
+    conn: &mut impl ConnectionLike,
+    check_name: &str,
+    nonce: &str,
+) -> Result<bool, RedisError> {
+    let claimed: Option<String> = redis::cmd("SET")
+        .arg(format!("{NONCE_KEY_PREFIX}{check_name}:{nonce}"))
+        .arg(1)
+        .arg("NX")
+        .arg("EX")
+        .arg(SIGNATURE_TOLERANCE_SECONDS * 2)
+        .query_async(conn)
+        .await?;
+
+    Ok(claimed.is_some())
+}
+
+/**
+ * This function loads the scoped API keys from a YAML file, usually mounted from a Secret
+ */
+pub fn load_api_keys(path: &Path) -> Result<Vec<ScopedApiKey>, Error> {
+    let keys: Vec<ScopedApiKey> = serde_yaml::from_str(&fs::read_to_string(path)?)?;
+
+    if let Some(key) = keys.iter().find(|key| key.key.is_empty()) {
+        anyhow::bail!("The API key {} is empty", key.name);
+    }
+
+    Ok(keys)
+}
+
+/// The scope an endpoint requires, as a type so that it can be checked by the request guard
+pub trait RequiredScope: Send + Sync + 'static {
+    const SCOPE: Scope;
+}
+
+/// Reading checks, results and the state of the controller
+pub struct Read;
+
+/// Muting, suspending, redirecting, acknowledging and running checks on demand
+pub struct Mute;
+
+/// Submitting passive check results
+pub struct SubmitResult;
+
+/// Everything, including the import of checks
+pub struct Admin;
+
+impl RequiredScope for Read {
+    const SCOPE: Scope = Scope::Read;
+}
+
+impl RequiredScope for Mute {
+    const SCOPE: Scope = Scope::Mute;
+}
+
+impl RequiredScope for SubmitResult {
+    const SCOPE: Scope = Scope::SubmitResult;
+}
+
+impl RequiredScope for Admin {
+    const SCOPE: Scope = Scope::Admin;
+}
+
+/// The signing keys of the issuer, with the time they were fetched
+struct CachedJwks {
+    keys: JwkSet,
+    fetched_at: Instant,
+}
+
+/// The validation of the JWT bearer tokens issued by an OIDC provider, mapping a claim of the
+/// tokens to scopes
+pub struct OidcVerifier {
+    issuer: String,
+    audience: Option<String>,
+    jwks_url: Option<String>,
+    scope_claim: String,
+    scope_mapping: HashMap<String, Scope>,
+    algorithms: Vec<Algorithm>, // Accepted for the keys which do not name their algorithm
+    http_client: reqwest::Client,
+    jwks: RwLock<Option<CachedJwks>>,
+    last_fetch: Mutex<Option<Instant>>, // Held while fetching, so that a single fetch runs at once
+}
+
+impl OidcVerifier {
+    /**
+     * This function builds the verifier of the bearer tokens, if an OIDC issuer is configured
+     */
+    pub fn from_config(config: &PinglowConfig) -> Option<OidcVerifier> {
+        let issuer = config.oidc_issuer.clone()?;
+
+        Some(OidcVerifier {
+            issuer,
+            audience: config.oidc_audience.clone(),
+            jwks_url: config.oidc_jwks_url.clone(),
+            scope_claim: config.oidc_scope_claim.clone(),
+            scope_mapping: config.oidc_scope_mapping.clone(),
+            algorithms: config.oidc_algorithms.clone(),
+            http_client: reqwest::Client::new(),
+            jwks: RwLock::new(None),
+            last_fetch: Mutex::new(None),
+        })
+    }
+
+    /**
+     * This function validates a bearer token, returning the scopes it grants
+     */
+    pub async fn verify(&self, token: &str) -> Result<Vec<Scope>, Error> {
+        let header = decode_header(token)?;
+        let kid = header
+            .kid
+            .ok_or(anyhow!("The token does not name its signing key"))?;
+
+        let jwk = match self.find_key(&kid, false).await? {
+            Some(jwk) => jwk,
+            // The keys may have been rotated since they were fetched
+            None => self
+                .find_key(&kid, true)
+                .await?
+                .ok_or(anyhow!("Unknown signing key {kid}"))?,
+        };
+
+        // The algorithm is never chosen by the token: it must be the one of its key or, if the key
+        // does not name one, one of the accepted algorithms
+        let algorithms = match jwk.common.key_algorithm {
+            Some(key_algorithm) => vec![key_algorithm.to_string().parse::<Algorithm>()?],
+            None => self.algorithms.clone(),
+        };
+        if !algorithms.contains(&header.alg) {
+            return Err(anyhow!(
+                "The token is signed with the unexpected algorithm {:?}",
+                header.alg
+            ));
+        }
+
+        let mut validation = Validation::new(header.alg);
+        validation.algorithms = algorithms;
+        validation.set_issuer(&[&self.issuer]);
+        match &self.audience {
+            Some(audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false,
+        }
+
+        let claims = decode::<Value>(token, &DecodingKey::from_jwk(&jwk)?, &validation)?.claims;
+
+        Ok(self.scopes(&claims))
+    }
+
+    /**
+     * This function maps the values of the scope claim, either a space separated string or an
+     * array, to scopes: through the configured mapping or, failing that, by name
+     */
+    fn scopes(&self, claims: &Value) -> Vec<Scope> {
+        let values: Vec<&str> = match claims.get(&self.scope_claim) {
+            Some(Value::String(values)) => values.split_whitespace().collect(),
+            Some(Value::Array(values)) => values.iter().filter_map(Value::as_str).collect(),
+            _ => Vec::new(),
+        };
+
+        values
+            .into_iter()
+            .filter_map(|value| {
+                self.scope_mapping
+                    .get(value)
+                    .copied()
+                    .or_else(|| Scope::from_name(value))
+            })
+            .collect()
+    }
+
+    /**
+     * This function looks up a signing key of the issuer, fetching the keys if they are missing,
+     * expired or, when refresh is set, possibly rotated. Concurrent lookups wait for a single
+     * fetch, and the keys are not fetched again within JWKS_MIN_REFRESH of the last attempt
+     */
+    async fn find_key(&self, kid: &str, refresh: bool) -> Result<Option<Jwk>, Error> {
+        if let Some(jwk) = self.cached_key(kid, refresh).await {
+            return Ok(jwk);
+        }
+
+        let mut last_fetch = self.last_fetch.lock().await;
+
+        // The keys may have been fetched while waiting for another lookup
+        if let Some(jwk) = self.cached_key(kid, refresh).await {
+            return Ok(jwk);
+        }
+
+        if last_fetch.is_some_and(|last_fetch| last_fetch.elapsed() < JWKS_MIN_REFRESH) {
+            // Keep using the keys already known, even if expired, until the next fetch
+            return match self.jwks.read().await.as_ref() {
+                Some(jwks) => Ok(jwks.keys.find(kid).cloned()),
+                None => Err(anyhow!(
+                    "The signing keys of {} are unavailable",
+                    self.issuer
+                )),
+            };
+        }
+        *last_fetch = Some(Instant::now());
+
+        let keys = self.fetch_keys().await?;
+        let jwk = keys.find(kid).cloned();
+
+        *self.jwks.write().await = Some(CachedJwks {
+            keys,
+            fetched_at: Instant::now(),
+        });
+
+        Ok(jwk)
+    }
+
+    /**
+     * This function looks up a signing key among the cached ones, returning None when they are
+     * missing, expired or, when refresh is set, old enough to be fetched again
+     */
+    async fn cached_key(&self, kid: &str, refresh: bool) -> Option<Option<Jwk>> {
+        let jwks = self.jwks.read().await;
+        let jwks = jwks.as_ref()?;
+        let age = jwks.fetched_at.elapsed();
+
+        (age < JWKS_TTL && (!refresh || age < JWKS_MIN_REFRESH))
+            .then(|| jwks.keys.find(kid).cloned())
+    }
+
+    /**
+     * This function fetches the signing keys of the issuer, from the configured JWKS endpoint or
+     * from the one advertised by its discovery document
+     */
+    async fn fetch_keys(&self) -> Result<JwkSet, Error> {
+        let jwks_url = match &self.jwks_url {
+            Some(jwks_url) => jwks_url.clone(),
+            None => {
+                let discovery: Value = self
+                    .get_json(&format!(
+                        "{}/.well-known/openid-configuration",
+                        self.issuer.trim_end_matches('/')
+                    ))
+                    .await?;
+
+                discovery
+                    .get("jwks_uri")
+                    .and_then(Value::as_str)
+                    .ok_or(anyhow!(
+                        "The issuer {} does not advertise its signing keys",
+                        self.issuer
+                    ))?
+                    .to_string()
+            }
+        };
+
+        info!(
+            "Fetching the signing keys of {} from {jwks_url}",
+            self.issuer
+        );
+
+        self.get_json(&jwks_url).await
+    }
+
+    async fn get_json<T: serde::de::DeserializeOwned>(&self, url: &str) -> Result<T, Error> {
+        let body = self
+            .http_client
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+
+        Ok(serde_json::from_str(&body)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sign(key: &str, timestamp: i64, nonce: &str, payload: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes()).unwrap();
+        mac.update(format!("{timestamp}.{nonce}.{payload}").as_bytes());
+        hex::encode(mac.finalize().into_bytes())
+    }
+
+    #[test]
+    fn signature_covers_the_nonce() {
+        let now = Utc::now().timestamp();
+        let signature = sign("key", now, "n1", "{}");
+
+        assert!(verify_result_signature("key", now, "n1", &signature, "{}"));
+        assert!(!verify_result_signature("key", now, "n2", &signature, "{}"));
+        assert!(!verify_result_signature(
+            "other", now, "n1", &signature, "{}"
+        ));
+
+        // Expired timestamps and missing nonces are rejected
+        let old = now - SIGNATURE_TOLERANCE_SECONDS - 1;
+        assert!(!verify_result_signature(
+            "key",
+            old,
+            "n1",
+            &sign("key", old, "n1", "{}"),
+            "{}"
+        ));
+        assert!(!verify_result_signature(
+            "key",
+            now,
+            "",
+            &sign("key", now, "", "{}"),
+            "{}"
+        ));
+    }
+}
@@ -0,0 +1,332 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
+
+use anyhow::Error;
+use chrono::{TimeDelta, Utc};
+use log::{error, warn};
+use pinglow_common::{
+    dto::RunnerDto,
+    redis::{checks_streams, RUNNERS_KEY, RUNNER_HEARTBEAT_INTERVAL_SECONDS},
+};
+use redis::{aio::MultiplexedConnection, Client as RedisClient, Value};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::{fallback::InternalErrors, reclaimer::CHECKS_DLQ_STREAM, results::RESULTS_DLQ_STREAM};
+
+/// The checks waiting for a runner, used as autoscaling signal
+#[derive(Debug, Default, Serialize, ToSchema)]
+pub struct QueueBacklog {
+    /// Checks not yet picked up by a runner plus the ones being executed
+    pub pending_checks: u64,
+    /// Checks not yet picked up by a runner
+    pub queued_checks: u64,
+    /// Checks picked up by a runner but not yet completed
+    pub running_checks: u64,
+    /// Age of the oldest pending check, 0 if there is none
+    pub oldest_pending_age_seconds: f64,
+}
+
+/// The messages moved to the dead-letter streams, which need to be inspected
+#[derive(Debug, Default, Serialize, ToSchema)]
+pub struct DeadLetters {
+    /// Checks never completed by the runners
+    pub checks: u64,
+    /// Results which could not be decoded or processed
+    pub results: u64,
+}
+
+// Heartbeats missed before a runner is reported as unhealthy
+const MISSED_HEARTBEATS: i64 = 3;
+
+// Age after which the heartbeat of a runner which did not stop cleanly is forgotten
+const FORGOTTEN_RUNNER_AGE: TimeDelta = TimeDelta::hours(1);
+
+/**
+ * This function returns the runners which published a heartbeat, sorted by name, forgetting
+ * the ones which disappeared long ago without removing it
+ */
+pub async fn runners(redis_client: &RedisClient) -> Result<Vec<RunnerDto>, Error> {
+    let mut conn = redis_client.get_multiplexed_async_connection().await?;
+
+    let heartbeats: HashMap<String, String> = redis::cmd("HGETALL")
+        .arg(RUNNERS_KEY)
+        .query_async(&mut conn)
+        .await?;
+
+    let now = Utc::now();
+    let mut runners = Vec::with_capacity(heartbeats.len());
+    let mut forgotten = Vec::new();
+
+    for (name, heartbeat) in heartbeats {
+        match serde_json::from_str::<RunnerDto>(&heartbeat) {
+            Ok(runner) if now - runner.last_seen > FORGOTTEN_RUNNER_AGE => forgotten.push(name),
+            Ok(mut runner) => {
+                runner.healthy = now - runner.last_seen
+                    <= TimeDelta::seconds(
+                        MISSED_HEARTBEATS * RUNNER_HEARTBEAT_INTERVAL_SECONDS as i64,
+                    );
+                runners.push(runner);
+            }
+            Err(e) => {
+                warn!("Cannot deserialize the heartbeat of runner {name}: {e}");
+                forgotten.push(name);
+            }
+        }
+    }
+
+    if !forgotten.is_empty() {
+        redis::cmd("HDEL")
+            .arg(RUNNERS_KEY)
+            .arg(&forgotten)
+            .query_async::<()>(&mut conn)
+            .await?;
+    }
+
+    runners.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(runners)
+}
+
+/**
+ * This function reports as internal errors the runners which stop sending heartbeats without
+ * stopping cleanly, once they turn unhealthy
+ */
+pub async fn watch_runners(redis_client: RedisClient, internal_errors: InternalErrors) {
+    let mut interval =
+        tokio::time::interval(Duration::from_secs(RUNNER_HEARTBEAT_INTERVAL_SECONDS));
+    let mut healthy: HashSet<String> = HashSet::new();
+
+    loop {
+        interval.tick().await;
+
+        let current = match runners(&redis_client).await {
+            Ok(current) => current,
+            Err(e) => {
+                error!("Error reading the heartbeats of the runners: {e}");
+                continue;
+            }
+        };
+
+        for runner in current.iter().filter(|runner| !runner.healthy) {
+            if healthy.contains(&runner.name) {
+                internal_errors.report(
+                    format!("runner/{}", runner.name),
+                    format!(
+                        "The runner {} has not sent a heartbeat since {}",
+                        runner.name, runner.last_seen
+                    ),
+                );
+            }
+        }
+
+        healthy = current
+            .into_iter()
+            .filter(|runner| runner.healthy)
+            .map(|runner| runner.name)
+            .collect();
+    }
+}
+
+/**
+ * This function returns the length of the dead-letter streams
+ */
+pub async fn dead_letters(redis_client: &RedisClient) -> Result<DeadLetters, Error> {
+    let mut conn = redis_client.get_multiplexed_async_connection().await?;
+
+    let (checks, results): (u64, u64) = redis::pipe()
+        .cmd("XLEN")
+        .arg(CHECKS_DLQ_STREAM)
+        .cmd("XLEN")
+        .arg(RESULTS_DLQ_STREAM)
+        .query_async(&mut conn)
+        .await?;
+
+    Ok(DeadLetters { checks, results })
+}
+
+/**
+ * This function computes the backlog of the checks streams from the state of their workers group
+ */
+pub async fn queue_backlog(redis_client: &RedisClient) -> Result<QueueBacklog, Error> {
+    let mut conn = redis_client.get_multiplexed_async_connection().await?;
+
+    let mut running_checks = 0;
+    let mut queued_checks = 0;
+    let mut oldest_ids = vec![];
+
+    for stream in checks_streams(&mut conn).await? {
+        let (stream_running, stream_queued, stream_oldest_ids) =
+            stream_backlog(&mut conn, &stream).await?;
+        running_checks += stream_running;
+        queued_checks += stream_queued;
+        oldest_ids.extend(stream_oldest_ids);
+    }
+
+    let now_millis = chrono::Utc::now().timestamp_millis();
+    let oldest_pending_age_seconds = oldest_ids
+        .into_iter()
+        .filter_map(|id| id_millis(&id))
+        .min()
+        .map(|millis| (now_millis - millis).max(0) as f64 / 1000.0)
+        .unwrap_or(0.0);
+
+    Ok(QueueBacklog {
+        pending_checks: running_checks + queued_checks,
+        queued_checks,
+        running_checks,
+        oldest_pending_age_seconds,
+    })
+}
+
+/**
+ * This function returns the running and the queued checks of a stream, with the ids of the
+ * oldest ones
+ */
+async fn stream_backlog(
+    conn: &mut MultiplexedConnection,
+    stream: &str,
+) -> Result<(u64, u64, Vec<String>), Error> {
+    let groups: Value = redis::cmd("XINFO")
+        .arg("GROUPS")
+        .arg(stream)
+        .query_async(conn)
+        .await?;
+
+    // Without the group, no runner ever read the stream and all its checks are queued
+    let Some(group) = stream_group(groups, "workers") else {
+        let queued_checks: u64 = redis::cmd("XLEN").arg(stream).query_async(conn).await?;
+        let oldest_queued = match queued_checks {
+            0 => None,
+            _ => first_id_after(conn, stream, "0-0").await?,
+        };
+        return Ok((0, queued_checks, oldest_queued.into_iter().collect()));
+    };
+
+    let running_checks = group_field(&group, "pending").and_then(as_u64).unwrap_or(0);
+    let queued_checks = group_field(&group, "lag").and_then(as_u64).unwrap_or(0);
+    let last_delivered_id = group_field(&group, "last-delivered-id").and_then(as_string);
+
+    // The oldest pending check is either the oldest one not acked or the first one not delivered
+    let oldest_running = if running_checks > 0 {
+        oldest_unacked_id(conn, stream).await?
+    } else {
+        None
+    };
+    let oldest_queued = match (queued_checks, last_delivered_id) {
+        (0, _) | (_, None) => None,
+        (_, Some(last_delivered_id)) => first_id_after(conn, stream, &last_delivered_id).await?,
+    };
+
+    Ok((
+        running_checks,
+        queued_checks,
+        [oldest_running, oldest_queued]
+            .into_iter()
+            .flatten()
+            .collect(),
+    ))
+}
+
+async fn oldest_unacked_id(
+    conn: &mut MultiplexedConnection,
+    stream: &str,
+) -> Result<Option<String>, Error> {
+    // XPENDING summary: [count, smallest id, greatest id, consumers]
+    let summary: Value = redis::cmd("XPENDING")
+        .arg(stream)
+        .arg("workers")
+        .query_async(conn)
+        .await?;
+
+    let Value::Array(fields) = summary else {
+        return Ok(None);
+    };
+
+    Ok(fields.into_iter().nth(1).and_then(|id| as_string(&id)))
+}
+
+async fn first_id_after(
+    conn: &mut MultiplexedConnection,
+    stream: &str,
+    id: &str,
+) -> Result<Option<String>, Error> {
+    let entries: Value = redis::cmd("XRANGE")
+        .arg(stream)
+        .arg(format!("({id}"))
+        .arg("+")
+        .arg("COUNT")
+        .arg(1)
+        .query_async(conn)
+        .await?;
+
+    let Value::Array(entries) = entries else {
+        return Ok(None);
+    };
+
+    // entry = [id, fields]
+    Ok(entries.into_iter().next().and_then(|entry| match entry {
+        Value::Array(entry) => entry.first().and_then(as_string),
+        _ => None,
+    }))
+}
+
+/// Returns the fields of a consumer group, as key-value pairs
+fn stream_group(groups: Value, name: &str) -> Option<Vec<(String, Value)>> {
+    let Value::Array(groups) = groups else {
+        return None;
+    };
+
+    groups.into_iter().map(key_values).find(|fields| {
+        fields
+            .iter()
+            .any(|(k, v)| k == "name" && as_string(v).as_deref() == Some(name))
+    })
+}
+
+/// Converts a RESP2 flat array or a RESP3 map into key-value pairs
+fn key_values(value: Value) -> Vec<(String, Value)> {
+    match value {
+        Value::Map(pairs) => pairs
+            .into_iter()
+            .filter_map(|(k, v)| Some((as_string(&k)?, v)))
+            .collect(),
+        Value::Array(items) => {
+            let mut items = items.into_iter();
+            let mut pairs = vec![];
+            while let (Some(k), Some(v)) = (items.next(), items.next()) {
+                if let Some(k) = as_string(&k) {
+                    pairs.push((k, v));
+                }
+            }
+            pairs
+        }
+        _ => vec![],
+    }
+}
+
+fn group_field<'a>(group: &'a [(String, Value)], key: &str) -> Option<&'a Value> {
+    group.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+}
+
+fn as_string(value: &Value) -> Option<String> {
+    match value {
+        Value::BulkString(bytes) => Some(String::from_utf8_lossy(bytes).to_string()),
+        Value::SimpleString(s) => Some(s.clone()),
+        _ => None,
+    }
+}
+
+fn as_u64(value: &Value) -> Option<u64> {
+    match value {
+        Value::Int(i) => u64::try_from(*i).ok(),
+        _ => as_string(value)?.parse().ok(),
+    }
+}
+
+/// Stream ids are in the form <unix millis>-<sequence>
+fn id_millis(id: &str) -> Option<i64> {
+    id.split_once('-')?.0.parse().ok()
+}
@@ -0,0 +1,188 @@
+use chrono::{DateTime, TimeDelta, Utc};
+use pinglow_common::PinglowCheck;
+
+const ICAL_DATE_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+/**
+ * This function renders the maintenance windows and the active mutes of the checks as an
+ * iCalendar document
+ */
+pub fn calendar_to_ical<'a>(
+    checks: impl Iterator<Item = &'a PinglowCheck>,
+    now: DateTime<Utc>,
+) -> String {
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        "PRODID:-//Pinglow//Pinglow//EN".to_string(),
+        "X-WR-CALNAME:Pinglow".to_string(),
+    ];
+
+    for check in checks {
+        for window in &check.maintenance_windows {
+            let summary = match &window.description {
+                Some(description) => format!("{} maintenance: {description}", check.check_name),
+                None => format!("{} maintenance", check.check_name),
+            };
+
+            // The start identifies the window, the windows of a check being edited as a list
+            let uid = format!(
+                "maintenance-{}-{}",
+                check.check_name,
+                window.start.format(ICAL_DATE_FORMAT)
+            );
+            lines.extend(event(&uid, now, window.start, window.end, &summary));
+        }
+
+        if check.mute_notifications != Some(true) {
+            continue;
+        }
+
+        // Indefinite mutes are shown on the current day only
+        let (end, summary) = match check.mute_notifications_until {
+            Some(until) if until <= now => continue,
+            Some(until) => (until, format!("{} muted", check.check_name)),
+            None => (
+                now + TimeDelta::days(1),
+                format!("{} muted indefinitely", check.check_name),
+            ),
+        };
+
+        // The mutes set directly on the Check resource do not record their start
+        let start = check.mute_notifications_since.unwrap_or(now);
+        let uid = format!("mute-{}", check.check_name);
+        lines.extend(event(&uid, now, start, end, &summary));
+    }
+
+    lines.push("END:VCALENDAR".to_string());
+
+    // iCalendar lines are terminated by CRLF
+    let mut calendar = lines.join("\r\n");
+    calendar.push_str("\r\n");
+    calendar
+}
+
+/**
+ * This function returns the lines of a calendar event
+ */
+fn event(
+    uid: &str,
+    now: DateTime<Utc>,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    summary: &str,
+) -> [String; 7] {
+    [
+        "BEGIN:VEVENT".to_string(),
+        format!("UID:{}@pinglow", escape_text(uid)),
+        format!("DTSTAMP:{}", now.format(ICAL_DATE_FORMAT)),
+        format!("DTSTART:{}", start.format(ICAL_DATE_FORMAT)),
+        format!("DTEND:{}", end.format(ICAL_DATE_FORMAT)),
+        format!("SUMMARY:{}", escape_text(summary)),
+        "END:VEVENT".to_string(),
+    ]
+}
+
+fn escape_text(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use serde_json::json;
+
+    fn check(spec: serde_json::Value) -> PinglowCheck {
+        let mut check = json!({ "check_name": "db", "passive": false });
+        check
+            .as_object_mut()
+            .unwrap()
+            .extend(spec.as_object().unwrap().clone());
+        serde_json::from_value(check).unwrap()
+    }
+
+    fn events(calendar: &str) -> Vec<Vec<&str>> {
+        calendar
+            .split("BEGIN:VEVENT\r\n")
+            .skip(1)
+            .map(|event| {
+                event
+                    .split("\r\n")
+                    .take_while(|line| *line != "END:VEVENT")
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn mutes_start_when_muted() {
+        let now = Utc.with_ymd_and_hms(2026, 3, 10, 12, 0, 0).unwrap();
+        let checks = [
+            check(json!({
+                "mute_notifications": true,
+                "mute_notifications_since": "2026-03-09T08:30:00Z",
+                "mute_notifications_until": "2026-03-11T08:30:00Z"
+            })),
+            check(
+                json!({ "mute_notifications": true, "mute_notifications_until": "2026-03-10T11:00:00Z" }),
+            ),
+            check(json!({ "mute_notifications": false })),
+        ];
+
+        let calendar = calendar_to_ical(checks.iter(), now);
+
+        assert_eq!(
+            events(&calendar),
+            vec![vec![
+                "UID:mute-db@pinglow",
+                "DTSTAMP:20260310T120000Z",
+                "DTSTART:20260309T083000Z",
+                "DTEND:20260311T083000Z",
+                "SUMMARY:db muted",
+            ]]
+        );
+    }
+
+    #[test]
+    fn maintenance_windows_are_exported() {
+        let now = Utc.with_ymd_and_hms(2026, 3, 10, 12, 0, 0).unwrap();
+        let checks = [check(json!({
+            "maintenance_windows": [
+                {
+                    "start": "2026-03-14T22:00:00Z",
+                    "end": "2026-03-15T02:00:00Z",
+                    "description": "Disk replacement, rack 4"
+                },
+                { "start": "2026-03-21T22:00:00Z", "end": "2026-03-21T23:00:00Z" }
+            ]
+        }))];
+
+        let calendar = calendar_to_ical(checks.iter(), now);
+
+        assert_eq!(
+            events(&calendar),
+            vec![
+                vec![
+                    "UID:maintenance-db-20260314T220000Z@pinglow",
+                    "DTSTAMP:20260310T120000Z",
+                    "DTSTART:20260314T220000Z",
+                    "DTEND:20260315T020000Z",
+                    "SUMMARY:db maintenance: Disk replacement\\, rack 4",
+                ],
+                vec![
+                    "UID:maintenance-db-20260321T220000Z@pinglow",
+                    "DTSTAMP:20260310T120000Z",
+                    "DTSTART:20260321T220000Z",
+                    "DTEND:20260321T230000Z",
+                    "SUMMARY:db maintenance",
+                ],
+            ]
+        );
+        assert!(calendar.starts_with("BEGIN:VCALENDAR\r\n"));
+        assert!(calendar.ends_with("END:VCALENDAR\r\n"));
+    }
+}
@@ -1,21 +1,77 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+    time::Instant,
+};
 
 use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use k8s_openapi::api::core::v1::Secret;
+use kube::runtime::reflector::Store;
 use kube::CustomResource;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use tokio::sync::RwLock;
 
-use dashmap::DashMap;
+use pinglow_common::{
+    CheckJobSpec, CheckResultStatus, ConcreteTelegramChannel, HttpCheckSpec, MaintenanceWindow,
+    PinglowCheck, PushService, TcpCheckSpec, ThresholdSpec,
+};
 
-use pinglow_common::{CheckResultStatus, PinglowCheck};
+pub type SharedPinglowChecks = Arc<DashMap<String, Arc<PinglowCheck>>>;
+pub type SharedChecks = Store<Check>;
+pub type SharedSecrets = Arc<DashMap<String, CachedSecret>>;
+pub type SharedRedirects = Arc<DashMap<String, NotificationRedirect>>;
 
-pub type SharedPinglowChecks = Arc<RwLock<HashMap<String, Arc<PinglowCheck>>>>;
-pub type SharedChecks = Arc<DashMap<String, Arc<Check>>>;
+/// A secret fetched from the API server, fetched again once older than the cache TTL
+#[derive(Clone)]
+pub struct CachedSecret {
+    pub secret: Arc<Secret>,
+    pub fetched_at: Instant,
+}
+
+/**
+ * This function returns the key of a secret in the cache, which holds the secrets of all the
+ * watched namespaces
+ */
+pub fn secret_cache_key(namespace: &str, secret_name: &str) -> String {
+    format!("{namespace}/{secret_name}")
+}
 
-pub fn map_command_exit_code_to_check_result(exit_code: Option<i32>) -> CheckResultStatus {
+/**
+ * This function returns the identity of a check, or of a group, keying it throughout Pinglow:
+ * its name in the target namespace, namespace/name in the other watched namespaces
+ */
+pub fn check_identity(namespace: &str, name: &str, target_namespace: &str) -> String {
+    match namespace == target_namespace {
+        true => name.to_string(),
+        false => format!("{namespace}/{name}"),
+    }
+}
+
+/**
+ * This function returns the identity of the check referenced from a namespace, either by its name
+ * in the same namespace or as namespace/name
+ */
+pub fn qualify_check_ref(reference: &str, namespace: &str, target_namespace: &str) -> String {
+    match reference.split_once('/') {
+        Some((namespace, name)) => check_identity(namespace, name, target_namespace),
+        None => check_identity(namespace, reference, target_namespace),
+    }
+}
+
+/// A temporary replacement of the notification channels of a check
+#[derive(Clone, Debug)]
+pub struct NotificationRedirect {
+    pub channel: ConcreteTelegramChannel,
+    pub until: DateTime<Utc>,
+}
+
+pub fn map_command_exit_code_to_check_result(
+    check: &PinglowCheck,
+    exit_code: Option<i32>,
+) -> CheckResultStatus {
     if let Some(exit_code) = exit_code {
-        return CheckResultStatus::from(exit_code);
+        return check.status_from_exit_code(exit_code);
     }
     CheckResultStatus::CheckError
 }
@@ -30,7 +86,52 @@ pub fn map_command_exit_code_to_check_result(exit_code: Option<i32>) -> CheckRes
 #[allow(non_snake_case)]
 pub struct TelegramChannelSpec {
     pub chatId: String,
-    pub botTokenRef: String, // The name of the secret
+    pub botTokenRef: String,             // The name of the secret
+    pub proxy: Option<String>,           // e.g. http://proxy:3128 or socks5://proxy:1080
+    pub messageTemplate: Option<String>, // Handlebars template replacing the default message
+}
+
+#[derive(CustomResource, Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[kube(
+    group = "pinglow.io",
+    version = "v1alpha1",
+    kind = "SlackChannel",
+    namespaced
+)]
+#[allow(non_snake_case)]
+pub struct SlackChannelSpec {
+    pub webhookUrlRef: String, // The name of the secret holding the incoming webhook URL
+    pub messageTemplate: Option<String>, // Handlebars template replacing the default message
+}
+
+#[derive(CustomResource, Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[kube(
+    group = "pinglow.io",
+    version = "v1alpha1",
+    kind = "DiscordChannel",
+    namespaced
+)]
+#[allow(non_snake_case)]
+pub struct DiscordChannelSpec {
+    pub webhookUrlRef: String, // The name of the secret holding the webhook URL
+    pub messageTemplate: Option<String>, // Handlebars template replacing the default message
+}
+
+#[derive(CustomResource, Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[kube(
+    group = "pinglow.io",
+    version = "v1alpha1",
+    kind = "PushChannel",
+    namespaced
+)]
+#[allow(non_snake_case)]
+pub struct PushChannelSpec {
+    pub service: PushService,
+    pub serverUrl: String,                        // e.g. https://ntfy.sh
+    pub topic: Option<String>,                    // The ntfy topic
+    pub tokenRef: Option<String>,                 // The name of the secret holding the token
+    pub priorities: Option<BTreeMap<String, u8>>, // Status -> priority
+    pub messageTemplate: Option<String>, // Handlebars template replacing the default message
 }
 
 #[derive(CustomResource, Deserialize, Serialize, Clone, Debug, JsonSchema)]
@@ -41,7 +142,110 @@ pub struct CheckSpec {
     pub interval: Option<u64>,
     pub secretRefs: Option<Vec<String>>,
     pub telegramChannelRefs: Option<Vec<String>>,
+    pub slackChannelRefs: Option<Vec<String>>,
+    pub pushChannelRefs: Option<Vec<String>>,
+    pub discordChannelRefs: Option<Vec<String>>,
     pub muteNotifications: Option<bool>,
     pub muteNotificationsUntil: Option<DateTime<Utc>>,
+    pub muteNotificationsSince: Option<DateTime<Utc>>, // When the notifications were muted
+    pub maintenanceWindows: Option<Vec<MaintenanceWindow>>, // Planned downtimes muting the notifications
+    pub suspend: Option<bool>, // Stop scheduling the check, which stays visible in the API
     pub passive: bool,
+    pub resultRetentionDays: Option<u64>,
+    pub hmacSecretRef: Option<String>,
+    pub heartbeatTokenRef: Option<String>, // Secret holding the token of the heartbeat URL
+    pub webhooks: Option<Vec<String>>,
+    pub args: Option<Vec<String>>,
+    pub env: Option<BTreeMap<String, String>>,
+    pub parameters: Option<BTreeMap<String, String>>, // Values of the {{name}} placeholders of the script
+    pub job: Option<CheckJobSpec>,                    // Run the check as a Kubernetes Job
+    pub notifyOnStateChange: Option<bool>,            // Notify only when the status changes
+    pub notifyOnRecovery: Option<bool>,               // Notify when the status goes back to Ok
+    pub renotifyIntervalMinutes: Option<u64>, // Notify again a persistent failure every N minutes
+    pub minNotificationIntervalSeconds: Option<u64>, // At most one message per channel every N seconds
+    pub timeoutSeconds: Option<u64>, // Kill the script after N seconds, overriding the one of the script
+    pub retries: Option<u32>,        // Retry a failed execution N times before reporting it
+    pub retryDelaySeconds: Option<u64>,
+    pub dependsOn: Option<Vec<String>>, // Parent checks whose failure suppresses the notifications
+    pub exitCodeMapping: Option<BTreeMap<String, i32>>, // Exit code -> status, overriding the Nagios convention
+    pub httpCheck: Option<HttpCheckSpec>, // Probe an HTTP endpoint instead of running a script
+    pub tcpCheck: Option<TcpCheckSpec>,   // Connect to a TCP port instead of running a script
+    pub runnerSelector: Option<BTreeMap<String, String>>, // Labels of the runners allowed to execute the check
+    pub thresholds: Option<BTreeMap<String, ThresholdSpec>>, // Perf key -> warn/crit ranges escalating the result
+    pub anomalySigma: Option<f64>, // Deviation from the baseline of the perf data producing a Warning
+}
+
+/// How the statuses of the members of a group make up the status of the group
+#[derive(Deserialize, Serialize, Clone, Debug, Default, JsonSchema, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum GroupAggregation {
+    #[default]
+    WorstOf, // The worst status among the members
+    Quorum,   // Ok while enough members are Ok
+    Weighted, // Critical once the failing members weigh enough
+}
+
+#[derive(CustomResource, Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[kube(
+    group = "pinglow.io",
+    version = "v1alpha1",
+    kind = "CheckGroup",
+    namespaced,
+    status = "CheckGroupStatus"
+)]
+#[allow(non_snake_case)]
+pub struct CheckGroupSpec {
+    pub checks: Option<Vec<String>>, // Names of the member checks
+    pub selector: Option<BTreeMap<String, String>>, // Labels selecting further member checks
+    pub aggregation: Option<GroupAggregation>,
+    pub quorum: Option<usize>, // Members which must be Ok with the quorum, a majority by default
+    pub weights: Option<BTreeMap<String, f64>>, // Check -> weight with the weighted aggregation, 1 by default
+    pub criticalShare: Option<f64>, // Share of the weight failing making the group Critical, 0.5 by default
+    pub telegramChannelRefs: Option<Vec<String>>,
+    pub slackChannelRefs: Option<Vec<String>>,
+    pub pushChannelRefs: Option<Vec<String>>,
+    pub discordChannelRefs: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default, JsonSchema)]
+#[allow(non_snake_case)]
+pub struct CheckGroupStatus {
+    pub status: Option<String>, // Aggregated status, e.g. Ok or Critical
+    pub members: Option<usize>,
+    pub lastTransitionTime: Option<DateTime<Utc>>,
+}
+
+#[derive(CustomResource, Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[kube(
+    group = "pinglow.io",
+    version = "v1alpha1",
+    kind = "SnmpTrapRule",
+    namespaced
+)]
+#[allow(non_snake_case)]
+pub struct SnmpTrapRuleSpec {
+    pub trapOid: String,
+    pub checkRef: String,
+    pub status: Option<i32>, // Status used when no mapping applies, critical by default
+    pub statusVarbindOid: Option<String>,
+    pub statusMapping: Option<HashMap<String, i32>>, // Varbind value -> status
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identities() {
+        assert_eq!(check_identity("pinglow", "web", "pinglow"), "web");
+        assert_eq!(check_identity("team-a", "web", "pinglow"), "team-a/web");
+
+        assert_eq!(qualify_check_ref("db", "team-a", "pinglow"), "team-a/db");
+        assert_eq!(qualify_check_ref("pinglow/db", "team-a", "pinglow"), "db");
+        assert_eq!(
+            qualify_check_ref("team-b/db", "team-a", "pinglow"),
+            "team-b/db"
+        );
+        assert_eq!(qualify_check_ref("db", "pinglow", "pinglow"), "db");
+    }
 }
@@ -1,14 +1,68 @@
-use std::env;
+use std::{collections::HashMap, env, path::Path};
+
+use jsonwebtoken::Algorithm;
+
+use crate::auth::{load_api_keys, Scope, ScopedApiKey};
 
 #[derive(Debug, Clone)]
 pub struct PinglowConfig {
     pub target_namespace: String,
+    pub watch_namespaces: Option<Vec<String>>, // Namespaces of the watched checks, all of them when none
     pub db: String,
     pub db_host: String,
     pub db_user: String,
     pub db_user_password: String,
+    pub db_pool_size: usize,
+    pub secret_cache_ttl_seconds: u64,
     pub api_key: String,
+    pub api_keys: Vec<ScopedApiKey>,
+    pub oidc_issuer: Option<String>,
+    pub oidc_audience: Option<String>,
+    pub oidc_jwks_url: Option<String>,
+    pub oidc_algorithms: Vec<Algorithm>, // Accepted for the signing keys which do not name theirs
+    pub oidc_scope_claim: String,
+    pub oidc_scope_mapping: HashMap<String, Scope>,
     pub redis_password: String,
+    pub results_batch_size: usize,
+    pub results_concurrency: usize,
+    pub result_retention_days: u64,
+    pub purge_results_on_delete: bool, // Delete the history of a check along with its resource
+    pub anomaly_baseline_days: u64, // Window of the performance data the anomalies are judged against
+    pub snmp_trap_address: Option<String>,
+    pub snmp_community: Option<String>,
+    pub webhook_urls: Vec<String>,
+    pub webhook_secret: Option<String>,
+    pub webhook_max_retries: u32,
+    pub kafka_brokers: Option<String>,
+    pub kafka_topic: String,
+    pub standalone_config_dir: Option<String>,
+    pub notification_max_attempts: i32,
+    pub notification_proxy: Option<String>,
+    pub check_error_channel: Option<String>, // kind/name of the channel notified of the errors of the checks without channels
+    pub default_channel: Option<String>, // kind/name of the channel notified of the checks without channels and of the internal errors
+    pub metrics_require_api_key: bool,
+    pub api_docs: bool, // Serve the OpenAPI document and the Swagger UI
+    pub status_page_label: Option<String>, // Label selector of the checks on the public status page
+    pub api_address: String,
+    pub api_port: u16,
+    pub api_tls_cert: Option<String>,
+    pub api_tls_key: Option<String>,
+    pub api_rate_limit: u32,
+    pub api_trusted_proxy_header: Option<String>, // Header with the client address set by a proxy
+    pub api_max_payload_bytes: u64,
+    pub admission_tls_cert: Option<String>,
+    pub admission_tls_key: Option<String>,
+    pub admission_port: u16,
+    pub check_reclaim_idle_seconds: u64,
+    pub check_max_deliveries: u32,
+    pub scheduler_jitter_percent: u64,
+    pub scheduler_stagger: bool,
+    pub scheduler_max_dispatch_per_second: u32,
+    pub leader_election: bool,
+    pub leader_lease_name: String,
+    pub leader_lease_duration_seconds: u64,
+    pub leader_identity: String,
+    pub shutdown_grace_period_seconds: u64,
 }
 
 /**
@@ -17,12 +71,209 @@ pub struct PinglowConfig {
 pub fn get_config_from_env() -> PinglowConfig {
     PinglowConfig {
         target_namespace: env::var("NAMESPACE").unwrap_or("pinglow".to_string()),
+        watch_namespaces: parse_watch_namespaces(
+            env::var("WATCH_NAMESPACES").ok().as_deref(),
+            &env::var("NAMESPACE").unwrap_or("pinglow".to_string()),
+        ),
         api_key: env::var("API_KEY").expect("The variable API_KEY must be set"),
+        api_keys: env::var("API_KEYS_FILE")
+            .map(|path| load_api_keys(Path::new(&path)).expect("Invalid API_KEYS_FILE"))
+            .unwrap_or_default(),
+        oidc_issuer: env::var("OIDC_ISSUER").ok(),
+        oidc_audience: env::var("OIDC_AUDIENCE").ok(),
+        oidc_jwks_url: env::var("OIDC_JWKS_URL").ok(),
+        oidc_algorithms: env::var("OIDC_ALGORITHMS")
+            .unwrap_or("RS256".to_string())
+            .split(',')
+            .map(|algorithm| algorithm.trim().parse().expect("Invalid OIDC_ALGORITHMS"))
+            .collect(),
+        oidc_scope_claim: env::var("OIDC_SCOPE_CLAIM").unwrap_or("scope".to_string()),
+        oidc_scope_mapping: env::var("OIDC_SCOPE_MAPPING")
+            .map(|mapping| parse_scope_mapping(&mapping))
+            .unwrap_or_default(),
         db: env::var("DB").unwrap_or("pinglow".to_string()),
         db_host: env::var("DB_HOST").unwrap_or("localhost".to_string()),
         db_user: env::var("DB_USER").expect("The variable DB_USER must be set"),
         db_user_password: env::var("DB_USER_PASSWORD")
             .expect("The variable DB_USER_PASSWORD must be set"),
+        secret_cache_ttl_seconds: env::var("SECRET_CACHE_TTL_SECONDS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(300),
+        db_pool_size: env::var("DB_POOL_SIZE")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(16),
         redis_password: env::var("REDIS_PASSWORD").expect("Redis password must be set"),
+        results_batch_size: env::var("RESULTS_BATCH_SIZE")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(10),
+        results_concurrency: env::var("RESULTS_CONCURRENCY")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(4),
+        result_retention_days: env::var("RESULT_RETENTION_DAYS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(7),
+        purge_results_on_delete: env::var("PURGE_RESULTS_ON_DELETE")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(false),
+        anomaly_baseline_days: env::var("ANOMALY_BASELINE_DAYS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(28),
+        snmp_trap_address: env::var("SNMP_TRAP_ADDRESS").ok(),
+        snmp_community: env::var("SNMP_COMMUNITY").ok(),
+        webhook_urls: env::var("WEBHOOK_URLS")
+            .map(|urls| {
+                urls.split(',')
+                    .map(|url| url.trim().to_string())
+                    .filter(|url| !url.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default(),
+        webhook_secret: env::var("WEBHOOK_SECRET").ok(),
+        webhook_max_retries: env::var("WEBHOOK_MAX_RETRIES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(3),
+        kafka_brokers: env::var("KAFKA_BROKERS").ok(),
+        kafka_topic: env::var("KAFKA_TOPIC").unwrap_or("pinglow.results".to_string()),
+        standalone_config_dir: env::var("STANDALONE_CONFIG_DIR").ok(),
+        notification_max_attempts: env::var("NOTIFICATION_MAX_ATTEMPTS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(5),
+        notification_proxy: env::var("NOTIFICATION_PROXY").ok(),
+        check_error_channel: env::var("CHECK_ERROR_CHANNEL").ok(),
+        default_channel: env::var("DEFAULT_CHANNEL").ok(),
+        api_docs: env::var("API_DOCS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(true),
+        status_page_label: env::var("STATUS_PAGE_LABEL").ok(),
+        metrics_require_api_key: env::var("METRICS_REQUIRE_API_KEY")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(false),
+        api_address: env::var("API_ADDRESS").unwrap_or("0.0.0.0".to_string()),
+        api_port: env::var("API_PORT")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(8000),
+        api_tls_cert: env::var("API_TLS_CERT").ok(),
+        api_tls_key: env::var("API_TLS_KEY").ok(),
+        api_rate_limit: env::var("API_RATE_LIMIT")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(600),
+        api_trusted_proxy_header: env::var("API_TRUSTED_PROXY_HEADER").ok(),
+        api_max_payload_bytes: env::var("API_MAX_PAYLOAD_BYTES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(1024 * 1024),
+        admission_tls_cert: env::var("ADMISSION_TLS_CERT").ok(),
+        admission_tls_key: env::var("ADMISSION_TLS_KEY").ok(),
+        admission_port: env::var("ADMISSION_PORT")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(8443),
+        check_reclaim_idle_seconds: env::var("CHECK_RECLAIM_IDLE_SECONDS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(600),
+        check_max_deliveries: env::var("CHECK_MAX_DELIVERIES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(3),
+        scheduler_jitter_percent: env::var("SCHEDULER_JITTER_PERCENT")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0),
+        scheduler_stagger: env::var("SCHEDULER_STAGGER")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(true),
+        scheduler_max_dispatch_per_second: env::var("SCHEDULER_MAX_DISPATCH_PER_SECOND")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0),
+        leader_election: env::var("LEADER_ELECTION")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(false),
+        leader_lease_name: env::var("LEADER_LEASE_NAME").unwrap_or("pinglow-leader".to_string()),
+        leader_lease_duration_seconds: env::var("LEADER_LEASE_DURATION_SECONDS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(15),
+        leader_identity: env::var("POD_NAME")
+            .or(env::var("HOSTNAME"))
+            .unwrap_or("pinglow".to_string()),
+        shutdown_grace_period_seconds: env::var("SHUTDOWN_GRACE_PERIOD_SECONDS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(40),
+    }
+}
+
+impl PinglowConfig {
+    /**
+     * This function returns the scopes of the watched resources: a namespace each or, in
+     * cluster-wide mode, a single scope without namespace
+     */
+    pub fn watch_scopes(&self) -> Vec<Option<String>> {
+        match &self.watch_namespaces {
+            Some(namespaces) => namespaces.iter().cloned().map(Some).collect(),
+            None => vec![None],
+        }
+    }
+}
+
+/**
+ * This function parses the namespaces of the watched checks, given as a comma separated list or
+ * as * for all of them, the target namespace being watched when none is given
+ */
+fn parse_watch_namespaces(namespaces: Option<&str>, target_namespace: &str) -> Option<Vec<String>> {
+    let namespaces = namespaces.map(str::trim).unwrap_or_default();
+
+    if namespaces == "*" {
+        return None;
     }
+
+    let mut watched: Vec<String> = namespaces
+        .split(',')
+        .map(|namespace| namespace.trim().to_string())
+        .filter(|namespace| !namespace.is_empty())
+        .collect();
+    watched.dedup();
+
+    if watched.is_empty() {
+        watched.push(target_namespace.to_string());
+    }
+
+    Some(watched)
+}
+
+/**
+ * This function parses the mapping of the values of the OIDC scope claim to scopes, given as a
+ * comma separated list of value=scope
+ */
+fn parse_scope_mapping(mapping: &str) -> HashMap<String, Scope> {
+    mapping
+        .split(',')
+        .filter(|entry| !entry.trim().is_empty())
+        .map(|entry| {
+            let (value, scope) = entry
+                .split_once('=')
+                .expect("OIDC_SCOPE_MAPPING entries must be value=scope");
+            let scope = Scope::from_name(scope.trim())
+                .unwrap_or_else(|| panic!("Unknown scope {scope} in OIDC_SCOPE_MAPPING"));
+
+            (value.trim().to_string(), scope)
+        })
+        .collect()
 }
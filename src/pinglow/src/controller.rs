@@ -1,12 +1,18 @@
 use std::sync::Arc;
 
 use crate::{
-    check::{Check, SharedChecks, TelegramChannel},
+    check::{
+        check_identity, secret_cache_key, Check, DiscordChannel, PushChannel, SharedChecks,
+        SharedSecrets, SlackChannel, TelegramChannel,
+    },
     config::PinglowConfig,
     error::ReconcileError,
+    fallback::InternalErrors,
     load_single_runnable_check,
     scheduler::RunnableCheckEvent,
+    scoped_api, teardown,
 };
+use deadpool_postgres::Pool;
 use futures::StreamExt;
 use k8s_openapi::api::core::v1::Secret;
 use kube::{
@@ -23,6 +29,7 @@ use kube::{
 };
 use log::{debug, error, info};
 use pinglow_common::Script;
+use redis::Client as RedisClient;
 use std::time::Duration;
 use tokio::sync::mpsc::Sender;
 
@@ -34,22 +41,41 @@ struct ContextData {
     client: Client,
     config: PinglowConfig,
     event_rx: Sender<RunnableCheckEvent>,
-    shared_checks: SharedChecks,
+    secret_cache: SharedSecrets,
+    internal_errors: InternalErrors,
+    redis_client: RedisClient,
+    db_pool: Pool,
 }
 
+/**
+ * This function watches the checks of a namespace, or of all of them when none is given, and the
+ * resources they reference
+ */
+#[allow(clippy::too_many_arguments)]
 pub async fn watch_resources(
+    client: Client,
     pinglow_config: PinglowConfig,
+    namespace: Option<String>,
     event_rx: Sender<RunnableCheckEvent>,
-    shared_original_checks: SharedChecks,
+    secret_cache: SharedSecrets,
+    internal_errors: InternalErrors,
+    redis_client: RedisClient,
+    db_pool: Pool,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
-    let client = Client::try_default().await?;
-    let checks: Api<Check> = Api::namespaced(client.clone(), &pinglow_config.target_namespace);
-    let scripts: Api<Script> = Api::namespaced(client.clone(), &pinglow_config.target_namespace);
+    let namespace = namespace.as_deref();
+
+    let checks: Api<Check> = scoped_api(&client, namespace);
+    let scripts: Api<Script> = scoped_api(&client, namespace);
+
+    let secrets: Api<Secret> = scoped_api(&client, namespace);
 
-    let secrets: Api<Secret> = Api::namespaced(client.clone(), &pinglow_config.target_namespace);
+    let telegram_channels: Api<TelegramChannel> = scoped_api(&client, namespace);
 
-    let telegram_channels: Api<TelegramChannel> =
-        Api::namespaced(client.clone(), &pinglow_config.target_namespace);
+    let slack_channels: Api<SlackChannel> = scoped_api(&client, namespace);
+
+    let push_channels: Api<PushChannel> = scoped_api(&client, namespace);
+
+    let discord_channels: Api<DiscordChannel> = scoped_api(&client, namespace);
 
     let config = watcher::Config::default();
 
@@ -57,23 +83,44 @@ pub async fn watch_resources(
         client: client.clone(),
         config: pinglow_config.clone(),
         event_rx,
-        shared_checks: shared_original_checks.clone(),
+        secret_cache: secret_cache.clone(),
+        internal_errors,
+        redis_client,
+        db_pool,
     });
 
-    // Set up the controller
-    Controller::new(checks, watcher::Config::default())
+    // Set up the controller, its reflector store holds the checks as seen by the watch stream
+    let controller = Controller::new(checks, watcher::Config::default());
+    let shared_original_checks: SharedChecks = controller.store();
+
+    controller
         .watches(scripts, config.clone(), {
-            let shared = Arc::clone(&shared_original_checks);
+            let shared = shared_original_checks.clone();
             move |script| map_script_to_checks(script, shared.clone())
         })
         .watches(secrets, config.clone(), {
-            let shared = Arc::clone(&shared_original_checks);
-            move |secret| map_secret_to_checks(secret, shared.clone())
+            let shared = shared_original_checks.clone();
+            move |secret| {
+                invalidate_cached_secret(&secret, &secret_cache);
+                map_secret_to_checks(secret, shared.clone())
+            }
         })
-        .watches(telegram_channels, config, {
-            let shared = Arc::clone(&shared_original_checks);
+        .watches(telegram_channels, config.clone(), {
+            let shared = shared_original_checks.clone();
             move |channel| map_channel_to_checks(channel, shared.clone())
         })
+        .watches(slack_channels, config.clone(), {
+            let shared = shared_original_checks.clone();
+            move |channel| map_slack_channel_to_checks(channel, shared.clone())
+        })
+        .watches(push_channels, config.clone(), {
+            let shared = shared_original_checks.clone();
+            move |channel| map_push_channel_to_checks(channel, shared.clone())
+        })
+        .watches(discord_channels, config, {
+            let shared = shared_original_checks.clone();
+            move |channel| map_discord_channel_to_checks(channel, shared.clone())
+        })
         .run(reconcile, error_policy, context)
         .for_each(|res| async move {
             match res {
@@ -88,18 +135,18 @@ pub async fn watch_resources(
 
 /// The reconciler that will be called when either object change
 async fn reconcile(check: Arc<Check>, ctx: Arc<ContextData>) -> Result<Action, ReconcileError> {
-    let check_name =
-        check
-            .metadata
-            .name
-            .as_ref()
-            .ok_or(ReconcileError::PropertyExtractionError(format!(
-                "Error in extracting the name for {check:?}",
-            )))?;
+    let name = check
+        .metadata
+        .name
+        .as_ref()
+        .ok_or(ReconcileError::PropertyExtractionError(format!(
+            "Error in extracting the name for {check:?}",
+        )))?;
 
     let namespace = check
         .namespace()
         .unwrap_or(ctx.config.target_namespace.to_string());
+    let check_name = check_identity(&namespace, name, &ctx.config.target_namespace);
     let client = ctx.client.clone();
     let api: Api<Check> = Api::namespaced(client, &namespace);
 
@@ -114,10 +161,13 @@ async fn reconcile(check: Arc<Check>, ctx: Arc<ContextData>) -> Result<Action, R
                     // Normal reconcile logic
                     info!("Reconciling Check: {}", c.name_any());
 
-                    let runnable_check =
-                        load_single_runnable_check(&check, &ctx.client, &ctx.config).await?;
-
-                    ctx.shared_checks.insert(check_name.clone(), check);
+                    let runnable_check = load_single_runnable_check(
+                        &check,
+                        &ctx.client,
+                        &ctx.config,
+                        &ctx.secret_cache,
+                    )
+                    .await?;
 
                     ctx.event_rx
                         .send(RunnableCheckEvent::AddOrUpdate(Arc::new(runnable_check)))
@@ -131,15 +181,39 @@ async fn reconcile(check: Arc<Check>, ctx: Arc<ContextData>) -> Result<Action, R
                     // Called when the object is being deleted
                     info!("Cleanup for deleted Check: {}", c.name_any());
 
-                    ctx.shared_checks.remove(check_name);
                     ctx.event_rx
-                        .send(RunnableCheckEvent::Remove(check_name.to_string()))
+                        .send(RunnableCheckEvent::Remove(check_name.clone()))
                         .await
                         .map_err(|e| {
                             error!("Failed to send event for check {check_name}: {e:?}");
                             ReconcileError::SendError(format!("Error sending event: {e}"))
                         })?;
 
+                    // The executions not yet picked up by a runner are dropped, and so will be
+                    // the results of the ones in flight
+                    let removed =
+                        teardown::remove_queued_executions(&ctx.redis_client, &check_name)
+                            .await
+                            .map_err(|e| {
+                                ReconcileError::GeneralError(format!(
+                                    "Error removing the queued executions of {check_name}: {e}"
+                                ))
+                            })?;
+                    if removed > 0 {
+                        info!("Removed {removed} queued execution(s) of {check_name}");
+                    }
+
+                    if ctx.config.purge_results_on_delete {
+                        let deleted = teardown::purge_history(&ctx.db_pool, &check_name)
+                            .await
+                            .map_err(|e| {
+                                ReconcileError::GeneralError(format!(
+                                    "Error purging the history of {check_name}: {e}"
+                                ))
+                            })?;
+                        info!("Purged {deleted} row(s) of the history of {check_name}");
+                    }
+
                     // Once this function returns Ok, the finalizer is removed automatically
                     Ok(Action::await_change()) // stop reconciling
                 }
@@ -151,11 +225,23 @@ async fn reconcile(check: Arc<Check>, ctx: Arc<ContextData>) -> Result<Action, R
 }
 /// an error handler that will be called when the reconciler fails with access to both the
 /// object that caused the failure and the actual error
-fn error_policy(obj: Arc<Check>, error: &ReconcileError, _ctx: Arc<ContextData>) -> Action {
+fn error_policy(obj: Arc<Check>, error: &ReconcileError, ctx: Arc<ContextData>) -> Action {
     error!(
         "Reconciliation error for check {:?}: {:?}",
         obj.metadata.name, error
     );
+
+    let check_name = check_identity(
+        &obj.namespace()
+            .unwrap_or(ctx.config.target_namespace.clone()),
+        &obj.name_any(),
+        &ctx.config.target_namespace,
+    );
+    ctx.internal_errors.report(
+        format!("reconcile/{check_name}"),
+        format!("The check {check_name} cannot be reconciled: {error}"),
+    );
+
     Action::requeue(Duration::from_secs(60))
 }
 
@@ -163,21 +249,19 @@ fn map_script_to_checks(
     script: Script,
     shared_original_checks: SharedChecks,
 ) -> Vec<ObjectRef<Check>> {
+    let script_namespace = script.namespace();
     let script_name = script.metadata.name.unwrap_or_default();
 
     let matching_checks: Vec<_> = shared_original_checks
-        .iter()
-        .filter_map(|entry| {
-            if entry
+        .state()
+        .into_iter()
+        .filter(|check| check.namespace() == script_namespace)
+        .filter(|check| {
+            check
                 .spec
                 .scriptRef
                 .as_ref()
                 .is_some_and(|s| *s == script_name)
-            {
-                Some(entry.value().clone())
-            } else {
-                None
-            }
         })
         .collect();
 
@@ -189,29 +273,51 @@ fn map_script_to_checks(
     object_refs
 }
 
+/// Drops a secret from the cache when the watched version differs from the cached one, so that
+/// the next reconcile of the dependent checks fetches the fresh content
+fn invalidate_cached_secret(secret: &Secret, secret_cache: &SharedSecrets) {
+    let Some(secret_name) = secret.metadata.name.as_ref() else {
+        return;
+    };
+
+    let namespace = secret.namespace().unwrap_or_default();
+
+    secret_cache.remove_if(&secret_cache_key(&namespace, secret_name), |_, cached| {
+        cached.secret.metadata.resource_version != secret.metadata.resource_version
+    });
+}
+
 fn map_secret_to_checks(
-    script: Secret,
+    secret: Secret,
     shared_original_checks: SharedChecks,
 ) -> Vec<ObjectRef<Check>> {
-    let secret_name = script.metadata.name.unwrap_or_default();
+    let secret_namespace = secret.namespace();
+    let secret_name = secret.metadata.name.unwrap_or_default();
 
     let matching_checks: Vec<_> = shared_original_checks
-        .iter()
-        .filter_map(|entry| {
-            let matching_secrets: Vec<_> = entry
-                .value()
+        .state()
+        .into_iter()
+        .filter(|check| check.namespace() == secret_namespace)
+        .filter(|check| {
+            let in_secret_refs = check
                 .spec
                 .secretRefs
-                .as_ref()?
-                .iter()
-                .filter(|s| s.to_string() == secret_name)
-                .collect();
+                .as_ref()
+                .is_some_and(|refs| refs.contains(&secret_name));
 
-            if !matching_secrets.is_empty() {
-                Some(entry.value().clone())
-            } else {
-                None
-            }
+            let is_hmac_secret = check
+                .spec
+                .hmacSecretRef
+                .as_ref()
+                .is_some_and(|s| *s == secret_name);
+
+            let is_heartbeat_secret = check
+                .spec
+                .heartbeatTokenRef
+                .as_ref()
+                .is_some_and(|s| *s == secret_name);
+
+            in_secret_refs || is_hmac_secret || is_heartbeat_secret
         })
         .collect();
 
@@ -227,13 +333,15 @@ fn map_channel_to_checks(
     channel: TelegramChannel,
     shared_original_checks: SharedChecks,
 ) -> Vec<ObjectRef<Check>> {
+    let channel_namespace = channel.namespace();
     let channel_name = channel.metadata.name.unwrap_or_default();
 
     let matching_checks: Vec<_> = shared_original_checks
-        .iter()
-        .filter_map(|entry| {
-            let matching_channels: Vec<_> = entry
-                .value()
+        .state()
+        .into_iter()
+        .filter(|check| check.namespace() == channel_namespace)
+        .filter_map(|check| {
+            let matching_channels: Vec<_> = check
                 .spec
                 .telegramChannelRefs
                 .as_ref()?
@@ -242,7 +350,7 @@ fn map_channel_to_checks(
                 .collect();
 
             if !matching_channels.is_empty() {
-                Some(entry.value().clone())
+                Some(check.clone())
             } else {
                 None
             }
@@ -256,3 +364,69 @@ fn map_channel_to_checks(
 
     object_refs
 }
+
+fn map_slack_channel_to_checks(
+    channel: SlackChannel,
+    shared_original_checks: SharedChecks,
+) -> Vec<ObjectRef<Check>> {
+    let channel_namespace = channel.namespace();
+    let channel_name = channel.metadata.name.unwrap_or_default();
+
+    shared_original_checks
+        .state()
+        .into_iter()
+        .filter(|check| check.namespace() == channel_namespace)
+        .filter(|check| {
+            check
+                .spec
+                .slackChannelRefs
+                .as_ref()
+                .is_some_and(|refs| refs.contains(&channel_name))
+        })
+        .map(|check| ObjectRef::from(check.as_ref()))
+        .collect()
+}
+
+fn map_push_channel_to_checks(
+    channel: PushChannel,
+    shared_original_checks: SharedChecks,
+) -> Vec<ObjectRef<Check>> {
+    let channel_namespace = channel.namespace();
+    let channel_name = channel.metadata.name.unwrap_or_default();
+
+    shared_original_checks
+        .state()
+        .into_iter()
+        .filter(|check| check.namespace() == channel_namespace)
+        .filter(|check| {
+            check
+                .spec
+                .pushChannelRefs
+                .as_ref()
+                .is_some_and(|refs| refs.contains(&channel_name))
+        })
+        .map(|check| ObjectRef::from(check.as_ref()))
+        .collect()
+}
+
+fn map_discord_channel_to_checks(
+    channel: DiscordChannel,
+    shared_original_checks: SharedChecks,
+) -> Vec<ObjectRef<Check>> {
+    let channel_namespace = channel.namespace();
+    let channel_name = channel.metadata.name.unwrap_or_default();
+
+    shared_original_checks
+        .state()
+        .into_iter()
+        .filter(|check| check.namespace() == channel_namespace)
+        .filter(|check| {
+            check
+                .spec
+                .discordChannelRefs
+                .as_ref()
+                .is_some_and(|refs| refs.contains(&channel_name))
+        })
+        .map(|check| ObjectRef::from(check.as_ref()))
+        .collect()
+}
@@ -0,0 +1,63 @@
+use std::time::Duration;
+
+use anyhow::Error;
+use deadpool_postgres::{Pool, PoolError};
+use log::{info, warn};
+
+// Bounds of the exponential backoff while waiting for the database
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/**
+ * This function tells whether an error is due to the database being unreachable, rather than
+ * to the data being written, so that the operation can be retried once it is back
+ */
+pub fn is_unavailable(error: &Error) -> bool {
+    if error.downcast_ref::<PoolError>().is_some() {
+        return true;
+    }
+
+    match error.downcast_ref::<tokio_postgres::Error>() {
+        Some(e) => {
+            e.is_closed()
+                || std::error::Error::source(e).is_some_and(|source| source.is::<std::io::Error>())
+        }
+        None => false,
+    }
+}
+
+/**
+ * This function waits, with an exponential backoff, until a connection to the database can be
+ * established again
+ */
+pub async fn wait_until_available(db_pool: &Pool) {
+    let mut backoff = INITIAL_BACKOFF;
+
+    loop {
+        let outcome = match db_pool.get().await {
+            Ok(client) => client
+                .simple_query("SELECT 1")
+                .await
+                .map(|_| ())
+                .map_err(Error::from),
+            Err(e) => Err(e.into()),
+        };
+
+        match outcome {
+            Ok(()) => {
+                if backoff > INITIAL_BACKOFF {
+                    info!("TimescaleDB is reachable again");
+                }
+                return;
+            }
+            Err(e) => {
+                warn!(
+                    "TimescaleDB is unreachable, retrying in {} seconds: {e}",
+                    backoff.as_secs()
+                );
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+        }
+    }
+}
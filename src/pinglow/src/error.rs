@@ -6,6 +6,15 @@ pub enum ReconcileError {
     #[error("TelegramChannel '{0}' not found")]
     TelegramChannelNotFound(String),
 
+    #[error("SlackChannel '{0}' not found")]
+    SlackChannelNotFound(String),
+
+    #[error("PushChannel '{0}' not found")]
+    PushChannelNotFound(String),
+
+    #[error("DiscordChannel '{0}' not found")]
+    DiscordChannelNotFound(String),
+
     #[error("Secret '{0}' not found")]
     SecretNotFound(String),
 
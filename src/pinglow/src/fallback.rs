@@ -0,0 +1,253 @@
+use std::{
+    sync::{LazyLock, RwLock},
+    time::Duration,
+};
+
+use chrono::Utc;
+use deadpool_postgres::Pool;
+use kube::Client;
+use log::{debug, error, info};
+use pinglow_common::{CheckResult, CheckResultStatus};
+use tokio::sync::mpsc;
+
+use crate::{
+    check::SharedSecrets, config::PinglowConfig, error::ReconcileError, notification,
+    notifier::NotificationChannel, resolve_discord_channel, resolve_push_channel,
+    resolve_slack_channel, resolve_telegram_channel,
+};
+
+// How often the fallback channels are resolved again, to follow the changes of their resources
+const REFRESH_INTERVAL: Duration = Duration::from_secs(300);
+
+// An internal error is notified again at most once in this interval while it persists
+const INTERNAL_ERROR_COOLDOWN_SECONDS: u64 = 3600;
+
+// Internal errors waiting to be notified, the ones exceeding it are only logged
+const INTERNAL_ERRORS_CAPACITY: usize = 100;
+
+// Channel notified of the CheckError results of the checks without channels, once resolved
+static CHECK_ERROR_CHANNEL: LazyLock<RwLock<Option<NotificationChannel>>> =
+    LazyLock::new(|| RwLock::new(None));
+
+// Channel notified of the results of the checks without channels and of the internal errors
+static DEFAULT_CHANNEL: LazyLock<RwLock<Option<NotificationChannel>>> =
+    LazyLock::new(|| RwLock::new(None));
+
+/**
+ * This function returns the channel notified of the CheckError results of the checks without
+ * channels, if one is configured and could be resolved
+ */
+pub fn check_error_channel() -> Option<NotificationChannel> {
+    CHECK_ERROR_CHANNEL
+        .read()
+        .ok()
+        .and_then(|channel| channel.clone())
+}
+
+/**
+ * This function returns the channel notified of the results of the checks without channels and of
+ * the internal errors, if one is configured and could be resolved
+ */
+pub fn default_channel() -> Option<NotificationChannel> {
+    DEFAULT_CHANNEL
+        .read()
+        .ok()
+        .and_then(|channel| channel.clone())
+}
+
+/**
+ * This function returns the channel notified of a result of a check without channels: the check
+ * error channel for a CheckError, if any, and the default channel otherwise
+ */
+pub fn channel_for(status: &CheckResultStatus) -> Option<NotificationChannel> {
+    match status {
+        CheckResultStatus::CheckError => check_error_channel().or_else(default_channel),
+        _ => default_channel(),
+    }
+}
+
+/**
+ * This function returns the fallback channels which are currently resolved
+ */
+pub fn channels() -> Vec<NotificationChannel> {
+    check_error_channel()
+        .into_iter()
+        .chain(default_channel())
+        .collect()
+}
+
+/**
+ * This function resolves a channel referenced as kind/name, e.g. telegram/ops, in a namespace
+ */
+pub async fn resolve_channel(
+    reference: &str,
+    namespace: &str,
+    client: &Client,
+    config: &PinglowConfig,
+    secret_cache: &SharedSecrets,
+) -> Result<NotificationChannel, ReconcileError> {
+    let (kind, name) = reference.split_once('/').ok_or_else(|| {
+        ReconcileError::GeneralError(format!("Invalid channel '{reference}', expected kind/name"))
+    })?;
+
+    Ok(match kind {
+        "telegram" => NotificationChannel::Telegram(
+            resolve_telegram_channel(name, namespace, client, config, secret_cache).await?,
+        ),
+        "slack" => NotificationChannel::Slack(
+            resolve_slack_channel(name, namespace, client, config, secret_cache).await?,
+        ),
+        "push" => NotificationChannel::Push(
+            resolve_push_channel(name, namespace, client, config, secret_cache).await?,
+        ),
+        "discord" => NotificationChannel::Discord(
+            resolve_discord_channel(name, namespace, client, config, secret_cache).await?,
+        ),
+        _ => {
+            return Err(ReconcileError::GeneralError(format!(
+                "Unknown channel kind '{kind}' in '{reference}'"
+            )))
+        }
+    })
+}
+
+/**
+ * This function resolves the configured fallback channels and keeps them up to date, a channel
+ * which cannot be resolved any more being kept as it was
+ */
+pub async fn run(client: Client, config: PinglowConfig, secret_cache: SharedSecrets) {
+    let fallbacks: Vec<(String, &RwLock<Option<NotificationChannel>>)> = [
+        (config.check_error_channel.clone(), &*CHECK_ERROR_CHANNEL),
+        (config.default_channel.clone(), &*DEFAULT_CHANNEL),
+    ]
+    .into_iter()
+    .filter_map(|(reference, slot)| reference.map(|reference| (reference, slot)))
+    .collect();
+
+    if fallbacks.is_empty() {
+        return;
+    }
+
+    if let Some(reference) = &config.check_error_channel {
+        info!("Notifying the errors of the checks without channels to {reference}");
+    }
+    if let Some(reference) = &config.default_channel {
+        info!("Notifying the checks without channels and the internal errors to {reference}");
+    }
+
+    let mut interval = tokio::time::interval(REFRESH_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        for (reference, slot) in &fallbacks {
+            match resolve_channel(
+                reference,
+                &config.target_namespace,
+                &client,
+                &config,
+                &secret_cache,
+            )
+            .await
+            {
+                Ok(channel) => {
+                    if let Ok(mut current) = slot.write() {
+                        *current = Some(channel);
+                    }
+                }
+                Err(e) => error!("Error resolving the fallback channel {reference}: {e}"),
+            }
+        }
+    }
+}
+
+/// An error of the controller itself, such as a check which cannot be reconciled or a runner
+/// which stopped sending heartbeats
+#[derive(Debug)]
+pub struct InternalError {
+    /// What failed, e.g. reconcile/my-check, used as check name of the notification
+    pub source: String,
+    pub message: String,
+}
+
+/// The sender of the internal errors to notify to the default channel
+#[derive(Clone)]
+pub struct InternalErrors(mpsc::Sender<InternalError>);
+
+impl InternalErrors {
+    pub fn channel() -> (InternalErrors, mpsc::Receiver<InternalError>) {
+        let (tx, rx) = mpsc::channel(INTERNAL_ERRORS_CAPACITY);
+        (InternalErrors(tx), rx)
+    }
+
+    /**
+     * This function reports an internal error, without waiting for it to be notified
+     */
+    pub fn report(&self, source: String, message: String) {
+        if let Err(e) = self.0.try_send(InternalError { source, message }) {
+            debug!("Internal error not notified: {e}");
+        }
+    }
+}
+
+/**
+ * This function notifies the internal errors to the default channel, each of them at most once
+ * per hour while it persists
+ */
+pub async fn notify_internal_errors(
+    mut errors: mpsc::Receiver<InternalError>,
+    db_pool: Pool,
+    http_client: reqwest::Client,
+) {
+    while let Some(internal_error) = errors.recv().await {
+        let Some(channel) = default_channel() else {
+            continue;
+        };
+
+        let result = CheckResult {
+            check_name: internal_error.source,
+            output: internal_error.message,
+            status: CheckResultStatus::CheckError,
+            timestamp: Some(Utc::now()),
+            telegram_channels: Default::default(),
+            mute_notifications: None,
+            mute_notifications_until: None,
+            maintenance_windows: Default::default(),
+            webhooks: Default::default(),
+            diagnostics: None,
+            slack_channels: Default::default(),
+            push_channels: Default::default(),
+            discord_channels: Default::default(),
+            notify_on_state_change: false,
+            notify_on_recovery: false,
+            renotify_interval_minutes: None,
+            min_notification_interval_seconds: Some(INTERNAL_ERROR_COOLDOWN_SECONDS),
+            soft_failure: false,
+            depends_on: Default::default(),
+            suppressed_by: None,
+            anomaly_sigma: None,
+            script_hash: None,
+            run_id: None,
+        };
+
+        let db_client = match db_pool.get().await {
+            Ok(db_client) => db_client,
+            Err(e) => {
+                error!(
+                    "Error notifying the internal error of {}: {e}",
+                    result.check_name
+                );
+                continue;
+            }
+        };
+
+        if let Err(e) =
+            notification::notify(&db_client, &http_client, &[channel], &result, None, None).await
+        {
+            error!(
+                "Error notifying the internal error of {}: {e}",
+                result.check_name
+            );
+        }
+    }
+}
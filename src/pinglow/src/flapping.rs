@@ -0,0 +1,169 @@
+use chrono::{DateTime, Utc};
+use pinglow_common::CheckResultStatus;
+use tokio_postgres::Client as PostgresClient;
+
+// Results of a check considered to detect whether it is flapping
+const FLAP_WINDOW: i64 = 20;
+
+// Percentage of state changes in the window above which a check starts flapping
+const FLAP_START_THRESHOLD: f64 = 50.0;
+
+// Percentage of state changes in the window below which a check stops flapping
+const FLAP_STOP_THRESHOLD: f64 = 25.0;
+
+/// The flapping state of a check after one of its results
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FlapState {
+    Stable,
+    Started,
+    Flapping,
+    Stopped,
+}
+
+/**
+ * This function returns the percentage of state changes among the last results of a check,
+ * most recent first, or None while the check has not enough results
+ */
+fn state_change_percentage(statuses: &[CheckResultStatus]) -> Option<f64> {
+    if (statuses.len() as i64) < FLAP_WINDOW {
+        return None;
+    }
+
+    let changes = statuses
+        .windows(2)
+        .filter(|pair| pair[0] != pair[1])
+        .count();
+
+    Some(changes as f64 * 100.0 / (statuses.len() - 1) as f64)
+}
+
+/**
+ * This function returns the flapping state of a check given whether it was flapping and the
+ * percentage of state changes among its last results. A check starts flapping when its state
+ * changes too often and stops only once they become rare, so that it does not keep switching
+ * between the two states
+ */
+fn next_state(flapping: bool, percentage: Option<f64>) -> FlapState {
+    match percentage {
+        Some(percentage) if !flapping && percentage >= FLAP_START_THRESHOLD => FlapState::Started,
+        Some(percentage) if flapping && percentage < FLAP_STOP_THRESHOLD => FlapState::Stopped,
+        _ if flapping => FlapState::Flapping,
+        _ => FlapState::Stable,
+    }
+}
+
+/**
+ * This function updates the flapping state of a check after a result is written, reading the
+ * current state and the last results in a single query
+ */
+pub async fn update(
+    db_client: &PostgresClient,
+    check_name: &str,
+) -> Result<FlapState, tokio_postgres::Error> {
+    let row = db_client
+        .query_one(
+            "SELECT EXISTS (SELECT 1 FROM check_flapping WHERE check_name = $1) AS flapping,
+            ARRAY(SELECT status FROM check_result WHERE check_name = $1 AND NOT soft_failure ORDER BY timestamp DESC LIMIT $2) AS statuses",
+            &[&check_name, &FLAP_WINDOW],
+        )
+        .await?;
+
+    let statuses: Vec<CheckResultStatus> = row
+        .get::<_, Vec<i16>>("statuses")
+        .into_iter()
+        .map(CheckResultStatus::from)
+        .collect();
+
+    let state = next_state(row.get("flapping"), state_change_percentage(&statuses));
+
+    match state {
+        FlapState::Started => {
+            db_client
+                .execute(
+                    "INSERT INTO check_flapping (check_name) VALUES ($1) ON CONFLICT DO NOTHING",
+                    &[&check_name],
+                )
+                .await?;
+        }
+        FlapState::Stopped => {
+            db_client
+                .execute(
+                    "DELETE FROM check_flapping WHERE check_name = $1",
+                    &[&check_name],
+                )
+                .await?;
+        }
+        FlapState::Flapping | FlapState::Stable => {}
+    }
+
+    Ok(state)
+}
+
+/**
+ * This function returns since when a check is flapping, if it is
+ */
+pub async fn flapping_since(
+    db_client: &PostgresClient,
+    check_name: &str,
+) -> Result<Option<DateTime<Utc>>, tokio_postgres::Error> {
+    let row = db_client
+        .query_opt(
+            "SELECT started_at FROM check_flapping WHERE check_name = $1",
+            &[&check_name],
+        )
+        .await?;
+
+    Ok(row.map(|row| row.get("started_at")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /**
+     * This function returns the statuses of a window with the given number of state changes
+     */
+    fn window_with_changes(changes: usize) -> Vec<CheckResultStatus> {
+        (0..FLAP_WINDOW as usize)
+            .map(|i| {
+                if i.min(changes) % 2 == 1 {
+                    CheckResultStatus::Critical
+                } else {
+                    CheckResultStatus::Ok
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn flap_ratio() {
+        assert_eq!(state_change_percentage(&window_with_changes(0)), Some(0.0));
+        assert_eq!(
+            state_change_percentage(&window_with_changes(FLAP_WINDOW as usize)),
+            Some(100.0)
+        );
+        assert_eq!(state_change_percentage(&window_with_changes(0)[..5]), None);
+    }
+
+    #[test]
+    fn enter_and_leave_thresholds() {
+        // 10 changes out of 19 pairs start the flapping, 9 do not
+        let entering = state_change_percentage(&window_with_changes(10));
+        let below = state_change_percentage(&window_with_changes(9));
+        assert_eq!(next_state(false, entering), FlapState::Started);
+        assert_eq!(next_state(false, below), FlapState::Stable);
+
+        // Between the thresholds, a flapping check keeps flapping
+        assert_eq!(next_state(true, below), FlapState::Flapping);
+
+        // 4 changes out of 19 pairs stop the flapping, 5 do not
+        let leaving = state_change_percentage(&window_with_changes(4));
+        let above = state_change_percentage(&window_with_changes(5));
+        assert_eq!(next_state(true, leaving), FlapState::Stopped);
+        assert_eq!(next_state(true, above), FlapState::Flapping);
+
+        // Without enough results the state does not change
+        assert_eq!(next_state(false, None), FlapState::Stable);
+        assert_eq!(next_state(true, None), FlapState::Flapping);
+    }
+}
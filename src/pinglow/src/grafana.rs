@@ -0,0 +1,339 @@
+use chrono::{DateTime, Utc};
+use rocket::{get, http::Status, post, response::status, serde::json::Json, State};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{
+    api::{ApiKey, DbClient},
+    check::SharedPinglowChecks,
+};
+
+// Separator between the check name and the performance data key in a target
+const PERF_DATA_SEPARATOR: char = ':';
+
+// Maximum number of annotations returned by a single request
+const MAX_ANNOTATIONS: i64 = 1000;
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct GrafanaRange {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct GrafanaSearchRequest {
+    #[serde(default)]
+    pub target: Option<String>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct GrafanaMetricsRequest {
+    #[serde(default)]
+    pub metric: Option<String>, // The metric whose payloads are requested, all metrics if empty
+}
+
+/// A metric as listed by the recent versions of the JSON datasource
+#[derive(Debug, Serialize, ToSchema)]
+pub struct GrafanaMetric {
+    pub label: String,
+    pub value: String, // The target of the queries
+    #[schema(value_type = Vec<Object>)]
+    pub payloads: Vec<serde_json::Value>, // Options of the metric, none are supported
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct GrafanaTarget {
+    pub target: String,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct GrafanaQueryRequest {
+    pub range: GrafanaRange,
+    pub targets: Vec<GrafanaTarget>,
+    #[serde(default)]
+    pub max_data_points: Option<usize>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct GrafanaTimeSeries {
+    pub target: String,
+    pub datapoints: Vec<(f64, i64)>, // (value, unix timestamp in milliseconds)
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct GrafanaAnnotationQuery {
+    pub name: Option<String>,
+    pub query: Option<String>, // The check name, all checks if empty
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct GrafanaAnnotationsRequest {
+    pub range: GrafanaRange,
+    pub annotation: GrafanaAnnotationQuery,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct GrafanaAnnotation {
+    pub annotation: Option<String>,
+    pub time: i64,
+    pub title: String,
+    pub text: String,
+    pub tags: Vec<String>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/grafana",
+    responses(
+        (status = 200, description = "The datasource is reachable")
+    )
+)]
+#[get("/")]
+pub async fn grafana_test(_key: ApiKey) -> Status {
+    Status::Ok
+}
+
+#[utoipa::path(
+    post,
+    path = "/grafana/search",
+    request_body = GrafanaSearchRequest,
+    responses(
+        (status = 200, description = "The available targets: check names for the status and <check>:<key> for the performance data", body = [String])
+    )
+)]
+#[post("/search", data = "<request>")]
+pub async fn grafana_search(
+    _key: ApiKey,
+    checks: &State<SharedPinglowChecks>,
+    client: DbClient,
+    request: Json<GrafanaSearchRequest>,
+) -> Result<Json<Vec<String>>, status::Custom<String>> {
+    let mut targets: Vec<String> = targets(checks, &client)
+        .await?
+        .into_iter()
+        .map(|(target, _)| target)
+        .filter(|target| {
+            request
+                .target
+                .as_ref()
+                .is_none_or(|filter| target.contains(filter.as_str()))
+        })
+        .collect();
+
+    targets.sort();
+
+    Ok(Json(targets))
+}
+
+#[utoipa::path(
+    post,
+    path = "/grafana/metrics",
+    request_body = GrafanaMetricsRequest,
+    responses(
+        (status = 200, description = "The available metrics: the status of each check and its performance data keys", body = [GrafanaMetric])
+    )
+)]
+#[post("/metrics", data = "<request>")]
+pub async fn grafana_metrics(
+    _key: ApiKey,
+    checks: &State<SharedPinglowChecks>,
+    client: DbClient,
+    request: Json<GrafanaMetricsRequest>,
+) -> Result<Json<Vec<GrafanaMetric>>, status::Custom<String>> {
+    let mut metrics: Vec<GrafanaMetric> = targets(checks, &client)
+        .await?
+        .into_iter()
+        .filter(|(target, _)| {
+            request
+                .metric
+                .as_ref()
+                .filter(|metric| !metric.is_empty())
+                .is_none_or(|metric| target == metric)
+        })
+        .map(|(value, label)| GrafanaMetric {
+            label,
+            value,
+            payloads: vec![],
+        })
+        .collect();
+
+    metrics.sort_by(|a, b| a.value.cmp(&b.value));
+
+    Ok(Json(metrics))
+}
+
+#[utoipa::path(
+    post,
+    path = "/grafana/metric-payload-options",
+    responses(
+        (status = 200, description = "The options of the payloads of a metric, none since the metrics have no payloads", body = [GrafanaMetric])
+    )
+)]
+#[post("/metric-payload-options")]
+pub async fn grafana_metric_payload_options(_key: ApiKey) -> Json<Vec<GrafanaMetric>> {
+    Json(vec![])
+}
+
+/**
+ * This function returns the targets which can be queried along with their labels: the status of
+ * each check and each of its performance data keys
+ */
+async fn targets(
+    checks: &SharedPinglowChecks,
+    client: &DbClient,
+) -> Result<Vec<(String, String)>, status::Custom<String>> {
+    let check_names: Vec<String> = checks.iter().map(|check| check.key().clone()).collect();
+
+    let perf_keys = client
+        .query(
+            "SELECT DISTINCT check_name, perf_key FROM check_result_perf_data WHERE check_name = ANY($1)",
+            &[&check_names],
+        )
+        .await
+        .map_err(|e| {
+            status::Custom(
+                Status::InternalServerError,
+                format!("Error retrieving the performance data keys: {e}"),
+            )
+        })?;
+
+    Ok(check_names
+        .into_iter()
+        .map(|check_name| {
+            let label = format!("{check_name} status");
+            (check_name, label)
+        })
+        .chain(perf_keys.iter().map(|row| {
+            let check_name: String = row.get("check_name");
+            let perf_key: String = row.get("perf_key");
+            (
+                format!("{check_name}{PERF_DATA_SEPARATOR}{perf_key}"),
+                format!("{check_name} {perf_key}"),
+            )
+        }))
+        .collect())
+}
+
+#[utoipa::path(
+    post,
+    path = "/grafana/query",
+    request_body = GrafanaQueryRequest,
+    responses(
+        (status = 200, description = "The time series of the requested targets", body = [GrafanaTimeSeries])
+    )
+)]
+#[post("/query", data = "<request>")]
+pub async fn grafana_query(
+    _key: ApiKey,
+    client: DbClient,
+    request: Json<GrafanaQueryRequest>,
+) -> Result<Json<Vec<GrafanaTimeSeries>>, status::Custom<String>> {
+    let mut series = Vec::with_capacity(request.targets.len());
+
+    for target in request.targets.iter() {
+        // A target is either a check (its status) or a performance data key of a check
+        let rows = match target.target.split_once(PERF_DATA_SEPARATOR) {
+            Some((check_name, perf_key)) => {
+                client
+                    .query(
+                        "SELECT timestamp, perf_value::float8 AS value FROM check_result_perf_data WHERE check_name = $1 AND perf_key = $2 AND timestamp BETWEEN $3 AND $4 ORDER BY timestamp",
+                        &[&check_name, &perf_key, &request.range.from, &request.range.to],
+                    )
+                    .await
+            }
+            None => {
+                client
+                    .query(
+                        "SELECT timestamp, status::float8 AS value FROM check_result WHERE check_name = $1 AND timestamp BETWEEN $2 AND $3 ORDER BY timestamp",
+                        &[&target.target, &request.range.from, &request.range.to],
+                    )
+                    .await
+            }
+        }
+        .map_err(|e| {
+            status::Custom(
+                Status::InternalServerError,
+                format!("Error retrieving the data of {}: {e}", target.target),
+            )
+        })?;
+
+        // Keep at most the number of points Grafana is able to display
+        let step = request
+            .max_data_points
+            .filter(|max| *max > 0)
+            .map(|max| rows.len().div_ceil(max).max(1))
+            .unwrap_or(1);
+
+        let datapoints = rows
+            .iter()
+            .step_by(step)
+            .map(|row| {
+                let timestamp: DateTime<Utc> = row.get("timestamp");
+                (row.get("value"), timestamp.timestamp_millis())
+            })
+            .collect();
+
+        series.push(GrafanaTimeSeries {
+            target: target.target.clone(),
+            datapoints,
+        });
+    }
+
+    Ok(Json(series))
+}
+
+#[utoipa::path(
+    post,
+    path = "/grafana/annotations",
+    request_body = GrafanaAnnotationsRequest,
+    responses(
+        (status = 200, description = "The status changes of the checks in the requested range", body = [GrafanaAnnotation])
+    )
+)]
+#[post("/annotations", data = "<request>")]
+pub async fn grafana_annotations(
+    _key: ApiKey,
+    client: DbClient,
+    request: Json<GrafanaAnnotationsRequest>,
+) -> Result<Json<Vec<GrafanaAnnotation>>, status::Custom<String>> {
+    let check_name = request
+        .annotation
+        .query
+        .as_ref()
+        .filter(|query| !query.is_empty());
+
+    // Only the results changing the status of a check are relevant as annotations
+    let rows = client
+        .query(
+            "SELECT timestamp, check_name, status, output FROM (SELECT timestamp, check_name, status, output, LAG(status) OVER (PARTITION BY check_name ORDER BY timestamp) AS previous_status FROM check_result WHERE timestamp BETWEEN $1 AND $2 AND ($3::text IS NULL OR check_name = $3)) AS results WHERE previous_status IS DISTINCT FROM status ORDER BY timestamp LIMIT $4",
+            &[&request.range.from, &request.range.to, &check_name, &MAX_ANNOTATIONS],
+        )
+        .await
+        .map_err(|e| {
+            status::Custom(
+                Status::InternalServerError,
+                format!("Error retrieving the status changes: {e}"),
+            )
+        })?;
+
+    let annotations = rows
+        .iter()
+        .map(|row| {
+            let timestamp: DateTime<Utc> = row.get("timestamp");
+            let check_name: String = row.get("check_name");
+            let status: i16 = row.get("status");
+            let status = pinglow_common::CheckResultStatus::from(status);
+
+            GrafanaAnnotation {
+                annotation: request.annotation.name.clone(),
+                time: timestamp.timestamp_millis(),
+                title: format!("{check_name}: {status:?}"),
+                text: row.get("output"),
+                tags: vec![check_name, format!("{status:?}")],
+            }
+        })
+        .collect();
+
+    Ok(Json(annotations))
+}
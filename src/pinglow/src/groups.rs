@@ -0,0 +1,529 @@
+use std::{collections::HashMap, time::Duration};
+
+use anyhow::Error;
+use chrono::Utc;
+use deadpool_postgres::Pool;
+use kube::{
+    api::{ListParams, Patch, PatchParams},
+    Api, Client, ResourceExt,
+};
+use log::{error, info};
+use pinglow_common::{
+    dto::{CheckGroupDto, GroupMemberDto},
+    CheckResult, CheckResultStatus,
+};
+use serde_json::json;
+use tokio_postgres::Client as PostgresClient;
+
+use crate::{
+    check::{
+        check_identity, qualify_check_ref, CheckGroup, CheckGroupSpec, GroupAggregation,
+        SharedPinglowChecks, SharedSecrets,
+    },
+    config::PinglowConfig,
+    notification,
+    notifier::NotificationChannel,
+    resolve_discord_channel, resolve_push_channel, resolve_slack_channel, resolve_telegram_channel,
+    scoped_api,
+};
+
+const EVALUATION_INTERVAL: Duration = Duration::from_secs(60);
+
+// Share of the weight of the members failing which makes a weighted group Critical by default
+const DEFAULT_CRITICAL_SHARE: f64 = 0.5;
+
+/**
+ * This function returns the identities of the members of a group: the checks it lists, by name
+ * in its namespace or as namespace/name, and the ones of its namespace having all the labels of
+ * its selector
+ */
+pub fn members(
+    spec: &CheckGroupSpec,
+    namespace: &str,
+    config: &PinglowConfig,
+    shared_checks: &SharedPinglowChecks,
+) -> Vec<String> {
+    let mut members: Vec<String> = spec
+        .checks
+        .iter()
+        .flatten()
+        .map(|reference| qualify_check_ref(reference, namespace, &config.target_namespace))
+        .collect();
+
+    if let Some(selector) = spec.selector.as_ref().filter(|s| !s.is_empty()) {
+        members.extend(
+            shared_checks
+                .iter()
+                .filter(|check| check.namespace.as_deref() == Some(namespace))
+                .filter(|check| {
+                    selector
+                        .iter()
+                        .all(|(key, value)| check.labels.get(key) == Some(value))
+                })
+                .map(|check| check.check_name.clone()),
+        );
+    }
+
+    members.sort();
+    members.dedup();
+    members
+}
+
+/**
+ * This function returns the last status of each member, other than a soft failure, Pending for
+ * the ones without results
+ */
+async fn member_statuses(
+    db_client: &PostgresClient,
+    members: &[String],
+) -> Result<Vec<GroupMemberDto>, tokio_postgres::Error> {
+    let rows = db_client
+        .query(
+            "SELECT names.check_name, r.status FROM UNNEST($1::text[]) AS names(check_name)
+            JOIN LATERAL (
+                SELECT status FROM check_result WHERE check_name = names.check_name AND NOT soft_failure ORDER BY timestamp DESC LIMIT 1
+            ) r ON TRUE",
+            &[&members],
+        )
+        .await?;
+
+    let statuses: HashMap<String, i16> = rows
+        .iter()
+        .map(|row| (row.get("check_name"), row.get("status")))
+        .collect();
+
+    Ok(members
+        .iter()
+        .map(|check_name| GroupMemberDto {
+            check_name: check_name.clone(),
+            status: statuses
+                .get(check_name)
+                .map(|status| CheckResultStatus::from(*status))
+                .unwrap_or(CheckResultStatus::Pending),
+        })
+        .collect())
+}
+
+/**
+ * This function computes the status of a group from the ones of its members, the pending members
+ * being left out. A group without any result is Pending
+ */
+pub fn aggregate(spec: &CheckGroupSpec, members: &[GroupMemberDto]) -> CheckResultStatus {
+    let known: Vec<&GroupMemberDto> = members
+        .iter()
+        .filter(|member| member.status != CheckResultStatus::Pending)
+        .collect();
+
+    if known.is_empty() {
+        return CheckResultStatus::Pending;
+    }
+
+    match spec.aggregation.clone().unwrap_or_default() {
+        GroupAggregation::WorstOf => known
+            .iter()
+            .map(|member| member.status.clone())
+            .max_by_key(CheckResultStatus::severity)
+            .unwrap_or(CheckResultStatus::Pending),
+        GroupAggregation::Quorum => {
+            let quorum = spec.quorum.unwrap_or(members.len() / 2 + 1);
+            let ok = known
+                .iter()
+                .filter(|member| member.status == CheckResultStatus::Ok)
+                .count();
+            let ok_or_warning = known
+                .iter()
+                .filter(|member| {
+                    matches!(
+                        member.status,
+                        CheckResultStatus::Ok | CheckResultStatus::Warning
+                    )
+                })
+                .count();
+
+            if ok >= quorum {
+                CheckResultStatus::Ok
+            } else if ok_or_warning >= quorum {
+                CheckResultStatus::Warning
+            } else {
+                CheckResultStatus::Critical
+            }
+        }
+        GroupAggregation::Weighted => {
+            let weight = |member: &GroupMemberDto| {
+                spec.weights
+                    .as_ref()
+                    .and_then(|weights| weights.get(&member.check_name))
+                    .copied()
+                    .unwrap_or(1.0)
+                    .max(0.0)
+            };
+            let weight_of = |statuses: &[CheckResultStatus]| -> f64 {
+                known
+                    .iter()
+                    .filter(|member| statuses.contains(&member.status))
+                    .map(|member| weight(member))
+                    .sum()
+            };
+
+            let total: f64 = known.iter().map(|member| weight(member)).sum();
+            let failing = weight_of(&[CheckResultStatus::Critical, CheckResultStatus::CheckError]);
+            let degraded = weight_of(&[CheckResultStatus::Warning]);
+
+            if total <= 0.0 {
+                CheckResultStatus::Pending
+            } else if failing / total >= spec.criticalShare.unwrap_or(DEFAULT_CRITICAL_SHARE) {
+                CheckResultStatus::Critical
+            } else if failing + degraded > 0.0 {
+                CheckResultStatus::Warning
+            } else {
+                CheckResultStatus::Ok
+            }
+        }
+    }
+}
+
+/**
+ * This function computes the status of a group along with the ones of its members
+ */
+pub async fn evaluate(
+    group: &CheckGroup,
+    shared_checks: &SharedPinglowChecks,
+    config: &PinglowConfig,
+    db_client: &PostgresClient,
+) -> Result<CheckGroupDto, tokio_postgres::Error> {
+    let namespace = group
+        .namespace()
+        .unwrap_or_else(|| config.target_namespace.clone());
+
+    let members = members(&group.spec, &namespace, config, shared_checks);
+    let members = member_statuses(db_client, &members).await?;
+    let aggregation = group.spec.aggregation.clone().unwrap_or_default();
+
+    // The weights are keyed like the checks of the group, by name in its namespace
+    let mut spec = group.spec.clone();
+    spec.weights = spec.weights.map(|weights| {
+        weights
+            .into_iter()
+            .map(|(check, weight)| {
+                let check = qualify_check_ref(&check, &namespace, &config.target_namespace);
+                (check, weight)
+            })
+            .collect()
+    });
+
+    Ok(CheckGroupDto {
+        name: check_identity(&namespace, &group.name_any(), &config.target_namespace),
+        aggregation: serde_json::to_value(&aggregation)
+            .ok()
+            .and_then(|value| value.as_str().map(str::to_string))
+            .unwrap_or_default(),
+        status: aggregate(&spec, &members),
+        members,
+    })
+}
+
+/**
+ * This function lists the groups of the watched namespaces
+ */
+pub async fn list_groups(
+    client: &Client,
+    config: &PinglowConfig,
+) -> Result<Vec<CheckGroup>, kube::Error> {
+    let mut groups = vec![];
+
+    for scope in config.watch_scopes() {
+        let api: Api<CheckGroup> = scoped_api(client, scope.as_deref());
+        groups.extend(api.list(&ListParams::default()).await?);
+    }
+
+    Ok(groups)
+}
+
+/**
+ * This function periodically evaluates the groups, recording their status in their status
+ * subresource and notifying its changes to the channels of the group
+ */
+pub async fn run(
+    client: Client,
+    db_pool: Pool,
+    http_client: reqwest::Client,
+    shared_checks: SharedPinglowChecks,
+    config: PinglowConfig,
+    secret_cache: SharedSecrets,
+) {
+    let mut interval = tokio::time::interval(EVALUATION_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let groups = match list_groups(&client, &config).await {
+            Ok(groups) => groups,
+            Err(e) => {
+                error!("Error listing the check groups: {e}");
+                continue;
+            }
+        };
+
+        for group in groups {
+            if let Err(e) = update_group(
+                &group,
+                &client,
+                &db_pool,
+                &http_client,
+                &shared_checks,
+                &config,
+                &secret_cache,
+            )
+            .await
+            {
+                error!("Error evaluating the check group {}: {e}", group.name_any());
+            }
+        }
+    }
+}
+
+/**
+ * This function evaluates a group and, when its status changed, records and notifies it. The
+ * first evaluation of a group and the ones leaving Pending for Ok are not notified
+ */
+#[allow(clippy::too_many_arguments)]
+async fn update_group(
+    group: &CheckGroup,
+    client: &Client,
+    db_pool: &Pool,
+    http_client: &reqwest::Client,
+    shared_checks: &SharedPinglowChecks,
+    config: &PinglowConfig,
+    secret_cache: &SharedSecrets,
+) -> Result<(), Error> {
+    let db_client = db_pool.get().await?;
+    let evaluation = evaluate(group, shared_checks, config, &db_client).await?;
+
+    let status = format!("{:?}", evaluation.status);
+    let current = group.status.clone().unwrap_or_default();
+
+    if current.status.as_deref() == Some(status.as_str())
+        && current.members == Some(evaluation.members.len())
+    {
+        return Ok(());
+    }
+
+    let changed = current.status.as_deref() != Some(status.as_str());
+    let last_transition_time = match changed {
+        true => Some(Utc::now()),
+        false => current.lastTransitionTime,
+    };
+
+    let namespace = group
+        .namespace()
+        .unwrap_or_else(|| config.target_namespace.clone());
+    let api: Api<CheckGroup> = Api::namespaced(client.clone(), &namespace);
+
+    api.patch_status(
+        &group.name_any(),
+        &PatchParams::default(),
+        &Patch::Merge(json!({
+            "status": {
+                "status": status,
+                "members": evaluation.members.len(),
+                "lastTransitionTime": last_transition_time,
+            }
+        })),
+    )
+    .await?;
+
+    let notable = match current.status.as_deref() {
+        None => false,
+        Some(previous) => {
+            changed
+                && evaluation.status != CheckResultStatus::Pending
+                && !(previous == "Pending" && evaluation.status == CheckResultStatus::Ok)
+        }
+    };
+
+    if !notable {
+        return Ok(());
+    }
+
+    info!("The check group {} is now {status}", evaluation.name);
+
+    let channels = resolve_channels(&group.spec, &namespace, client, config, secret_cache).await?;
+    if channels.is_empty() {
+        return Ok(());
+    }
+
+    let failing: Vec<String> = evaluation
+        .members
+        .iter()
+        .filter(|member| {
+            !matches!(
+                member.status,
+                CheckResultStatus::Ok | CheckResultStatus::Pending
+            )
+        })
+        .map(|member| format!("{} ({:?})", member.check_name, member.status))
+        .collect();
+
+    let output = match failing.is_empty() {
+        true => format!("All the {} members are Ok", evaluation.members.len()),
+        false => format!(
+            "{} of {} members not Ok: {}",
+            failing.len(),
+            evaluation.members.len(),
+            failing.join(", ")
+        ),
+    };
+
+    let result = CheckResult {
+        check_name: format!("group/{}", evaluation.name),
+        output,
+        status: evaluation.status,
+        timestamp: Some(Utc::now()),
+        telegram_channels: Default::default(),
+        mute_notifications: None,
+        mute_notifications_until: None,
+        maintenance_windows: Default::default(),
+        webhooks: Default::default(),
+        diagnostics: None,
+        slack_channels: Default::default(),
+        push_channels: Default::default(),
+        discord_channels: Default::default(),
+        notify_on_state_change: true,
+        notify_on_recovery: true,
+        renotify_interval_minutes: None,
+        min_notification_interval_seconds: None,
+        soft_failure: false,
+        depends_on: Default::default(),
+        suppressed_by: None,
+        anomaly_sigma: None,
+        script_hash: None,
+        run_id: None,
+    };
+
+    notification::notify(&db_client, http_client, &channels, &result, None, None).await
+}
+
+/**
+ * This function resolves the notification channels of a group, which are in its namespace
+ */
+async fn resolve_channels(
+    spec: &CheckGroupSpec,
+    namespace: &str,
+    client: &Client,
+    config: &PinglowConfig,
+    secret_cache: &SharedSecrets,
+) -> Result<Vec<NotificationChannel>, Error> {
+    let mut channels = vec![];
+
+    for channel in spec.telegramChannelRefs.iter().flatten() {
+        channels.push(NotificationChannel::Telegram(
+            resolve_telegram_channel(channel, namespace, client, config, secret_cache).await?,
+        ));
+    }
+    for channel in spec.slackChannelRefs.iter().flatten() {
+        channels.push(NotificationChannel::Slack(
+            resolve_slack_channel(channel, namespace, client, config, secret_cache).await?,
+        ));
+    }
+    for channel in spec.pushChannelRefs.iter().flatten() {
+        channels.push(NotificationChannel::Push(
+            resolve_push_channel(channel, namespace, client, config, secret_cache).await?,
+        ));
+    }
+    for channel in spec.discordChannelRefs.iter().flatten() {
+        channels.push(NotificationChannel::Discord(
+            resolve_discord_channel(channel, namespace, client, config, secret_cache).await?,
+        ));
+    }
+
+    Ok(channels)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    fn spec(aggregation: GroupAggregation) -> CheckGroupSpec {
+        CheckGroupSpec {
+            checks: None,
+            selector: None,
+            aggregation: Some(aggregation),
+            quorum: None,
+            weights: None,
+            criticalShare: None,
+            telegramChannelRefs: None,
+            slackChannelRefs: None,
+            pushChannelRefs: None,
+            discordChannelRefs: None,
+        }
+    }
+
+    fn members(statuses: &[(&str, CheckResultStatus)]) -> Vec<GroupMemberDto> {
+        statuses
+            .iter()
+            .map(|(check_name, status)| GroupMemberDto {
+                check_name: check_name.to_string(),
+                status: status.clone(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn worst_of() {
+        let group = members(&[
+            ("a", CheckResultStatus::Ok),
+            ("b", CheckResultStatus::Warning),
+            ("c", CheckResultStatus::Pending),
+        ]);
+
+        assert_eq!(
+            aggregate(&spec(GroupAggregation::WorstOf), &group),
+            CheckResultStatus::Warning
+        );
+        assert_eq!(
+            aggregate(&spec(GroupAggregation::WorstOf), &group[2..]),
+            CheckResultStatus::Pending
+        );
+    }
+
+    #[test]
+    fn quorum() {
+        let group = members(&[
+            ("a", CheckResultStatus::Ok),
+            ("b", CheckResultStatus::Ok),
+            ("c", CheckResultStatus::Warning),
+            ("d", CheckResultStatus::Critical),
+        ]);
+
+        // A majority of the 4 members is 3
+        assert_eq!(
+            aggregate(&spec(GroupAggregation::Quorum), &group),
+            CheckResultStatus::Warning
+        );
+
+        let mut two = spec(GroupAggregation::Quorum);
+        two.quorum = Some(2);
+        assert_eq!(aggregate(&two, &group), CheckResultStatus::Ok);
+
+        let mut all = spec(GroupAggregation::Quorum);
+        all.quorum = Some(4);
+        assert_eq!(aggregate(&all, &group), CheckResultStatus::Critical);
+    }
+
+    #[test]
+    fn weighted() {
+        let group = members(&[
+            ("primary", CheckResultStatus::Critical),
+            ("replica-1", CheckResultStatus::Ok),
+            ("replica-2", CheckResultStatus::Ok),
+        ]);
+
+        let mut weighted = spec(GroupAggregation::Weighted);
+        assert_eq!(aggregate(&weighted, &group), CheckResultStatus::Warning);
+
+        weighted.weights = Some(BTreeMap::from([("primary".to_string(), 2.0)]));
+        assert_eq!(aggregate(&weighted, &group), CheckResultStatus::Critical);
+    }
+}
@@ -0,0 +1,660 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::Error;
+use chrono::Utc;
+use k8s_openapi::api::{
+    batch::v1::Job,
+    core::v1::{ConfigMap, Event, Pod},
+};
+use kube::{
+    api::{DeleteParams, ListParams, LogParams, PostParams},
+    Api, Client,
+};
+use log::{debug, error};
+use pinglow_common::{
+    error::SerializeError, source::read_content, CheckJobSpec, CheckResult, CheckResultStatus,
+    PinglowCheck, Script, ScriptLanguage, ScriptSpec, DIAGNOSTICS_LOG_LINES,
+};
+use redis::Client as RedisClient;
+use serde_json::json;
+
+use crate::check::map_command_exit_code_to_check_result;
+
+// Images used when the check does not specify one, by language of the script
+const DEFAULT_IMAGE: &str = "python:3.11-slim";
+const DEFAULT_NODE_IMAGE: &str = "node:20-slim";
+const DEFAULT_POWERSHELL_IMAGE: &str = "mcr.microsoft.com/powershell:latest";
+
+// How often the status of a running Job is polled
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+// Maximum duration of a Job without activeDeadlineSeconds, after which its result is abandoned
+const DEFAULT_MAX_DURATION: Duration = Duration::from_secs(3600);
+
+// Installs the requirements (keeping their output out of the check output) and runs the script
+const JOB_COMMAND: &str = r#"if [ -n "$PINGLOW_REQUIREMENTS" ]; then pip install -q $PINGLOW_REQUIREMENTS > /tmp/pip.log 2>&1 || { cat /tmp/pip.log; exit 3; }; fi; exec python -c "$PINGLOW_SCRIPT" "$@""#;
+
+// Installs the npm packages next to the script, where node looks for them, and runs it
+const NODE_JOB_COMMAND: &str = r#"mkdir -p /tmp/pinglow && cd /tmp/pinglow && printf '%s' "$PINGLOW_SCRIPT" > script.js || exit 3; if [ -n "$PINGLOW_REQUIREMENTS" ]; then npm install --no-save --no-audit --no-fund $PINGLOW_REQUIREMENTS > npm.log 2>&1 || { cat npm.log; exit 3; }; fi; exec node script.js "$@""#;
+
+const BASH_JOB_COMMAND: &str = r#"exec bash -c "$PINGLOW_SCRIPT" pinglow "$@""#;
+
+const POWERSHELL_JOB_COMMAND: &str = r#"printf '%s' "$PINGLOW_SCRIPT" > /tmp/script.ps1 || exit 3; exec pwsh -NoProfile -NonInteractive -File /tmp/script.ps1 "$@""#;
+
+// Runs the script in the custom images, the arguments being appended quoted to the command
+const POWERSHELL_DIRECT_COMMAND: &str = "& ([scriptblock]::Create($env:PINGLOW_SCRIPT))";
+
+/// What a Job produced: the output of its pod, the exit code and, on CheckError, the diagnostics
+struct JobOutcome {
+    output: String,
+    exit_code: Option<i32>,
+    diagnostics: Option<String>,
+    script_hash: Option<String>, // Hash of the script run by the Job, as read from its source
+}
+
+/// A Job created for a check, still to be waited for
+struct StartedJob {
+    jobs: Api<Job>,
+    pods: Api<Pod>,
+    events: Api<Event>,
+    job_name: String,
+    script_hash: String, // Hash of the script run by the Job, as read from its source
+}
+
+/// Runs the checks which request it as Kubernetes Jobs, in place of the Redis runners
+#[derive(Clone)]
+pub struct JobExecutor {
+    client: Option<Client>,
+    namespace: String, // Namespace of the Jobs of the checks without one
+    redis_client: RedisClient,
+}
+
+impl JobExecutor {
+    pub fn new(client: Option<Client>, namespace: String, redis_client: RedisClient) -> Self {
+        JobExecutor {
+            client,
+            namespace,
+            redis_client,
+        }
+    }
+
+    /**
+     * This function runs the check in a Job, waits for its completion and sends back the result
+     * through the results stream, as the runners do
+     */
+    pub async fn execute(&self, check: Arc<PinglowCheck>) {
+        let outcome = match self.create_job(&check).await {
+            Ok(started) => self.collect_job(&check, started).await,
+            Err(e) => Err(e),
+        };
+
+        self.publish_outcome(&check, outcome).await;
+    }
+
+    /**
+     * This function creates the Job of the check, failing if it cannot be created, and sends back
+     * its result in the background once it completes
+     */
+    pub async fn start(&self, check: Arc<PinglowCheck>) -> Result<(), Error> {
+        let started = self.create_job(&check).await?;
+
+        let executor = self.clone();
+        tokio::spawn(async move {
+            let outcome = executor.collect_job(&check, started).await;
+            executor.publish_outcome(&check, outcome).await;
+        });
+
+        Ok(())
+    }
+
+    /**
+     * This function sends back the result of a Job, a CheckError if it could not be run
+     */
+    async fn publish_outcome(&self, check: &PinglowCheck, outcome: Result<JobOutcome, Error>) {
+        let (output, status, diagnostics, script_hash) = match outcome {
+            Ok(outcome) => (
+                outcome.output,
+                map_command_exit_code_to_check_result(check, outcome.exit_code),
+                outcome.diagnostics,
+                outcome.script_hash,
+            ),
+            Err(e) => (
+                format!("Error running the check job: {e}"),
+                CheckResultStatus::CheckError,
+                None,
+                None,
+            ),
+        };
+
+        let result = CheckResult {
+            check_name: check.check_name.clone(),
+            output,
+            status,
+            timestamp: Some(Utc::now()),
+            telegram_channels: check.telegram_channels.clone().into(),
+            mute_notifications: check.mute_notifications,
+            mute_notifications_until: check.mute_notifications_until,
+            maintenance_windows: check.maintenance_windows.clone().into(),
+            webhooks: check.webhooks.clone().into(),
+            diagnostics,
+            slack_channels: check.slack_channels.clone().into(),
+            push_channels: check.push_channels.clone().into(),
+            discord_channels: check.discord_channels.clone().into(),
+            notify_on_state_change: check.notify_on_state_change,
+            notify_on_recovery: check.notify_on_recovery,
+            renotify_interval_minutes: check.renotify_interval_minutes,
+            min_notification_interval_seconds: check.min_notification_interval_seconds,
+            soft_failure: false,
+            depends_on: check.depends_on.clone().into(),
+            suppressed_by: None,
+            anomaly_sigma: check.anomaly_sigma,
+            script_hash,
+            run_id: check.run_id.clone(),
+        };
+
+        if let Err(e) = self.publish_result(&result).await {
+            error!(
+                "Error sending the result of check {}: {e}",
+                check.check_name
+            );
+        }
+    }
+
+    /**
+     * This function creates the Job running the check, in the namespace of the check
+     */
+    async fn create_job(&self, check: &PinglowCheck) -> Result<StartedJob, Error> {
+        // Jobs need the cluster, which is not available in standalone mode
+        let client = self
+            .client
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("Jobs are not available in standalone mode"))?;
+
+        // The Job runs in the namespace of its check, with its script
+        let namespace = check.namespace.as_deref().unwrap_or(&self.namespace);
+        let jobs: Api<Job> = Api::namespaced(client.clone(), namespace);
+        let pods: Api<Pod> = Api::namespaced(client.clone(), namespace);
+        let events: Api<Event> = Api::namespaced(client.clone(), namespace);
+        let scripts: Api<Script> = Api::namespaced(client.clone(), namespace);
+        let config_maps: Api<ConfigMap> = Api::namespaced(client, namespace);
+
+        let script_name =
+            check
+                .script_ref
+                .as_ref()
+                .ok_or(pinglow_common::error::ScriptError::NoScriptFound(
+                    check.check_name.clone(),
+                ))?;
+        let script = resolve_content(scripts.get(script_name).await?.spec, &config_maps).await?;
+
+        let job = jobs
+            .create(&PostParams::default(), &build_job(check, &script)?)
+            .await?;
+        let job_name = job.metadata.name.clone().unwrap_or_default();
+        debug!("Started job {job_name} for check {}", check.check_name);
+
+        Ok(StartedJob {
+            jobs,
+            pods,
+            events,
+            job_name,
+            script_hash: script.content_hash(),
+        })
+    }
+
+    /**
+     * This function waits for the completion of the Job of the check and collects its outcome,
+     * deleting the Job afterwards
+     */
+    async fn collect_job(
+        &self,
+        check: &PinglowCheck,
+        started: StartedJob,
+    ) -> Result<JobOutcome, Error> {
+        let StartedJob {
+            jobs,
+            pods,
+            events,
+            job_name,
+            script_hash,
+        } = started;
+
+        let result = self.wait_for_job(&jobs, &pods, &job_name, check).await;
+
+        // Collect the diagnostics of a CheckError before the Job and its pod disappear
+        let result = match result {
+            Ok((mut outcome, pod))
+                if map_command_exit_code_to_check_result(check, outcome.exit_code)
+                    == CheckResultStatus::CheckError =>
+            {
+                outcome.diagnostics =
+                    Some(collect_diagnostics(&jobs, &pods, &events, &job_name, pod.as_ref()).await);
+                Ok(outcome)
+            }
+            result => result.map(|(outcome, _)| outcome),
+        };
+        let result = result.map(|outcome| JobOutcome {
+            script_hash: Some(script_hash),
+            ..outcome
+        });
+
+        // Remove the Job and its pod, the result has been collected
+        if let Err(e) = jobs.delete(&job_name, &DeleteParams::background()).await {
+            error!("Error deleting job {job_name}: {e}");
+        }
+
+        result
+    }
+
+    async fn wait_for_job(
+        &self,
+        jobs: &Api<Job>,
+        pods: &Api<Pod>,
+        job_name: &str,
+        check: &PinglowCheck,
+    ) -> Result<(JobOutcome, Option<Pod>), Error> {
+        let max_duration = check
+            .job
+            .as_ref()
+            .and_then(|job| job.activeDeadlineSeconds)
+            .or(check.timeout_seconds.map(|seconds| seconds as i64))
+            .map(|seconds| Duration::from_secs(seconds.max(0) as u64) + POLL_INTERVAL * 2)
+            .unwrap_or(DEFAULT_MAX_DURATION);
+        let started = tokio::time::Instant::now();
+
+        loop {
+            tokio::time::sleep(POLL_INTERVAL).await;
+
+            let job = jobs.get_status(job_name).await?;
+            let status = job.status.unwrap_or_default();
+
+            if status.succeeded.unwrap_or(0) > 0 || status.failed.unwrap_or(0) > 0 {
+                break;
+            }
+
+            if started.elapsed() > max_duration {
+                let outcome = JobOutcome {
+                    output: format!("Job {job_name} did not complete in {max_duration:?}"),
+                    exit_code: None,
+                    diagnostics: None,
+                    script_hash: None,
+                };
+                return Ok((outcome, None));
+            }
+        }
+
+        // Collect the output and exit code of the pod of the Job
+        let pod = pods
+            .list(&ListParams::default().labels(&format!("job-name={job_name}")))
+            .await?
+            .items
+            .into_iter()
+            .next();
+
+        let Some(pod) = pod else {
+            let outcome = JobOutcome {
+                output: format!("Job {job_name} failed without running a pod"),
+                exit_code: None,
+                diagnostics: None,
+                script_hash: None,
+            };
+            return Ok((outcome, None));
+        };
+
+        let pod_name = pod.metadata.name.clone().unwrap_or_default();
+        let exit_code = pod
+            .status
+            .clone()
+            .and_then(|s| s.container_statuses)
+            .and_then(|statuses| statuses.into_iter().next())
+            .and_then(|s| s.state)
+            .and_then(|s| s.terminated)
+            .map(|terminated| terminated.exit_code);
+
+        let output = pods
+            .logs(&pod_name, &LogParams::default())
+            .await
+            .unwrap_or_else(|e| format!("Cannot retrieve the output of pod {pod_name}: {e}"));
+
+        let outcome = JobOutcome {
+            output,
+            exit_code,
+            diagnostics: None,
+            script_hash: None,
+        };
+
+        Ok((outcome, Some(pod)))
+    }
+
+    async fn publish_result(&self, result: &CheckResult) -> Result<(), Error> {
+        let payload = serde_json::to_string(result).map_err(|e| {
+            SerializeError::SerializationError(format!("Error serializing result: {e}"))
+        })?;
+
+        let mut redis_conn = self.redis_client.get_multiplexed_async_connection().await?;
+
+        redis::cmd("XADD")
+            .arg("pinglow:results")
+            .arg("*")
+            .arg("payload")
+            .arg(payload)
+            .query_async::<()>(&mut redis_conn)
+            .await?;
+
+        Ok(())
+    }
+}
+
+/**
+ * This function describes why a Job ended with a CheckError: its conditions, the state of the
+ * container, the events of the Job and its pod and the last lines of the log, which include
+ * the pip output when the requirements cannot be installed
+ */
+async fn collect_diagnostics(
+    jobs: &Api<Job>,
+    pods: &Api<Pod>,
+    events: &Api<Event>,
+    job_name: &str,
+    pod: Option<&Pod>,
+) -> String {
+    let mut diagnostics = vec![];
+
+    if let Ok(job) = jobs.get_status(job_name).await {
+        for condition in job.status.and_then(|s| s.conditions).unwrap_or_default() {
+            diagnostics.push(format!(
+                "Job condition {}: {} {}",
+                condition.type_,
+                condition.reason.unwrap_or_default(),
+                condition.message.unwrap_or_default()
+            ));
+        }
+    }
+
+    let mut involved_objects = vec![job_name.to_string()];
+
+    if let Some(pod) = pod {
+        let pod_name = pod.metadata.name.clone().unwrap_or_default();
+        let status = pod.status.clone().unwrap_or_default();
+
+        diagnostics.push(format!(
+            "Pod {pod_name} phase: {} {}",
+            status.phase.unwrap_or_default(),
+            status.reason.unwrap_or_default()
+        ));
+
+        for container in status.container_statuses.unwrap_or_default() {
+            let state = container.state.unwrap_or_default();
+            if let Some(waiting) = state.waiting {
+                diagnostics.push(format!(
+                    "Container {} waiting: {} {}",
+                    container.name,
+                    waiting.reason.unwrap_or_default(),
+                    waiting.message.unwrap_or_default()
+                ));
+            }
+            if let Some(terminated) = state.terminated {
+                diagnostics.push(format!(
+                    "Container {} terminated with exit code {}: {} {}",
+                    container.name,
+                    terminated.exit_code,
+                    terminated.reason.unwrap_or_default(),
+                    terminated.message.unwrap_or_default()
+                ));
+            }
+        }
+
+        involved_objects.push(pod_name);
+    }
+
+    for name in involved_objects.iter() {
+        let params = ListParams::default().fields(&format!("involvedObject.name={name}"));
+        match events.list(&params).await {
+            Ok(list) => {
+                for event in list.items {
+                    diagnostics.push(format!(
+                        "Event {name}: {} {} {}",
+                        event.type_.unwrap_or_default(),
+                        event.reason.unwrap_or_default(),
+                        event.message.unwrap_or_default()
+                    ));
+                }
+            }
+            Err(e) => diagnostics.push(format!("Cannot retrieve the events of {name}: {e}")),
+        }
+    }
+
+    if let Some(pod_name) = pod.and_then(|pod| pod.metadata.name.as_ref()) {
+        let params = LogParams {
+            tail_lines: Some(DIAGNOSTICS_LOG_LINES as i64),
+            ..LogParams::default()
+        };
+        match pods.logs(pod_name, &params).await {
+            Ok(log) => diagnostics.push(format!("Last log lines:\n{log}")),
+            Err(e) => diagnostics.push(format!("Cannot retrieve the log of {pod_name}: {e}")),
+        }
+    }
+
+    diagnostics.join("\n")
+}
+
+/**
+ * This function returns the script with its content read from its source, if any: a key of a
+ * ConfigMap of the namespace of the check or a file served at a git URL
+ */
+async fn resolve_content(
+    script: ScriptSpec,
+    config_maps: &Api<ConfigMap>,
+) -> Result<ScriptSpec, Error> {
+    let Some(source) = &script.content_from else {
+        return Ok(script);
+    };
+
+    let content = read_content(
+        source,
+        |name| async move { config_maps.get(&name).await.ok() },
+    )
+    .await?;
+
+    Ok(script.with_content(content))
+}
+
+/**
+ * This function returns the image of the Job running a script, the one of the check, of the script
+ * or the default one of its language, along with its command. The default images install the
+ * requirements through sh before running the script, while the custom images, expected to ship
+ * them, run the interpreter directly
+ */
+fn job_command(
+    script: &ScriptSpec,
+    check_image: Option<&str>,
+    args: Vec<String>,
+) -> Result<(String, Vec<String>), Error> {
+    let language = script.language.as_ref().unwrap_or(&ScriptLanguage::Python);
+
+    // Kubernetes expands $(VAR) in the command, the arguments are kept as they are
+    let args = args.into_iter().map(|arg| arg.replace('$', "$$"));
+
+    let (default_image, shell_command, direct_command): (&str, &str, &[&str]) = match language {
+        ScriptLanguage::Python => (
+            DEFAULT_IMAGE,
+            JOB_COMMAND,
+            &["python", "-c", "$(PINGLOW_SCRIPT)"],
+        ),
+        ScriptLanguage::Bash => (
+            DEFAULT_IMAGE,
+            BASH_JOB_COMMAND,
+            &["bash", "-c", "$(PINGLOW_SCRIPT)", "pinglow"],
+        ),
+        // The script name keeps the arguments at the same position as when running a file
+        ScriptLanguage::Node => (
+            DEFAULT_NODE_IMAGE,
+            NODE_JOB_COMMAND,
+            &["node", "-e", "$(PINGLOW_SCRIPT)", "script.js"],
+        ),
+        ScriptLanguage::PowerShell => (
+            DEFAULT_POWERSHELL_IMAGE,
+            POWERSHELL_JOB_COMMAND,
+            &[
+                "pwsh",
+                "-NoProfile",
+                "-NonInteractive",
+                "-Command",
+                POWERSHELL_DIRECT_COMMAND,
+            ],
+        ),
+        ScriptLanguage::Wasm => anyhow::bail!("WASM scripts can only run in the runners"),
+    };
+
+    let Some(image) = check_image.or(script.image.as_deref()) else {
+        let command = ["sh", "-c", shell_command, "pinglow"]
+            .into_iter()
+            .map(str::to_string)
+            .chain(args)
+            .collect();
+        return Ok((default_image.to_string(), command));
+    };
+
+    let mut command: Vec<String> = direct_command.iter().map(|part| part.to_string()).collect();
+    match language {
+        // The command of pwsh is a single string, the arguments must be PowerShell literals
+        ScriptLanguage::PowerShell => {
+            command.extend(args.map(|arg| format!("'{}'", arg.replace('\'', "''"))))
+        }
+        _ => command.extend(args),
+    }
+
+    Ok((image.to_string(), command))
+}
+
+/**
+ * This function builds the Job running the script of the check, with the pod customizations
+ * of its spec. Secrets are referenced, so that their values never appear in the Job
+ */
+fn build_job(check: &PinglowCheck, script: &ScriptSpec) -> Result<Job, Error> {
+    let default_spec = CheckJobSpec::default();
+    let spec = check.job.as_ref().unwrap_or(&default_spec);
+    let (image, command) = job_command(script, spec.image.as_deref(), check.templated_args())?;
+
+    let mut env: Vec<serde_json::Value> = vec![
+        json!({ "name": "PINGLOW_SCRIPT", "value": check.render_script(&script.content) }),
+        json!({
+            "name": "PINGLOW_REQUIREMENTS",
+            "value": script
+                .python_requirements
+                .iter()
+                .chain(&script.npm_dependencies)
+                .flatten()
+                .cloned()
+                .collect::<Vec<_>>()
+                .join(" ")
+        }),
+    ];
+    env.extend(
+        check
+            .metadata_env()
+            .into_iter()
+            .chain(check.env.clone())
+            .map(|(name, value)| json!({ "name": name, "value": value })),
+    );
+
+    let env_from: Vec<serde_json::Value> = check
+        .secret_refs
+        .iter()
+        .map(|name| json!({ "secretRef": { "name": name } }))
+        .collect();
+
+    // Job names are limited in length, leave room for the generated suffix
+    let name_prefix: String = check.resource_name().chars().take(50).collect();
+    let check_label: String = check.resource_name().chars().take(63).collect();
+
+    let job = json!({
+        "apiVersion": "batch/v1",
+        "kind": "Job",
+        "metadata": {
+            "generateName": format!("{}-", name_prefix.trim_end_matches('-')),
+            "labels": {
+                "app.kubernetes.io/managed-by": "pinglow",
+                "pinglow.io/check": check_label.trim_end_matches(['-', '.']),
+            }
+        },
+        "spec": {
+            "backoffLimit": 0,
+            "ttlSecondsAfterFinished": 600,
+            "activeDeadlineSeconds": spec
+                .activeDeadlineSeconds
+                .or(check.timeout_seconds.map(|seconds| seconds as i64)),
+            "template": {
+                "spec": {
+                    "restartPolicy": "Never",
+                    "nodeSelector": spec.nodeSelector,
+                    "tolerations": spec.tolerations,
+                    "imagePullSecrets": spec.imagePullSecrets.as_ref().map(|secrets| {
+                        secrets.iter().map(|name| json!({ "name": name })).collect::<Vec<_>>()
+                    }),
+                    "containers": [{
+                        "name": "check",
+                        "image": image,
+                        "command": command,
+                        "env": env,
+                        "envFrom": env_from,
+                        "resources": spec.resources,
+                    }]
+                }
+            }
+        }
+    });
+
+    Ok(serde_json::from_value(job)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn script(language: &str, image: Option<&str>) -> ScriptSpec {
+        serde_json::from_value(json!({ "language": language, "content": "", "image": image }))
+            .unwrap()
+    }
+
+    #[test]
+    fn entrypoint_by_language_and_image() {
+        let args = vec!["a b".to_string(), "$(HOME)".to_string()];
+
+        // The default images install the requirements through sh
+        let (image, command) = job_command(&script("Python", None), None, args.clone()).unwrap();
+        assert_eq!(image, DEFAULT_IMAGE);
+        assert_eq!(command[..4], ["sh", "-c", JOB_COMMAND, "pinglow"]);
+        assert_eq!(command[4..], ["a b", "$$(HOME)"]);
+
+        // The custom images run the interpreter, the image of the check winning over the script
+        let (image, command) = job_command(
+            &script("Bash", Some("bash:5")),
+            Some("alpine"),
+            args.clone(),
+        )
+        .unwrap();
+        assert_eq!(image, "alpine");
+        assert_eq!(
+            command,
+            [
+                "bash",
+                "-c",
+                "$(PINGLOW_SCRIPT)",
+                "pinglow",
+                "a b",
+                "$$(HOME)"
+            ]
+        );
+
+        let (image, command) = job_command(
+            &script("PowerShell", Some("pwsh:7")),
+            None,
+            vec!["it's".into()],
+        )
+        .unwrap();
+        assert_eq!(image, "pwsh:7");
+        assert_eq!(command.last().unwrap(), "'it''s'");
+
+        assert!(job_command(&script("Wasm", None), None, vec![]).is_err());
+    }
+}
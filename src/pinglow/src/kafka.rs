@@ -0,0 +1,93 @@
+#[cfg(feature = "kafka")]
+use log::error;
+use log::warn;
+use pinglow_common::CheckResult;
+#[cfg(feature = "kafka")]
+use rdkafka::{
+    producer::{FutureProducer, FutureRecord},
+    ClientConfig,
+};
+#[cfg(feature = "kafka")]
+use tokio_util::task::TaskTracker;
+
+use crate::config::PinglowConfig;
+#[cfg(feature = "kafka")]
+use crate::sinks::ExportedResult;
+
+/// Mirrors the check results to a Kafka topic, keyed by check name
+pub struct KafkaExporter {
+    #[cfg(feature = "kafka")]
+    producer: FutureProducer,
+    #[cfg(feature = "kafka")]
+    topic: String,
+    #[cfg(feature = "kafka")]
+    deliveries: TaskTracker,
+}
+
+impl KafkaExporter {
+    /**
+     * This function creates the exporter when Kafka brokers are configured
+     */
+    #[cfg(feature = "kafka")]
+    pub fn new(config: &PinglowConfig, deliveries: TaskTracker) -> Option<Self> {
+        let brokers = config.kafka_brokers.as_ref()?;
+
+        match ClientConfig::new()
+            .set("bootstrap.servers", brokers)
+            .set("message.timeout.ms", "30000")
+            .create::<FutureProducer>()
+        {
+            Ok(producer) => Some(KafkaExporter {
+                producer,
+                topic: config.kafka_topic.clone(),
+                deliveries,
+            }),
+            Err(e) => {
+                warn!("Cannot create the Kafka producer, results will not be exported: {e}");
+                None
+            }
+        }
+    }
+
+    #[cfg(not(feature = "kafka"))]
+    pub fn new(config: &PinglowConfig, _deliveries: tokio_util::task::TaskTracker) -> Option<Self> {
+        if config.kafka_brokers.is_some() {
+            warn!("Kafka brokers configured, but Pinglow was built without the kafka feature");
+        }
+        None
+    }
+
+    /**
+     * This function enqueues the result in the producer, logging failed deliveries
+     */
+    #[cfg(feature = "kafka")]
+    pub fn send(&self, result: &CheckResult) {
+        let payload = match serde_json::to_string(&ExportedResult::from(result)) {
+            Ok(payload) => payload,
+            Err(e) => {
+                error!("Cannot serialize the result for Kafka: {e}");
+                return;
+            }
+        };
+
+        let record = FutureRecord::to(&self.topic)
+            .key(&result.check_name)
+            .payload(&payload);
+
+        match self.producer.send_result(record) {
+            Ok(delivery) => {
+                self.deliveries.spawn(async move {
+                    match delivery.await {
+                        Ok(Err((e, _))) => error!("Error exporting result to Kafka: {e}"),
+                        Err(_) => error!("Kafka delivery of a result was cancelled"),
+                        Ok(Ok(_)) => {}
+                    }
+                });
+            }
+            Err((e, _)) => error!("Cannot enqueue result for Kafka: {e}"),
+        }
+    }
+
+    #[cfg(not(feature = "kafka"))]
+    pub fn send(&self, _result: &CheckResult) {}
+}
@@ -0,0 +1,259 @@
+use std::time::Duration;
+
+use k8s_openapi::{
+    api::coordination::v1::{Lease, LeaseSpec},
+    apimachinery::pkg::apis::meta::v1::MicroTime,
+    jiff::{SignedDuration, Timestamp},
+};
+use kube::{
+    api::{ObjectMeta, PostParams},
+    Api, Client,
+};
+use log::{error, info, warn};
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+
+use crate::config::PinglowConfig;
+
+/// Whether this instance leads the replicas of the controller, shared with the tasks which must
+/// run on a single replica
+#[derive(Clone)]
+pub struct Leadership(watch::Receiver<bool>);
+
+impl Leadership {
+    /**
+     * This function returns the leadership of an instance running alone, which always leads
+     */
+    pub fn always() -> Self {
+        Self(watch::channel(true).1)
+    }
+
+    pub fn is_leader(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    /**
+     * This function waits until this instance leads
+     */
+    pub async fn acquired(&mut self) {
+        if self.0.wait_for(|leading| *leading).await.is_err() {
+            // The election stopped before this instance could lead
+            std::future::pending::<()>().await;
+        }
+    }
+
+    /**
+     * This function waits until this instance, after having led, loses the leadership
+     */
+    pub async fn lost(&mut self) {
+        self.acquired().await;
+
+        if self.0.wait_for(|leading| !*leading).await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+/// The election of the leader among the replicas of the controller, through a Lease held by the
+/// leader and renewed before it expires
+pub struct LeaderElector {
+    api: Api<Lease>,
+    lease_name: String,
+    identity: String,
+    lease_duration: Duration,
+    leading: watch::Sender<bool>,
+}
+
+impl LeaderElector {
+    /**
+     * This function builds the elector of the leader, if the leader election is enabled
+     */
+    pub fn from_config(client: &Client, config: &PinglowConfig) -> Option<LeaderElector> {
+        if !config.leader_election {
+            return None;
+        }
+
+        Some(LeaderElector {
+            api: Api::namespaced(client.clone(), &config.target_namespace),
+            lease_name: config.leader_lease_name.clone(),
+            identity: config.leader_identity.clone(),
+            lease_duration: Duration::from_secs(config.leader_lease_duration_seconds.max(3)),
+            leading: watch::channel(false).0,
+        })
+    }
+
+    pub fn leadership(&self) -> Leadership {
+        Leadership(self.leading.subscribe())
+    }
+
+    /**
+     * This function campaigns for the leadership and renews it, a fifth of the lease duration
+     * apart, until the shutdown, when the lease is released for a standby to take over. A leader
+     * which cannot renew its lease within the renew deadline, two thirds of the lease duration,
+     * steps down, leaving its tasks the time to stop before a standby can take the lease over
+     */
+    pub async fn run(self, shutdown: CancellationToken) {
+        let mut interval = tokio::time::interval(self.lease_duration / 5);
+        let renew_deadline = self.lease_duration * 2 / 3;
+        let mut last_renewal = tokio::time::Instant::now();
+
+        info!(
+            "Campaigning for the leadership as {} through the lease {}",
+            self.identity, self.lease_name
+        );
+
+        loop {
+            let leading = *self.leading.borrow();
+            let deadline = last_renewal + renew_deadline;
+
+            tokio::select! {
+                _ = shutdown.cancelled() => break,
+                _ = tokio::time::sleep_until(deadline), if leading => {
+                    error!(
+                        "The lease {} could not be renewed before the renew deadline",
+                        self.lease_name
+                    );
+                    self.leading.send_replace(false);
+                    continue;
+                }
+                _ = interval.tick() => {}
+            }
+
+            // The lease is renewed as of the start of the attempt, which must complete before
+            // the renew deadline for a leader to keep leading
+            let attempt = tokio::time::Instant::now();
+            let renewal = if leading {
+                match tokio::time::timeout_at(deadline, self.try_acquire_or_renew()).await {
+                    Ok(renewal) => renewal,
+                    Err(_) => {
+                        error!(
+                            "The lease {} could not be renewed before the renew deadline",
+                            self.lease_name
+                        );
+                        self.leading.send_replace(false);
+                        continue;
+                    }
+                }
+            } else {
+                self.try_acquire_or_renew().await
+            };
+
+            match renewal {
+                Ok(true) => {
+                    last_renewal = attempt;
+                    if !leading {
+                        info!("Acquired the leadership");
+                        self.leading.send_replace(true);
+                    }
+                }
+                Ok(false) => {
+                    if leading {
+                        error!(
+                            "The lease {} was taken over by another replica",
+                            self.lease_name
+                        );
+                        self.leading.send_replace(false);
+                    }
+                }
+                Err(e) => warn!("Error renewing the lease {}: {e}", self.lease_name),
+            }
+        }
+
+        if *self.leading.borrow() {
+            match self.release().await {
+                Ok(()) => info!("Released the leadership"),
+                Err(e) => error!("Error releasing the lease {}: {e}", self.lease_name),
+            }
+        }
+    }
+
+    /**
+     * This function takes the lease if it is free, expired or already held by this instance,
+     * renewing it, and tells whether this instance holds it. A concurrent update of the lease by
+     * another replica makes the attempt fail
+     */
+    async fn try_acquire_or_renew(&self) -> Result<bool, kube::Error> {
+        let now = Timestamp::now();
+
+        let Some(mut lease) = self.api.get_opt(&self.lease_name).await? else {
+            let lease = Lease {
+                metadata: ObjectMeta {
+                    name: Some(self.lease_name.clone()),
+                    ..Default::default()
+                },
+                spec: Some(LeaseSpec {
+                    holder_identity: Some(self.identity.clone()),
+                    lease_duration_seconds: Some(self.lease_duration.as_secs() as i32),
+                    acquire_time: Some(MicroTime(now)),
+                    renew_time: Some(MicroTime(now)),
+                    lease_transitions: Some(0),
+                    ..Default::default()
+                }),
+            };
+
+            return match self.api.create(&PostParams::default(), &lease).await {
+                Ok(_) => Ok(true),
+                Err(kube::Error::Api(status))
+                    if status.is_conflict() || status.is_already_exists() =>
+                {
+                    Ok(false)
+                }
+                Err(e) => Err(e),
+            };
+        };
+
+        let spec = lease.spec.get_or_insert_with(Default::default);
+        let held = spec.holder_identity.as_deref() == Some(self.identity.as_str());
+        let expired = match (&spec.renew_time, spec.lease_duration_seconds) {
+            (Some(renew_time), Some(duration)) => {
+                renew_time.0 + SignedDuration::from_secs(duration.into()) < now
+            }
+            _ => true,
+        };
+
+        if !held && spec.holder_identity.is_some() && !expired {
+            return Ok(false);
+        }
+
+        if !held {
+            spec.holder_identity = Some(self.identity.clone());
+            spec.acquire_time = Some(MicroTime(now));
+            spec.lease_transitions = Some(spec.lease_transitions.unwrap_or(0) + 1);
+        }
+        spec.lease_duration_seconds = Some(self.lease_duration.as_secs() as i32);
+        spec.renew_time = Some(MicroTime(now));
+
+        // The resource version of the lease makes the update fail if another replica changed it
+        match self
+            .api
+            .replace(&self.lease_name, &PostParams::default(), &lease)
+            .await
+        {
+            Ok(_) => Ok(true),
+            Err(kube::Error::Api(status)) if status.is_conflict() => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /**
+     * This function frees the lease, so that a standby does not wait for it to expire
+     */
+    async fn release(&self) -> Result<(), kube::Error> {
+        let mut lease = self.api.get(&self.lease_name).await?;
+
+        if let Some(spec) = lease.spec.as_mut() {
+            if spec.holder_identity.as_deref() != Some(self.identity.as_str()) {
+                return Ok(());
+            }
+            spec.holder_identity = None;
+        }
+
+        self.api
+            .replace(&self.lease_name, &PostParams::default(), &lease)
+            .await?;
+
+        self.leading.send_replace(false);
+
+        Ok(())
+    }
+}
@@ -1,53 +1,106 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::BTreeMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use anyhow::Error;
 use base64::{engine::general_purpose, Engine};
-use chrono::{Local, Utc};
-use html_escape::encode_safe;
+use chrono::{DateTime, TimeDelta, Utc};
+use deadpool_postgres::Pool;
 use k8s_openapi::api::core::v1::Secret;
-use kube::{Api, Client};
-use log::error;
+use k8s_openapi::NamespaceResourceScope;
+use kube::{Api, Client, Resource, ResourceExt};
+use log::warn;
 use tokio_postgres::Client as PostgresClient;
 
 use crate::{
-    check::{Check, TelegramChannel},
+    check::{
+        check_identity, qualify_check_ref, secret_cache_key, CachedSecret, Check, DiscordChannel,
+        PushChannel, SharedRedirects, SharedSecrets, SlackChannel, TelegramChannel,
+    },
     config::PinglowConfig,
     error::ReconcileError,
+    flapping::FlapState,
+    notifier::NotificationChannel,
+    sinks::ResultSinks,
 };
 
 use pinglow_common::{
-    CheckResult, CheckResultStatus, ConcreteTelegramChannel, PinglowCheck, Script,
+    CheckResult, CheckResultStatus, ConcreteDiscordChannel, ConcretePushChannel,
+    ConcreteSlackChannel, ConcreteTelegramChannel, PinglowCheck, Script, ScriptSpec,
 };
 
+pub mod ack;
+pub mod admission;
+pub mod anomaly;
 pub mod api;
+pub mod auth;
+pub mod backlog;
+pub mod calendar;
 pub mod check;
 pub mod config;
 pub mod controller;
+pub mod db;
 pub mod error;
+pub mod fallback;
+pub mod flapping;
+pub mod grafana;
+pub mod groups;
+pub mod job;
+pub mod kafka;
+pub mod leader;
+pub mod metrics;
+pub mod notification;
+pub mod notifier;
+pub mod reclaimer;
 pub mod results;
+pub mod retention;
 pub mod scheduler;
+pub mod sinks;
+pub mod snmp;
+pub mod standalone;
+pub mod status_page;
+pub mod teardown;
+pub mod webhook;
+
+/**
+ * This function returns the API of a kind of resources in a watched scope: a namespace or, when
+ * none is given, the whole cluster
+ */
+pub fn scoped_api<K>(client: &Client, namespace: Option<&str>) -> Api<K>
+where
+    K: Resource<Scope = NamespaceResourceScope>,
+    <K as Resource>::DynamicType: Default,
+{
+    match namespace {
+        Some(namespace) => Api::namespaced(client.clone(), namespace),
+        None => Api::all(client.clone()),
+    }
+}
 
 pub async fn load_single_runnable_check(
     check: &Check,
     client: &Client,
     config: &PinglowConfig,
+    secret_cache: &SharedSecrets,
 ) -> Result<PinglowCheck, ReconcileError> {
-    let scripts: Api<Script> = Api::namespaced(client.clone(), &config.target_namespace);
-
-    let secrets: Api<Secret> = Api::namespaced(client.clone(), &config.target_namespace);
+    // The resources referenced by the check are looked up in its own namespace
+    let namespace = check
+        .namespace()
+        .unwrap_or_else(|| config.target_namespace.clone());
 
-    let telegram_channels_api: Api<TelegramChannel> =
-        Api::namespaced(client.clone(), &config.target_namespace);
+    let scripts: Api<Script> = Api::namespaced(client.clone(), &namespace);
 
     // Get the script name from the check specification
     let script_name = &check.spec.scriptRef;
 
     // Retrieve the check name and use a default one if not found (unlikely)
-    let check_name = check
-        .metadata
-        .name
-        .clone()
-        .unwrap_or("Unnamed check".to_string());
+    let check_name = check_identity(
+        &namespace,
+        check.metadata.name.as_deref().unwrap_or("Unnamed check"),
+        &config.target_namespace,
+    );
 
     // Retrieve the corresponding script
     let mut script = None;
@@ -60,42 +113,87 @@ pub async fn load_single_runnable_check(
                 .map_err(|_| ReconcileError::ScriptNotFound(script_name.clone()))?,
         );
     }
+
+    if let Some(script) = &script {
+        validate_parameters(check, &script.spec)?;
+    }
     let mut telegram_channels = vec![];
 
     if let Some(channels) = &check.spec.telegramChannelRefs {
         for channel in channels.iter() {
-            // Get concrete channel
-            let channel = telegram_channels_api
-                .get(channel)
-                .await
-                .map_err(|_| ReconcileError::TelegramChannelNotFound(channel.to_string()))?;
+            telegram_channels.push(
+                resolve_telegram_channel(channel, &namespace, client, config, secret_cache).await?,
+            );
+        }
+    }
 
-            let bot_secret = secrets
-                .get(&channel.spec.botTokenRef)
-                .await
-                .map_err(|_| ReconcileError::SecretNotFound(channel.spec.botTokenRef.clone()))?;
-
-            let bot_token = bot_secret
-                .data
-                .and_then(|d| d.get("botToken").cloned())
-                .ok_or("Cannot find botToken")
-                .map_err(|_| ReconcileError::SecretNotFound("botToken".to_owned()))?;
-
-            telegram_channels.push(ConcreteTelegramChannel {
-                chat_id: channel.spec.chatId.clone(),
-                bot_token: String::from_utf8_lossy(&bot_token.0).to_string(),
-            });
+    let mut slack_channels = vec![];
+
+    if let Some(channels) = &check.spec.slackChannelRefs {
+        for channel in channels.iter() {
+            slack_channels.push(
+                resolve_slack_channel(channel, &namespace, client, config, secret_cache).await?,
+            );
+        }
+    }
+
+    let mut push_channels = vec![];
+
+    if let Some(channels) = &check.spec.pushChannelRefs {
+        for channel in channels.iter() {
+            push_channels.push(
+                resolve_push_channel(channel, &namespace, client, config, secret_cache).await?,
+            );
+        }
+    }
+
+    let mut discord_channels = vec![];
+
+    if let Some(channels) = &check.spec.discordChannelRefs {
+        for channel in channels.iter() {
+            discord_channels.push(
+                resolve_discord_channel(channel, &namespace, client, config, secret_cache).await?,
+            );
         }
     }
 
-    // Check if we have secrets
-    let secrets = if let Some(secrets_refs) = &check.spec.secretRefs {
+    // Secrets are resolved by the runners, only their versions travel with the check
+    let secret_versions = match &check.spec.secretRefs {
+        Some(secrets_refs) => {
+            secret_versions(client, config, &namespace, secrets_refs, secret_cache).await
+        }
+        None => BTreeMap::new(),
+    };
+
+    // Shared key used to verify signed passive results, if any
+    let hmac_key = if let Some(hmac_secret_ref) = &check.spec.hmacSecretRef {
         Some(
-            fetch_secrets(&config.target_namespace, secrets_refs)
-                .await
-                .map_err(|e| {
-                    ReconcileError::GeneralError(format!("Error fetching secrets: {e}"))
-                })?,
+            get_secret_value(
+                hmac_secret_ref,
+                "hmacKey",
+                &namespace,
+                client,
+                config,
+                secret_cache,
+            )
+            .await?,
+        )
+    } else {
+        None
+    };
+
+    // Token authenticating the heartbeats of the check, if any
+    let heartbeat_token = if let Some(token_secret_ref) = &check.spec.heartbeatTokenRef {
+        Some(
+            get_secret_value(
+                token_secret_ref,
+                "heartbeatToken",
+                &namespace,
+                client,
+                config,
+                secret_cache,
+            )
+            .await?,
         )
     } else {
         None
@@ -104,40 +202,407 @@ pub async fn load_single_runnable_check(
     // Build the runnable check object
     let runnable_check = PinglowCheck {
         passive: check.spec.passive,
-        script: script.map(|s| s.spec),
+        script_ref: script_name.clone(),
+        script_hash: script.as_ref().map(|s| s.spec.content_hash()),
         interval: check.spec.interval,
         check_name,
-        secrets,
+        namespace: Some(namespace.clone()),
+        secrets: None,
         telegram_channels,
+        slack_channels,
+        push_channels,
+        discord_channels,
+        notify_on_state_change: check.spec.notifyOnStateChange.unwrap_or(false),
+        notify_on_recovery: check.spec.notifyOnRecovery.unwrap_or(false),
+        renotify_interval_minutes: check.spec.renotifyIntervalMinutes,
+        min_notification_interval_seconds: check.spec.minNotificationIntervalSeconds,
+        retries: check.spec.retries.unwrap_or(0),
+        retry_delay_seconds: check.spec.retryDelaySeconds,
+        depends_on: check
+            .spec
+            .dependsOn
+            .iter()
+            .flatten()
+            .map(|reference| qualify_check_ref(reference, &namespace, &config.target_namespace))
+            .collect(),
+        timeout_seconds: check
+            .spec
+            .timeoutSeconds
+            .or(script.and_then(|s| s.spec.timeout_seconds)),
         mute_notifications: check.spec.muteNotifications,
         mute_notifications_until: check.spec.muteNotificationsUntil,
+        mute_notifications_since: check.spec.muteNotificationsSince,
+        maintenance_windows: check.spec.maintenanceWindows.clone().unwrap_or_default(),
+        suspended: check.spec.suspend.unwrap_or(false),
+        result_retention_days: check.spec.resultRetentionDays,
+        webhooks: check.spec.webhooks.clone().unwrap_or_default(),
+        args: check.spec.args.clone().unwrap_or_default(),
+        env: check.spec.env.clone().unwrap_or_default(),
+        parameters: check.spec.parameters.clone().unwrap_or_default(),
+        labels: check.metadata.labels.clone().unwrap_or_default(),
+        annotations: script_annotations(check),
+        exit_code_mapping: exit_code_mapping(check),
+        thresholds: check.spec.thresholds.clone().unwrap_or_default(),
+        anomaly_sigma: check.spec.anomalySigma,
+        secret_refs: check.spec.secretRefs.clone().unwrap_or_default(),
+        secret_versions,
+        job: check.spec.job.clone(),
+        http_check: check.spec.httpCheck.clone(),
+        tcp_check: check.spec.tcpCheck.clone(),
+        runner_selector: check.spec.runnerSelector.clone().unwrap_or_default(),
+        hmac_key,
+        heartbeat_token,
+        run_id: None,
     };
 
     Ok(runnable_check)
 }
 
-async fn fetch_secrets(
+/**
+ * This function checks that a check defines a value for every placeholder of its script, the
+ * content read from a source being checked by the runners instead
+ */
+pub fn validate_parameters(check: &Check, script: &ScriptSpec) -> Result<(), ReconcileError> {
+    let missing = script.missing_parameters(&check.spec.parameters.clone().unwrap_or_default());
+
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(ReconcileError::GeneralError(format!(
+            "Missing parameters of the script: {}",
+            missing.join(", ")
+        )))
+    }
+}
+
+/**
+ * This function returns the exit code mapping of a check, ignoring the keys which are not
+ * valid exit codes
+ */
+pub fn exit_code_mapping(check: &Check) -> BTreeMap<i32, i32> {
+    check
+        .spec
+        .exitCodeMapping
+        .iter()
+        .flatten()
+        .filter_map(|(exit_code, status)| match exit_code.parse() {
+            Ok(exit_code) => Some((exit_code, *status)),
+            Err(_) => {
+                warn!("Ignoring invalid exit code {exit_code} in the mapping of a check");
+                None
+            }
+        })
+        .collect()
+}
+
+/**
+ * This function returns the annotations of a check exposed to its script, leaving out the
+ * ones set by the tooling (e.g. the last applied configuration of kubectl)
+ */
+pub fn script_annotations(check: &Check) -> BTreeMap<String, String> {
+    check
+        .metadata
+        .annotations
+        .iter()
+        .flatten()
+        .filter(|(key, _)| !key.starts_with("kubectl.kubernetes.io/"))
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect()
+}
+
+/**
+ * This function resolves a TelegramChannel resource into a concrete channel, including its bot token
+ */
+pub async fn resolve_telegram_channel(
+    channel_name: &str,
+    namespace: &str,
+    client: &Client,
+    config: &PinglowConfig,
+    secret_cache: &SharedSecrets,
+) -> Result<ConcreteTelegramChannel, ReconcileError> {
+    let telegram_channels_api: Api<TelegramChannel> = Api::namespaced(client.clone(), namespace);
+
+    // Get concrete channel
+    let channel = telegram_channels_api
+        .get(channel_name)
+        .await
+        .map_err(|_| ReconcileError::TelegramChannelNotFound(channel_name.to_string()))?;
+
+    let bot_token = get_secret_value(
+        &channel.spec.botTokenRef,
+        "botToken",
+        namespace,
+        client,
+        config,
+        secret_cache,
+    )
+    .await?;
+
+    Ok(ConcreteTelegramChannel {
+        chat_id: channel.spec.chatId.clone(),
+        bot_token,
+        proxy: channel.spec.proxy.clone(),
+        message_template: channel.spec.messageTemplate.clone(),
+    })
+}
+
+/**
+ * This function resolves a SlackChannel resource into a concrete channel, including its webhook URL
+ */
+pub async fn resolve_slack_channel(
+    channel_name: &str,
+    namespace: &str,
+    client: &Client,
+    config: &PinglowConfig,
+    secret_cache: &SharedSecrets,
+) -> Result<ConcreteSlackChannel, ReconcileError> {
+    let slack_channels_api: Api<SlackChannel> = Api::namespaced(client.clone(), namespace);
+
+    let channel = slack_channels_api
+        .get(channel_name)
+        .await
+        .map_err(|_| ReconcileError::SlackChannelNotFound(channel_name.to_string()))?;
+
+    let webhook_url = get_secret_value(
+        &channel.spec.webhookUrlRef,
+        "webhookUrl",
+        namespace,
+        client,
+        config,
+        secret_cache,
+    )
+    .await?;
+
+    Ok(ConcreteSlackChannel {
+        name: channel_name.to_string(),
+        webhook_url,
+        message_template: channel.spec.messageTemplate.clone(),
+    })
+}
+
+/**
+ * This function resolves a DiscordChannel resource into a concrete channel, including its webhook URL
+ */
+pub async fn resolve_discord_channel(
+    channel_name: &str,
+    namespace: &str,
+    client: &Client,
+    config: &PinglowConfig,
+    secret_cache: &SharedSecrets,
+) -> Result<ConcreteDiscordChannel, ReconcileError> {
+    let discord_channels_api: Api<DiscordChannel> = Api::namespaced(client.clone(), namespace);
+
+    let channel = discord_channels_api
+        .get(channel_name)
+        .await
+        .map_err(|_| ReconcileError::DiscordChannelNotFound(channel_name.to_string()))?;
+
+    let webhook_url = get_secret_value(
+        &channel.spec.webhookUrlRef,
+        "webhookUrl",
+        namespace,
+        client,
+        config,
+        secret_cache,
+    )
+    .await?;
+
+    Ok(ConcreteDiscordChannel {
+        name: channel_name.to_string(),
+        webhook_url,
+        message_template: channel.spec.messageTemplate.clone(),
+    })
+}
+
+/**
+ * This function resolves a PushChannel resource into a concrete channel, including its token
+ */
+pub async fn resolve_push_channel(
+    channel_name: &str,
+    namespace: &str,
+    client: &Client,
+    config: &PinglowConfig,
+    secret_cache: &SharedSecrets,
+) -> Result<ConcretePushChannel, ReconcileError> {
+    let push_channels_api: Api<PushChannel> = Api::namespaced(client.clone(), namespace);
+
+    let channel = push_channels_api
+        .get(channel_name)
+        .await
+        .map_err(|_| ReconcileError::PushChannelNotFound(channel_name.to_string()))?;
+
+    let token = match &channel.spec.tokenRef {
+        Some(token_ref) => Some(
+            get_secret_value(token_ref, "token", namespace, client, config, secret_cache).await?,
+        ),
+        None => None,
+    };
+
+    Ok(ConcretePushChannel {
+        name: channel_name.to_string(),
+        service: channel.spec.service,
+        server_url: channel.spec.serverUrl.clone(),
+        topic: channel.spec.topic.clone(),
+        token,
+        priorities: channel.spec.priorities.clone().unwrap_or_default(),
+        message_template: channel.spec.messageTemplate.clone(),
+    })
+}
+
+/**
+ * This function returns the value of a single key of a secret
+ */
+async fn get_secret_value(
+    secret_name: &str,
+    key: &str,
+    namespace: &str,
+    client: &Client,
+    config: &PinglowConfig,
+    secret_cache: &SharedSecrets,
+) -> Result<String, ReconcileError> {
+    let secrets: Api<Secret> = Api::namespaced(client.clone(), namespace);
+
+    let secret = get_cached_secret(&secrets, namespace, secret_cache, secret_name, config)
+        .await
+        .map_err(|_| ReconcileError::SecretNotFound(secret_name.to_string()))?;
+
+    let value = secret
+        .data
+        .as_ref()
+        .and_then(|d| d.get(key).cloned())
+        .ok_or("Cannot find key")
+        .map_err(|_| ReconcileError::SecretNotFound(key.to_owned()))?;
+
+    Ok(String::from_utf8_lossy(&value.0).to_string())
+}
+
+/**
+ * This function returns a secret from the cache, fetching it from the API server on a cache miss.
+ * Cached entries are invalidated by the secret watcher of the controller and, in case a change
+ * is missed while the watch reconnects, expire after the configured TTL
+ */
+async fn get_cached_secret(
+    secrets_api: &Api<Secret>,
+    namespace: &str,
+    secret_cache: &SharedSecrets,
+    secret_name: &str,
+    config: &PinglowConfig,
+) -> Result<Arc<Secret>, kube::Error> {
+    let ttl = Duration::from_secs(config.secret_cache_ttl_seconds);
+    let cache_key = secret_cache_key(namespace, secret_name);
+
+    if let Some(cached) = secret_cache.get(&cache_key) {
+        if cached.fetched_at.elapsed() < ttl {
+            return Ok(cached.secret.clone());
+        }
+    }
+
+    let secret = Arc::new(secrets_api.get(secret_name).await?);
+    secret_cache.insert(
+        cache_key,
+        CachedSecret {
+            secret: secret.clone(),
+            fetched_at: Instant::now(),
+        },
+    );
+
+    Ok(secret)
+}
+
+/**
+ * This function returns the resource versions of the secrets referenced by a check, which
+ * tell the runners when the values they cached are stale
+ */
+async fn secret_versions(
+    client: &Client,
+    config: &PinglowConfig,
     namespace: &str,
     secret_names: &[String],
-) -> Result<HashMap<String, String>, Error> {
-    let client = Client::try_default().await?;
-    let secrets_api: Api<Secret> = Api::namespaced(client, namespace);
+    secret_cache: &SharedSecrets,
+) -> BTreeMap<String, String> {
+    let secrets_api: Api<Secret> = Api::namespaced(client.clone(), namespace);
 
-    let mut map = HashMap::new();
+    let mut versions = BTreeMap::new();
 
     for secret_name in secret_names {
-        if let Ok(secret) = secrets_api.get(secret_name).await {
-            if let Some(data) = secret.data {
-                for (key, value) in data {
-                    // Secrets are base64 encoded
-                    let decoded = std::str::from_utf8(&value.0)?;
-                    map.insert(key.clone(), decoded.to_string());
-                }
+        if let Ok(secret) =
+            get_cached_secret(&secrets_api, namespace, secret_cache, secret_name, config).await
+        {
+            if let Some(version) = &secret.metadata.resource_version {
+                versions.insert(secret_name.clone(), version.clone());
             }
         }
     }
 
-    Ok(map)
+    versions
+}
+
+/**
+ * This function returns the status of the last result stored for a check, if any
+ */
+pub async fn last_status(
+    db_client: &PostgresClient,
+    check_name: &str,
+) -> Result<Option<CheckResultStatus>, tokio_postgres::Error> {
+    let row = db_client
+        .query_opt(
+            "SELECT status FROM check_result WHERE check_name = $1 AND NOT soft_failure ORDER BY timestamp DESC LIMIT 1",
+            &[&check_name],
+        )
+        .await?;
+
+    Ok(row.map(|row| CheckResultStatus::from(row.get::<_, i16>("status"))))
+}
+
+/**
+ * This function tells whether the last notification of a check, as recorded in the outbox,
+ * is older than the renotify interval
+ */
+async fn renotify_due(
+    db_client: &PostgresClient,
+    check_name: &str,
+    interval_minutes: u64,
+) -> Result<bool, tokio_postgres::Error> {
+    let row = db_client
+        .query_one(
+            "SELECT MAX(created_at) AS last_notification FROM notification_outbox WHERE check_name = $1",
+            &[&check_name],
+        )
+        .await?;
+
+    let last_notification: Option<DateTime<Utc>> = row.get("last_notification");
+
+    Ok(last_notification.is_none_or(|last_notification| {
+        Utc::now() - last_notification >= TimeDelta::minutes(interval_minutes as i64)
+    }))
+}
+
+/**
+ * This function returns the first of the parent checks whose last result is critical, if any
+ */
+async fn critical_parent(
+    db_client: &PostgresClient,
+    parents: &[String],
+) -> Result<Option<String>, tokio_postgres::Error> {
+    let rows = db_client
+        .query(
+            "SELECT DISTINCT ON (check_name) check_name, status FROM check_result WHERE check_name = ANY($1) AND NOT soft_failure ORDER BY check_name, timestamp DESC",
+            &[&parents],
+        )
+        .await?;
+
+    let critical: Vec<String> = rows
+        .into_iter()
+        .filter(|row| {
+            CheckResultStatus::from(row.get::<_, i16>("status")) == CheckResultStatus::Critical
+        })
+        .map(|row| row.get("check_name"))
+        .collect();
+
+    Ok(parents
+        .iter()
+        .find(|parent| critical.contains(parent))
+        .cloned())
 }
 
 /**
@@ -145,17 +610,188 @@ async fn fetch_secrets(
  * to the DB and send it, if needed, to the notification channel
  */
 pub async fn process_check_result(
-    result: CheckResult,
+    mut result: CheckResult,
     image_jpg_base64: Option<String>,
-    db_client: &Arc<PostgresClient>,
+    db_pool: &Pool,
     http_client: &reqwest::Client,
+    redirects: &SharedRedirects,
+    sinks: &ResultSinks,
 ) -> Result<(), Error> {
+    let (db_client, previous_status) = write_check_result(&mut result, db_pool).await?;
+
+    dispatch_check_result(
+        result,
+        previous_status,
+        image_jpg_base64,
+        &db_client,
+        http_client,
+        redirects,
+        sinks,
+    )
+    .await
+}
+
+/**
+ * This function prepares a result and writes it to the DB, returning the connection used along
+ * with the previous status of the check
+ */
+async fn write_check_result(
+    result: &mut CheckResult,
+    db_pool: &Pool,
+) -> Result<(deadpool_postgres::Object, Option<CheckResultStatus>), Error> {
+    let db_client = db_pool.get().await?;
+
+    let previous_status = prepare_check_result(result, &db_client).await?;
+
     // Write result to DB
-    result.write_to_db(db_client.clone()).await?;
+    let timer = metrics::DB_WRITE_DURATION.start_timer();
+    result.write_to_db(&db_client).await?;
+    timer.observe_duration();
+
+    Ok((db_client, previous_status))
+}
+
+/**
+ * This function processes a result received outside of the results stream, e.g. through the API,
+ * queueing it in the stream when the DB is unavailable so that it is written once it is back.
+ * Only a result which could not be written is queued, not to be written twice. The image of a
+ * queued result is not kept
+ */
+#[allow(clippy::too_many_arguments)]
+pub async fn process_or_buffer_check_result(
+    mut result: CheckResult,
+    image_jpg_base64: Option<String>,
+    db_pool: &Pool,
+    redis_client: &redis::Client,
+    http_client: &reqwest::Client,
+    redirects: &SharedRedirects,
+    sinks: &ResultSinks,
+) -> Result<(), Error> {
+    let check_name = result.check_name.clone();
+    let payload = serde_json::to_string(&result)?;
+
+    let (db_client, previous_status) = match write_check_result(&mut result, db_pool).await {
+        Ok(written) => written,
+        Err(e) if db::is_unavailable(&e) => {
+            warn!("TimescaleDB is unavailable, queueing the result of {check_name}: {e}");
+            return results::enqueue(redis_client, &payload).await;
+        }
+        Err(e) => return Err(e),
+    };
+
+    dispatch_check_result(
+        result,
+        previous_status,
+        image_jpg_base64,
+        &db_client,
+        http_client,
+        redirects,
+        sinks,
+    )
+    .await
+}
+
+/**
+ * This function prepares a result before it is written, returning the previous status of the
+ * check when it is needed to decide about the notifications
+ */
+pub async fn prepare_check_result(
+    result: &mut CheckResult,
+    db_client: &PostgresClient,
+) -> Result<Option<CheckResultStatus>, Error> {
+    let throttled = result.notify_on_state_change || result.renotify_interval_minutes.is_some();
+
+    // The previous status is needed only to notify on state changes and recoveries
+    let previous_status = if throttled || result.notify_on_recovery {
+        last_status(db_client, &result.check_name).await?
+    } else {
+        None
+    };
+
+    // The result is still recorded, but not notified, while a parent check is critical
+    if !result.depends_on.is_empty() && !result.soft_failure {
+        result.suppressed_by = critical_parent(db_client, &result.depends_on).await?;
+    }
+
+    // A result whose metrics deviate from their baseline is a Warning even if the check passed
+    anomaly::flag_anomalies(result, db_client).await?;
+
+    Ok(previous_status)
+}
+
+/**
+ * This function sends a result already written to the DB to the sinks and, if needed, to the
+ * notification channels
+ */
+pub async fn dispatch_check_result(
+    result: CheckResult,
+    previous_status: Option<CheckResultStatus>,
+    image_jpg_base64: Option<String>,
+    db_client: &PostgresClient,
+    http_client: &reqwest::Client,
+    redirects: &SharedRedirects,
+    sinks: &ResultSinks,
+) -> Result<(), Error> {
+    let throttled = result.notify_on_state_change || result.renotify_interval_minutes.is_some();
+
+    metrics::RESULTS_PROCESSED
+        .with_label_values(&[&format!("{:?}", result.status)])
+        .inc();
+
+    // A failed attempt is only recorded, the check is retried before reporting the failure
+    if result.soft_failure {
+        return Ok(());
+    }
 
-    // Send result to telegram channels
-    if result.status != CheckResultStatus::Ok
-        && result.status != CheckResultStatus::Pending
+    // Mirror the result to the webhooks and exporters
+    sinks.send(&result);
+
+    // An acknowledged failure is not notified again until the check recovers
+    let acknowledged = match result.status {
+        CheckResultStatus::Ok => {
+            ack::clear(db_client, &result.check_name).await?;
+            false
+        }
+        CheckResultStatus::Pending => false,
+        _ => ack::active_ack(db_client, &result.check_name)
+            .await?
+            .is_some(),
+    };
+
+    let notable = match result.status {
+        // Ok results are notified only as recoveries, when requested
+        CheckResultStatus::Ok => {
+            (result.notify_on_state_change || result.notify_on_recovery)
+                && result
+                    .status
+                    .is_notable_transition(previous_status.as_ref())
+        }
+        CheckResultStatus::Pending => false,
+        // Persistent failures are notified again once the renotify interval has elapsed
+        _ if throttled => {
+            result
+                .status
+                .is_notable_transition(previous_status.as_ref())
+                || match result.renotify_interval_minutes {
+                    Some(minutes) => renotify_due(db_client, &result.check_name, minutes).await?,
+                    None => false,
+                }
+        }
+        _ => true,
+    };
+
+    // A flapping check is notified only when it starts and stops flapping
+    let flap_state = flapping::update(db_client, &result.check_name).await?;
+    let notable = match flap_state {
+        FlapState::Started | FlapState::Stopped => true,
+        FlapState::Flapping => false,
+        FlapState::Stable => notable,
+    };
+
+    // Send result to the notification channels
+    if notable
+        && !acknowledged
+        && result.suppressed_by.is_none()
         && match result.mute_notifications {
             Some(true) => {
                 match result.mute_notifications_until {
@@ -165,59 +801,46 @@ pub async fn process_check_result(
             }
             _ => true, // if mute_notifications is None or false we send the notification
         }
+        && !result
+            .maintenance_windows
+            .iter()
+            .any(|window| window.is_active(Utc::now()))
     {
-        let timestamp_local = result
-            .timestamp
-            .unwrap_or_else(Utc::now)
-            .with_timezone(&Local);
-
-        let message = format!("<b>Date</b>: {0}\n<b>Check name</b>: {1} \n<b>Status</b>: {2:?}\n<b>Output</b>\n<pre>{3}</pre>", timestamp_local.format("%Y-%m-%d %H:%M:%S %Z"), result.check_name, result.status, encode_safe(&result.get_output()));
-
         let decoded_image: Option<Vec<u8>> = image_jpg_base64
             .as_ref()
             .map(|img| general_purpose::STANDARD.decode(img))
             .transpose()?;
 
-        for channel in result.telegram_channels.iter() {
-            let result = if let Some(ref image) = decoded_image {
-                let url = format!(
-                    "https://api.telegram.org/bot{}/sendPhoto",
-                    channel.bot_token
-                );
-
-                let form = reqwest::multipart::Form::new()
-                    .text("chat_id", channel.chat_id.clone())
-                    .text("caption", message.clone())
-                    .text("parse_mode", "HTML")
-                    .part(
-                        "photo",
-                        reqwest::multipart::Part::bytes(image.clone())
-                            .file_name(format!("{}.jpg", result.check_name))
-                            .mime_str("image/jpeg")?,
-                    );
-
-                http_client.post(&url).multipart(form).send().await
-            } else {
-                let url = format!(
-                    "https://api.telegram.org/bot{}/sendMessage",
-                    channel.bot_token
-                );
-
-                http_client
-                    .post(&url)
-                    .form(&[
-                        ("chat_id", channel.chat_id.clone()),
-                        ("text", message.clone()),
-                        ("parse_mode", "HTML".to_string()),
-                    ])
-                    .send()
-                    .await
-            };
-
-            if let Err(e) = result {
-                error!("Error when sending check result to Telegram channel: {e}");
-            }
+        // A temporary redirect replaces the channels of the check until it expires
+        redirects.remove_if(&result.check_name, |_, redirect| {
+            redirect.until <= Utc::now()
+        });
+        let mut channels: Vec<NotificationChannel> = match redirects.get(&result.check_name) {
+            Some(redirect) => vec![NotificationChannel::Telegram(redirect.channel.clone())],
+            None => NotificationChannel::of_result(&result),
+        };
+
+        // A check without channels is notified to the fallback channel, if any
+        if channels.is_empty() {
+            channels.extend(fallback::channel_for(&result.status));
         }
+
+        // A check which starts or stops flapping is notified as such, rather than by its result
+        let flapping = match flap_state {
+            FlapState::Started => Some(true),
+            FlapState::Stopped => Some(false),
+            _ => None,
+        };
+
+        notification::notify(
+            db_client,
+            http_client,
+            &channels,
+            &result,
+            flapping,
+            decoded_image,
+        )
+        .await?;
     }
     Ok(())
 }
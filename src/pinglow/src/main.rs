@@ -1,31 +1,40 @@
-use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 
 use dashmap::DashMap;
 use env_logger::{self, Builder};
-use log::{error, info};
+use log::{error, info, warn};
 use pinglow::check::Check;
-use pinglow::{load_single_runnable_check, results};
+use pinglow::job::JobExecutor;
+use pinglow::leader::{LeaderElector, Leadership};
+use pinglow::sinks::ResultSinks;
+use pinglow::{
+    anomaly, backlog, fallback, groups, load_single_runnable_check, notification, reclaimer,
+    results, retention, scoped_api, snmp, standalone,
+};
 use pinglow_common::redis::init_streams;
+use pinglow_common::telemetry::init_tracing;
 use tokio::signal::unix::signal;
 use tokio::sync::mpsc::Sender;
 use tokio::{
     signal::unix::SignalKind,
-    sync::{mpsc, RwLock},
+    sync::{mpsc, watch},
 };
+use tokio_util::sync::CancellationToken;
 
+use deadpool_postgres::{Manager, ManagerConfig, Pool, RecyclingMethod};
 use kube::{Api, Client};
 use tokio_postgres::NoTls;
 
+use pinglow::admission::start_admission_server;
 use pinglow::api::start_rocket;
-use pinglow::check::SharedPinglowChecks;
+use pinglow::check::{SharedPinglowChecks, SharedRedirects, SharedSecrets};
 use pinglow::controller::watch_resources;
 use pinglow::scheduler::RunnableCheckEvent;
 use pinglow::{
-    check::SharedChecks,
     config::{get_config_from_env, PinglowConfig},
     error::ReconcileError,
-    scheduler::scheduler_loop,
+    scheduler::{scheduler_loop, SchedulerOptions},
 };
 use pinglow_common::redis::redis_client;
 
@@ -39,35 +48,39 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Initialize the logger
     Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
 
+    let tracer_provider = init_tracing("pinglow");
+
     // Get the configuration
     let config = get_config_from_env();
 
     info!("Connecting to timescaledb");
 
-    // Connect to the DB
-    let (mut postgres_client, connection) = tokio_postgres::connect(
-        &format!(
-            "host={} user={} password={} dbname={}",
-            config.db_host, config.db_user, config.db_user_password, config.db
-        ),
-        NoTls,
-    )
-    .await?;
+    // Pool of connections to the DB, so that a slow query does not block the others
+    let mut postgres_config = tokio_postgres::Config::new();
+    postgres_config
+        .host(&config.db_host)
+        .user(&config.db_user)
+        .password(&config.db_user_password)
+        .dbname(&config.db);
 
-    // The connection object performs the actual communication with the database,
-    // so spawn it off to run on its own.
-    tokio::spawn(async move {
-        if let Err(e) = connection.await {
-            error!("Error when connecting to TimescaleDB: {e}");
-        }
-    });
+    let manager = Manager::from_config(
+        postgres_config,
+        NoTls,
+        ManagerConfig {
+            recycling_method: RecyclingMethod::Fast,
+        },
+    );
+    let db_pool = Pool::builder(manager)
+        .max_size(config.db_pool_size.max(1))
+        .build()?;
 
     // Apply migrations
-    embedded::migrations::runner()
-        .run_async(&mut postgres_client)
-        .await?;
-
-    let postgres_client_arc = Arc::new(postgres_client);
+    {
+        let mut postgres_client = db_pool.get().await?;
+        embedded::migrations::runner()
+            .run_async(&mut **postgres_client)
+            .await?;
+    }
 
     info!("Connecting to redis");
 
@@ -80,38 +93,284 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     } // conn dropped here
 
     // Hashmap that holds the checks currently loaded
-    let shared_checks: SharedPinglowChecks = Arc::new(RwLock::new(HashMap::new()));
-    let shared_original_checks: SharedChecks = Arc::new(DashMap::new());
+    let shared_checks: SharedPinglowChecks = Arc::new(DashMap::new());
+
+    // Cache of the secrets referenced by the checks, invalidated by the secrets watcher
+    let secret_cache: SharedSecrets = Arc::new(DashMap::new());
 
     // Channels to communicate checks update events
     let (event_tx, event_rx) = mpsc::channel::<RunnableCheckEvent>(100);
 
-    // Load all the available checks
-    load_checks(&config, event_tx.clone(), shared_original_checks.clone()).await?;
+    // Set by the scheduler once all the checks existing at startup are loaded
+    let (synced_tx, synced_rx) = watch::channel(false);
+
+    // Errors of the controller itself, notified to the default channel by the leader
+    let (internal_errors, internal_errors_rx) = fallback::InternalErrors::channel();
+
+    // Client of the cluster shared by all the tasks, there is none in standalone mode
+    let kube_client = match &config.standalone_config_dir {
+        Some(_) => None,
+        None => Some(Client::try_default().await?),
+    };
+
+    if let Some(config_dir) = &config.standalone_config_dir {
+        // Standalone mode: the resources are defined in local files instead of CRDs
+        info!("Running in standalone mode, loading resources from {config_dir}");
+        tokio::spawn(standalone::watch_resources(
+            config_dir.into(),
+            event_tx,
+            synced_tx,
+        ));
+    } else if let Some(kube_client) = &kube_client {
+        for scope in config.watch_scopes() {
+            // Load all the available checks
+            load_checks(
+                kube_client,
+                &config,
+                scope.as_deref(),
+                event_tx.clone(),
+                &secret_cache,
+            )
+            .await?;
+
+            // Thread to watch for the changes in Pinglow resources
+            tokio::spawn(watch_resources(
+                kube_client.clone(),
+                config.clone(),
+                scope,
+                event_tx.clone(),
+                secret_cache.clone(),
+                internal_errors.clone(),
+                redis_client.clone(),
+                db_pool.clone(),
+            ));
+        }
+
+        event_tx
+            .send(RunnableCheckEvent::Synced(synced_tx))
+            .await
+            .ok();
+    }
 
-    // Thread to watch for the changes in Pinglow resources
-    tokio::spawn(watch_resources(
-        config.clone(),
-        event_tx,
-        shared_original_checks,
-    ));
+    // Token used to stop the leader election, releasing the leadership, once everything else stopped
+    let election_shutdown = CancellationToken::new();
+
+    // The replicas elect the one which schedules the checks and processes the results, while the
+    // others only serve the API. An instance running alone always leads
+    let (mut leadership, election) = match kube_client
+        .as_ref()
+        .and_then(|client| LeaderElector::from_config(client, &config))
+    {
+        Some(elector) => (
+            elector.leadership(),
+            Some(tokio::spawn(elector.run(election_shutdown.clone()))),
+        ),
+        None => (Leadership::always(), None),
+    };
+
+    // Token used to stop the scheduler, which exports its queue for the next instance
+    let scheduler_shutdown = CancellationToken::new();
 
     // Spawn the task which will schedule the checks in a continuous way
     let mut scheduler = tokio::spawn(scheduler_loop(
         event_rx,
         shared_checks.clone(),
         redis_client.clone(),
+        JobExecutor::new(
+            kube_client.clone(),
+            config.target_namespace.clone(),
+            redis_client.clone(),
+        ),
+        SchedulerOptions::from_config(&config),
+        leadership.clone(),
+        scheduler_shutdown.clone(),
     ));
 
-    // Spawn the task that will process the results
-    let mut result_consumer = tokio::spawn(results::run(
-        redis_client.clone(),
-        postgres_client_arc.clone(),
+    // HTTP client shared by the result consumer and the API to deliver notifications
+    let http_client = notification::http_client(config.notification_proxy.as_deref())?;
+
+    // Temporary notification redirects set through the API
+    let redirects: SharedRedirects = Arc::new(DashMap::new());
+
+    // Sinks mirroring every processed result
+    let sinks = Arc::new(ResultSinks::new(
+        &config,
+        http_client.clone(),
+        &redis_client,
     ));
 
+    // Token used to stop the result consumer, which drains the results already read
+    let results_shutdown = CancellationToken::new();
+
+    // Spawn the task that will process the results, along with the maintenance tasks, once leading
+    let mut result_consumer = tokio::spawn({
+        let mut leadership = leadership.clone();
+        let redis_client = redis_client.clone();
+        let db_pool = db_pool.clone();
+        let http_client = http_client.clone();
+        let redirects = redirects.clone();
+        let shared_checks = shared_checks.clone();
+        let sinks = sinks.clone();
+        let config = config.clone();
+        let results_shutdown = results_shutdown.clone();
+        let kube_client = kube_client.clone();
+        let secret_cache = secret_cache.clone();
+
+        async move {
+            tokio::select! {
+                _ = leadership.acquired() => {}
+                _ = results_shutdown.cancelled() => return Ok(()),
+            }
+
+            // Spawn the task re-delivering the checks lost by crashed runners
+            tokio::spawn(reclaimer::run(redis_client.clone(), config.clone()));
+
+            // Spawn the task that drops the expired check results
+            tokio::spawn(retention::run(
+                db_pool.clone(),
+                kube_client.clone(),
+                shared_checks.clone(),
+                synced_rx,
+                config.clone(),
+            ));
+
+            // Spawn the task computing the baselines of the performance data
+            tokio::spawn(anomaly::run(
+                db_pool.clone(),
+                shared_checks.clone(),
+                config.clone(),
+            ));
+
+            // Spawn the task evaluating the check groups, which are resources of the cluster
+            if let Some(kube_client) = kube_client {
+                tokio::spawn(groups::run(
+                    kube_client,
+                    db_pool.clone(),
+                    http_client.clone(),
+                    shared_checks.clone(),
+                    config.clone(),
+                    secret_cache,
+                ));
+            }
+
+            // Spawn the tasks notifying the internal errors, among which the runners lost
+            tokio::spawn(fallback::notify_internal_errors(
+                internal_errors_rx,
+                db_pool.clone(),
+                http_client.clone(),
+            ));
+            tokio::spawn(backlog::watch_runners(
+                redis_client.clone(),
+                internal_errors,
+            ));
+
+            // Spawn the task retrying the notifications whose delivery did not complete
+            tokio::spawn(notification::retry_worker(
+                db_pool.clone(),
+                http_client.clone(),
+                shared_checks.clone(),
+                redirects.clone(),
+                config.clone(),
+            ));
+
+            // Spawn the task relaying the results of the external agents
+            tokio::spawn(results::relay_agent_results(
+                redis_client.clone(),
+                shared_checks.clone(),
+                config.clone(),
+                results_shutdown.clone(),
+            ));
+
+            results::run(
+                redis_client,
+                db_pool,
+                http_client,
+                redirects,
+                shared_checks,
+                sinks,
+                config,
+                results_shutdown,
+            )
+            .await
+        }
+    });
+
+    // Resolve the optional channels notified of the checks without channels
+    let fallback_configured =
+        config.check_error_channel.is_some() || config.default_channel.is_some();
+    if fallback_configured && config.standalone_config_dir.is_some() {
+        warn!("The fallback channels are not available in standalone mode");
+    } else if let Some(kube_client) = &kube_client {
+        tokio::spawn(fallback::run(
+            kube_client.clone(),
+            config.clone(),
+            secret_cache.clone(),
+        ));
+    }
+
+    // Spawn the optional SNMP trap receiver
+    if config.snmp_trap_address.is_some() && config.standalone_config_dir.is_some() {
+        warn!("The SNMP trap receiver is not available in standalone mode");
+    } else if let (Some(_), Some(snmp_client)) = (&config.snmp_trap_address, kube_client.clone()) {
+        let snmp_config = config.clone();
+        let snmp_checks = shared_checks.clone();
+        let snmp_db_pool = db_pool.clone();
+        let snmp_redis_client = redis_client.clone();
+        let snmp_http_client = http_client.clone();
+        let snmp_redirects = redirects.clone();
+        let snmp_sinks = sinks.clone();
+        tokio::spawn(async move {
+            if let Err(e) = snmp::run(
+                snmp_client,
+                snmp_config,
+                snmp_checks,
+                snmp_db_pool,
+                snmp_redis_client,
+                snmp_http_client,
+                snmp_redirects,
+                snmp_sinks,
+            )
+            .await
+            {
+                error!("SNMP trap receiver terminated: {e}");
+            }
+        });
+    }
+
+    // Spawn the optional validation webhook, which needs the cluster to look up the scripts
+    let admission_shutdown = if let Some(kube_client) = &kube_client {
+        match start_admission_server(&config, kube_client.clone()).await? {
+            Some(admission_server) => {
+                let admission_shutdown = admission_server.shutdown();
+                tokio::spawn(async move {
+                    if let Err(e) = admission_server.launch().await {
+                        error!("Validation webhook terminated: {e}");
+                    }
+                });
+                Some(admission_shutdown)
+            }
+            None => None,
+        }
+    } else {
+        None
+    };
+
+    // Time given to the scheduler, the in-flight results and the deliveries to complete on shutdown
+    let grace_period = Duration::from_secs(config.shutdown_grace_period_seconds);
+
     // Spawn the task to host Rocket to handle API requests
-    let (rocket, rocket_shutdown) =
-        start_rocket(config, shared_checks.clone(), postgres_client_arc.clone()).await?;
+    let (rocket, rocket_shutdown) = start_rocket(
+        config,
+        shared_checks.clone(),
+        db_pool.clone(),
+        http_client,
+        redirects,
+        secret_cache.clone(),
+        sinks.clone(),
+        redis_client.clone(),
+        kube_client,
+    )
+    .await?;
     let rocket_handle = tokio::spawn(async move {
         rocket.launch().await?;
         Ok::<(), rocket::Error>(())
@@ -127,6 +386,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         _ = &mut result_consumer => {
             info!("Result thread terminated");
         }
+        // A replica which lost the leadership restarts, to come back as a standby
+        _ = leadership.lost() => {
+            error!("Lost the leadership");
+        }
 
         // In case we receive a sigterm we exit to teardown our jobs in a clean way (especially rocket)
         _ = sigint.recv() => {
@@ -137,12 +400,56 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     }
 
     info!("Shutting down...");
+    let deadline = tokio::time::Instant::now() + grace_period;
     rocket_shutdown.notify();
+    if let Some(admission_shutdown) = admission_shutdown {
+        admission_shutdown.notify();
+    }
 
-    scheduler.abort();
-    result_consumer.abort();
+    // Let the scheduler hand over its queue before exiting
+    scheduler_shutdown.cancel();
+    if !scheduler.is_finished()
+        && tokio::time::timeout_at(deadline, &mut scheduler)
+            .await
+            .is_err()
+    {
+        warn!("Timeout exporting the scheduler queue");
+        scheduler.abort();
+    }
+
+    // Let the result consumer process and ack the results it has already read
+    results_shutdown.cancel();
+    if !result_consumer.is_finished()
+        && tokio::time::timeout_at(deadline, &mut result_consumer)
+            .await
+            .is_err()
+    {
+        warn!("Timeout draining the in-flight results, they will be processed after restart");
+        result_consumer.abort();
+    }
+
+    // Let the webhooks and Kafka deliver the results already processed
+    if tokio::time::timeout_at(deadline, sinks.flush())
+        .await
+        .is_err()
+    {
+        warn!("Timeout delivering the results to the webhooks and Kafka");
+    }
     let _ = rocket_handle.await?;
 
+    // Release the leadership last, once the queue is exported and the results drained
+    election_shutdown.cancel();
+    if let Some(election) = election {
+        let _ = election.await;
+    }
+
+    // Flush the spans still to be exported
+    if let Some(tracer_provider) = tracer_provider {
+        if let Err(e) = tracer_provider.shutdown() {
+            error!("Error flushing the traces: {e}");
+        }
+    }
+
     Ok(())
 }
 
@@ -150,32 +457,20 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
  * This function is used to load all the checks from the CR of the pinglow namespace
  */
 async fn load_checks(
+    client: &Client,
     config: &PinglowConfig,
+    namespace: Option<&str>,
     event_rx: Sender<RunnableCheckEvent>,
-    shared_checks: SharedChecks,
+    secret_cache: &SharedSecrets,
 ) -> Result<(), ReconcileError> {
-    // Create the kube client
-    let client = Client::try_default().await?;
-
-    // Get all checks and scripts from the target namespace
-    let checks: Api<Check> = Api::namespaced(client.clone(), &config.target_namespace);
+    // Get all checks from the watched namespace, or from all of them
+    let checks: Api<Check> = scoped_api(client, namespace);
 
     let check_list = checks.list(&Default::default()).await?;
 
     for check in check_list.iter() {
-        let check_name =
-            check
-                .metadata
-                .name
-                .as_ref()
-                .ok_or(ReconcileError::PropertyExtractionError(
-                    "Cannot extract check name".to_string(),
-                ))?;
-
-        // TODO: avoid cloning here
-        shared_checks.insert(check_name.to_owned(), Arc::new(check.clone()));
-
-        let runnable_check = load_single_runnable_check(check, &client, config).await?;
+        let runnable_check =
+            load_single_runnable_check(check, client, config, secret_cache).await?;
 
         event_rx
             .send(RunnableCheckEvent::AddOrUpdate(Arc::new(runnable_check)))
@@ -192,6 +487,12 @@ async fn load_checks(
 mod tests {
     use std::process::Command;
 
+    use k8s_openapi::apiextensions_apiserver::pkg::apis::apiextensions::v1::{
+        CustomResourceDefinition, JSONSchemaProps, JSONSchemaPropsOrArray, JSONSchemaPropsOrBool,
+    };
+    use regex::Regex;
+    use serde::Deserialize;
+
     #[test]
     fn check_formatting() {
         let status = Command::new("cargo")
@@ -201,4 +502,67 @@ mod tests {
 
         assert!(status.success(), "Code is not properly formatted");
     }
+
+    /**
+     * This function checks that the fields referenced by the validation rules of a schema and of
+     * its nested schemas are among their properties, returning the rules which are not
+     */
+    fn unknown_rule_fields(schema: &JSONSchemaProps, path: &str) -> Vec<String> {
+        let field = Regex::new(r"self\.(\w+)").unwrap();
+        let mut unknown = vec![];
+
+        for validation in schema.x_kubernetes_validations.iter().flatten() {
+            for captures in field.captures_iter(&validation.rule) {
+                let name = &captures[1];
+                if !schema
+                    .properties
+                    .as_ref()
+                    .is_some_and(|p| p.contains_key(name))
+                {
+                    unknown.push(format!("{path}: {name} in {}", validation.rule));
+                }
+            }
+        }
+
+        for (name, property) in schema.properties.iter().flatten() {
+            unknown.extend(unknown_rule_fields(property, &format!("{path}.{name}")));
+        }
+        if let Some(JSONSchemaPropsOrArray::Schema(items)) = &schema.items {
+            unknown.extend(unknown_rule_fields(items, &format!("{path}[]")));
+        }
+        if let Some(JSONSchemaPropsOrBool::Schema(values)) = &schema.additional_properties {
+            unknown.extend(unknown_rule_fields(values, &format!("{path}{{}}")));
+        }
+
+        unknown
+    }
+
+    #[test]
+    fn crds_are_valid() {
+        let crds_dir = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../../helm-charts/pinglow/crds"
+        );
+
+        for entry in std::fs::read_dir(crds_dir).expect("failed to read the CRDs") {
+            let path = entry.unwrap().path();
+            let content = std::fs::read_to_string(&path).unwrap();
+
+            for document in serde_yaml::Deserializer::from_str(&content) {
+                let crd = CustomResourceDefinition::deserialize(document)
+                    .unwrap_or_else(|e| panic!("Invalid CRD in {}: {e}", path.display()));
+
+                for version in &crd.spec.versions {
+                    let schema = version
+                        .schema
+                        .as_ref()
+                        .and_then(|schema| schema.open_api_v3_schema.as_ref())
+                        .expect("CRD without schema");
+
+                    let unknown = unknown_rule_fields(schema, &crd.spec.names.kind);
+                    assert!(unknown.is_empty(), "Unknown rule fields: {unknown:?}");
+                }
+            }
+        }
+    }
 }
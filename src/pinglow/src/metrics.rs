@@ -0,0 +1,154 @@
+use std::sync::LazyLock;
+
+use prometheus::{
+    register_gauge, register_histogram, register_int_counter, register_int_counter_vec,
+    register_int_gauge, Encoder, Gauge, Histogram, IntCounter, IntCounterVec, IntGauge,
+    TextEncoder,
+};
+
+use chrono::{DateTime, Utc};
+
+use crate::backlog::QueueBacklog;
+
+pub static CHECKS_SCHEDULED: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "pinglow_checks_scheduled_total",
+        "Checks dispatched by the scheduler, to the runners or as Jobs"
+    )
+    .expect("Valid metric")
+});
+
+pub static RESULTS_PROCESSED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "pinglow_results_processed_total",
+        "Check results processed, by status",
+        &["status"]
+    )
+    .expect("Valid metric")
+});
+
+pub static NOTIFICATIONS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "pinglow_notifications_total",
+        "Notification delivery attempts, by kind of channel and outcome",
+        &["channel", "outcome"]
+    )
+    .expect("Valid metric")
+});
+
+pub static DB_WRITE_DURATION: LazyLock<Histogram> = LazyLock::new(|| {
+    register_histogram!(
+        "pinglow_db_write_duration_seconds",
+        "Time spent writing a check result, or a batch of them, to the database"
+    )
+    .expect("Valid metric")
+});
+
+pub static QUEUED_CHECKS: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!(
+        "pinglow_queue_queued_checks",
+        "Checks in the Redis queue not yet picked up by a runner"
+    )
+    .expect("Valid metric")
+});
+
+pub static RUNNING_CHECKS: LazyLock<IntGauge> = LazyLock::new(|| {
+    register_int_gauge!(
+        "pinglow_queue_running_checks",
+        "Checks picked up by a runner but not yet completed"
+    )
+    .expect("Valid metric")
+});
+
+pub static OLDEST_PENDING_AGE: LazyLock<Gauge> = LazyLock::new(|| {
+    register_gauge!(
+        "pinglow_queue_oldest_pending_age_seconds",
+        "Age of the oldest check waiting for or being executed by a runner"
+    )
+    .expect("Valid metric")
+});
+
+/**
+ * This function updates the queue gauges from the current backlog
+ */
+pub fn set_queue_backlog(backlog: &QueueBacklog) {
+    QUEUED_CHECKS.set(backlog.queued_checks as i64);
+    RUNNING_CHECKS.set(backlog.running_checks as i64);
+    OLDEST_PENDING_AGE.set(backlog.oldest_pending_age_seconds);
+}
+
+/// The latest value of a performance data key of a check
+pub struct PerfDataSample {
+    pub check_name: String,
+    pub key: String,
+    pub uom: Option<String>,
+    pub value: f32,
+    pub timestamp: DateTime<Utc>,
+}
+
+/**
+ * This function renders all the registered metrics in the Prometheus text format
+ */
+pub fn render() -> Result<String, prometheus::Error> {
+    let mut buffer = vec![];
+    TextEncoder::new().encode(&prometheus::gather(), &mut buffer)?;
+
+    Ok(String::from_utf8_lossy(&buffer).to_string())
+}
+
+/**
+ * This function renders the latest performance data of the checks as OpenMetrics gauges, each
+ * sample carrying the time it was measured at
+ */
+pub fn render_perf_data(samples: &[PerfDataSample]) -> String {
+    let mut text = String::from(
+        "# TYPE pinglow_perf gauge\n# HELP pinglow_perf Latest performance data of the checks, by check and key\n",
+    );
+
+    for sample in samples {
+        text.push_str(&format!(
+            "pinglow_perf{{check=\"{}\",key=\"{}\",uom=\"{}\"}} {} {:.3}\n",
+            escape_label_value(&sample.check_name),
+            escape_label_value(&sample.key),
+            escape_label_value(sample.uom.as_deref().unwrap_or_default()),
+            sample.value,
+            sample.timestamp.timestamp_millis() as f64 / 1000.0
+        ));
+    }
+
+    text.push_str("# EOF\n");
+    text
+}
+
+/**
+ * This function escapes the backslashes, quotes and line feeds of a label value
+ */
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    #[test]
+    fn perf_data_gauges() {
+        let samples = [PerfDataSample {
+            check_name: "disk".into(),
+            key: "/var \"log\"".into(),
+            uom: Some("MB".into()),
+            value: 2643.5,
+            timestamp: Utc.timestamp_millis_opt(1_700_000_000_250).unwrap(),
+        }];
+
+        assert_eq!(
+            render_perf_data(&samples),
+            "# TYPE pinglow_perf gauge\n# HELP pinglow_perf Latest performance data of the checks, by check and key\npinglow_perf{check=\"disk\",key=\"/var \\\"log\\\"\",uom=\"MB\"} 2643.5 1700000000.250\n# EOF\n"
+        );
+    }
+}
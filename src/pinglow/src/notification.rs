@@ -0,0 +1,461 @@
+use std::{
+    sync::LazyLock,
+    time::{Duration, Instant},
+};
+
+use anyhow::Error;
+use dashmap::DashMap;
+use deadpool_postgres::Pool;
+use log::{error, info, warn};
+use pinglow_common::CheckResult;
+use tokio_postgres::Client as PostgresClient;
+
+use crate::{
+    check::{SharedPinglowChecks, SharedRedirects},
+    config::PinglowConfig,
+    fallback, metrics,
+    notifier::{NotificationChannel, Notifier},
+};
+
+// How often the outbox is scanned for notifications to retry
+const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+// Maximum number of notifications retried at every scan
+const RETRY_BATCH_SIZE: i64 = 100;
+
+// Delay before the first retry of a failed notification, doubled at every further failure
+const RETRY_BASE_DELAY_SECONDS: f64 = 30.0;
+
+// Maximum delay between two retries of a notification
+const RETRY_MAX_DELAY_SECONDS: f64 = 3600.0;
+
+// Maximum number of notifications delivered to a channel within a rate limit window
+const RATE_LIMIT: u32 = 20;
+
+// Window of the rate limit of the channels
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(60);
+
+// Notifications delivered to every channel in the current window, by channel kind and id
+static RATE_LIMIT_WINDOWS: LazyLock<DashMap<String, (Instant, u32)>> = LazyLock::new(DashMap::new);
+
+/**
+ * This function builds the HTTP client used to deliver notifications, sending every request
+ * through the given HTTP or SOCKS proxy, if any
+ */
+pub fn http_client(proxy: Option<&str>) -> Result<reqwest::Client, reqwest::Error> {
+    let mut builder = reqwest::Client::builder();
+
+    if let Some(proxy) = proxy {
+        builder = builder.proxy(reqwest::Proxy::all(proxy)?);
+    }
+
+    builder.build()
+}
+
+/// A notification recorded in the outbox, waiting to be delivered
+pub struct OutboxNotification {
+    pub id: i64,
+    pub check_name: String,
+    pub channel_kind: String,
+    pub channel_id: String,
+    pub message: String,
+    pub image: Option<Vec<u8>>,
+    pub attempts: i32,
+}
+
+/**
+ * This function records a notification in the outbox before its delivery is attempted
+ */
+pub async fn enqueue(
+    db_client: &PostgresClient,
+    check_name: &str,
+    channel: &NotificationChannel,
+    message: String,
+    image: Option<Vec<u8>>,
+) -> Result<OutboxNotification, tokio_postgres::Error> {
+    let row = db_client
+        .query_one(
+            "INSERT INTO notification_outbox (check_name, channel_kind, channel_id, message, image, next_attempt_at) VALUES ($1, $2, $3, $4, $5, NOW() + $6 * INTERVAL '1 second') RETURNING id",
+            &[
+                &check_name,
+                &channel.kind(),
+                &channel.id(),
+                &message,
+                &image,
+                // Leave some time to the first delivery attempt before considering it stuck
+                &RETRY_BASE_DELAY_SECONDS,
+            ],
+        )
+        .await?;
+
+    Ok(OutboxNotification {
+        id: row.get("id"),
+        check_name: check_name.to_string(),
+        channel_kind: channel.kind().to_string(),
+        channel_id: channel.id().to_string(),
+        message,
+        image,
+        attempts: 0,
+    })
+}
+
+/**
+ * This function tells whether a notification can be delivered to a channel without exceeding its
+ * rate limit, counting it if so
+ */
+fn within_rate_limit(channel: &NotificationChannel) -> bool {
+    let mut window = RATE_LIMIT_WINDOWS
+        .entry(format!("{}:{}", channel.kind(), channel.id()))
+        .or_insert((Instant::now(), 0));
+
+    if window.0.elapsed() >= RATE_LIMIT_WINDOW {
+        *window = (Instant::now(), 0);
+    }
+
+    if window.1 >= RATE_LIMIT {
+        return false;
+    }
+
+    window.1 += 1;
+    true
+}
+
+/**
+ * This function delivers a notification of the outbox and records the outcome. A failed
+ * notification stays pending for the retry worker, with an exponential backoff, unless
+ * max_attempts has been reached. A notification exceeding the rate limit of its channel is not
+ * attempted, but postponed to the next window. The older notifications of the check still pending
+ * for the channel are superseded, so that they are not delivered after this one
+ */
+pub async fn deliver(
+    db_client: &PostgresClient,
+    http_client: &reqwest::Client,
+    channel: &NotificationChannel,
+    notification: &OutboxNotification,
+    max_attempts: Option<i32>,
+) -> Result<(), Error> {
+    // A notification read by the retry worker may have been superseded in the meantime
+    let pending = db_client
+        .query_opt(
+            "SELECT 1 FROM notification_outbox WHERE id = $1 AND status = 'pending'",
+            &[&notification.id],
+        )
+        .await?;
+    if pending.is_none() {
+        return Ok(());
+    }
+
+    let superseded = db_client
+        .execute(
+            "UPDATE notification_outbox SET status = 'superseded', updated_at = NOW() WHERE check_name = $1 AND channel_kind = $2 AND channel_id = $3 AND status = 'pending' AND id < $4",
+            &[
+                &notification.check_name,
+                &notification.channel_kind,
+                &notification.channel_id,
+                &notification.id,
+            ],
+        )
+        .await?;
+    if superseded > 0 {
+        info!(
+            "Superseded {superseded} pending notification(s) of {} to {} channel {}",
+            notification.check_name,
+            channel.kind(),
+            channel.id()
+        );
+    }
+
+    if !within_rate_limit(channel) {
+        warn!(
+            "Rate limit of {} channel {} exceeded, postponing notification {}",
+            channel.kind(),
+            channel.id(),
+            notification.id
+        );
+
+        db_client
+            .execute(
+                "UPDATE notification_outbox SET updated_at = NOW(), next_attempt_at = NOW() + $2 * INTERVAL '1 second' WHERE id = $1",
+                &[&notification.id, &RATE_LIMIT_WINDOW.as_secs_f64()],
+            )
+            .await?;
+
+        return Ok(());
+    }
+
+    let outcome = channel.send(http_client, notification).await;
+
+    metrics::NOTIFICATIONS
+        .with_label_values(&[
+            channel.kind(),
+            if outcome.is_ok() {
+                "success"
+            } else {
+                "failure"
+            },
+        ])
+        .inc();
+
+    // Every attempt is recorded, to tell afterwards whether and when a notification went out. The
+    // record is best-effort: failing it must not leave a delivered notification pending, to be
+    // sent again by the retry worker
+    if let Err(e) = db_client
+        .execute(
+            "INSERT INTO notification_attempts (notification_id, check_name, channel_kind, channel_id, success, error) VALUES ($1, $2, $3, $4, $5, $6)",
+            &[
+                &notification.id,
+                &notification.check_name,
+                &channel.kind(),
+                &channel.id(),
+                &outcome.is_ok(),
+                &outcome.as_ref().err().map(|e| e.to_string()),
+            ],
+        )
+        .await
+    {
+        error!(
+            "Error recording the delivery attempt of notification {}: {e}",
+            notification.id
+        );
+    }
+
+    match &outcome {
+        Ok(()) => {
+            db_client
+                .execute(
+                    "UPDATE notification_outbox SET status = 'sent', attempts = attempts + 1, updated_at = NOW(), last_error = NULL WHERE id = $1",
+                    &[&notification.id],
+                )
+                .await?;
+        }
+        Err(e) => {
+            // Give up once the attempts are exhausted, the notification stays in the outbox as failed
+            let status = if max_attempts.is_some_and(|max| notification.attempts + 1 >= max) {
+                "failed"
+            } else {
+                "pending"
+            };
+
+            let delay = (RETRY_BASE_DELAY_SECONDS * 2f64.powi(notification.attempts))
+                .min(RETRY_MAX_DELAY_SECONDS);
+
+            db_client
+                .execute(
+                    "UPDATE notification_outbox SET status = $2, attempts = attempts + 1, updated_at = NOW(), next_attempt_at = NOW() + $4 * INTERVAL '1 second', last_error = $3 WHERE id = $1",
+                    &[&notification.id, &status, &e.to_string(), &delay],
+                )
+                .await?;
+        }
+    }
+
+    outcome
+}
+
+/**
+ * This function enforces the cooldown of a check on a channel. Within the window following the
+ * last message the notification is counted as suppressed and None is returned, otherwise a new
+ * window starts and the number of notifications suppressed in the previous one is returned
+ */
+async fn cooldown(
+    db_client: &PostgresClient,
+    check_name: &str,
+    channel: &NotificationChannel,
+    interval_seconds: u64,
+) -> Result<Option<i32>, tokio_postgres::Error> {
+    let cooling_down = db_client
+        .execute(
+            "UPDATE notification_cooldown SET suppressed = suppressed + 1 WHERE check_name = $1 AND channel_kind = $2 AND channel_id = $3 AND last_sent_at > NOW() - $4 * INTERVAL '1 second'",
+            &[
+                &check_name,
+                &channel.kind(),
+                &channel.id(),
+                &(interval_seconds as f64),
+            ],
+        )
+        .await?;
+
+    if cooling_down > 0 {
+        return Ok(None);
+    }
+
+    let row = db_client
+        .query_one(
+            "WITH previous AS (SELECT suppressed FROM notification_cooldown WHERE check_name = $1 AND channel_kind = $2 AND channel_id = $3)
+            INSERT INTO notification_cooldown (check_name, channel_kind, channel_id) VALUES ($1, $2, $3)
+            ON CONFLICT (check_name, channel_kind, channel_id) DO UPDATE SET last_sent_at = NOW(), suppressed = 0
+            RETURNING (SELECT suppressed FROM previous) AS suppressed",
+            &[&check_name, &channel.kind(), &channel.id()],
+        )
+        .await?;
+
+    Ok(Some(row.get::<_, Option<i32>>("suppressed").unwrap_or(0)))
+}
+
+/**
+ * This function notifies a result to the given channels, each one formatting the message in its
+ * own way. When flapping is set, the check is notified as having started (true) or stopped (false)
+ * flapping instead of its result. The messages about the results are subject to the cooldown of
+ * the check, if any
+ */
+pub async fn notify(
+    db_client: &PostgresClient,
+    http_client: &reqwest::Client,
+    channels: &[NotificationChannel],
+    result: &CheckResult,
+    flapping: Option<bool>,
+    image: Option<Vec<u8>>,
+) -> Result<(), Error> {
+    for channel in channels {
+        let message = match flapping {
+            Some(started) => channel.format_flapping(result, started),
+            None => {
+                let suppressed = match result.min_notification_interval_seconds {
+                    Some(interval) => {
+                        match cooldown(db_client, &result.check_name, channel, interval).await? {
+                            Some(suppressed) => suppressed,
+                            None => {
+                                info!(
+                                    "Notification of {} to {} channel {} suppressed by the cooldown",
+                                    result.check_name,
+                                    channel.kind(),
+                                    channel.id()
+                                );
+                                continue;
+                            }
+                        }
+                    }
+                    None => 0,
+                };
+
+                channel.message(result, suppressed)
+            }
+        };
+        let image = image.clone().filter(|_| channel.accepts_images());
+
+        // Record the notification first, so that it is retried if the delivery does not complete
+        let outbox_notification =
+            enqueue(db_client, &result.check_name, channel, message, image).await?;
+
+        if let Err(e) = deliver(db_client, http_client, channel, &outbox_notification, None).await {
+            error!(
+                "Error when sending check result to {} channel: {e}",
+                channel.kind()
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/**
+ * This function periodically retries the notifications left pending in the outbox, either because
+ * their delivery failed or because the controller stopped before attempting it
+ */
+pub async fn retry_worker(
+    db_pool: Pool,
+    http_client: reqwest::Client,
+    shared_checks: SharedPinglowChecks,
+    redirects: SharedRedirects,
+    config: PinglowConfig,
+) {
+    let mut interval = tokio::time::interval(RETRY_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        match retry_pending(&db_pool, &http_client, &shared_checks, &redirects, &config).await {
+            Ok(0) => {}
+            Ok(retried) => info!("Retried {retried} pending notification(s)"),
+            Err(e) => error!("Error retrying pending notifications: {e}"),
+        }
+    }
+}
+
+async fn retry_pending(
+    db_pool: &Pool,
+    http_client: &reqwest::Client,
+    shared_checks: &SharedPinglowChecks,
+    redirects: &SharedRedirects,
+    config: &PinglowConfig,
+) -> Result<usize, Error> {
+    let db_client = db_pool.get().await?;
+
+    let rows = db_client
+        .query(
+            "SELECT id, check_name, channel_kind, channel_id, message, image, attempts FROM notification_outbox WHERE status = 'pending' AND next_attempt_at <= NOW() ORDER BY next_attempt_at LIMIT $1",
+            &[&RETRY_BATCH_SIZE],
+        )
+        .await?;
+
+    let mut retried = 0;
+
+    for row in rows {
+        let notification = OutboxNotification {
+            id: row.get("id"),
+            check_name: row.get("check_name"),
+            channel_kind: row.get("channel_kind"),
+            channel_id: row.get("channel_id"),
+            message: row.get("message"),
+            image: row.get("image"),
+            attempts: row.get("attempts"),
+        };
+
+        // Bot tokens are not stored in the outbox, look the channel up again
+        let Some(channel) = find_channel(&notification, shared_checks, redirects) else {
+            warn!(
+                "Channel {} of check {} no longer exists, dropping notification {}",
+                notification.channel_id, notification.check_name, notification.id
+            );
+            db_client
+                .execute(
+                    "UPDATE notification_outbox SET status = 'failed', updated_at = NOW(), last_error = 'Channel not found' WHERE id = $1",
+                    &[&notification.id],
+                )
+                .await?;
+            continue;
+        };
+
+        if let Err(e) = deliver(
+            &db_client,
+            http_client,
+            &channel,
+            &notification,
+            Some(config.notification_max_attempts),
+        )
+        .await
+        {
+            error!("Error retrying notification {}: {e}", notification.id);
+        }
+
+        retried += 1;
+    }
+
+    Ok(retried)
+}
+
+fn find_channel(
+    notification: &OutboxNotification,
+    shared_checks: &SharedPinglowChecks,
+    redirects: &SharedRedirects,
+) -> Option<NotificationChannel> {
+    if notification.channel_kind == "telegram" {
+        if let Some(redirect) = redirects.get(&notification.check_name) {
+            if redirect.channel.chat_id == notification.channel_id {
+                return Some(NotificationChannel::Telegram(redirect.channel.clone()));
+            }
+        }
+    }
+
+    // The internal errors are not notified on behalf of a check, only to the fallback channels
+    let channels = match shared_checks.get(&notification.check_name) {
+        Some(check) => NotificationChannel::of_check(&check),
+        None => Vec::new(),
+    };
+
+    channels
+        .into_iter()
+        .chain(fallback::channels())
+        .find(|channel| {
+            channel.kind() == notification.channel_kind && channel.id() == notification.channel_id
+        })
+}
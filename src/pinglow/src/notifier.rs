@@ -0,0 +1,863 @@
+use std::{future::Future, sync::LazyLock};
+
+use anyhow::Error;
+use chrono::{Local, Utc};
+use dashmap::DashMap;
+use handlebars::Handlebars;
+use html_escape::encode_safe;
+use log::warn;
+use pinglow_common::{
+    CheckResult, CheckResultStatus, ConcreteDiscordChannel, ConcretePushChannel,
+    ConcreteSlackChannel, ConcreteTelegramChannel, PinglowCheck, PushService,
+};
+use serde_json::{json, Value};
+
+use crate::notification::{self, OutboxNotification};
+
+// Characters of the output kept in the messages, within the limits of the platforms: 4096 for a
+// Telegram message, 3000 for the text of a Slack block and 2000 for a Discord message
+const TELEGRAM_MAX_OUTPUT_CHARS: usize = 3500;
+const SLACK_MAX_OUTPUT_CHARS: usize = 2900;
+const DISCORD_MAX_OUTPUT_CHARS: usize = 1900;
+
+// Characters of a templated Discord message, written in the description of its embed
+const DISCORD_MAX_TEXT_CHARS: usize = 4000;
+
+// Appended to a truncated output
+const TRUNCATION_MARK: &str = "\n… (truncated)";
+
+// Clients of the channels with their own proxy, by proxy URL
+static PROXY_CLIENTS: LazyLock<DashMap<String, reqwest::Client>> = LazyLock::new(DashMap::new);
+
+// Renderers of the message templates, escaping the values as HTML for Telegram
+static TELEGRAM_TEMPLATES: LazyLock<Handlebars<'static>> = LazyLock::new(Handlebars::new);
+
+// and as mrkdwn for Slack, where only &, < and > have to be escaped
+static SLACK_TEMPLATES: LazyLock<Handlebars<'static>> = LazyLock::new(|| {
+    let mut handlebars = Handlebars::new();
+    handlebars.register_escape_fn(|value| {
+        value
+            .replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+    });
+    handlebars
+});
+
+// and not at all for the push notifications, which are plain text, and for Discord, whose markdown
+// is meant to be written in the templates
+static PLAIN_TEMPLATES: LazyLock<Handlebars<'static>> = LazyLock::new(|| {
+    let mut handlebars = Handlebars::new();
+    handlebars.register_escape_fn(handlebars::no_escape);
+    handlebars
+});
+
+/// A destination of the notifications, formatting the results in its own format and delivering
+/// the messages recorded in the outbox
+pub trait Notifier {
+    /// The kind of the channel, as stored in the outbox
+    fn kind(&self) -> &'static str;
+
+    /// The identifier of the channel within its kind, never a secret
+    fn id(&self) -> &str;
+
+    /// The template replacing the default text of the messages, if any
+    fn message_template(&self) -> Option<&str>;
+
+    /// The renderer of the templates, escaping the values as required by the channel
+    fn templates(&self) -> &'static Handlebars<'static>;
+
+    /// The message describing a result, with the given text in place of the default one and the
+    /// number of messages suppressed by the cooldown of the check since the previous one
+    fn format(&self, result: &CheckResult, text: Option<String>, suppressed: i32) -> String;
+
+    /// The message sent when a check starts or stops flapping
+    fn format_flapping(&self, result: &CheckResult, started: bool) -> String;
+
+    /// Whether the image attached to a result is delivered along with the message
+    fn accepts_images(&self) -> bool {
+        false
+    }
+
+    /// Characters of the output and long output available to the templates, once escaped
+    fn max_template_output_chars(&self) -> Option<usize> {
+        None
+    }
+
+    /// Delivers a message of the outbox to the channel
+    fn send(
+        &self,
+        http_client: &reqwest::Client,
+        notification: &OutboxNotification,
+    ) -> impl Future<Output = Result<(), Error>> + Send;
+
+    /**
+     * This function returns the message describing a result, rendering the template of the channel
+     * if any. An invalid template is reported and the default message is sent instead
+     */
+    fn message(&self, result: &CheckResult, suppressed: i32) -> String {
+        let text = self.message_template().and_then(|template| {
+            let data = template_data(
+                result,
+                suppressed,
+                self.max_template_output_chars(),
+                self.templates().get_escape_fn(),
+            );
+
+            match self.templates().render_template(template, &data) {
+                Ok(text) => Some(text),
+                Err(e) => {
+                    warn!(
+                        "Invalid message template of {} channel {}, sending the default message: {e}",
+                        self.kind(),
+                        self.id()
+                    );
+                    None
+                }
+            }
+        });
+
+        self.format(result, text, suppressed)
+    }
+}
+
+/**
+ * This function returns the variables available to the message templates. With a limit, the output
+ * and then the long output are truncated so that together, once escaped, they do not exceed it
+ */
+fn template_data(
+    result: &CheckResult,
+    suppressed: i32,
+    max_output_chars: Option<usize>,
+    escape: &dyn Fn(&str) -> String,
+) -> Value {
+    let mut output = result.get_output();
+    let mut long_output = result.get_long_output();
+
+    if let Some(max_chars) = max_output_chars {
+        output = truncate_unescaped(&output, max_chars, escape);
+        let remaining = max_chars.saturating_sub(escape(&output).chars().count());
+        long_output = long_output.map(|text| truncate_unescaped(&text, remaining, escape));
+    }
+
+    json!({
+        "check_name": result.check_name,
+        "status": format!("{:?}", result.status),
+        "output": output,
+        "long_output": long_output,
+        "perf_data": result.get_raw_perf_data(),
+        "timestamp": local_timestamp(result),
+        "diagnostics": result.diagnostics,
+        "recovered": result.status == CheckResultStatus::Ok,
+        "suppressed": suppressed,
+    })
+}
+
+fn local_timestamp(result: &CheckResult) -> String {
+    result
+        .timestamp
+        .unwrap_or_else(Utc::now)
+        .with_timezone(&Local)
+        .format("%Y-%m-%d %H:%M:%S %Z")
+        .to_string()
+}
+
+/**
+ * This function returns the one-line summary of a result, used as title of the messages
+ */
+fn summary(result: &CheckResult) -> String {
+    // Ok results are only notified when the check recovers
+    if result.status == CheckResultStatus::Ok {
+        format!("✅ {} recovered", result.check_name)
+    } else {
+        format!("{} is {:?}", result.check_name, result.status)
+    }
+}
+
+/**
+ * This function encodes a text with the given function, truncating it so that the encoded text,
+ * truncation mark included, does not exceed max_chars characters
+ */
+fn truncate(text: &str, max_chars: usize, encode: impl Fn(&str) -> String) -> String {
+    match prefix_within(text, max_chars, &encode) {
+        Some(prefix) => encode(prefix) + TRUNCATION_MARK,
+        None => encode(text),
+    }
+}
+
+/**
+ * This function truncates a text, left unencoded, so that it does not exceed max_chars characters
+ * once encoded, for the template variables which are escaped when rendered
+ */
+fn truncate_unescaped(text: &str, max_chars: usize, encode: impl Fn(&str) -> String) -> String {
+    match prefix_within(text, max_chars, &encode) {
+        Some(prefix) => prefix.to_string() + TRUNCATION_MARK,
+        None => text.to_string(),
+    }
+}
+
+/**
+ * This function returns the longest prefix of a text which, once encoded, leaves room for the
+ * truncation mark within max_chars characters, or None if the whole text fits
+ */
+fn prefix_within(text: &str, max_chars: usize, encode: impl Fn(&str) -> String) -> Option<&str> {
+    if encode(text).chars().count() <= max_chars {
+        return None;
+    }
+
+    let budget = max_chars.saturating_sub(TRUNCATION_MARK.chars().count());
+    let mut length = 0;
+
+    for (index, c) in text.char_indices() {
+        length += encode(c.encode_utf8(&mut [0; 4])).chars().count();
+        if length > budget {
+            return Some(&text[..index]);
+        }
+    }
+
+    Some(text)
+}
+
+/**
+ * This function returns the line appended to the default messages when some were suppressed by
+ * the cooldown of the check
+ */
+fn suppressed_note(suppressed: i32) -> Option<String> {
+    (suppressed > 0).then(|| {
+        format!("{suppressed} more notification(s) suppressed by the cooldown of the check")
+    })
+}
+
+/**
+ * This function returns the title and the details of the messages sent when a check starts or
+ * stops flapping
+ */
+fn flapping_summary(result: &CheckResult, started: bool) -> (String, &'static str) {
+    if started {
+        (
+            format!("🔁 {} is flapping", result.check_name),
+            "Its notifications are suppressed until it stabilizes",
+        )
+    } else {
+        (
+            format!("{} stopped flapping", result.check_name),
+            "Its notifications are sent again",
+        )
+    }
+}
+
+/**
+ * This function posts a JSON message to a webhook, keeping its URL, which is a secret, out of
+ * the errors
+ */
+async fn post_webhook(
+    http_client: &reqwest::Client,
+    webhook_url: &str,
+    message: &str,
+) -> Result<(), Error> {
+    http_client
+        .post(webhook_url)
+        .header("content-type", "application/json")
+        .body(message.to_string())
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| e.without_url())?;
+
+    Ok(())
+}
+
+/**
+ * This function returns the client to use for a Telegram channel: the shared one, unless the
+ * channel defines its own proxy
+ */
+fn channel_client(
+    http_client: &reqwest::Client,
+    channel: &ConcreteTelegramChannel,
+) -> Result<reqwest::Client, reqwest::Error> {
+    let Some(proxy) = &channel.proxy else {
+        return Ok(http_client.clone());
+    };
+
+    if let Some(client) = PROXY_CLIENTS.get(proxy) {
+        return Ok(client.clone());
+    }
+
+    let client = notification::http_client(Some(proxy))?;
+    PROXY_CLIENTS.insert(proxy.clone(), client.clone());
+
+    Ok(client)
+}
+
+impl Notifier for ConcreteTelegramChannel {
+    fn kind(&self) -> &'static str {
+        "telegram"
+    }
+
+    fn id(&self) -> &str {
+        &self.chat_id
+    }
+
+    fn message_template(&self) -> Option<&str> {
+        self.message_template.as_deref()
+    }
+
+    fn templates(&self) -> &'static Handlebars<'static> {
+        &TELEGRAM_TEMPLATES
+    }
+
+    fn max_template_output_chars(&self) -> Option<usize> {
+        Some(TELEGRAM_MAX_OUTPUT_CHARS)
+    }
+
+    fn format(&self, result: &CheckResult, text: Option<String>, suppressed: i32) -> String {
+        text.unwrap_or_else(|| {
+            // Ok results are only notified when the check recovers
+            let recovered = if result.status == CheckResultStatus::Ok {
+                "✅ <b>Recovered</b>\n"
+            } else {
+                ""
+            };
+
+            let note = suppressed_note(suppressed)
+                .map(|note| format!("\n<i>{}</i>", encode_safe(&note)))
+                .unwrap_or_default();
+
+            let output = truncate(&result.get_full_output(), TELEGRAM_MAX_OUTPUT_CHARS, |text| {
+                encode_safe(text).to_string()
+            });
+
+            format!("{recovered}<b>Date</b>: {0}\n<b>Check name</b>: {1} \n<b>Status</b>: {2:?}\n<b>Output</b>\n<pre>{3}</pre>{note}", local_timestamp(result), result.check_name, result.status, output)
+        })
+    }
+
+    fn format_flapping(&self, result: &CheckResult, started: bool) -> String {
+        let (title, details) = flapping_summary(result, started);
+
+        format!(
+            "<b>{0}</b>\n<b>Date</b>: {1}\n<b>Status</b>: {2:?}\n{details}",
+            encode_safe(&title),
+            local_timestamp(result),
+            result.status
+        )
+    }
+
+    fn accepts_images(&self) -> bool {
+        true
+    }
+
+    /**
+     * This function sends a message, with an optional image, to a Telegram channel
+     */
+    async fn send(
+        &self,
+        http_client: &reqwest::Client,
+        notification: &OutboxNotification,
+    ) -> Result<(), Error> {
+        let http_client = channel_client(http_client, self)?;
+
+        let request = if let Some(image) = &notification.image {
+            let url = format!("https://api.telegram.org/bot{}/sendPhoto", self.bot_token);
+
+            let form = reqwest::multipart::Form::new()
+                .text("chat_id", self.chat_id.clone())
+                .text("caption", notification.message.clone())
+                .text("parse_mode", "HTML")
+                .part(
+                    "photo",
+                    reqwest::multipart::Part::bytes(image.clone())
+                        .file_name(format!("{}.jpg", notification.check_name))
+                        .mime_str("image/jpeg")?,
+                );
+
+            http_client.post(&url).multipart(form)
+        } else {
+            let url = format!("https://api.telegram.org/bot{}/sendMessage", self.bot_token);
+
+            http_client.post(&url).form(&[
+                ("chat_id", self.chat_id.clone()),
+                ("text", notification.message.clone()),
+                ("parse_mode", "HTML".to_string()),
+            ])
+        };
+
+        request
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| e.without_url())?;
+
+        Ok(())
+    }
+}
+
+impl Notifier for ConcreteSlackChannel {
+    fn kind(&self) -> &'static str {
+        "slack"
+    }
+
+    fn id(&self) -> &str {
+        &self.name
+    }
+
+    fn message_template(&self) -> Option<&str> {
+        self.message_template.as_deref()
+    }
+
+    fn templates(&self) -> &'static Handlebars<'static> {
+        &SLACK_TEMPLATES
+    }
+
+    fn max_template_output_chars(&self) -> Option<usize> {
+        Some(SLACK_MAX_OUTPUT_CHARS)
+    }
+
+    /**
+     * This function builds the Block Kit message describing a result, sent to Slack as is
+     */
+    fn format(&self, result: &CheckResult, text: Option<String>, suppressed: i32) -> String {
+        if let Some(text) = text {
+            return json!({ "text": text }).to_string();
+        }
+
+        let summary = summary(result);
+
+        let mut message = json!({
+            "text": summary,
+            "blocks": [
+                {
+                    "type": "header",
+                    "text": { "type": "plain_text", "text": summary }
+                },
+                {
+                    "type": "section",
+                    "fields": [
+                        { "type": "mrkdwn", "text": format!("*Check name*\n{}", result.check_name) },
+                        { "type": "mrkdwn", "text": format!("*Status*\n{:?}", result.status) },
+                        { "type": "mrkdwn", "text": format!("*Date*\n{}", local_timestamp(result)) }
+                    ]
+                },
+                {
+                    "type": "section",
+                    "text": { "type": "mrkdwn", "text": format!("```{}```", truncate(&result.get_full_output(), SLACK_MAX_OUTPUT_CHARS, str::to_string)) }
+                }
+            ]
+        });
+
+        if let (Some(note), Some(blocks)) = (
+            suppressed_note(suppressed),
+            message.get_mut("blocks").and_then(Value::as_array_mut),
+        ) {
+            blocks.push(json!({
+                "type": "context",
+                "elements": [{ "type": "mrkdwn", "text": note }]
+            }));
+        }
+
+        message.to_string()
+    }
+
+    fn format_flapping(&self, result: &CheckResult, started: bool) -> String {
+        let (title, details) = flapping_summary(result, started);
+
+        json!({
+            "text": title,
+            "blocks": [
+                {
+                    "type": "header",
+                    "text": { "type": "plain_text", "text": title }
+                },
+                {
+                    "type": "section",
+                    "fields": [
+                        { "type": "mrkdwn", "text": format!("*Status*\n{:?}", result.status) },
+                        { "type": "mrkdwn", "text": format!("*Date*\n{}", local_timestamp(result)) }
+                    ]
+                },
+                {
+                    "type": "section",
+                    "text": { "type": "mrkdwn", "text": details }
+                }
+            ]
+        })
+        .to_string()
+    }
+
+    /**
+     * This function posts a Block Kit message to the incoming webhook of a Slack channel
+     */
+    async fn send(
+        &self,
+        http_client: &reqwest::Client,
+        notification: &OutboxNotification,
+    ) -> Result<(), Error> {
+        post_webhook(http_client, &self.webhook_url, &notification.message).await
+    }
+}
+
+/**
+ * This function builds the Discord message describing a result, as an embed colored by status
+ */
+fn discord_message(result: &CheckResult, title: &str, description: &str) -> String {
+    let color = match result.status {
+        CheckResultStatus::Ok => 0x2ECC71,
+        CheckResultStatus::Warning => 0xF1C40F,
+        CheckResultStatus::Critical => 0xE74C3C,
+        CheckResultStatus::CheckError => 0x9B59B6,
+        CheckResultStatus::Pending => 0x95A5A6,
+    };
+
+    json!({
+        "embeds": [
+            {
+                "title": title,
+                "description": description,
+                "color": color,
+                "fields": [
+                    { "name": "Check name", "value": result.check_name, "inline": true },
+                    { "name": "Status", "value": format!("{:?}", result.status), "inline": true }
+                ],
+                "timestamp": result.timestamp.unwrap_or_else(Utc::now).to_rfc3339()
+            }
+        ]
+    })
+    .to_string()
+}
+
+impl Notifier for ConcreteDiscordChannel {
+    fn kind(&self) -> &'static str {
+        "discord"
+    }
+
+    fn id(&self) -> &str {
+        &self.name
+    }
+
+    fn message_template(&self) -> Option<&str> {
+        self.message_template.as_deref()
+    }
+
+    fn templates(&self) -> &'static Handlebars<'static> {
+        &PLAIN_TEMPLATES
+    }
+
+    fn format(&self, result: &CheckResult, text: Option<String>, suppressed: i32) -> String {
+        let output = truncate(
+            &result.get_full_output(),
+            DISCORD_MAX_OUTPUT_CHARS,
+            str::to_string,
+        );
+        let description = match (text, suppressed_note(suppressed)) {
+            (Some(text), _) => truncate(&text, DISCORD_MAX_TEXT_CHARS, str::to_string),
+            (None, Some(note)) => format!("```{output}```\n*{note}*"),
+            (None, None) => format!("```{output}```"),
+        };
+
+        discord_message(result, &summary(result), &description)
+    }
+
+    fn format_flapping(&self, result: &CheckResult, started: bool) -> String {
+        let (title, details) = flapping_summary(result, started);
+
+        discord_message(result, &title, details)
+    }
+
+    /**
+     * This function posts a message to the webhook of a Discord channel
+     */
+    async fn send(
+        &self,
+        http_client: &reqwest::Client,
+        notification: &OutboxNotification,
+    ) -> Result<(), Error> {
+        post_webhook(http_client, &self.webhook_url, &notification.message).await
+    }
+}
+
+/**
+ * This function builds the payload of a push notification, with the priority of the status
+ */
+fn push_message(
+    channel: &ConcretePushChannel,
+    status: &CheckResultStatus,
+    title: &str,
+    message: &str,
+) -> String {
+    json!({
+        "title": title,
+        "message": message,
+        "priority": channel.priority(status),
+    })
+    .to_string()
+}
+
+impl Notifier for ConcretePushChannel {
+    fn kind(&self) -> &'static str {
+        "push"
+    }
+
+    fn id(&self) -> &str {
+        &self.name
+    }
+
+    fn message_template(&self) -> Option<&str> {
+        self.message_template.as_deref()
+    }
+
+    fn templates(&self) -> &'static Handlebars<'static> {
+        &PLAIN_TEMPLATES
+    }
+
+    fn format(&self, result: &CheckResult, text: Option<String>, suppressed: i32) -> String {
+        push_message(
+            self,
+            &result.status,
+            &summary(result),
+            &text.unwrap_or_else(|| match suppressed_note(suppressed) {
+                Some(note) => format!("{}\n{note}", result.get_full_output()),
+                None => result.get_full_output(),
+            }),
+        )
+    }
+
+    fn format_flapping(&self, result: &CheckResult, started: bool) -> String {
+        let (title, details) = flapping_summary(result, started);
+
+        push_message(self, &result.status, &title, details)
+    }
+
+    /**
+     * This function publishes a push notification to ntfy or Gotify
+     */
+    async fn send(
+        &self,
+        http_client: &reqwest::Client,
+        notification: &OutboxNotification,
+    ) -> Result<(), Error> {
+        let mut payload: Value = serde_json::from_str(&notification.message)?;
+        let server_url = self.server_url.trim_end_matches('/');
+
+        let request = match self.service {
+            PushService::Ntfy => {
+                let topic = self.topic.as_ref().ok_or(anyhow::anyhow!(
+                    "No topic defined for ntfy channel {}",
+                    self.name
+                ))?;
+                payload["topic"] = json!(topic);
+
+                let request = http_client.post(server_url);
+                match &self.token {
+                    Some(token) => request.bearer_auth(token),
+                    None => request,
+                }
+            }
+            PushService::Gotify => {
+                let request = http_client.post(format!("{server_url}/message"));
+                match &self.token {
+                    Some(token) => request.header("X-Gotify-Key", token),
+                    None => request,
+                }
+            }
+        };
+
+        request
+            .header("content-type", "application/json")
+            .body(payload.to_string())
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())?;
+
+        Ok(())
+    }
+}
+
+/// Any of the notification channels a check can be bound to
+#[derive(Debug, Clone)]
+pub enum NotificationChannel {
+    Telegram(ConcreteTelegramChannel),
+    Slack(ConcreteSlackChannel),
+    Push(ConcretePushChannel),
+    Discord(ConcreteDiscordChannel),
+}
+
+impl NotificationChannel {
+    /**
+     * This function returns the channels of a check, of any kind
+     */
+    pub fn of_check(check: &PinglowCheck) -> Vec<NotificationChannel> {
+        check
+            .telegram_channels
+            .iter()
+            .cloned()
+            .map(NotificationChannel::Telegram)
+            .chain(
+                check
+                    .slack_channels
+                    .iter()
+                    .cloned()
+                    .map(NotificationChannel::Slack),
+            )
+            .chain(
+                check
+                    .push_channels
+                    .iter()
+                    .cloned()
+                    .map(NotificationChannel::Push),
+            )
+            .chain(
+                check
+                    .discord_channels
+                    .iter()
+                    .cloned()
+                    .map(NotificationChannel::Discord),
+            )
+            .collect()
+    }
+
+    /**
+     * This function returns the channels a result is notified to, of any kind
+     */
+    pub fn of_result(result: &CheckResult) -> Vec<NotificationChannel> {
+        result
+            .telegram_channels
+            .iter()
+            .cloned()
+            .map(NotificationChannel::Telegram)
+            .chain(
+                result
+                    .slack_channels
+                    .iter()
+                    .cloned()
+                    .map(NotificationChannel::Slack),
+            )
+            .chain(
+                result
+                    .push_channels
+                    .iter()
+                    .cloned()
+                    .map(NotificationChannel::Push),
+            )
+            .chain(
+                result
+                    .discord_channels
+                    .iter()
+                    .cloned()
+                    .map(NotificationChannel::Discord),
+            )
+            .collect()
+    }
+}
+
+impl Notifier for NotificationChannel {
+    fn kind(&self) -> &'static str {
+        match self {
+            NotificationChannel::Telegram(channel) => channel.kind(),
+            NotificationChannel::Slack(channel) => channel.kind(),
+            NotificationChannel::Push(channel) => channel.kind(),
+            NotificationChannel::Discord(channel) => channel.kind(),
+        }
+    }
+
+    fn id(&self) -> &str {
+        match self {
+            NotificationChannel::Telegram(channel) => channel.id(),
+            NotificationChannel::Slack(channel) => channel.id(),
+            NotificationChannel::Push(channel) => channel.id(),
+            NotificationChannel::Discord(channel) => channel.id(),
+        }
+    }
+
+    fn message_template(&self) -> Option<&str> {
+        match self {
+            NotificationChannel::Telegram(channel) => Notifier::message_template(channel),
+            NotificationChannel::Slack(channel) => Notifier::message_template(channel),
+            NotificationChannel::Push(channel) => Notifier::message_template(channel),
+            NotificationChannel::Discord(channel) => Notifier::message_template(channel),
+        }
+    }
+
+    fn templates(&self) -> &'static Handlebars<'static> {
+        match self {
+            NotificationChannel::Telegram(channel) => channel.templates(),
+            NotificationChannel::Slack(channel) => channel.templates(),
+            NotificationChannel::Push(channel) => channel.templates(),
+            NotificationChannel::Discord(channel) => channel.templates(),
+        }
+    }
+
+    fn format(&self, result: &CheckResult, text: Option<String>, suppressed: i32) -> String {
+        match self {
+            NotificationChannel::Telegram(channel) => channel.format(result, text, suppressed),
+            NotificationChannel::Slack(channel) => channel.format(result, text, suppressed),
+            NotificationChannel::Push(channel) => channel.format(result, text, suppressed),
+            NotificationChannel::Discord(channel) => channel.format(result, text, suppressed),
+        }
+    }
+
+    fn format_flapping(&self, result: &CheckResult, started: bool) -> String {
+        match self {
+            NotificationChannel::Telegram(channel) => channel.format_flapping(result, started),
+            NotificationChannel::Slack(channel) => channel.format_flapping(result, started),
+            NotificationChannel::Push(channel) => channel.format_flapping(result, started),
+            NotificationChannel::Discord(channel) => channel.format_flapping(result, started),
+        }
+    }
+
+    fn accepts_images(&self) -> bool {
+        match self {
+            NotificationChannel::Telegram(channel) => channel.accepts_images(),
+            NotificationChannel::Slack(channel) => channel.accepts_images(),
+            NotificationChannel::Push(channel) => channel.accepts_images(),
+            NotificationChannel::Discord(channel) => channel.accepts_images(),
+        }
+    }
+
+    fn max_template_output_chars(&self) -> Option<usize> {
+        match self {
+            NotificationChannel::Telegram(channel) => channel.max_template_output_chars(),
+            NotificationChannel::Slack(channel) => channel.max_template_output_chars(),
+            NotificationChannel::Push(channel) => channel.max_template_output_chars(),
+            NotificationChannel::Discord(channel) => channel.max_template_output_chars(),
+        }
+    }
+
+    async fn send(
+        &self,
+        http_client: &reqwest::Client,
+        notification: &OutboxNotification,
+    ) -> Result<(), Error> {
+        match self {
+            NotificationChannel::Telegram(channel) => channel.send(http_client, notification).await,
+            NotificationChannel::Slack(channel) => channel.send(http_client, notification).await,
+            NotificationChannel::Push(channel) => channel.send(http_client, notification).await,
+            NotificationChannel::Discord(channel) => channel.send(http_client, notification).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncation() {
+        assert_eq!(truncate("short", 10, str::to_string), "short");
+
+        let truncated = truncate(&"x".repeat(100), 30, str::to_string);
+        assert_eq!(truncated.chars().count(), 30);
+        assert!(truncated.ends_with(TRUNCATION_MARK));
+
+        // The encoded characters count, and are never cut in the middle
+        let truncated = truncate(&"<".repeat(100), 30, |text| encode_safe(text).to_string());
+        assert!(truncated.chars().count() <= 30);
+        assert_eq!(
+            truncated.trim_end_matches(TRUNCATION_MARK),
+            "&lt;".repeat(4)
+        );
+
+        // The template variables are left unescaped, the escaping being done when rendering
+        let truncated =
+            truncate_unescaped(&"<".repeat(100), 30, |text| encode_safe(text).to_string());
+        assert_eq!(truncated, "<".repeat(4) + TRUNCATION_MARK);
+    }
+}
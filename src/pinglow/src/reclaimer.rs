@@ -0,0 +1,146 @@
+use std::time::Duration;
+
+use anyhow::Error;
+use log::{error, info, warn};
+use pinglow_common::{
+    redis::{checks_streams, parse_autoclaim_entries},
+    telemetry::TRACE_CONTEXT_FIELD,
+};
+use redis::{aio::MultiplexedConnection, Client as RedisClient, Value};
+
+use crate::config::PinglowConfig;
+
+const RECLAIM_INTERVAL: Duration = Duration::from_secs(60);
+
+// Consumer owning the entries while they are moved
+const RECLAIMER_CONSUMER: &str = "controller-reclaimer";
+
+pub const CHECKS_DLQ_STREAM: &str = "pinglow:checks:dlq";
+
+/**
+ * This function periodically re-delivers the checks picked up by a runner which never acked
+ * them, e.g. because it crashed, moving them to the dead-letter stream after too many deliveries
+ */
+pub async fn run(redis_client: RedisClient, config: PinglowConfig) {
+    let mut interval = tokio::time::interval(RECLAIM_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        match reclaim_checks(&redis_client, &config).await {
+            Ok(0) => {}
+            Ok(reclaimed) => info!("Reclaimed {reclaimed} stale check(s)"),
+            Err(e) => error!("Error reclaiming stale checks: {e}"),
+        }
+    }
+}
+
+async fn reclaim_checks(redis_client: &RedisClient, config: &PinglowConfig) -> Result<u64, Error> {
+    let mut conn = redis_client.get_multiplexed_async_connection().await?;
+
+    let mut reclaimed = 0;
+
+    for stream in checks_streams(&mut conn).await? {
+        // The group of a routed stream exists only once a matching runner started
+        match reclaim_stream(&mut conn, &stream, config).await {
+            Ok(stream_reclaimed) => reclaimed += stream_reclaimed,
+            Err(e) => warn!("Error reclaiming the stale checks of {stream}: {e}"),
+        }
+    }
+
+    Ok(reclaimed)
+}
+
+async fn reclaim_stream(
+    conn: &mut MultiplexedConnection,
+    stream: &str,
+    config: &PinglowConfig,
+) -> Result<u64, Error> {
+    let mut cursor = "0-0".to_string();
+    let mut reclaimed = 0;
+
+    loop {
+        let reply: Value = redis::cmd("XAUTOCLAIM")
+            .arg(stream)
+            .arg("workers")
+            .arg(RECLAIMER_CONSUMER)
+            .arg(config.check_reclaim_idle_seconds * 1000)
+            .arg(&cursor)
+            .arg("COUNT")
+            .arg(100)
+            .query_async(conn)
+            .await?;
+
+        let Some((next_cursor, entries)) = parse_autoclaim_entries(reply) else {
+            break;
+        };
+
+        for (id, fields) in entries {
+            redeliver(conn, stream, &id, &fields, config.check_max_deliveries).await?;
+            reclaimed += 1;
+        }
+
+        // The whole pending entries list has been scanned
+        if next_cursor == "0-0" {
+            break;
+        }
+        cursor = next_cursor;
+    }
+
+    Ok(reclaimed)
+}
+
+/**
+ * This function enqueues again a stale check, or moves it to the dead-letter stream once it
+ * has been delivered too many times, acking the original entry
+ */
+async fn redeliver(
+    conn: &mut MultiplexedConnection,
+    stream: &str,
+    id: &str,
+    fields: &std::collections::HashMap<String, String>,
+    max_deliveries: u32,
+) -> Result<(), Error> {
+    let deliveries: u32 = fields
+        .get("deliveries")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(1);
+    let payload = fields.get("payload").cloned().unwrap_or_default();
+
+    let mut pipe = redis::pipe();
+    pipe.atomic();
+
+    if deliveries >= max_deliveries {
+        warn!("Check {id} was not completed after {deliveries} deliveries, moving it to the dead-letter stream");
+        pipe.cmd("XADD")
+            .arg(CHECKS_DLQ_STREAM)
+            .arg("*")
+            .arg("payload")
+            .arg(payload)
+            .arg("original_id")
+            .arg(id)
+            .arg("stream")
+            .arg(stream)
+            .arg("deliveries")
+            .arg(deliveries);
+    } else {
+        pipe.cmd("XADD")
+            .arg(stream)
+            .arg("*")
+            .arg("payload")
+            .arg(payload)
+            .arg("deliveries")
+            .arg(deliveries + 1);
+
+        // The redelivery continues the trace of the original execution
+        if let Some(trace) = fields.get(TRACE_CONTEXT_FIELD) {
+            pipe.arg(TRACE_CONTEXT_FIELD).arg(trace);
+        }
+    }
+
+    pipe.cmd("XACK").arg(stream).arg("workers").arg(id);
+
+    pipe.query_async::<()>(conn).await?;
+
+    Ok(())
+}
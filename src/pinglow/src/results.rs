@@ -1,26 +1,96 @@
-use std::{sync::Arc, time::Duration};
+use std::{
+    collections::HashMap,
+    hash::{DefaultHasher, Hash, Hasher},
+    sync::Arc,
+    time::Duration,
+};
 
 use anyhow::Error;
+use chrono::Utc;
+use deadpool_postgres::Pool;
 use log::{debug, error};
-use pinglow_common::redis::parse_stream_payload;
-use pinglow_common::CheckResult;
+use opentelemetry::{
+    trace::{SpanKind, TraceContextExt},
+    Context,
+};
+use pinglow_common::dto::ProcessCheckResultPayload;
+use pinglow_common::perfdata::Range;
+use pinglow_common::redis::{parse_stream_entries, AGENT_RESULTS_STREAM};
+use pinglow_common::telemetry::{extract_context, start_span, TRACE_CONTEXT_FIELD};
+use pinglow_common::{CheckResult, CheckResultStatus};
 use redis::Client as RedisClient;
 use redis::{aio::MultiplexedConnection, AsyncConnectionConfig};
-use tokio::signal::unix::{signal, SignalKind};
-use tokio_postgres::Client;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
 
-use crate::process_check_result;
+use crate::{
+    auth::{self, Scope},
+    check::{SharedPinglowChecks, SharedRedirects},
+    config::PinglowConfig,
+    db, dispatch_check_result, metrics, prepare_check_result, process_check_result,
+    sinks::ResultSinks,
+    teardown,
+};
 
-pub async fn run(redis_client: RedisClient, postgres_client: Arc<Client>) -> Result<(), Error> {
-    let mut sigterm = signal(SignalKind::terminate())?;
-    let mut sigint = signal(SignalKind::interrupt())?;
+pub const RESULTS_DLQ_STREAM: &str = "pinglow:results:dlq";
 
-    let http_client = reqwest::Client::new();
+// Attempts to process a result before moving it to the dead-letter stream
+const MAX_PROCESSING_ATTEMPTS: u32 = 3;
 
+// Field naming the check of a result pushed by an external agent, whose payload is the one of the
+// passive results API rather than a full result sent by a runner
+const AGENT_CHECK_FIELD: &str = "check";
+
+// An agent result read from its stream: id, raw payload and fields
+type AgentEntry = (String, String, HashMap<String, String>);
+
+// A result read from the stream: id, raw payload, the decoded result and the context of the
+// span tracing its processing
+type ResultEntry = (String, String, CheckResult, Context);
+
+/**
+ * This function consumes the results stream until the shutdown token is cancelled, then waits
+ * for the results already read to be processed and acked before returning
+ */
+#[allow(clippy::too_many_arguments)]
+pub async fn run(
+    redis_client: RedisClient,
+    db_pool: Pool,
+    http_client: reqwest::Client,
+    redirects: SharedRedirects,
+    shared_checks: SharedPinglowChecks,
+    sinks: Arc<ResultSinks>,
+    config: PinglowConfig,
+    shutdown: CancellationToken,
+) -> Result<(), Error> {
     let mut async_connection = AsyncConnectionConfig::new();
     async_connection = async_connection.set_connection_timeout(Some(Duration::from_secs(30)));
     async_connection = async_connection.set_response_timeout(Some(Duration::from_secs(30)));
 
+    // Spawn the workers: results are partitioned by check name so that the results
+    // of the same check are always processed in order by the same worker
+    let (workers, worker_handles): (Vec<mpsc::Sender<ResultEntry>>, Vec<_>) =
+        (0..config.results_concurrency.max(1))
+            .map(|_| {
+                let (tx, rx) = mpsc::channel(config.results_batch_size.max(1));
+                let handle = tokio::spawn(result_worker(
+                    rx,
+                    config.results_batch_size.max(1),
+                    redis_client.clone(),
+                    async_connection.clone(),
+                    db_pool.clone(),
+                    http_client.clone(),
+                    redirects.clone(),
+                    shared_checks.clone(),
+                    sinks.clone(),
+                ));
+                (tx, handle)
+            })
+            .unzip();
+
+    // Start from the results read but not acked before the last shutdown, then move to new ones
+    let mut cursor = "0".to_string();
+
     loop {
         let mut redis_conn = redis_client
             .get_multiplexed_async_connection_with_config(&async_connection)
@@ -28,26 +98,59 @@ pub async fn run(redis_client: RedisClient, postgres_client: Arc<Client>) -> Res
             .expect("Cannot get connection to redis");
 
         tokio::select! {
-        _ = sigint.recv() => {
-        }
-        _ = sigterm.recv() => {
+        _ = shutdown.cancelled() => {
+            break;
         }
 
-        res = wait_for_result(&mut redis_conn) => {
+        res = wait_for_results(&mut redis_conn, config.results_batch_size, &cursor) => {
             match res {
-                Ok(Some((id, result))) => {
-                    // Process the result
-                    process_check_result(result, None, &postgres_client, &http_client).await?;
-
-                    // Ack in redis
-                    redis::cmd("XACK")
-                        .arg("pinglow:results")
-                        .arg("controller")
-                        .arg(id)
-                        .query_async::<()>(&mut redis_conn)
-                        .await?;
+                Ok(results) if !results.is_empty() => {
+                    if cursor != ">" {
+                        if let Some((id, _, _, _)) = results.last() {
+                            cursor = id.clone();
+                        }
+                    }
+
+                    // Dispatch each result to the worker owning its check
+                    for (id, payload, result, cx) in results {
+                        let result = match result {
+                            Ok(result) => result,
+                            Err(reason) => {
+                                // Undecodable or unauthorized results would otherwise block the consumer
+                                if let Err(e) = dead_letter(&mut redis_conn, "pinglow:results", &id, &payload, &reason).await {
+                                    error!("Error moving result {id} to the dead-letter stream: {e}");
+                                }
+                                continue;
+                            }
+                        };
+
+                        // The late results of a deleted check are dropped rather than stored again
+                        if !shared_checks.contains_key(&result.check_name)
+                            && teardown::is_deleted(&mut redis_conn, &result.check_name).await.unwrap_or(false)
+                        {
+                            debug!("Dropping result {id} of the deleted check {}", result.check_name);
+                            if let Err(e) = redis::cmd("XACK")
+                                .arg("pinglow:results")
+                                .arg("controller")
+                                .arg(&id)
+                                .query_async::<()>(&mut redis_conn)
+                                .await
+                            {
+                                error!("Error sending ack to redis for result {id}: {e}");
+                            }
+                            continue;
+                        }
+
+                        let result = with_thresholds(with_channels(result, &shared_checks), &shared_checks);
+                        let worker = &workers[partition(&result.check_name, workers.len())];
+                        worker.send((id, payload, result, cx)).await?;
+                    }
                 },
-                Ok(None) => {
+                Ok(_) if cursor != ">" => {
+                    // All the pending results have been recovered
+                    cursor = ">".to_string();
+                },
+                Ok(_) => {
                     // No task, sleep a bit to avoid busy loop
                      tokio::time::sleep(std::time::Duration::from_millis(100)).await;
                 },
@@ -66,11 +169,384 @@ pub async fn run(redis_client: RedisClient, postgres_client: Arc<Client>) -> Res
         }
         }
     }
+
+    // Stop dispatching and let the workers drain the results already read
+    drop(workers);
+    for handle in worker_handles {
+        handle.await?;
+    }
+
+    debug!("All the in-flight results have been processed");
+
+    Ok(())
 }
 
-async fn wait_for_result(
+/**
+ * This function processes sequentially the results dispatched to a single partition,
+ * acking each of them in redis once processed. When the results pile up, the ones already
+ * dispatched are written to the DB together
+ */
+#[allow(clippy::too_many_arguments)]
+async fn result_worker(
+    mut rx: mpsc::Receiver<ResultEntry>,
+    batch_size: usize,
+    redis_client: RedisClient,
+    async_connection: AsyncConnectionConfig,
+    db_pool: Pool,
+    http_client: reqwest::Client,
+    redirects: SharedRedirects,
+    shared_checks: SharedPinglowChecks,
+    sinks: Arc<ResultSinks>,
+) {
+    let mut redis_conn = redis_client
+        .get_multiplexed_async_connection_with_config(&async_connection)
+        .await
+        .expect("Cannot get connection to redis");
+
+    // A result of a check already in the batch, which goes in the following one
+    let mut next: Option<ResultEntry> = None;
+
+    while let Some(entry) = match next.take() {
+        Some(entry) => Some(entry),
+        None => rx.recv().await,
+    } {
+        let mut batch = vec![entry];
+
+        // A batch contains a single result per check, since each result of a check depends on
+        // the previous ones when deciding about the notifications
+        while batch.len() < batch_size {
+            match rx.try_recv() {
+                Ok(entry)
+                    if batch
+                        .iter()
+                        .any(|(_, _, r, _)| r.check_name == entry.2.check_name) =>
+                {
+                    next = Some(entry);
+                    break;
+                }
+                Ok(entry) => batch.push(entry),
+                Err(_) => break,
+            }
+        }
+
+        let ids: Vec<String> = batch.iter().map(|(id, _, _, _)| id.clone()).collect();
+        let spans: Vec<Context> = batch.iter().map(|(_, _, _, cx)| cx.clone()).collect();
+
+        if batch.len() == 1
+            || !process_batch(
+                &mut batch,
+                &mut redis_conn,
+                &db_pool,
+                &http_client,
+                &redirects,
+                &sinks,
+            )
+            .await
+        {
+            // Results are processed one by one when the batch cannot be written
+            for (id, payload, result, _) in batch {
+                process_entry(
+                    &id,
+                    &payload,
+                    result,
+                    &mut redis_conn,
+                    &db_pool,
+                    &http_client,
+                    &redirects,
+                    &shared_checks,
+                    &sinks,
+                )
+                .await;
+            }
+        }
+
+        // Ack in redis
+        if let Err(e) = redis::cmd("XACK")
+            .arg("pinglow:results")
+            .arg("controller")
+            .arg(&ids)
+            .query_async::<()>(&mut redis_conn)
+            .await
+        {
+            error!("Error sending ack to redis for results {ids:?}: {e}");
+        }
+
+        for cx in spans {
+            cx.span().end();
+        }
+    }
+}
+
+/**
+ * This function writes a batch of results with a single statement, then dispatches each of
+ * them. It returns false, leaving the batch untouched, if nothing could be written
+ */
+async fn process_batch(
+    batch: &mut Vec<ResultEntry>,
+    redis_conn: &mut MultiplexedConnection,
+    db_pool: &Pool,
+    http_client: &reqwest::Client,
+    redirects: &SharedRedirects,
+    sinks: &ResultSinks,
+) -> bool {
+    let postgres_client = match db_pool.get().await {
+        Ok(postgres_client) => postgres_client,
+        Err(e) => {
+            error!("Error getting a database connection: {e}");
+            return false;
+        }
+    };
+
+    let mut previous_statuses = Vec::with_capacity(batch.len());
+
+    for (id, _, result, _) in batch.iter_mut() {
+        match prepare_check_result(result, &postgres_client).await {
+            Ok(previous_status) => previous_statuses.push(previous_status),
+            Err(e) => {
+                error!("Error preparing check result {id}: {e}");
+                return false;
+            }
+        }
+    }
+
+    let results: Vec<&CheckResult> = batch.iter().map(|(_, _, result, _)| result).collect();
+
+    let timer = metrics::DB_WRITE_DURATION.start_timer();
+    if let Err(e) = CheckResult::write_batch_to_db(&results, &postgres_client).await {
+        error!(
+            "Error writing a batch of {} check results: {e}",
+            results.len()
+        );
+        return false;
+    }
+    timer.observe_duration();
+
+    for ((id, payload, result, _), previous_status) in batch.drain(..).zip(previous_statuses) {
+        // The result is already written, processing it again would duplicate it
+        if let Err(e) = dispatch_check_result(
+            result,
+            previous_status,
+            None,
+            &postgres_client,
+            http_client,
+            redirects,
+            sinks,
+        )
+        .await
+        {
+            error!("Error dispatching check result {id}, moving it to the dead-letter stream: {e}");
+            if let Err(e) =
+                dead_letter(redis_conn, "pinglow:results", &id, &payload, &e.to_string()).await
+            {
+                error!("Error moving result {id} to the dead-letter stream: {e}");
+            }
+        }
+    }
+
+    true
+}
+
+/**
+ * This function sets the notification channels of a result from the check they belong to, since
+ * the channels are not sent through the stream along with their credentials
+ */
+fn with_channels(mut result: CheckResult, shared_checks: &SharedPinglowChecks) -> CheckResult {
+    if let Some(check) = shared_checks.get(&result.check_name) {
+        result.telegram_channels = check.telegram_channels.clone().into();
+        result.slack_channels = check.slack_channels.clone().into();
+        result.push_channels = check.push_channels.clone().into();
+        result.discord_channels = check.discord_channels.clone().into();
+    }
+
+    result
+}
+
+/**
+ * This function escalates an Ok or Warning result to Warning or Critical when its performance data
+ * crosses the thresholds of the check, noting the metrics responsible in the output
+ */
+fn with_thresholds(mut result: CheckResult, shared_checks: &SharedPinglowChecks) -> CheckResult {
+    if !matches!(
+        result.status,
+        CheckResultStatus::Ok | CheckResultStatus::Warning
+    ) {
+        return result;
+    }
+
+    let Some(check) = shared_checks.get(&result.check_name) else {
+        return result;
+    };
+
+    if check.thresholds.is_empty() {
+        return result;
+    }
+
+    let mut status = CheckResultStatus::Ok;
+    let mut notes = vec![];
+
+    for perf_data in result.get_perf_data() {
+        let Some(threshold) = check.thresholds.get(&perf_data.label) else {
+            continue;
+        };
+
+        let crossed = [
+            (CheckResultStatus::Critical, &threshold.crit),
+            (CheckResultStatus::Warning, &threshold.warn),
+        ]
+        .into_iter()
+        .find_map(|(level, range)| {
+            let range = range.as_deref()?;
+            Range::parse(range)?
+                .alerts(perf_data.value as f64)
+                .then_some((level, range))
+        });
+
+        if let Some((level, range)) = crossed {
+            notes.push(format!(
+                "{}={} beyond the {} threshold {range}",
+                perf_data.label,
+                perf_data.value,
+                format!("{level:?}").to_lowercase()
+            ));
+            if level.severity() > status.severity() {
+                status = level;
+            }
+        }
+    }
+
+    if status.severity() > result.status.severity() {
+        result.annotate_status_line(&notes.join(", "));
+        result.status = status;
+    }
+
+    result
+}
+
+/**
+ * This function processes a single result, retrying a few times before moving it to the
+ * dead-letter stream
+ */
+#[allow(clippy::too_many_arguments)]
+async fn process_entry(
+    id: &str,
+    payload: &str,
+    result: CheckResult,
+    redis_conn: &mut MultiplexedConnection,
+    db_pool: &Pool,
+    http_client: &reqwest::Client,
+    redirects: &SharedRedirects,
+    shared_checks: &SharedPinglowChecks,
+    sinks: &ResultSinks,
+) {
+    // The result is decoded again at every attempt, from its serialized form since the payload
+    // pushed by an external agent is not a full result
+    let serialized = serde_json::to_string(&result).unwrap_or_default();
+    let mut result = Some(result);
+    let mut attempt = 1;
+
+    // Process the result, retrying a few times before giving up on it
+    while let Some(current) = result.take().or_else(|| {
+        serde_json::from_str(&serialized)
+            .ok()
+            .map(|result| with_channels(result, shared_checks))
+    }) {
+        match process_check_result(current, None, db_pool, http_client, redirects, sinks).await {
+            Ok(()) => break,
+            // The result is fine, it is kept until the DB is back without consuming attempts
+            Err(e) if db::is_unavailable(&e) => {
+                error!("Error processing check result {id}, waiting for the database: {e}");
+                db::wait_until_available(db_pool).await;
+            }
+            Err(e) if attempt < MAX_PROCESSING_ATTEMPTS => {
+                error!("Error processing check result {id} (attempt {attempt}): {e}");
+                tokio::time::sleep(Duration::from_secs(attempt as u64)).await;
+                attempt += 1;
+            }
+            Err(e) => {
+                error!(
+                    "Error processing check result {id}, moving it to the dead-letter stream: {e}"
+                );
+                if let Err(e) =
+                    dead_letter(redis_conn, "pinglow:results", id, payload, &e.to_string()).await
+                {
+                    error!("Error moving result {id} to the dead-letter stream: {e}");
+                }
+                break;
+            }
+        }
+    }
+}
+
+/**
+ * This function queues a result in the results stream, to be processed by the consumer
+ */
+pub async fn enqueue(redis_client: &RedisClient, payload: &str) -> Result<(), Error> {
+    let mut redis_conn = redis_client.get_multiplexed_async_connection().await?;
+
+    redis::cmd("XADD")
+        .arg("pinglow:results")
+        .arg("*")
+        .arg("payload")
+        .arg(payload)
+        .query_async::<()>(&mut redis_conn)
+        .await?;
+
+    Ok(())
+}
+
+/**
+ * This function moves a result which cannot be processed to the dead-letter stream, along with
+ * the reason, acking it on its stream so that the healthy results keep flowing
+ */
+async fn dead_letter(
+    conn: &mut MultiplexedConnection,
+    stream: &str,
+    id: &str,
+    payload: &str,
+    reason: &str,
+) -> Result<(), Error> {
+    redis::pipe()
+        .atomic()
+        .cmd("XADD")
+        .arg(RESULTS_DLQ_STREAM)
+        .arg("*")
+        .arg("payload")
+        .arg(payload)
+        .arg("original_id")
+        .arg(id)
+        .arg("original_stream")
+        .arg(stream)
+        .arg("reason")
+        .arg(reason)
+        .arg("failed_at")
+        .arg(chrono::Utc::now().to_rfc3339())
+        .cmd("XACK")
+        .arg(stream)
+        .arg("controller")
+        .arg(id)
+        .query_async::<()>(conn)
+        .await?;
+
+    Ok(())
+}
+
+fn partition(check_name: &str, partitions: usize) -> usize {
+    let mut hasher = DefaultHasher::new();
+    check_name.hash(&mut hasher);
+    (hasher.finish() % partitions as u64) as usize
+}
+
+/**
+ * This function reads a batch of results: new ones with the cursor ">", otherwise the ones
+ * already delivered to this consumer after the cursor id. The processing of each result is
+ * traced from here, continuing the trace of its execution. A result which cannot be accepted
+ * comes with the reason, to move it to the dead-letter stream
+ */
+async fn wait_for_results(
     conn: &mut MultiplexedConnection,
-) -> Result<Option<(String, CheckResult)>, Error> {
+    batch_size: usize,
+    cursor: &str,
+) -> Result<Vec<(String, String, Result<CheckResult, String>, Context)>, Error> {
     let value: Option<redis::Value> = redis::cmd("XREADGROUP")
         .arg("GROUP")
         .arg("controller")
@@ -78,28 +554,301 @@ async fn wait_for_result(
         .arg("BLOCK")
         .arg(15000)
         .arg("COUNT")
-        .arg(1) // fetch one message at a time
+        .arg(batch_size.max(1)) // fetch up to a batch of messages at a time
         .arg("STREAMS")
         .arg("pinglow:results")
-        .arg(">")
+        .arg(cursor)
         .query_async(conn)
         .await?;
 
-    let Some(value) = value else { return Ok(None) };
+    let Some(value) = value else {
+        return Ok(vec![]);
+    };
 
-    let (id, fields) = parse_stream_payload(value).ok_or(
+    let entries = parse_stream_entries(value).ok_or(
         pinglow_common::error::SerializeError::DeserializationError(
-            "Cannot extract id and fields from redis message".into(),
+            "Cannot extract entries from redis message".into(),
         ),
     )?;
 
-    let payload = fields.get("payload").ok_or(
+    let mut results = Vec::with_capacity(entries.len());
+
+    for (id, fields) in entries {
+        let payload = fields.get("payload").cloned().unwrap_or_default();
+
+        // The results of the agents are only accepted, once authenticated, from their own stream
+        let result = match fields.contains_key(AGENT_CHECK_FIELD) {
+            true => Err(format!(
+                "Agent results must be pushed on the {AGENT_RESULTS_STREAM} stream"
+            )),
+            false => serde_json::from_str::<CheckResult>(&payload)
+                .map_err(|e| format!("Cannot deserialize the result: {e}")),
+        };
+
+        match result {
+            Ok(result) => {
+                let cx = start_span(
+                    "process result",
+                    SpanKind::Consumer,
+                    &extract_context(&fields),
+                    &result.check_name,
+                );
+                results.push((id, payload, Ok(result), cx));
+            }
+            Err(reason) => {
+                error!("Cannot accept result {id}: {reason}");
+                results.push((id, payload, Err(reason), Context::new()));
+            }
+        }
+    }
+
+    Ok(results)
+}
+
+/**
+ * This function relays the results pushed by the external agents onto the results stream, once
+ * authenticated, until the shutdown token is cancelled. The results which cannot be accepted are
+ * moved to the dead-letter stream
+ */
+pub async fn relay_agent_results(
+    redis_client: RedisClient,
+    shared_checks: SharedPinglowChecks,
+    config: PinglowConfig,
+    shutdown: CancellationToken,
+) {
+    let mut async_connection = AsyncConnectionConfig::new();
+    async_connection = async_connection.set_connection_timeout(Some(Duration::from_secs(30)));
+    async_connection = async_connection.set_response_timeout(Some(Duration::from_secs(30)));
+
+    // Start from the results read but not relayed before the last shutdown, then move to new ones
+    let mut cursor = "0".to_string();
+
+    loop {
+        let mut redis_conn = match redis_client
+            .get_multiplexed_async_connection_with_config(&async_connection)
+            .await
+        {
+            Ok(redis_conn) => redis_conn,
+            Err(e) => {
+                error!("Error connecting to redis to relay the agent results: {e}");
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+        };
+
+        let entries = tokio::select! {
+            _ = shutdown.cancelled() => break,
+            entries = read_agent_results(&mut redis_conn, config.results_batch_size, &cursor) => entries,
+        };
+
+        match entries {
+            Ok(entries) if !entries.is_empty() => {
+                if cursor != ">" {
+                    if let Some((id, _, _)) = entries.last() {
+                        cursor = id.clone();
+                    }
+                }
+
+                for (id, payload, fields) in entries {
+                    let result =
+                        agent_result(&mut redis_conn, &payload, &fields, &shared_checks, &config)
+                            .await;
+                    let relayed = match result {
+                        Ok(result) => relay(&mut redis_conn, &id, &result, &fields).await,
+                        Err(reason) => {
+                            error!("Cannot accept agent result {id}: {reason}");
+                            dead_letter(
+                                &mut redis_conn,
+                                AGENT_RESULTS_STREAM,
+                                &id,
+                                &payload,
+                                &reason,
+                            )
+                            .await
+                        }
+                    };
+
+                    if let Err(e) = relayed {
+                        error!("Error relaying agent result {id}: {e}");
+                    }
+                }
+            }
+            Ok(_) if cursor != ">" => {
+                // All the pending results have been recovered
+                cursor = ">".to_string();
+            }
+            Ok(_) => {}
+            Err(e) => {
+                if !e.to_string().contains("timed out") {
+                    error!("Error waiting for agent results: {e}");
+                }
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        }
+    }
+}
+
+/**
+ * This function reads a batch of agent results, new ones with the cursor ">", otherwise the ones
+ * already delivered after the cursor id
+ */
+async fn read_agent_results(
+    conn: &mut MultiplexedConnection,
+    batch_size: usize,
+    cursor: &str,
+) -> Result<Vec<AgentEntry>, Error> {
+    let value: Option<redis::Value> = redis::cmd("XREADGROUP")
+        .arg("GROUP")
+        .arg("controller")
+        .arg("controller-1")
+        .arg("BLOCK")
+        .arg(15000)
+        .arg("COUNT")
+        .arg(batch_size.max(1))
+        .arg("STREAMS")
+        .arg(AGENT_RESULTS_STREAM)
+        .arg(cursor)
+        .query_async(conn)
+        .await?;
+
+    let Some(value) = value else {
+        return Ok(vec![]);
+    };
+
+    let entries = parse_stream_entries(value).ok_or(
         pinglow_common::error::SerializeError::DeserializationError(
-            "The expected payload field was not found".into(),
+            "Cannot extract entries from redis message".into(),
         ),
     )?;
 
-    let result: CheckResult = serde_json::from_str(payload)?;
+    Ok(entries
+        .into_iter()
+        .map(|(id, fields)| {
+            let payload = fields.get("payload").cloned().unwrap_or_default();
+            (id, payload, fields)
+        })
+        .collect())
+}
+
+/**
+ * This function moves an authenticated agent result to the results stream, keeping its trace
+ * context, and acks it on the agents stream
+ */
+async fn relay(
+    conn: &mut MultiplexedConnection,
+    id: &str,
+    result: &CheckResult,
+    fields: &HashMap<String, String>,
+) -> Result<(), Error> {
+    let payload = serde_json::to_string(result)?;
+
+    let mut pipe = redis::pipe();
+    pipe.atomic()
+        .cmd("XADD")
+        .arg("pinglow:results")
+        .arg("*")
+        .arg("payload")
+        .arg(payload);
+    if let Some(trace) = fields.get(TRACE_CONTEXT_FIELD) {
+        pipe.arg(TRACE_CONTEXT_FIELD).arg(trace);
+    }
+    pipe.cmd("XACK")
+        .arg(AGENT_RESULTS_STREAM)
+        .arg("controller")
+        .arg(id)
+        .query_async::<()>(conn)
+        .await?;
+
+    Ok(())
+}
+
+/**
+ * This function builds the result of a check pushed onto the agents stream, which is
+ * authenticated like the signed passive results: the timestamp, nonce and signature fields are
+ * computed either with the HMAC key of the check or, when the key_id field names a scoped API key
+ * granting the submit-result scope, with that key. The keys themselves never travel through the
+ * stream, and a nonce is accepted once
+ */
+async fn agent_result(
+    conn: &mut MultiplexedConnection,
+    payload: &str,
+    fields: &HashMap<String, String>,
+    shared_checks: &SharedPinglowChecks,
+    config: &PinglowConfig,
+) -> Result<CheckResult, String> {
+    let check_name = fields
+        .get(AGENT_CHECK_FIELD)
+        .ok_or(format!("Missing {AGENT_CHECK_FIELD} field"))?;
+
+    let check = shared_checks
+        .get(check_name)
+        .map(|check| check.value().clone())
+        .ok_or(format!("Unknown check {check_name}"))?;
+
+    let (Some(timestamp), Some(nonce), Some(signature)) = (
+        fields.get("timestamp").and_then(|t| t.parse().ok()),
+        fields.get("nonce"),
+        fields.get("signature"),
+    ) else {
+        return Err(format!("Missing signature for check {check_name}"));
+    };
+
+    let authorized = match fields.get("key_id") {
+        Some(key_id) => config
+            .api_keys
+            .iter()
+            .find(|scoped| scoped.name == *key_id)
+            .is_some_and(|scoped| {
+                scoped.grants(Scope::SubmitResult)
+                    && auth::verify_result_signature(
+                        &scoped.key,
+                        timestamp,
+                        nonce,
+                        signature,
+                        payload,
+                    )
+            }),
+        None => check.hmac_key.as_ref().is_some_and(|key| {
+            auth::verify_result_signature(key, timestamp, nonce, signature, payload)
+        }),
+    };
+
+    if !authorized {
+        return Err(format!("Invalid signature for check {check_name}"));
+    }
+
+    match auth::claim_nonce(conn, check_name, nonce).await {
+        Ok(true) => {}
+        Ok(false) => return Err(format!("Nonce {nonce} already used for check {check_name}")),
+        Err(e) => return Err(format!("Cannot record the nonce: {e}")),
+    }
+
+    let payload: ProcessCheckResultPayload =
+        serde_json::from_str(payload).map_err(|e| format!("Invalid payload: {e}"))?;
 
-    Ok(Some((id, result)))
+    Ok(CheckResult {
+        check_name: check_name.to_owned(),
+        output: payload.output,
+        status: payload.status.into(),
+        timestamp: Some(Utc::now()),
+        telegram_channels: check.telegram_channels.clone().into(),
+        mute_notifications: check.mute_notifications,
+        mute_notifications_until: check.mute_notifications_until,
+        maintenance_windows: check.maintenance_windows.clone().into(),
+        webhooks: check.webhooks.clone().into(),
+        diagnostics: None,
+        slack_channels: check.slack_channels.clone().into(),
+        push_channels: check.push_channels.clone().into(),
+        discord_channels: check.discord_channels.clone().into(),
+        notify_on_state_change: check.notify_on_state_change,
+        notify_on_recovery: check.notify_on_recovery,
+        renotify_interval_minutes: check.renotify_interval_minutes,
+        min_notification_interval_seconds: check.min_notification_interval_seconds,
+        soft_failure: false,
+        depends_on: check.depends_on.clone().into(),
+        suppressed_by: None,
+        anomaly_sigma: check.anomaly_sigma,
+        script_hash: None,
+        run_id: None,
+    })
 }
@@ -0,0 +1,209 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use anyhow::Error;
+use chrono::{DateTime, TimeDelta, Utc};
+use deadpool_postgres::Pool;
+use kube::{Client, ResourceExt};
+use log::{error, info, warn};
+use tokio::sync::watch;
+
+use crate::{
+    check::{check_identity, Check, SharedPinglowChecks},
+    config::PinglowConfig,
+    scoped_api,
+};
+
+const PRUNE_INTERVAL: Duration = Duration::from_secs(3600);
+
+/**
+ * This function periodically drops the check results older than the retention of their check,
+ * once the checks are all loaded: the results of a check not loaded yet would be pruned with the
+ * default retention
+ */
+pub async fn run(
+    db_pool: Pool,
+    kube_client: Option<Client>,
+    shared_checks: SharedPinglowChecks,
+    mut synced: watch::Receiver<bool>,
+    config: PinglowConfig,
+) {
+    if synced.wait_for(|synced| *synced).await.is_err() {
+        warn!("The checks were never loaded, the expired check results are not pruned");
+        return;
+    }
+
+    let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        match prune_results(&db_pool, kube_client.as_ref(), &shared_checks, &config).await {
+            Ok(deleted) => info!("Pruned {deleted} expired check result(s)"),
+            Err(e) => error!("Error pruning expired check results: {e}"),
+        }
+    }
+}
+
+/**
+ * This function returns the instant before which the results expire with a retention, if the
+ * retention can be represented
+ */
+fn retention_threshold(now: DateTime<Utc>, retention_days: u64) -> Option<DateTime<Utc>> {
+    let retention = TimeDelta::try_days(i64::try_from(retention_days).ok()?)?;
+
+    now.checked_sub_signed(retention)
+}
+
+/**
+ * This function returns the retention overrides declared by the Check resources, including the
+ * ones of the checks which could not be loaded
+ */
+async fn declared_retentions(
+    client: &Client,
+    config: &PinglowConfig,
+) -> Result<BTreeMap<String, u64>, Error> {
+    let mut retentions = BTreeMap::new();
+
+    for scope in config.watch_scopes() {
+        let checks = scoped_api::<Check>(client, scope.as_deref())
+            .list(&Default::default())
+            .await?;
+
+        for check in checks {
+            if let Some(retention_days) = check.spec.resultRetentionDays {
+                let namespace = check
+                    .namespace()
+                    .unwrap_or_else(|| config.target_namespace.clone());
+                retentions.insert(
+                    check_identity(&namespace, &check.name_any(), &config.target_namespace),
+                    retention_days,
+                );
+            }
+        }
+    }
+
+    Ok(retentions)
+}
+
+async fn prune_results(
+    db_pool: &Pool,
+    kube_client: Option<&Client>,
+    shared_checks: &SharedPinglowChecks,
+    config: &PinglowConfig,
+) -> Result<u64, Error> {
+    let postgres_client = db_pool.get().await?;
+    let now = Utc::now();
+
+    // Checks overriding the default retention, the resources being authoritative for the checks
+    // which are not loaded: their results must not be dropped with a shorter retention
+    let mut overrides: BTreeMap<String, u64> = shared_checks
+        .iter()
+        .filter_map(|check| Some((check.check_name.clone(), check.result_retention_days?)))
+        .collect();
+
+    if let Some(client) = kube_client {
+        for (check_name, retention_days) in declared_retentions(client, config).await? {
+            let retention = overrides.entry(check_name).or_insert(retention_days);
+            *retention = (*retention).max(retention_days);
+        }
+    }
+
+    let overridden_checks: Vec<&String> = overrides.keys().collect();
+
+    let default_threshold =
+        retention_threshold(now, config.result_retention_days).ok_or_else(|| {
+            anyhow::anyhow!("Invalid retention of {} days", config.result_retention_days)
+        })?;
+
+    // Chunks older than every retention can be dropped as a whole, which is much cheaper than
+    // deleting their rows and also works on compressed chunks
+    let longest_retention_days = overrides
+        .values()
+        .copied()
+        .chain([config.result_retention_days])
+        .max()
+        .unwrap_or(config.result_retention_days);
+    // A retention too long to be represented keeps all the chunks
+    let chunks_threshold = retention_threshold(now, longest_retention_days);
+    if chunks_threshold.is_none() {
+        warn!("Not dropping any chunk, the longest retention of {longest_retention_days} days is invalid");
+    }
+
+    let mut deleted = 0;
+
+    for table in [
+        "check_result",
+        "check_result_perf_data",
+        "check_diagnostics",
+    ] {
+        if let Some(chunks_threshold) = chunks_threshold {
+            let dropped_chunks = postgres_client
+                .query(
+                    "SELECT drop_chunks($1::text::regclass, older_than => $2)",
+                    &[&table, &chunks_threshold],
+                )
+                .await?;
+
+            if !dropped_chunks.is_empty() {
+                info!(
+                    "Dropped {} expired chunk(s) of {table}",
+                    dropped_chunks.len()
+                );
+            }
+        }
+
+        // The remaining rows are pruned depending on the retention of their check
+        deleted += postgres_client
+            .execute(
+                &format!("DELETE FROM {table} WHERE timestamp < $1 AND NOT (check_name = ANY($2))"),
+                &[&default_threshold, &overridden_checks],
+            )
+            .await?;
+
+        for (check_name, retention_days) in overrides.iter() {
+            let Some(threshold) = retention_threshold(now, *retention_days) else {
+                warn!("Not pruning the results of {check_name}, its retention of {retention_days} days is invalid");
+                continue;
+            };
+
+            deleted += postgres_client
+                .execute(
+                    &format!("DELETE FROM {table} WHERE check_name = $1 AND timestamp < $2"),
+                    &[check_name, &threshold],
+                )
+                .await?;
+        }
+    }
+
+    // Delivered or abandoned notifications are kept as long as the default retention
+    postgres_client
+        .execute(
+            "DELETE FROM notification_outbox WHERE status <> 'pending' AND updated_at < $1",
+            &[&default_threshold],
+        )
+        .await?;
+
+    // and so are their delivery attempts
+    postgres_client
+        .execute(
+            "DELETE FROM notification_attempts WHERE timestamp < $1",
+            &[&default_threshold],
+        )
+        .await?;
+
+    Ok(deleted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn thresholds() {
+        let now = Utc::now();
+
+        assert_eq!(retention_threshold(now, 1), Some(now - TimeDelta::days(1)));
+        assert_eq!(retention_threshold(now, u64::MAX), None);
+        assert_eq!(retention_threshold(now, i64::MAX as u64), None);
+    }
+}
@@ -1,46 +1,207 @@
 use anyhow::Error;
+use chrono::{DateTime, Utc};
 use log::debug;
 use log::error;
 use log::info;
-use pinglow_common::{PinglowCheck, ScheduledCheck};
+use opentelemetry::{
+    trace::{SpanKind, TraceContextExt},
+    Context,
+};
+use pinglow_common::{
+    redis::{checks_stream, ROUTED_CHECKS_STREAMS_KEY},
+    telemetry::{inject_context, start_span, TRACE_CONTEXT_FIELD},
+    PinglowCheck, ScheduledCheck,
+};
 use redis::Client as RedisClient;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{DefaultHasher, Hash, Hasher};
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::select;
+use tokio_util::sync::CancellationToken;
 
-use tokio::{sync::mpsc, time::Instant};
+use tokio::{
+    sync::{mpsc, watch},
+    time::Instant,
+};
 
 use crate::check::SharedPinglowChecks;
+use crate::config::PinglowConfig;
+use crate::job::JobExecutor;
+use crate::leader::Leadership;
+use crate::metrics;
 use pinglow_common::error::SerializeError;
 
+// Redis hash holding the next run of each check while the scheduler is handed over
+const HANDOFF_KEY: &str = "pinglow:scheduler:handoff";
+
+// Maximum jitter, as a percentage of the interval, so that a run never comes before the previous one
+const MAX_JITTER_PERCENT: u64 = 50;
+
+/// How the runs of the checks are spread over time, to avoid load spikes on the runners and
+/// the targets when many checks share the same interval
+#[derive(Debug, Clone, Copy)]
+pub struct SchedulerOptions {
+    pub jitter_percent: u64, // Random offset of every run, up to this percentage of the interval
+    pub stagger: bool,       // Spread the first run of the checks over their interval
+    pub max_dispatch_per_second: u32, // 0 for no limit
+}
+
+impl SchedulerOptions {
+    pub fn from_config(config: &PinglowConfig) -> Self {
+        Self {
+            jitter_percent: config.scheduler_jitter_percent.min(MAX_JITTER_PERCENT),
+            stagger: config.scheduler_stagger,
+            max_dispatch_per_second: config.scheduler_max_dispatch_per_second,
+        }
+    }
+}
+
+/// The checks dispatched within the current second, bounded by max_dispatch_per_second
+struct DispatchBudget {
+    max_per_second: u32,
+    window_start: Instant,
+    dispatched: u32,
+}
+
+impl DispatchBudget {
+    fn new(max_per_second: u32) -> Self {
+        Self {
+            max_per_second,
+            window_start: Instant::now(),
+            dispatched: 0,
+        }
+    }
+
+    /**
+     * This function tells whether another check can be dispatched in the current second,
+     * counting it if so
+     */
+    fn take(&mut self) -> bool {
+        if self.max_per_second == 0 {
+            return true;
+        }
+
+        if self.window_start.elapsed() >= Duration::from_secs(1) {
+            self.window_start = Instant::now();
+            self.dispatched = 0;
+        }
+
+        if self.dispatched >= self.max_per_second {
+            return false;
+        }
+
+        self.dispatched += 1;
+        true
+    }
+
+    /**
+     * This function returns when the dispatch can resume, if the budget of the current second is
+     * exhausted
+     */
+    fn exhausted_until(&self) -> Option<Instant> {
+        let next_window = self.window_start + Duration::from_secs(1);
+
+        (self.max_per_second > 0
+            && self.dispatched >= self.max_per_second
+            && next_window > Instant::now())
+        .then_some(next_window)
+    }
+}
+
+/**
+ * This function returns the delay before the first run of a check. When staggering, the checks
+ * are spread over their interval by a hash of their name, which keeps the offset of a check
+ * stable across restarts
+ */
+fn first_run_delay(check_name: &str, interval: u64, stagger: bool) -> Duration {
+    if !stagger || interval == 0 {
+        return Duration::from_secs(interval);
+    }
+
+    let mut hasher = DefaultHasher::new();
+    check_name.hash(&mut hasher);
+
+    Duration::from_millis(hasher.finish() % (interval * 1000))
+}
+
+/**
+ * This function returns the run following the given one, one interval later and moved by a
+ * random jitter of up to jitter_percent of the interval in either direction. The runs which
+ * would already be due, for a check delayed by more than its interval, are skipped
+ */
+fn following_run(
+    previous_run: Instant,
+    now: Instant,
+    interval: u64,
+    jitter_percent: u64,
+) -> Instant {
+    let mut next_run = previous_run + Duration::from_secs(interval);
+    if interval > 0 && next_run <= now {
+        let missed = (now - next_run).as_secs() / interval + 1;
+        next_run += Duration::from_secs(missed * interval);
+    }
+    let max_jitter = interval * 1000 * jitter_percent / 100;
+
+    if max_jitter == 0 {
+        return next_run;
+    }
+
+    next_run - Duration::from_millis(max_jitter)
+        + Duration::from_millis(fastrand::u64(0..=2 * max_jitter))
+}
+
+/// The scheduled checks ordered by next run, the name of the check making every key unique even
+/// when several checks are due at the same instant
+type CheckQueue = BTreeMap<(Instant, String), ScheduledCheck>;
+
+/**
+ * This function adds a check to the queue, keyed by its next run and its name
+ */
+fn schedule(queue: &mut CheckQueue, scheduled_check: ScheduledCheck) {
+    queue.insert(
+        (
+            scheduled_check.next_run,
+            scheduled_check.check.check_name.clone(),
+        ),
+        scheduled_check,
+    );
+}
+
 pub enum RunnableCheckEvent {
     AddOrUpdate(Arc<PinglowCheck>),
-    Remove(String), // check_name
+    Remove(String),              // check_name
+    Synced(watch::Sender<bool>), // Set once the checks sent before it are all loaded
 }
 
 /**
  * This function handles the addition/update and removal of checks when an events on the Kube side occurs
  */
-async fn handle_check_event(
+fn handle_check_event(
     event: RunnableCheckEvent,
-    queue: &mut BTreeMap<Instant, ScheduledCheck>,
+    queue: &mut CheckQueue,
     shared_checks: SharedPinglowChecks,
+    handoff: &mut HashMap<String, Instant>,
+    options: &SchedulerOptions,
 ) {
     match event {
         RunnableCheckEvent::AddOrUpdate(check) => {
             let check_name = check.check_name.clone();
 
-            shared_checks
-                .write()
-                .await
-                .insert(check_name.clone(), check.clone());
+            shared_checks.insert(check_name.clone(), check.clone());
 
             // Skip putting in queue passive checks
             if check.passive {
                 return;
             }
 
+            // Take suspended checks out of the queue, they stay visible in the API
+            if check.suspended {
+                queue.retain(|_i, scheduled_check| scheduled_check.check.check_name != check_name);
+                handoff.remove(&check_name);
+                return;
+            }
+
             // Skip check where check interval is node defined (should not happen though)
             let interval = if let Some(interval) = check.interval {
                 interval
@@ -56,109 +217,503 @@ async fn handle_check_event(
 
             let next_run = if let Some(removed) = removed {
                 removed.next_run
+            } else if let Some(next_run) = handoff.remove(&check_name) {
+                // Resume the schedule exported by the previous instance
+                next_run
             } else {
-                Instant::now() + Duration::from_secs(interval)
+                Instant::now() + first_run_delay(&check_name, interval, options.stagger)
             };
 
-            queue.insert(next_run, ScheduledCheck { next_run, check });
+            schedule(queue, ScheduledCheck { next_run, check });
         }
         RunnableCheckEvent::Remove(check_name) => {
-            shared_checks.write().await.remove(&check_name);
+            shared_checks.remove(&check_name);
             queue.retain(|_i, scheduled_check| scheduled_check.check.check_name != check_name);
         }
+        RunnableCheckEvent::Synced(synced) => {
+            synced.send_replace(true);
+        }
+    }
+}
+
+/**
+ * This function moves the checks already queued to the next run exported by the previous leader
+ */
+fn resume_handoff(queue: &mut CheckQueue, handoff: &mut HashMap<String, Instant>) {
+    let resumed: Vec<ScheduledCheck> = queue
+        .extract_if(.., |(_, check_name), _| handoff.contains_key(check_name))
+        .map(|(_, scheduled_check)| scheduled_check)
+        .collect();
+
+    for mut scheduled_check in resumed {
+        if let Some(next_run) = handoff.remove(&scheduled_check.check.check_name) {
+            scheduled_check.next_run = next_run;
+        }
+        schedule(queue, scheduled_check);
     }
 }
 
 /**
- * This function continuously schedule checks based on the interval
+ * This function continuously schedule checks based on the interval. A standby keeps its queue up
+ * to date, but dispatches the checks only once it leads
  */
 pub async fn scheduler_loop(
     mut event_rx: mpsc::Receiver<RunnableCheckEvent>,
     shared_checks: SharedPinglowChecks,
     redis_client: RedisClient,
+    job_executor: JobExecutor,
+    options: SchedulerOptions,
+    mut leadership: Leadership,
+    shutdown: CancellationToken,
 ) {
-    let mut queue: BTreeMap<Instant, ScheduledCheck> = BTreeMap::new();
+    let mut queue = CheckQueue::new();
+    let mut budget = DispatchBudget::new(options.max_dispatch_per_second);
+    let mut handoff: HashMap<String, Instant> = HashMap::new();
+    let mut leading = false;
 
     info!("Starting checks scheduling");
 
     // Continuosly loop
     loop {
+        let was_leading = leading;
+        leading = leadership.is_leader();
+
+        // Import the schedule exported by the previous instance, if any, when taking over
+        if leading && !was_leading {
+            handoff = match import_queue(&redis_client).await {
+                Ok(handoff) => handoff,
+                Err(e) => {
+                    error!("Error importing the scheduler queue: {e}");
+                    HashMap::new()
+                }
+            };
+            resume_handoff(&mut queue, &mut handoff);
+        }
+
         // Check if there's a scheduled task
-        if let Some((_check_instant, mut scheduled_check)) =
-            queue.iter().next().map(|(k, v)| (*k, v.clone()))
-        {
+        if let Some(scheduled_check) = queue.values().next().cloned().filter(|_| leading) {
             debug!("Next check is {scheduled_check:?}");
 
+            // Wait for the next second when its dispatch budget is exhausted
+            let wake_up = match budget.exhausted_until() {
+                Some(until) => until.max(scheduled_check.next_run),
+                None => scheduled_check.next_run,
+            };
+
             let now = Instant::now();
-            let delay = scheduled_check.next_run.saturating_duration_since(now);
+            let delay = wake_up.saturating_duration_since(now);
 
             select! {
+                _ = shutdown.cancelled() => {
+                    break;
+                }
                 maybe_event = event_rx.recv() => {
                     if let Some(event) = maybe_event {
-                        handle_check_event(event, &mut queue, shared_checks.clone()).await
+                        handle_check_event(event, &mut queue, shared_checks.clone(), &mut handoff, &options)
                     }
                 }
                 _ = tokio::time::sleep(delay) => {
-                    // Check still valid? (not removed)
-                    if !shared_checks
-                        .read()
-                        .await
-                        .contains_key(&scheduled_check.check.check_name)
-                    {
-                        continue; // Skip deleted check
+                    dispatch_due_checks(&mut queue, &shared_checks, &redis_client, &job_executor, &options, &mut budget).await;
+                }
+            }
+        } else {
+            // No scheduled checks, or not leading, wait for events
+            select! {
+                _ = shutdown.cancelled() => {
+                    break;
+                }
+                maybe_event = event_rx.recv() => {
+                    if let Some(event) = maybe_event {
+                        handle_check_event(event, &mut queue, shared_checks.clone(), &mut handoff, &options)
                     }
+                }
+                _ = leadership.acquired(), if !leading => {}
+            }
+        }
+    }
 
-                    // Skip checks if interval is not defined
-                    let check_interval = if let Some(interval) = scheduled_check.check.interval {
-                        Duration::from_secs(interval)
-                    } else {
-                        continue;
-                    };
+    // Export the queue so that the next leader can take over the schedule
+    if leadership.is_leader() {
+        match export_queue(&redis_client, &queue).await {
+            Ok(()) => info!("Exported {} scheduled check(s)", queue.len()),
+            Err(e) => error!("Error exporting the scheduler queue: {e}"),
+        }
+    }
+}
+
+/**
+ * This function dispatches every check whose next run has passed, within the dispatch budget,
+ * enqueueing the ones for the runners in a single batch, and schedules their next run. The
+ * checks exceeding the budget stay due until the next second
+ */
+async fn dispatch_due_checks(
+    queue: &mut CheckQueue,
+    shared_checks: &SharedPinglowChecks,
+    redis_client: &RedisClient,
+    job_executor: &JobExecutor,
+    options: &SchedulerOptions,
+    budget: &mut DispatchBudget,
+) {
+    let now = Instant::now();
+    let mut to_enqueue: Vec<Arc<PinglowCheck>> = vec![];
+    let mut rescheduled: Vec<ScheduledCheck> = vec![];
 
-                    // Remove the check since it is being executed
-                    queue.retain(|_i, check_in_queue| check_in_queue.check.check_name != scheduled_check.check.check_name);
+    while let Some(entry) = queue.first_entry() {
+        if entry.get().next_run > now {
+            break;
+        }
 
-                    let mut redis_conn = redis_client
-                    .get_multiplexed_async_connection()
-                    .await
-                    .expect("Cannot get connection to redis");
+        // Skip deleted checks and checks without interval, they are not rescheduled
+        let check = &entry.get().check;
+        if !shared_checks.contains_key(&check.check_name) {
+            entry.remove();
+            continue;
+        }
+        let Some(interval) = check.interval else {
+            entry.remove();
+            continue;
+        };
 
-                    redis_conn.set_response_timeout(Duration::from_secs(30));
+        if !budget.take() {
+            break;
+        }
 
-                    // Send the task in the queue
-                    if let Err(e) = enqueue_check(&mut redis_conn, &scheduled_check.check).await {
-                        error!("Error sending check to execution queue: {e}")
-                    }
+        let mut scheduled_check = entry.remove();
 
-                    // Schedule the next run
-                    scheduled_check.next_run += check_interval;
-                    queue.insert(scheduled_check.next_run, scheduled_check);
-                }
-            }
+        if scheduled_check.check.job.is_some() {
+            // Run the check in its own Kubernetes Job
+            let job_executor = job_executor.clone();
+            let check = scheduled_check.check.clone();
+            tokio::spawn(async move { job_executor.execute(check).await });
+            metrics::CHECKS_SCHEDULED.inc();
         } else {
-            // No scheduled checks, wait for events
-            if let Some(event) = event_rx.recv().await {
-                handle_check_event(event, &mut queue, shared_checks.clone()).await
-            }
+            to_enqueue.push(scheduled_check.check.clone());
         }
+
+        // Schedule the next run, keeping the slot of the check while skipping the missed runs
+        scheduled_check.next_run = following_run(
+            scheduled_check.next_run,
+            now,
+            interval,
+            options.jitter_percent,
+        );
+        rescheduled.push(scheduled_check);
+    }
+
+    for scheduled_check in rescheduled {
+        schedule(queue, scheduled_check);
+    }
+
+    if to_enqueue.is_empty() {
+        return;
+    }
+
+    let mut redis_conn = redis_client
+        .get_multiplexed_async_connection()
+        .await
+        .expect("Cannot get connection to redis");
+
+    redis_conn.set_response_timeout(Duration::from_secs(30));
+
+    // Send the tasks in the queue
+    match enqueue_checks(&mut redis_conn, &to_enqueue).await {
+        Ok(ids) => {
+            metrics::CHECKS_SCHEDULED.inc_by(ids.len() as u64);
+            debug!("Enqueued {} check(s)", ids.len())
+        }
+        Err(e) => error!("Error sending checks to execution queue: {e}"),
     }
 }
 
-pub async fn enqueue_check(
-    conn: &mut redis::aio::MultiplexedConnection,
-    check: &Arc<PinglowCheck>,
-) -> Result<String, Error> {
-    let payload = serde_json::to_string(check.as_ref())
-        .map_err(|e| SerializeError::SerializationError(format!("Error serializing check: {e}")))?;
-
-    // XADD pinglow:tasks * payload "<json>"
-    let id: String = redis::cmd("XADD")
-        .arg("pinglow:checks")
-        .arg("*")
-        .arg("payload")
-        .arg(payload)
-        .query_async(conn)
+/**
+ * This function writes the next run of every scheduled check in redis, as a wall-clock timestamp
+ */
+async fn export_queue(redis_client: &RedisClient, queue: &CheckQueue) -> Result<(), Error> {
+    let mut redis_conn = redis_client.get_multiplexed_async_connection().await?;
+
+    let now = Instant::now();
+    let wall_clock_now = Utc::now();
+
+    let mut cmd = redis::cmd("HSET");
+    cmd.arg(HANDOFF_KEY);
+
+    for scheduled_check in queue.values() {
+        let delay = scheduled_check.next_run.saturating_duration_since(now);
+        let next_run = wall_clock_now + delay;
+        cmd.arg(&scheduled_check.check.check_name)
+            .arg(next_run.timestamp_millis());
+    }
+
+    if !queue.is_empty() {
+        cmd.query_async::<()>(&mut redis_conn).await?;
+    }
+
+    Ok(())
+}
+
+/**
+ * This function reads and consumes the schedule exported by a previous instance
+ */
+async fn import_queue(redis_client: &RedisClient) -> Result<HashMap<String, Instant>, Error> {
+    let mut redis_conn = redis_client.get_multiplexed_async_connection().await?;
+
+    let exported: HashMap<String, i64> = redis::cmd("HGETALL")
+        .arg(HANDOFF_KEY)
+        .query_async(&mut redis_conn)
         .await?;
 
-    Ok(id)
+    redis::cmd("DEL")
+        .arg(HANDOFF_KEY)
+        .query_async::<()>(&mut redis_conn)
+        .await?;
+
+    let now = Instant::now();
+    let wall_clock_now = Utc::now();
+
+    let handoff: HashMap<String, Instant> = exported
+        .into_iter()
+        .filter_map(|(check_name, next_run)| {
+            let next_run = DateTime::<Utc>::from_timestamp_millis(next_run)?;
+            // Checks already due are run as soon as possible
+            let delay = (next_run - wall_clock_now).to_std().unwrap_or_default();
+            Some((check_name, now + delay))
+        })
+        .collect();
+
+    info!("Imported {} scheduled check(s)", handoff.len());
+
+    Ok(handoff)
+}
+
+/**
+ * This function adds the checks to the execution queue in a single round trip. A check with a
+ * runner selector goes to the stream of the matching runners. Each execution starts a new trace,
+ * whose context travels with the check to the runner
+ */
+pub async fn enqueue_checks(
+    conn: &mut redis::aio::MultiplexedConnection,
+    checks: &[Arc<PinglowCheck>],
+) -> Result<Vec<String>, Error> {
+    let mut pipe = redis::pipe();
+    let mut spans = Vec::with_capacity(checks.len());
+
+    for check in checks {
+        let payload = serde_json::to_string(check.as_ref()).map_err(|e| {
+            SerializeError::SerializationError(format!("Error serializing check: {e}"))
+        })?;
+
+        let cx = start_span(
+            "enqueue check",
+            SpanKind::Producer,
+            &Context::new(),
+            &check.check_name,
+        );
+
+        let stream = checks_stream(&check.runner_selector);
+
+        // XADD <stream> * payload "<json>" trace "<context>"
+        pipe.cmd("XADD")
+            .arg(&stream)
+            .arg("*")
+            .arg("payload")
+            .arg(payload)
+            .arg(TRACE_CONTEXT_FIELD)
+            .arg(inject_context(&cx));
+
+        // The routed streams are tracked to reclaim their checks and compute their backlog
+        if !check.runner_selector.is_empty() {
+            pipe.cmd("SADD")
+                .arg(ROUTED_CHECKS_STREAMS_KEY)
+                .arg(&stream)
+                .ignore();
+        }
+
+        spans.push(cx);
+    }
+
+    let ids = pipe.query_async::<Vec<String>>(conn).await;
+
+    for cx in spans {
+        cx.span().end();
+    }
+
+    Ok(ids?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use dashmap::DashMap;
+    use serde_json::json;
+
+    const CHECKS: usize = 5000;
+
+    fn check(check_name: &str, interval: u64) -> Arc<PinglowCheck> {
+        Arc::new(
+            serde_json::from_value(json!({
+                "check_name": check_name,
+                "passive": false,
+                "interval": interval,
+            }))
+            .expect("Invalid check"),
+        )
+    }
+
+    fn options(stagger: bool) -> SchedulerOptions {
+        SchedulerOptions {
+            jitter_percent: 0,
+            stagger,
+            max_dispatch_per_second: 0,
+        }
+    }
+
+    fn add_checks(
+        queue: &mut CheckQueue,
+        shared_checks: &SharedPinglowChecks,
+        handoff: &mut HashMap<String, Instant>,
+        options: &SchedulerOptions,
+    ) {
+        for i in 0..CHECKS {
+            handle_check_event(
+                RunnableCheckEvent::AddOrUpdate(check(&format!("check-{i}"), 60)),
+                queue,
+                shared_checks.clone(),
+                handoff,
+                options,
+            );
+        }
+    }
+
+    #[test]
+    fn same_interval_checks_are_all_scheduled() {
+        let mut queue = CheckQueue::new();
+        let shared_checks: SharedPinglowChecks = Arc::new(DashMap::new());
+
+        add_checks(
+            &mut queue,
+            &shared_checks,
+            &mut HashMap::new(),
+            &options(false),
+        );
+
+        assert_eq!(queue.len(), CHECKS);
+        assert_eq!(shared_checks.len(), CHECKS);
+    }
+
+    #[test]
+    fn checks_due_at_the_same_instant_are_all_scheduled() {
+        let mut queue = CheckQueue::new();
+        let shared_checks: SharedPinglowChecks = Arc::new(DashMap::new());
+
+        // Every check resumes the same next run from the handoff
+        let next_run = Instant::now() + Duration::from_secs(30);
+        let mut handoff: HashMap<String, Instant> = (0..CHECKS)
+            .map(|i| (format!("check-{i}"), next_run))
+            .collect();
+
+        add_checks(&mut queue, &shared_checks, &mut handoff, &options(false));
+
+        assert_eq!(queue.len(), CHECKS);
+        assert!(queue.values().all(|sc| sc.next_run == next_run));
+    }
+
+    #[test]
+    fn staggered_checks_are_spread_over_their_interval() {
+        let mut queue = CheckQueue::new();
+        let shared_checks: SharedPinglowChecks = Arc::new(DashMap::new());
+
+        add_checks(
+            &mut queue,
+            &shared_checks,
+            &mut HashMap::new(),
+            &options(true),
+        );
+
+        assert_eq!(queue.len(), CHECKS);
+
+        // The first runs fall in every tenth of the interval
+        let mut tenths = [0; 10];
+        for i in 0..CHECKS {
+            let delay = first_run_delay(&format!("check-{i}"), 60, true);
+            assert!(delay < Duration::from_secs(60));
+            tenths[delay.as_secs() as usize / 6] += 1;
+        }
+        assert!(tenths.iter().all(|count| *count > 0));
+    }
+
+    #[test]
+    fn updated_and_removed_checks_leave_no_duplicates() {
+        let mut queue = CheckQueue::new();
+        let shared_checks: SharedPinglowChecks = Arc::new(DashMap::new());
+        let mut handoff = HashMap::new();
+        let options = options(false);
+
+        add_checks(&mut queue, &shared_checks, &mut handoff, &options);
+        add_checks(&mut queue, &shared_checks, &mut handoff, &options);
+        assert_eq!(queue.len(), CHECKS);
+
+        for i in 0..CHECKS / 2 {
+            handle_check_event(
+                RunnableCheckEvent::Remove(format!("check-{i}")),
+                &mut queue,
+                shared_checks.clone(),
+                &mut handoff,
+                &options,
+            );
+        }
+
+        assert_eq!(queue.len(), CHECKS - CHECKS / 2);
+        assert_eq!(shared_checks.len(), CHECKS - CHECKS / 2);
+    }
+
+    #[test]
+    fn rescheduled_checks_keep_their_slot() {
+        let mut queue = CheckQueue::new();
+        let next_run = Instant::now();
+
+        for i in 0..CHECKS {
+            schedule(
+                &mut queue,
+                ScheduledCheck {
+                    next_run,
+                    check: check(&format!("check-{i}"), 60),
+                },
+            );
+        }
+
+        // Pop every due check and schedule its next run, as the dispatch does
+        let mut rescheduled = vec![];
+        while let Some(entry) = queue.first_entry() {
+            let mut scheduled_check = entry.remove();
+            scheduled_check.next_run = following_run(scheduled_check.next_run, next_run, 60, 0);
+            rescheduled.push(scheduled_check);
+        }
+        for scheduled_check in rescheduled {
+            schedule(&mut queue, scheduled_check);
+        }
+
+        assert_eq!(queue.len(), CHECKS);
+        assert!(queue
+            .values()
+            .all(|sc| sc.next_run == next_run + Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn delayed_checks_skip_the_missed_runs() {
+        let previous_run = Instant::now();
+
+        // A check delayed by the dispatch budget, or an overloaded scheduler, is not due again
+        let now = previous_run + Duration::from_secs(150);
+        assert_eq!(
+            following_run(previous_run, now, 60, 0),
+            previous_run + Duration::from_secs(180)
+        );
+
+        let now = previous_run + Duration::from_secs(60);
+        assert_eq!(
+            following_run(previous_run, now, 60, 0),
+            previous_run + Duration::from_secs(120)
+        );
+    }
 }
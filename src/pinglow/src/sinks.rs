@@ -0,0 +1,207 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use log::{error, warn};
+use pinglow_common::CheckResult;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, mpsc};
+use tokio_util::task::TaskTracker;
+
+use crate::{config::PinglowConfig, kafka::KafkaExporter, webhook::Webhooks};
+
+// Results buffered for every live subscriber, the slower ones skip the oldest results
+const LIVE_CAPACITY: usize = 1024;
+
+// Redis channel through which the leader shares the results it processes with the standbys
+const LIVE_CHANNEL: &str = "pinglow:results:live";
+
+// Delay before subscribing again to the live results after losing the subscription
+const LIVE_RESUBSCRIBE_DELAY_SECONDS: u64 = 5;
+
+/// The destinations mirroring every processed check result, independently of notifications
+pub struct ResultSinks {
+    pub webhooks: Webhooks,
+    pub kafka: Option<KafkaExporter>,
+    pub live: broadcast::Sender<ExportedResult>,
+    live_fanout: Option<mpsc::Sender<ExportedResult>>, // Results published to every replica
+    deliveries: TaskTracker, // Background deliveries of the webhooks and Kafka
+}
+
+/// The stable representation of a check result sent to the sinks
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ExportedResult {
+    pub check_name: String,
+    pub status: i16,
+    pub output: String,
+    pub long_output: Option<String>,
+    pub perf_data: Vec<(String, f32)>,
+    pub timestamp: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run_id: Option<String>, // Run requested through the API producing the result
+}
+
+impl From<&CheckResult> for ExportedResult {
+    fn from(result: &CheckResult) -> Self {
+        ExportedResult {
+            check_name: result.check_name.clone(),
+            status: result.status.to_number(),
+            output: result.get_output(),
+            long_output: result.get_long_output(),
+            perf_data: result
+                .get_perf_data()
+                .into_iter()
+                .map(|perf_data| (perf_data.label, perf_data.value))
+                .collect(),
+            timestamp: result.timestamp.unwrap_or_else(Utc::now),
+            run_id: result.run_id.clone(),
+        }
+    }
+}
+
+impl ResultSinks {
+    /**
+     * This function builds the sinks. With the leader election, the live results go through
+     * Redis, so that the replicas which do not process them can stream them as well
+     */
+    pub fn new(
+        config: &PinglowConfig,
+        http_client: reqwest::Client,
+        redis_client: &redis::Client,
+    ) -> Self {
+        let deliveries = TaskTracker::new();
+        let live = broadcast::channel(LIVE_CAPACITY).0;
+
+        let live_fanout = config.leader_election.then(|| {
+            let (fanout, results) = mpsc::channel(LIVE_CAPACITY);
+            tokio::spawn(publish_live_results(redis_client.clone(), results));
+            tokio::spawn(subscribe_live_results(redis_client.clone(), live.clone()));
+            fanout
+        });
+
+        ResultSinks {
+            webhooks: Webhooks::new(config, http_client, deliveries.clone()),
+            kafka: KafkaExporter::new(config, deliveries.clone()),
+            live,
+            live_fanout,
+            deliveries,
+        }
+    }
+
+    /**
+     * This function waits for the deliveries in progress to complete, on shutdown
+     */
+    pub async fn flush(&self) {
+        self.deliveries.close();
+        self.deliveries.wait().await;
+    }
+
+    /**
+     * This function subscribes to the results processed from now on, as streamed by the API
+     */
+    pub fn subscribe(&self) -> broadcast::Receiver<ExportedResult> {
+        self.live.subscribe()
+    }
+
+    /**
+     * This function mirrors the result to all the configured sinks, without waiting for the
+     * deliveries to complete
+     */
+    pub fn send(&self, result: &CheckResult) {
+        self.webhooks.send(result);
+
+        if let Some(kafka) = &self.kafka {
+            kafka.send(result);
+        }
+
+        // Nobody may be listening, which is not an error
+        match &self.live_fanout {
+            Some(fanout) => {
+                if fanout.try_send(result.into()).is_err() {
+                    warn!("Too many live results to publish, skipping one");
+                }
+            }
+            None => {
+                let _ = self.live.send(result.into());
+            }
+        }
+    }
+}
+
+/**
+ * This function publishes the live results of this replica to all the replicas, itself included
+ */
+async fn publish_live_results(
+    redis_client: redis::Client,
+    mut results: mpsc::Receiver<ExportedResult>,
+) {
+    let mut conn = None;
+
+    while let Some(result) = results.recv().await {
+        let payload = match serde_json::to_string(&result) {
+            Ok(payload) => payload,
+            Err(e) => {
+                error!("Cannot serialize the live result: {e}");
+                continue;
+            }
+        };
+
+        if conn.is_none() {
+            match redis_client.get_multiplexed_async_connection().await {
+                Ok(c) => conn = Some(c),
+                Err(e) => {
+                    error!("Error connecting to redis to publish the live results: {e}");
+                    continue;
+                }
+            }
+        }
+
+        if let Some(c) = conn.as_mut() {
+            if let Err(e) = redis::cmd("PUBLISH")
+                .arg(LIVE_CHANNEL)
+                .arg(payload)
+                .query_async::<()>(c)
+                .await
+            {
+                error!("Error publishing the live result: {e}");
+                conn = None;
+            }
+        }
+    }
+}
+
+/**
+ * This function feeds the live results of this replica with the ones published by the leader,
+ * subscribing again whenever the subscription is lost
+ */
+async fn subscribe_live_results(
+    redis_client: redis::Client,
+    live: broadcast::Sender<ExportedResult>,
+) {
+    loop {
+        match redis_client.get_async_pubsub().await {
+            Ok(mut pubsub) => match pubsub.subscribe(LIVE_CHANNEL).await {
+                Ok(()) => {
+                    let mut messages = pubsub.on_message();
+                    while let Some(message) = messages.next().await {
+                        let result = message
+                            .get_payload::<String>()
+                            .map_err(anyhow::Error::from)
+                            .and_then(|payload| Ok(serde_json::from_str(&payload)?));
+                        match result {
+                            Ok(result) => {
+                                let _ = live.send(result);
+                            }
+                            Err(e) => error!("Cannot read the live result: {e}"),
+                        }
+                    }
+                    warn!("Lost the subscription to the live results");
+                }
+                Err(e) => error!("Error subscribing to the live results: {e}"),
+            },
+            Err(e) => error!("Error connecting to redis to receive the live results: {e}"),
+        }
+
+        tokio::time::sleep(Duration::from_secs(LIVE_RESUBSCRIBE_DELAY_SECONDS)).await;
+    }
+}
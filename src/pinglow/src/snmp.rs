@@ -0,0 +1,589 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use anyhow::Error;
+use chrono::Utc;
+use deadpool_postgres::Pool;
+use futures::StreamExt;
+use kube::{
+    runtime::{reflector, watcher, WatchStreamExt},
+    Api, Client,
+};
+use log::{debug, error, info, warn};
+use pinglow_common::{CheckResult, CheckResultStatus};
+use tokio::net::UdpSocket;
+
+use crate::{
+    check::{
+        qualify_check_ref, SharedPinglowChecks, SharedRedirects, SnmpTrapRule, SnmpTrapRuleSpec,
+    },
+    config::PinglowConfig,
+    process_or_buffer_check_result,
+    sinks::ResultSinks,
+};
+
+// The varbind holding the OID of a SNMPv2 trap
+const SNMP_TRAP_OID: &str = "1.3.6.1.6.3.1.1.4.1.0";
+
+// The prefix of the SNMPv2 OIDs of the generic SNMPv1 traps (RFC 3584)
+const SNMP_TRAPS_OID: &str = "1.3.6.1.6.3.1.1.5";
+
+// The generic trap number of the SNMPv1 traps defined by the enterprise
+const ENTERPRISE_SPECIFIC_TRAP: i64 = 6;
+
+// SNMP versions
+const VERSION_1: i64 = 0;
+const VERSION_2C: i64 = 1;
+
+// BER tags
+const TAG_INTEGER: u8 = 0x02;
+const TAG_OCTET_STRING: u8 = 0x04;
+const TAG_NULL: u8 = 0x05;
+const TAG_OID: u8 = 0x06;
+const TAG_SEQUENCE: u8 = 0x30;
+const TAG_IP_ADDRESS: u8 = 0x40;
+const TAG_COUNTER32: u8 = 0x41;
+const TAG_GAUGE32: u8 = 0x42;
+const TAG_TIMETICKS: u8 = 0x43;
+const TAG_COUNTER64: u8 = 0x46;
+const TAG_SNMPV1_TRAP: u8 = 0xA4;
+const TAG_SNMPV2_TRAP: u8 = 0xA7;
+
+#[derive(Debug)]
+pub struct SnmpTrap {
+    pub community: String,
+    pub trap_oid: String,
+    pub varbinds: Vec<(String, String)>,
+}
+
+/**
+ * This function listens for SNMPv1 and SNMPv2c traps and converts the ones matching a SnmpTrapRule
+ * into results of the referenced passive check
+ */
+#[allow(clippy::too_many_arguments)]
+pub async fn run(
+    client: Client,
+    config: PinglowConfig,
+    shared_checks: SharedPinglowChecks,
+    db_pool: Pool,
+    redis_client: redis::Client,
+    http_client: reqwest::Client,
+    redirects: SharedRedirects,
+    sinks: Arc<ResultSinks>,
+) -> Result<(), Error> {
+    let Some(address) = config.snmp_trap_address.clone() else {
+        return Ok(());
+    };
+
+    // Keep the trap rules in sync with the cluster
+    let rules_api: Api<SnmpTrapRule> = Api::namespaced(client, &config.target_namespace);
+    let (rules, writer) = reflector::store();
+    tokio::spawn(
+        reflector(writer, watcher(rules_api, watcher::Config::default()))
+            .applied_objects()
+            .for_each(|rule| async move {
+                if let Err(e) = rule {
+                    error!("Error watching SNMP trap rules: {e}");
+                }
+            }),
+    );
+
+    let socket = UdpSocket::bind(&address).await?;
+    info!("Listening for SNMP traps on {address}");
+
+    let mut buffer = vec![0u8; 65535];
+
+    loop {
+        let (size, source) = match socket.recv_from(&mut buffer).await {
+            Ok(received) => received,
+            Err(e) => {
+                error!("Error receiving SNMP trap: {e}");
+                continue;
+            }
+        };
+
+        let Some(trap) = parse_trap(&buffer[..size]) else {
+            debug!("Ignoring unsupported SNMP message from {source}");
+            continue;
+        };
+
+        if config
+            .snmp_community
+            .as_ref()
+            .is_some_and(|community| *community != trap.community)
+        {
+            warn!("Ignoring SNMP trap from {source} with an invalid community");
+            continue;
+        }
+
+        for rule in rules
+            .state()
+            .iter()
+            .filter(|r| r.spec.trapOid == trap.trap_oid)
+        {
+            // Rules reference the checks of the other namespaces as namespace/name
+            let check_name = qualify_check_ref(
+                &rule.spec.checkRef,
+                &config.target_namespace,
+                &config.target_namespace,
+            );
+            let result = build_check_result(&rule.spec, &check_name, &trap, source, &shared_checks);
+
+            let Some(result) = result else {
+                warn!(
+                    "SNMP trap rule references the unknown passive check {}",
+                    rule.spec.checkRef
+                );
+                continue;
+            };
+
+            if let Err(e) = process_or_buffer_check_result(
+                result,
+                None,
+                &db_pool,
+                &redis_client,
+                &http_client,
+                &redirects,
+                &sinks,
+            )
+            .await
+            {
+                error!("Error processing SNMP trap result: {e}");
+            }
+        }
+    }
+}
+
+fn build_check_result(
+    rule: &SnmpTrapRuleSpec,
+    check_name: &str,
+    trap: &SnmpTrap,
+    source: SocketAddr,
+    shared_checks: &SharedPinglowChecks,
+) -> Option<CheckResult> {
+    let check = shared_checks.get(check_name)?.value().clone();
+    if !check.passive {
+        return None;
+    }
+
+    // Map the value of the configured varbind, falling back to the default status
+    let mapped_status = rule.statusVarbindOid.as_ref().and_then(|oid| {
+        let (_, value) = trap.varbinds.iter().find(|(name, _)| name == oid)?;
+        rule.statusMapping.as_ref()?.get(value).copied()
+    });
+
+    let status = mapped_status.or(rule.status).unwrap_or(2);
+
+    let varbinds: Vec<String> = trap
+        .varbinds
+        .iter()
+        .filter(|(name, _)| name != SNMP_TRAP_OID)
+        .map(|(name, value)| format!("{name}={value}"))
+        .collect();
+
+    Some(CheckResult {
+        check_name: check.check_name.clone(),
+        output: format!(
+            "SNMP trap {} from {}\n{}",
+            trap.trap_oid,
+            source.ip(),
+            varbinds.join("\n")
+        ),
+        status: CheckResultStatus::from(status),
+        timestamp: Some(Utc::now()),
+        telegram_channels: check.telegram_channels.clone().into(),
+        mute_notifications: check.mute_notifications,
+        mute_notifications_until: check.mute_notifications_until,
+        maintenance_windows: check.maintenance_windows.clone().into(),
+        webhooks: check.webhooks.clone().into(),
+        diagnostics: None,
+        slack_channels: check.slack_channels.clone().into(),
+        push_channels: check.push_channels.clone().into(),
+        discord_channels: check.discord_channels.clone().into(),
+        notify_on_state_change: check.notify_on_state_change,
+        notify_on_recovery: check.notify_on_recovery,
+        renotify_interval_minutes: check.renotify_interval_minutes,
+        min_notification_interval_seconds: check.min_notification_interval_seconds,
+        soft_failure: false,
+        depends_on: check.depends_on.clone().into(),
+        suppressed_by: None,
+        anomaly_sigma: check.anomaly_sigma,
+        script_hash: None,
+        run_id: None,
+    })
+}
+
+/**
+ * This function decodes a SNMPv1 or SNMPv2c trap message, returning None for any other message.
+ * The OID of a SNMPv1 trap is translated to its SNMPv2 form, so that the rules match both
+ */
+pub fn parse_trap(data: &[u8]) -> Option<SnmpTrap> {
+    let (tag, message) = read_tlv(&mut &data[..])?;
+    if tag != TAG_SEQUENCE {
+        return None;
+    }
+
+    let mut message = message;
+
+    let (tag, version) = read_tlv(&mut message)?;
+    if tag != TAG_INTEGER {
+        return None;
+    }
+
+    let (tag, community) = read_tlv(&mut message)?;
+    if tag != TAG_OCTET_STRING {
+        return None;
+    }
+
+    let (tag, mut pdu) = read_tlv(&mut message)?;
+    let (trap_oid, varbinds) = match (decode_integer(version), tag) {
+        (VERSION_1, TAG_SNMPV1_TRAP) => parse_v1_pdu(&mut pdu)?,
+        (VERSION_2C, TAG_SNMPV2_TRAP) => {
+            // request-id, error-status, error-index
+            for _ in 0..3 {
+                read_tlv(&mut pdu)?;
+            }
+
+            let varbinds = read_varbinds(&mut pdu)?;
+            let trap_oid = varbinds
+                .iter()
+                .find(|(name, _)| name == SNMP_TRAP_OID)
+                .map(|(_, value)| value.clone())?;
+
+            (trap_oid, varbinds)
+        }
+        _ => return None,
+    };
+
+    Some(SnmpTrap {
+        community: String::from_utf8_lossy(community).to_string(),
+        trap_oid,
+        varbinds,
+    })
+}
+
+/// Reads the fields of a SNMPv1 Trap-PDU, returning the SNMPv2 OID of the trap and the varbinds
+fn parse_v1_pdu(pdu: &mut &[u8]) -> Option<(String, Vec<(String, String)>)> {
+    let (tag, enterprise) = read_tlv(pdu)?;
+    if tag != TAG_OID {
+        return None;
+    }
+
+    // agent-addr
+    read_tlv(pdu)?;
+
+    let (tag, generic_trap) = read_tlv(pdu)?;
+    if tag != TAG_INTEGER {
+        return None;
+    }
+
+    let (tag, specific_trap) = read_tlv(pdu)?;
+    if tag != TAG_INTEGER {
+        return None;
+    }
+
+    // time-stamp
+    read_tlv(pdu)?;
+
+    let trap_oid = match decode_integer(generic_trap) {
+        ENTERPRISE_SPECIFIC_TRAP => format!(
+            "{}.0.{}",
+            decode_oid(enterprise)?,
+            decode_integer(specific_trap)
+        ),
+        generic_trap @ 0..ENTERPRISE_SPECIFIC_TRAP => {
+            format!("{SNMP_TRAPS_OID}.{}", generic_trap + 1)
+        }
+        _ => return None,
+    };
+
+    Some((trap_oid, read_varbinds(pdu)?))
+}
+
+/// Reads the list of the variable bindings of a PDU as (OID, value) pairs
+fn read_varbinds(pdu: &mut &[u8]) -> Option<Vec<(String, String)>> {
+    let (tag, mut varbind_list) = read_tlv(pdu)?;
+    if tag != TAG_SEQUENCE {
+        return None;
+    }
+
+    let mut varbinds = vec![];
+    while !varbind_list.is_empty() {
+        let (_, mut varbind) = read_tlv(&mut varbind_list)?;
+
+        let (tag, name) = read_tlv(&mut varbind)?;
+        if tag != TAG_OID {
+            return None;
+        }
+
+        let (tag, value) = read_tlv(&mut varbind)?;
+
+        varbinds.push((decode_oid(name)?, decode_value(tag, value)));
+    }
+
+    Some(varbinds)
+}
+
+/// Reads a BER tag-length-value, advancing the input
+fn read_tlv<'a>(data: &mut &'a [u8]) -> Option<(u8, &'a [u8])> {
+    let (&tag, rest) = data.split_first()?;
+    let (&first_length_byte, mut rest) = rest.split_first()?;
+
+    let length = if first_length_byte & 0x80 == 0 {
+        first_length_byte as usize
+    } else {
+        let length_bytes = (first_length_byte & 0x7F) as usize;
+        if length_bytes == 0 || length_bytes > std::mem::size_of::<usize>() {
+            return None;
+        }
+        let (bytes, remaining) = rest.split_at_checked(length_bytes)?;
+        rest = remaining;
+        bytes
+            .iter()
+            .fold(0usize, |length, &byte| (length << 8) | byte as usize)
+    };
+
+    let (value, remaining) = rest.split_at_checked(length)?;
+    *data = remaining;
+
+    Some((tag, value))
+}
+
+fn decode_integer(data: &[u8]) -> i64 {
+    let initial: i64 = if data.first().is_some_and(|b| b & 0x80 != 0) {
+        -1
+    } else {
+        0
+    };
+
+    data.iter()
+        .fold(initial, |value, &byte| (value << 8) | byte as i64)
+}
+
+fn decode_unsigned(data: &[u8]) -> u64 {
+    data.iter()
+        .fold(0u64, |value, &byte| (value << 8) | byte as u64)
+}
+
+fn decode_oid(data: &[u8]) -> Option<String> {
+    let (&first, rest) = data.split_first()?;
+
+    let mut components = vec![(first / 40) as u64, (first % 40) as u64];
+
+    let mut current = 0u64;
+    for &byte in rest {
+        current = (current << 7) | (byte & 0x7F) as u64;
+        if byte & 0x80 == 0 {
+            components.push(current);
+            current = 0;
+        }
+    }
+
+    Some(
+        components
+            .iter()
+            .map(|c| c.to_string())
+            .collect::<Vec<_>>()
+            .join("."),
+    )
+}
+
+fn decode_value(tag: u8, data: &[u8]) -> String {
+    match tag {
+        TAG_INTEGER => decode_integer(data).to_string(),
+        TAG_OCTET_STRING => String::from_utf8_lossy(data).to_string(),
+        TAG_NULL => String::new(),
+        TAG_OID => decode_oid(data).unwrap_or_default(),
+        TAG_IP_ADDRESS => data
+            .iter()
+            .map(|b| b.to_string())
+            .collect::<Vec<_>>()
+            .join("."),
+        TAG_COUNTER32 | TAG_GAUGE32 | TAG_TIMETICKS | TAG_COUNTER64 => {
+            decode_unsigned(data).to_string()
+        }
+        _ => data.iter().map(|b| format!("{b:02x}")).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tlv(tag: u8, value: &[u8]) -> Vec<u8> {
+        let mut encoded = vec![tag, value.len() as u8];
+        encoded.extend_from_slice(value);
+        encoded
+    }
+
+    fn oid(oid: &str) -> Vec<u8> {
+        let components: Vec<u64> = oid.split('.').map(|c| c.parse().unwrap()).collect();
+
+        let mut encoded = vec![(components[0] * 40 + components[1]) as u8];
+        for &component in &components[2..] {
+            let mut bytes = vec![(component & 0x7F) as u8];
+            let mut rest = component >> 7;
+            while rest > 0 {
+                bytes.push((rest & 0x7F) as u8 | 0x80);
+                rest >>= 7;
+            }
+            encoded.extend(bytes.iter().rev());
+        }
+
+        tlv(TAG_OID, &encoded)
+    }
+
+    fn varbind(name: &str, value: Vec<u8>) -> Vec<u8> {
+        tlv(TAG_SEQUENCE, &[oid(name), value].concat())
+    }
+
+    fn message(version: u8, pdu: Vec<u8>) -> Vec<u8> {
+        tlv(
+            TAG_SEQUENCE,
+            &[
+                tlv(TAG_INTEGER, &[version]),
+                tlv(TAG_OCTET_STRING, b"public"),
+                pdu,
+            ]
+            .concat(),
+        )
+    }
+
+    fn v2c_trap() -> Vec<u8> {
+        let varbinds = [
+            varbind("1.3.6.1.2.1.1.3.0", tlv(TAG_TIMETICKS, &[0x01, 0x00])),
+            varbind(SNMP_TRAP_OID, oid("1.3.6.1.6.3.1.1.5.3")),
+            varbind("1.3.6.1.2.1.2.2.1.1.2", tlv(TAG_INTEGER, &[0x02])),
+        ]
+        .concat();
+
+        let pdu = [
+            tlv(TAG_INTEGER, &[0x2A]),
+            tlv(TAG_INTEGER, &[0x00]),
+            tlv(TAG_INTEGER, &[0x00]),
+            tlv(TAG_SEQUENCE, &varbinds),
+        ]
+        .concat();
+
+        message(1, tlv(TAG_SNMPV2_TRAP, &pdu))
+    }
+
+    fn v1_trap(generic_trap: u8, specific_trap: u8) -> Vec<u8> {
+        let pdu = [
+            oid("1.3.6.1.4.1.9"),
+            tlv(TAG_IP_ADDRESS, &[10, 0, 0, 1]),
+            tlv(TAG_INTEGER, &[generic_trap]),
+            tlv(TAG_INTEGER, &[specific_trap]),
+            tlv(TAG_TIMETICKS, &[0x01, 0x00]),
+            tlv(
+                TAG_SEQUENCE,
+                &varbind("1.3.6.1.2.1.2.2.1.1.2", tlv(TAG_INTEGER, &[0x02])),
+            ),
+        ]
+        .concat();
+
+        message(0, tlv(TAG_SNMPV1_TRAP, &pdu))
+    }
+
+    #[test]
+    fn parses_v2c_traps() {
+        let trap = parse_trap(&v2c_trap()).unwrap();
+
+        assert_eq!(trap.community, "public");
+        assert_eq!(trap.trap_oid, "1.3.6.1.6.3.1.1.5.3");
+        assert_eq!(
+            trap.varbinds,
+            vec![
+                ("1.3.6.1.2.1.1.3.0".to_string(), "256".to_string()),
+                (SNMP_TRAP_OID.to_string(), "1.3.6.1.6.3.1.1.5.3".to_string()),
+                ("1.3.6.1.2.1.2.2.1.1.2".to_string(), "2".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_v1_traps() {
+        // linkDown
+        let trap = parse_trap(&v1_trap(2, 0)).unwrap();
+        assert_eq!(trap.community, "public");
+        assert_eq!(trap.trap_oid, "1.3.6.1.6.3.1.1.5.3");
+        assert_eq!(
+            trap.varbinds,
+            vec![("1.3.6.1.2.1.2.2.1.1.2".to_string(), "2".to_string())]
+        );
+
+        let trap = parse_trap(&v1_trap(6, 42)).unwrap();
+        assert_eq!(trap.trap_oid, "1.3.6.1.4.1.9.0.42");
+
+        assert!(parse_trap(&v1_trap(7, 0)).is_none());
+    }
+
+    #[test]
+    fn rejects_other_messages() {
+        // A SNMPv3 message and a v2c trap sent with the version of SNMPv1
+        let mut v3 = v2c_trap();
+        v3[4] = 3;
+        assert!(parse_trap(&v3).is_none());
+
+        let mut mismatched = v2c_trap();
+        mismatched[4] = 0;
+        assert!(parse_trap(&mismatched).is_none());
+
+        // A v2c trap without the snmpTrapOID varbind
+        let pdu = [
+            tlv(TAG_INTEGER, &[0x2A]),
+            tlv(TAG_INTEGER, &[0x00]),
+            tlv(TAG_INTEGER, &[0x00]),
+            tlv(
+                TAG_SEQUENCE,
+                &varbind("1.3.6.1.2.1.1.3.0", tlv(TAG_TIMETICKS, &[0x01])),
+            ),
+        ]
+        .concat();
+        assert!(parse_trap(&message(1, tlv(TAG_SNMPV2_TRAP, &pdu))).is_none());
+    }
+
+    #[test]
+    fn rejects_truncated_traps() {
+        for trap in [v2c_trap(), v1_trap(2, 0)] {
+            for length in 0..trap.len() {
+                assert!(
+                    parse_trap(&trap[..length]).is_none(),
+                    "prefix of {length} bytes"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_lengths() {
+        // The outer length exceeds the data
+        let mut trap = v2c_trap();
+        trap[1] += 1;
+        assert!(parse_trap(&trap).is_none());
+
+        // The length of the community exceeds the message
+        let mut trap = v2c_trap();
+        trap[6] = 0x7F;
+        assert!(parse_trap(&trap).is_none());
+
+        // Indefinite length
+        assert!(parse_trap(&[TAG_SEQUENCE, 0x80, 0x00, 0x00]).is_none());
+
+        // Long form lengths exceeding the data or a usize
+        assert!(parse_trap(&[TAG_SEQUENCE, 0x84, 0xFF, 0xFF, 0xFF, 0xFF, 0x00]).is_none());
+        assert!(parse_trap(&[TAG_SEQUENCE, 0x89, 0x01, 0, 0, 0, 0, 0, 0, 0, 0x00]).is_none());
+        assert!(parse_trap(&[
+            TAG_SEQUENCE,
+            0x88,
+            0xFF,
+            0xFF,
+            0xFF,
+            0xFF,
+            0xFF,
+            0xFF,
+            0xFF,
+            0xFF
+        ])
+        .is_none());
+    }
+}
@@ -0,0 +1,363 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use k8s_openapi::api::core::v1::Secret;
+use log::{error, info};
+use pinglow_common::{
+    error::SerializeError,
+    manifests::{manifests_fingerprint, read_manifests},
+    ConcreteDiscordChannel, ConcretePushChannel, ConcreteSlackChannel, ConcreteTelegramChannel,
+    PinglowCheck, Script,
+};
+use tokio::sync::{mpsc::Sender, watch};
+
+use crate::{
+    check::{Check, DiscordChannel, PushChannel, SlackChannel, TelegramChannel},
+    error::ReconcileError,
+    exit_code_mapping,
+    scheduler::RunnableCheckEvent,
+    script_annotations, validate_parameters,
+};
+
+// How often the configuration directory is checked for changes
+const RELOAD_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The resources defined in the local configuration directory
+#[derive(Default)]
+pub struct LocalResources {
+    pub checks: Vec<Check>,
+    pub scripts: HashMap<String, Script>,
+    pub telegram_channels: HashMap<String, TelegramChannel>,
+    pub slack_channels: HashMap<String, SlackChannel>,
+    pub push_channels: HashMap<String, PushChannel>,
+    pub discord_channels: HashMap<String, DiscordChannel>,
+    pub secrets: HashMap<String, Secret>,
+}
+
+/**
+ * This function reads the checks, scripts, channels and secrets defined in the directory
+ */
+pub fn load_resources(dir: &Path) -> Result<LocalResources, ReconcileError> {
+    let manifests = read_manifests(dir)
+        .map_err(|e| ReconcileError::GeneralError(format!("Error reading manifests: {e}")))?;
+
+    let mut resources = LocalResources::default();
+
+    for manifest in manifests {
+        let parse_error = |e: SerializeError| ReconcileError::GeneralError(e.to_string());
+
+        match manifest.kind.as_str() {
+            "Check" => resources
+                .checks
+                .push(manifest.parse().map_err(parse_error)?),
+            "Script" => {
+                resources.scripts.insert(
+                    manifest.name.clone(),
+                    manifest.parse().map_err(parse_error)?,
+                );
+            }
+            "TelegramChannel" => {
+                resources.telegram_channels.insert(
+                    manifest.name.clone(),
+                    manifest.parse().map_err(parse_error)?,
+                );
+            }
+            "SlackChannel" => {
+                resources.slack_channels.insert(
+                    manifest.name.clone(),
+                    manifest.parse().map_err(parse_error)?,
+                );
+            }
+            "PushChannel" => {
+                resources.push_channels.insert(
+                    manifest.name.clone(),
+                    manifest.parse().map_err(parse_error)?,
+                );
+            }
+            "DiscordChannel" => {
+                resources.discord_channels.insert(
+                    manifest.name.clone(),
+                    manifest.parse().map_err(parse_error)?,
+                );
+            }
+            "Secret" => {
+                resources.secrets.insert(
+                    manifest.name.clone(),
+                    manifest.parse().map_err(parse_error)?,
+                );
+            }
+            kind => info!("Ignoring unsupported resource {kind} {}", manifest.name),
+        }
+    }
+
+    Ok(resources)
+}
+
+impl LocalResources {
+    /**
+     * This function returns the values of a secret, merging its data and stringData
+     */
+    fn secret_values(&self, secret_name: &str) -> Result<HashMap<String, String>, ReconcileError> {
+        let secret = self
+            .secrets
+            .get(secret_name)
+            .ok_or(ReconcileError::SecretNotFound(secret_name.to_string()))?;
+
+        let mut values: HashMap<String, String> = secret
+            .data
+            .iter()
+            .flatten()
+            .map(|(key, value)| (key.clone(), String::from_utf8_lossy(&value.0).to_string()))
+            .collect();
+
+        values.extend(secret.string_data.clone().unwrap_or_default());
+
+        Ok(values)
+    }
+
+    fn secret_value(&self, secret_name: &str, key: &str) -> Result<String, ReconcileError> {
+        self.secret_values(secret_name)?
+            .remove(key)
+            .ok_or(ReconcileError::SecretNotFound(key.to_owned()))
+    }
+
+    /**
+     * This function builds the runnable version of a check, resolving its references
+     * against the local resources
+     */
+    pub fn runnable_check(&self, check: &Check) -> Result<PinglowCheck, ReconcileError> {
+        let check_name = check
+            .metadata
+            .name
+            .clone()
+            .unwrap_or("Unnamed check".to_string());
+
+        let script = check
+            .spec
+            .scriptRef
+            .as_ref()
+            .map(|script_name| {
+                self.scripts
+                    .get(script_name)
+                    .ok_or(ReconcileError::ScriptNotFound(script_name.clone()))
+            })
+            .transpose()?;
+
+        if let Some(script) = script {
+            validate_parameters(check, &script.spec)?;
+        }
+
+        let mut telegram_channels = vec![];
+        for channel_name in check.spec.telegramChannelRefs.iter().flatten() {
+            let channel = self.telegram_channels.get(channel_name).ok_or(
+                ReconcileError::TelegramChannelNotFound(channel_name.clone()),
+            )?;
+
+            telegram_channels.push(ConcreteTelegramChannel {
+                chat_id: channel.spec.chatId.clone(),
+                bot_token: self.secret_value(&channel.spec.botTokenRef, "botToken")?,
+                proxy: channel.spec.proxy.clone(),
+                message_template: channel.spec.messageTemplate.clone(),
+            });
+        }
+
+        let mut slack_channels = vec![];
+        for channel_name in check.spec.slackChannelRefs.iter().flatten() {
+            let channel = self
+                .slack_channels
+                .get(channel_name)
+                .ok_or(ReconcileError::SlackChannelNotFound(channel_name.clone()))?;
+
+            slack_channels.push(ConcreteSlackChannel {
+                name: channel_name.clone(),
+                webhook_url: self.secret_value(&channel.spec.webhookUrlRef, "webhookUrl")?,
+                message_template: channel.spec.messageTemplate.clone(),
+            });
+        }
+
+        let mut push_channels = vec![];
+        for channel_name in check.spec.pushChannelRefs.iter().flatten() {
+            let channel = self
+                .push_channels
+                .get(channel_name)
+                .ok_or(ReconcileError::PushChannelNotFound(channel_name.clone()))?;
+
+            push_channels.push(ConcretePushChannel {
+                name: channel_name.clone(),
+                service: channel.spec.service,
+                server_url: channel.spec.serverUrl.clone(),
+                topic: channel.spec.topic.clone(),
+                token: channel
+                    .spec
+                    .tokenRef
+                    .as_ref()
+                    .map(|token_ref| self.secret_value(token_ref, "token"))
+                    .transpose()?,
+                priorities: channel.spec.priorities.clone().unwrap_or_default(),
+                message_template: channel.spec.messageTemplate.clone(),
+            });
+        }
+
+        let mut discord_channels = vec![];
+        for channel_name in check.spec.discordChannelRefs.iter().flatten() {
+            let channel = self
+                .discord_channels
+                .get(channel_name)
+                .ok_or(ReconcileError::DiscordChannelNotFound(channel_name.clone()))?;
+
+            discord_channels.push(ConcreteDiscordChannel {
+                name: channel_name.clone(),
+                webhook_url: self.secret_value(&channel.spec.webhookUrlRef, "webhookUrl")?,
+                message_template: channel.spec.messageTemplate.clone(),
+            });
+        }
+
+        // Secrets are resolved by the runners from the same directory
+        for secret_name in check.spec.secretRefs.iter().flatten() {
+            self.secret_values(secret_name)?;
+        }
+
+        let hmac_key = check
+            .spec
+            .hmacSecretRef
+            .as_ref()
+            .map(|secret_name| self.secret_value(secret_name, "hmacKey"))
+            .transpose()?;
+
+        let heartbeat_token = check
+            .spec
+            .heartbeatTokenRef
+            .as_ref()
+            .map(|secret_name| self.secret_value(secret_name, "heartbeatToken"))
+            .transpose()?;
+
+        Ok(PinglowCheck {
+            passive: check.spec.passive,
+            script_ref: check.spec.scriptRef.clone(),
+            script_hash: script.map(|s| s.spec.content_hash()),
+            interval: check.spec.interval,
+            namespace: None,
+            check_name,
+            secrets: None,
+            telegram_channels,
+            slack_channels,
+            push_channels,
+            discord_channels,
+            notify_on_state_change: check.spec.notifyOnStateChange.unwrap_or(false),
+            notify_on_recovery: check.spec.notifyOnRecovery.unwrap_or(false),
+            renotify_interval_minutes: check.spec.renotifyIntervalMinutes,
+            min_notification_interval_seconds: check.spec.minNotificationIntervalSeconds,
+            retries: check.spec.retries.unwrap_or(0),
+            retry_delay_seconds: check.spec.retryDelaySeconds,
+            depends_on: check.spec.dependsOn.clone().unwrap_or_default(),
+            timeout_seconds: check
+                .spec
+                .timeoutSeconds
+                .or(script.and_then(|s| s.spec.timeout_seconds)),
+            mute_notifications: check.spec.muteNotifications,
+            mute_notifications_until: check.spec.muteNotificationsUntil,
+            mute_notifications_since: check.spec.muteNotificationsSince,
+            maintenance_windows: check.spec.maintenanceWindows.clone().unwrap_or_default(),
+            suspended: check.spec.suspend.unwrap_or(false),
+            result_retention_days: check.spec.resultRetentionDays,
+            webhooks: check.spec.webhooks.clone().unwrap_or_default(),
+            args: check.spec.args.clone().unwrap_or_default(),
+            env: check.spec.env.clone().unwrap_or_default(),
+            parameters: check.spec.parameters.clone().unwrap_or_default(),
+            labels: check.metadata.labels.clone().unwrap_or_default(),
+            annotations: script_annotations(check),
+            exit_code_mapping: exit_code_mapping(check),
+            thresholds: check.spec.thresholds.clone().unwrap_or_default(),
+            anomaly_sigma: check.spec.anomalySigma,
+            secret_refs: check.spec.secretRefs.clone().unwrap_or_default(),
+            secret_versions: BTreeMap::new(),
+            job: check.spec.job.clone(),
+            http_check: check.spec.httpCheck.clone(),
+            tcp_check: check.spec.tcpCheck.clone(),
+            runner_selector: check.spec.runnerSelector.clone().unwrap_or_default(),
+            hmac_key,
+            heartbeat_token,
+            run_id: None,
+        })
+    }
+}
+
+/**
+ * This function loads the checks from the local configuration directory and reloads them
+ * whenever the content of the directory changes, in place of the Kubernetes watchers
+ */
+pub async fn watch_resources(
+    dir: PathBuf,
+    event_tx: Sender<RunnableCheckEvent>,
+    synced: watch::Sender<bool>,
+) {
+    let mut synced = Some(synced);
+    let mut last_fingerprint = None;
+    let mut loaded_checks: HashSet<String> = HashSet::new();
+
+    loop {
+        match manifests_fingerprint(&dir) {
+            Ok(fingerprint) if Some(fingerprint) != last_fingerprint => {
+                match reload_checks(&dir, &event_tx, &mut loaded_checks).await {
+                    Ok(()) => {
+                        last_fingerprint = Some(fingerprint);
+
+                        // The first load completes the synchronization of the checks
+                        if let Some(synced) = synced.take() {
+                            event_tx.send(RunnableCheckEvent::Synced(synced)).await.ok();
+                        }
+                    }
+                    Err(e) => error!("Error loading checks from {}: {e}", dir.display()),
+                }
+            }
+            Ok(_) => {}
+            Err(e) => error!("Error reading {}: {e}", dir.display()),
+        }
+
+        tokio::time::sleep(RELOAD_INTERVAL).await;
+    }
+}
+
+async fn reload_checks(
+    dir: &Path,
+    event_tx: &Sender<RunnableCheckEvent>,
+    loaded_checks: &mut HashSet<String>,
+) -> Result<(), ReconcileError> {
+    let resources = load_resources(dir)?;
+
+    // Resolve all the checks first, so that an invalid configuration is not applied partially
+    let checks = resources
+        .checks
+        .iter()
+        .map(|check| resources.runnable_check(check))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let check_names: HashSet<String> = checks.iter().map(|c| c.check_name.clone()).collect();
+
+    for removed in loaded_checks.difference(&check_names) {
+        event_tx
+            .send(RunnableCheckEvent::Remove(removed.clone()))
+            .await
+            .ok();
+    }
+
+    for check in checks {
+        event_tx
+            .send(RunnableCheckEvent::AddOrUpdate(Arc::new(check)))
+            .await
+            .ok();
+    }
+
+    info!(
+        "Loaded {:?} check(s) from {}",
+        check_names.len(),
+        dir.display()
+    );
+    *loaded_checks = check_names;
+
+    Ok(())
+}
@@ -0,0 +1,256 @@
+use std::{
+    collections::HashMap,
+    sync::LazyLock,
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
+use deadpool_postgres::Pool;
+use handlebars::Handlebars;
+use pinglow_common::{
+    dto::{StatusPageCheckDto, StatusPageDto},
+    CheckResultStatus,
+};
+use rocket::{
+    get, http::Status, response::content::RawHtml, response::status, serde::json::Json, State,
+};
+use serde_json::json;
+use tokio::sync::Mutex;
+
+use crate::{api::matches_labels, check::SharedPinglowChecks, config::PinglowConfig};
+
+// Annotation of a check holding the name shown on the status page
+const DISPLAY_NAME_ANNOTATION: &str = "pinglow.io/display-name";
+
+// How long the summary is served before being computed again, as the endpoint is public
+const CACHE_TTL: Duration = Duration::from_secs(30);
+
+// How far back the results are read, bounding the time a check is reported in its status since
+const SINCE_WINDOW_DAYS: i64 = 30;
+
+// The last summary computed, with the time it was computed. The lock is held while computing it,
+// so that the concurrent requests wait for it rather than querying the database too
+static CACHE: LazyLock<Mutex<Option<(Instant, StatusPageDto)>>> =
+    LazyLock::new(|| Mutex::new(None));
+
+const STATUS_PAGE_TEMPLATE: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="utf-8" />
+    <meta name="viewport" content="width=device-width, initial-scale=1" />
+    <meta http-equiv="refresh" content="60" />
+    <title>Status</title>
+    <style>
+        body { font-family: sans-serif; max-width: 48rem; margin: 2rem auto; padding: 0 1rem; color: #222; }
+        .banner { padding: 1rem; border-radius: 0.5rem; color: #fff; font-size: 1.25rem; }
+        ul { list-style: none; padding: 0; }
+        li { display: flex; justify-content: space-between; padding: 0.75rem 0; border-bottom: 1px solid #ddd; }
+        .since, footer { color: #777; font-size: 0.875rem; }
+        .Ok { background: #2e7d32; } .Warning { background: #ed6c02; } .Critical { background: #c62828; }
+        .CheckError, .Pending { background: #757575; }
+        .status { color: #fff; padding: 0.125rem 0.5rem; border-radius: 0.25rem; }
+    </style>
+</head>
+<body>
+    <div class="banner {{status}}">{{summary}}</div>
+    <ul>
+        {{#each checks}}
+        <li>
+            <span>{{name}}{{#if since}} <span class="since">since {{since}}</span>{{/if}}</span>
+            <span class="status {{status}}">{{label}}</span>
+        </li>
+        {{/each}}
+    </ul>
+    <footer>Updated at {{updated_at}}</footer>
+</body>
+</html>
+"#;
+
+// Renderer of the status page, escaping the values as HTML
+static TEMPLATES: LazyLock<Handlebars<'static>> = LazyLock::new(|| {
+    let mut handlebars = Handlebars::new();
+    handlebars
+        .register_template_string("status_page", STATUS_PAGE_TEMPLATE)
+        .expect("Valid status page template");
+    handlebars
+});
+
+/**
+ * This function returns the wording of a status for the visitors of the status page
+ */
+fn status_label(status: &CheckResultStatus) -> &'static str {
+    match status {
+        CheckResultStatus::Ok => "Operational",
+        CheckResultStatus::Warning => "Degraded",
+        CheckResultStatus::Critical => "Outage",
+        CheckResultStatus::CheckError | CheckResultStatus::Pending => "Unknown",
+    }
+}
+
+/**
+ * This function returns the summary of the status page, computed at most once per CACHE_TTL
+ */
+async fn cached_status_page(
+    checks: &SharedPinglowChecks,
+    config: &PinglowConfig,
+    db_pool: &Pool,
+) -> Result<StatusPageDto, status::Custom<String>> {
+    let mut cache = CACHE.lock().await;
+
+    if let Some((computed_at, page)) = cache.as_ref() {
+        if computed_at.elapsed() < CACHE_TTL {
+            return Ok(page.clone());
+        }
+    }
+
+    let page = status_page(checks, config, db_pool).await?;
+    *cache = Some((Instant::now(), page.clone()));
+
+    Ok(page)
+}
+
+/**
+ * This function builds the summary of the checks matching the status page label: their display
+ * name, their last status other than a soft failure and when they entered it, within the last
+ * SINCE_WINDOW_DAYS
+ */
+async fn status_page(
+    checks: &SharedPinglowChecks,
+    config: &PinglowConfig,
+    db_pool: &Pool,
+) -> Result<StatusPageDto, status::Custom<String>> {
+    let client = db_pool.get().await.map_err(|e| {
+        status::Custom(
+            Status::ServiceUnavailable,
+            format!("Error getting a database connection: {e}"),
+        )
+    })?;
+
+    let selector = config.status_page_label.as_deref().unwrap_or_default();
+
+    let mut public_checks: Vec<(String, String)> = checks
+        .iter()
+        .filter(|check| matches_labels(&check.labels, &[selector]))
+        .map(|check| {
+            let display_name = check
+                .annotations
+                .get(DISPLAY_NAME_ANNOTATION)
+                .cloned()
+                .unwrap_or_else(|| check.check_name.clone());
+            (check.check_name.clone(), display_name)
+        })
+        .collect();
+    public_checks.sort_by(|a, b| a.1.cmp(&b.1));
+
+    let check_names: Vec<&String> = public_checks.iter().map(|(name, _)| name).collect();
+    let window_start = Utc::now() - chrono::Duration::days(SINCE_WINDOW_DAYS);
+
+    // The status changed with the first result following the last one with a different status,
+    // or at the start of the window if there is none within it
+    let rows = client
+        .query(
+            "SELECT names.check_name, r.status,
+                (SELECT MIN(c.timestamp) FROM check_result c WHERE c.check_name = names.check_name AND NOT c.soft_failure AND c.timestamp >= $2
+                    AND c.timestamp > COALESCE((SELECT MAX(d.timestamp) FROM check_result d WHERE d.check_name = names.check_name AND NOT d.soft_failure AND d.timestamp >= $2 AND d.status <> r.status), '-infinity'::timestamptz)) AS since
+            FROM UNNEST($1::text[]) AS names(check_name)
+            JOIN LATERAL (
+                SELECT status FROM check_result WHERE check_name = names.check_name AND NOT soft_failure AND timestamp >= $2 ORDER BY timestamp DESC LIMIT 1
+            ) r ON TRUE",
+            &[&check_names, &window_start],
+        )
+        .await
+        .map_err(|e| {
+            status::Custom(
+                Status::InternalServerError,
+                format!("Error retrieving the status of the checks: {e}"),
+            )
+        })?;
+
+    let statuses: HashMap<String, (i16, Option<DateTime<Utc>>)> = rows
+        .iter()
+        .map(|row| (row.get("check_name"), (row.get("status"), row.get("since"))))
+        .collect();
+
+    // The checks without results are pending, the least severe status
+    let status = statuses
+        .values()
+        .map(|(status, _)| CheckResultStatus::from(*status))
+        .max_by_key(CheckResultStatus::severity)
+        .unwrap_or(CheckResultStatus::Pending);
+
+    let checks: Vec<StatusPageCheckDto> = public_checks
+        .into_iter()
+        .map(|(check_name, name)| {
+            let (status, since) = match statuses.get(&check_name) {
+                Some((status, since)) => (CheckResultStatus::from(*status), *since),
+                None => (CheckResultStatus::Pending, None),
+            };
+            StatusPageCheckDto {
+                name,
+                status,
+                since,
+            }
+        })
+        .collect();
+
+    Ok(StatusPageDto {
+        status,
+        updated_at: Utc::now(),
+        checks,
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/status-page",
+    responses(
+        (status = 200, description = "The public summary of the checks matching STATUS_PAGE_LABEL, rendered as HTML unless JSON is accepted", body = StatusPageDto)
+    ),
+    security(())
+)]
+#[get("/status-page", format = "json")]
+pub async fn get_status_page(
+    checks: &State<SharedPinglowChecks>,
+    config: &State<PinglowConfig>,
+    db_pool: &State<Pool>,
+) -> Result<Json<StatusPageDto>, status::Custom<String>> {
+    Ok(Json(cached_status_page(checks, config, db_pool).await?))
+}
+
+#[get("/status-page", rank = 2)]
+pub async fn get_status_page_html(
+    checks: &State<SharedPinglowChecks>,
+    config: &State<PinglowConfig>,
+    db_pool: &State<Pool>,
+) -> Result<RawHtml<String>, status::Custom<String>> {
+    let page = cached_status_page(checks, config, db_pool).await?;
+
+    let summary = match page.status {
+        CheckResultStatus::Ok => "All systems operational",
+        CheckResultStatus::Warning => "Some systems are degraded",
+        CheckResultStatus::Critical => "Some systems are experiencing an outage",
+        CheckResultStatus::CheckError | CheckResultStatus::Pending => "The status is unknown",
+    };
+
+    let data = json!({
+        "status": format!("{:?}", page.status),
+        "summary": summary,
+        "updated_at": page.updated_at.format("%Y-%m-%d %H:%M UTC").to_string(),
+        "checks": page.checks.iter().map(|check| json!({
+            "name": check.name,
+            "status": format!("{:?}", check.status),
+            "label": status_label(&check.status),
+            "since": check.since.map(|since| since.format("%Y-%m-%d %H:%M UTC").to_string()),
+        })).collect::<Vec<_>>(),
+    });
+
+    TEMPLATES
+        .render("status_page", &data)
+        .map(RawHtml)
+        .map_err(|e| {
+            status::Custom(
+                Status::InternalServerError,
+                format!("Error rendering the status page: {e}"),
+            )
+        })
+}
@@ -0,0 +1,161 @@
+use std::collections::HashMap;
+
+use anyhow::Error;
+use deadpool_postgres::Pool;
+use pinglow_common::redis::{checks_streams, parse_range_entries};
+use redis::{aio::MultiplexedConnection, Client as RedisClient, Value};
+use serde::Deserialize;
+
+// Prefix of the keys marking the checks recently deleted, whose late results are dropped
+const DELETED_CHECK_KEY_PREFIX: &str = "pinglow:deleted:";
+
+// Time a deleted check is remembered, longer than any execution
+const DELETED_CHECK_TTL_SECONDS: u64 = 3600;
+
+// Entries of a checks stream scanned at once
+const SCAN_BATCH_SIZE: usize = 500;
+
+// Tables holding the history of the checks, purged along with them when configured
+const HISTORY_TABLES: [&str; 6] = [
+    "check_result",
+    "check_result_perf_data",
+    "check_diagnostics",
+    "check_ack",
+    "check_flapping",
+    "perf_baseline",
+];
+
+/// The part of a queued check identifying it
+#[derive(Deserialize)]
+struct QueuedCheck {
+    check_name: String,
+}
+
+/**
+ * This function marks a check as deleted, so that the results of its executions still in flight
+ * are dropped, and removes its executions still waiting in the checks streams. It returns the
+ * number of removed executions
+ */
+pub async fn remove_queued_executions(
+    redis_client: &RedisClient,
+    check_name: &str,
+) -> Result<usize, Error> {
+    let mut conn = redis_client.get_multiplexed_async_connection().await?;
+
+    redis::cmd("SET")
+        .arg(format!("{DELETED_CHECK_KEY_PREFIX}{check_name}"))
+        .arg(1)
+        .arg("EX")
+        .arg(DELETED_CHECK_TTL_SECONDS)
+        .query_async::<()>(&mut conn)
+        .await?;
+
+    let mut removed = 0;
+
+    for stream in checks_streams(&mut conn).await? {
+        removed += remove_from_stream(&mut conn, &stream, check_name).await?;
+    }
+
+    Ok(removed)
+}
+
+/**
+ * This function deletes the entries of a check from a checks stream, acking the ones already
+ * delivered so that they are not reclaimed
+ */
+async fn remove_from_stream(
+    conn: &mut MultiplexedConnection,
+    stream: &str,
+    check_name: &str,
+) -> Result<usize, Error> {
+    let mut start = "-".to_string();
+    let mut removed = 0;
+
+    loop {
+        let reply: Value = redis::cmd("XRANGE")
+            .arg(stream)
+            .arg(&start)
+            .arg("+")
+            .arg("COUNT")
+            .arg(SCAN_BATCH_SIZE)
+            .query_async(conn)
+            .await?;
+
+        let entries = parse_range_entries(reply).unwrap_or_default();
+
+        let ids: Vec<&String> = entries
+            .iter()
+            .filter(|(_, fields)| queued_check_name(fields).as_deref() == Some(check_name))
+            .map(|(id, _)| id)
+            .collect();
+
+        if !ids.is_empty() {
+            redis::cmd("XDEL")
+                .arg(stream)
+                .arg(&ids)
+                .query_async::<()>(conn)
+                .await?;
+
+            // The group of a routed stream exists only once a matching runner started
+            let _: Result<(), _> = redis::cmd("XACK")
+                .arg(stream)
+                .arg("workers")
+                .arg(&ids)
+                .query_async(conn)
+                .await;
+
+            removed += ids.len();
+        }
+
+        match entries.last() {
+            Some((last_id, _)) if entries.len() == SCAN_BATCH_SIZE => start = format!("({last_id}"),
+            _ => break,
+        }
+    }
+
+    Ok(removed)
+}
+
+/**
+ * This function returns the name of the check queued in a stream entry
+ */
+fn queued_check_name(fields: &HashMap<String, String>) -> Option<String> {
+    let payload = fields.get("payload")?;
+    serde_json::from_str::<QueuedCheck>(payload)
+        .ok()
+        .map(|check| check.check_name)
+}
+
+/**
+ * This function tells whether a check was recently deleted
+ */
+pub async fn is_deleted(
+    conn: &mut MultiplexedConnection,
+    check_name: &str,
+) -> Result<bool, redis::RedisError> {
+    redis::cmd("EXISTS")
+        .arg(format!("{DELETED_CHECK_KEY_PREFIX}{check_name}"))
+        .query_async(conn)
+        .await
+}
+
+/**
+ * This function deletes the results, performance data, diagnostics and the rest of the history
+ * of a check, returning the number of deleted rows
+ */
+pub async fn purge_history(db_pool: &Pool, check_name: &str) -> Result<u64, Error> {
+    let postgres_client = db_pool.get().await?;
+
+    let mut deleted = 0;
+
+    for table in HISTORY_TABLES {
+        deleted += postgres_client
+            .execute(
+                &format!("DELETE FROM {table} WHERE check_name = $1"),
+                &[&check_name],
+            )
+            .await?;
+    }
+
+    Ok(deleted)
+}
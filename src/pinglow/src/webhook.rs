@@ -0,0 +1,114 @@
+use std::time::Duration;
+
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use log::{debug, error};
+use pinglow_common::CheckResult;
+use sha2::Sha256;
+use tokio_util::task::TaskTracker;
+
+use crate::{config::PinglowConfig, sinks::ExportedResult};
+
+/// The webhooks receiving every processed check result as JSON
+#[derive(Clone, Debug)]
+pub struct Webhooks {
+    urls: Vec<String>,
+    secret: Option<String>,
+    max_retries: u32,
+    http_client: reqwest::Client,
+    deliveries: TaskTracker,
+}
+
+impl Webhooks {
+    pub fn new(
+        config: &PinglowConfig,
+        http_client: reqwest::Client,
+        deliveries: TaskTracker,
+    ) -> Self {
+        Webhooks {
+            urls: config.webhook_urls.clone(),
+            secret: config.webhook_secret.clone(),
+            max_retries: config.webhook_max_retries,
+            http_client,
+            deliveries,
+        }
+    }
+
+    /**
+     * This function sends the result to the global webhooks and the ones of its check.
+     * Deliveries happen in the background so that retries never slow down the results pipeline
+     */
+    pub fn send(&self, result: &CheckResult) {
+        let urls: Vec<String> = self
+            .urls
+            .iter()
+            .chain(result.webhooks.iter())
+            .cloned()
+            .collect();
+
+        if urls.is_empty() {
+            return;
+        }
+
+        let body = match serde_json::to_string(&ExportedResult::from(result)) {
+            Ok(body) => body,
+            Err(e) => {
+                error!("Cannot serialize the webhook payload: {e}");
+                return;
+            }
+        };
+
+        for url in urls {
+            let webhooks = self.clone();
+            let body = body.clone();
+            self.deliveries
+                .spawn(async move { webhooks.deliver(&url, &body).await });
+        }
+    }
+
+    async fn deliver(&self, url: &str, body: &str) {
+        for attempt in 0..=self.max_retries {
+            if attempt > 0 {
+                // Exponential backoff between the attempts
+                tokio::time::sleep(Duration::from_secs(1 << (attempt - 1).min(6))).await;
+            }
+
+            let mut request = self
+                .http_client
+                .post(url)
+                .header("content-type", "application/json")
+                .body(body.to_owned());
+
+            if let Some(secret) = &self.secret {
+                let timestamp = Utc::now().timestamp();
+                request = request
+                    .header("x-pinglow-timestamp", timestamp.to_string())
+                    .header("x-pinglow-signature", sign(secret, timestamp, body));
+            }
+
+            match request.send().await.and_then(|r| r.error_for_status()) {
+                Ok(_) => return,
+                Err(e) => debug!("Webhook delivery to {url} failed (attempt {attempt}): {e}"),
+            }
+        }
+
+        error!(
+            "Giving up delivering result to webhook {url} after {} attempts",
+            self.max_retries + 1
+        );
+    }
+}
+
+/**
+ * This function computes the hex encoded HMAC-SHA256 of `<timestamp>.<body>`, the same scheme
+ * used to verify signed passive results
+ */
+fn sign(secret: &str, timestamp: i64, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body.as_bytes());
+
+    hex::encode(mac.finalize().into_bytes())
+}